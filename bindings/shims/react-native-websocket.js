@@ -0,0 +1,148 @@
+// WebSocket for the webassembly client in a React Native WebView.
+//
+// Install the shim before the module is initialized so the
+// client opens sockets through the native side of the app:
+//
+//   import { installWebSocket } from "./react-native-websocket.js";
+//   installWebSocket();
+//
+// Messages posted to the app are JSON strings:
+//
+//   { type: "open", id, url }
+//   { type: "send", id, data }
+//   { type: "close", id }
+//
+// The app posts JSON strings to the WebView for the events
+// of each socket:
+//
+//   { type: "open", id }
+//   { type: "message", id, data }
+//   { type: "error", id, message }
+//   { type: "close", id, code, reason }
+//
+// Binary frames are base64-encoded.
+
+const CONNECTING = 0;
+const OPEN = 1;
+const CLOSING = 2;
+const CLOSED = 3;
+
+const sockets = new Map();
+let nextId = 0;
+let listening = false;
+
+function post(message) {
+  window.ReactNativeWebView.postMessage(JSON.stringify(message));
+}
+
+function encode(bytes) {
+  let binary = "";
+  for (let i = 0; i < bytes.length; i++) {
+    binary += String.fromCharCode(bytes[i]);
+  }
+  return btoa(binary);
+}
+
+function decode(data) {
+  const binary = atob(data);
+  const bytes = new Uint8Array(binary.length);
+  for (let i = 0; i < binary.length; i++) {
+    bytes[i] = binary.charCodeAt(i);
+  }
+  return bytes.buffer;
+}
+
+function receive(event) {
+  let message;
+  try {
+    message = JSON.parse(event.data);
+  } catch (e) {
+    return;
+  }
+  const socket = message && sockets.get(message.id);
+  if (!socket) {
+    return;
+  }
+  switch (message.type) {
+    case "open":
+      socket.readyState = OPEN;
+      socket.dispatch("onopen", { type: "open" });
+      break;
+    case "message":
+      socket.dispatch("onmessage", {
+        type: "message",
+        data: decode(message.data),
+      });
+      break;
+    case "error":
+      socket.dispatch("onerror", {
+        type: "error",
+        message: message.message,
+      });
+      break;
+    case "close":
+      socket.readyState = CLOSED;
+      sockets.delete(message.id);
+      socket.dispatch("onclose", {
+        type: "close",
+        code: message.code,
+        reason: message.reason,
+      });
+      break;
+  }
+}
+
+export class BridgeWebSocket {
+  constructor(url) {
+    this.url = url;
+    this.binaryType = "arraybuffer";
+    this.readyState = CONNECTING;
+    this.onopen = null;
+    this.onmessage = null;
+    this.onerror = null;
+    this.onclose = null;
+    this.id = nextId++;
+    sockets.set(this.id, this);
+    post({ type: "open", id: this.id, url });
+  }
+
+  send(data) {
+    if (this.readyState !== OPEN) {
+      throw new Error("socket is not open");
+    }
+    const bytes =
+      data instanceof Uint8Array ? data : new Uint8Array(data);
+    post({ type: "send", id: this.id, data: encode(bytes) });
+  }
+
+  close() {
+    if (this.readyState === CLOSING || this.readyState === CLOSED) {
+      return;
+    }
+    this.readyState = CLOSING;
+    post({ type: "close", id: this.id });
+  }
+
+  dispatch(handler, event) {
+    if (typeof this[handler] === "function") {
+      this[handler](event);
+    }
+  }
+}
+
+BridgeWebSocket.CONNECTING = CONNECTING;
+BridgeWebSocket.OPEN = OPEN;
+BridgeWebSocket.CLOSING = CLOSING;
+BridgeWebSocket.CLOSED = CLOSED;
+
+// Replace the WebSocket used by the client.
+export function installWebSocket() {
+  if (!listening) {
+    // Android delivers messages to the document and iOS
+    // to the window
+    window.addEventListener("message", receive);
+    document.addEventListener("message", receive);
+    listening = true;
+  }
+  globalThis.WebSocket = BridgeWebSocket;
+}
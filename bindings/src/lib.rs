@@ -1,12 +1,27 @@
 //! Webassembly bindings for the web platform.
 #![deny(missing_docs)]
 
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod storage;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod types;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use storage::IndexedDbStorage;
+
 #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
 mod bindings {
+    use crate::types::{
+        JsKeySharePromise, JsParticipants, JsPrivateKey,
+        JsSessionOptions, JsSignaturePromise,
+    };
+    use js_sys::{Array, Object, Promise, Reflect};
     use mpc_driver::{PrivateKey, SessionOptions};
     use mpc_protocol::{hex, PATTERN};
     use wasm_bindgen::prelude::*;
-    use wasm_bindgen_futures::future_to_promise;
+    use wasm_bindgen_futures::{
+        future_to_promise, spawn_local, JsFuture,
+    };
+    use web_sys::{DedicatedWorkerGlobalScope, MessageEvent};
 
     /// Initialize the panic hook and logging.
     #[doc(hidden)]
@@ -25,34 +40,34 @@ mod bindings {
     /// Distributed key generation.
     #[wasm_bindgen]
     pub fn keygen(
-        options: JsValue,
-        participants: JsValue,
-    ) -> Result<JsValue, JsError> {
+        options: JsSessionOptions,
+        participants: JsParticipants,
+    ) -> Result<JsKeySharePromise, JsError> {
         let options: SessionOptions =
-            serde_wasm_bindgen::from_value(options)?;
-        let participants = parse_participants(participants)?;
+            serde_wasm_bindgen::from_value(options.into())?;
+        let participants = parse_participants(participants.into())?;
         let fut = async move {
             let key_share =
                 mpc_driver::keygen(options, participants).await?;
             Ok(serde_wasm_bindgen::to_value(&key_share)?)
         };
-        Ok(future_to_promise(fut).into())
+        Ok(future_to_promise(fut).unchecked_into())
     }
 
     /// Sign a message.
     #[wasm_bindgen]
     pub fn sign(
-        options: JsValue,
-        participants: JsValue,
-        signing_key: JsValue,
-        message: JsValue,
-    ) -> Result<JsValue, JsError> {
+        options: JsSessionOptions,
+        participants: JsParticipants,
+        signing_key: JsPrivateKey,
+        message: String,
+    ) -> Result<JsSignaturePromise, JsError> {
         let options: SessionOptions =
-            serde_wasm_bindgen::from_value(options)?;
-        let participants = parse_participants(participants)?;
+            serde_wasm_bindgen::from_value(options.into())?;
+        let participants = parse_participants(participants.into())?;
         let signing_key: PrivateKey =
-            serde_wasm_bindgen::from_value(signing_key)?;
-        let message = parse_message(message)?;
+            serde_wasm_bindgen::from_value(signing_key.into())?;
+        let message = parse_message(&message)?;
         let fut = async move {
             let signature = mpc_driver::sign(
                 options,
@@ -63,7 +78,119 @@ mod bindings {
             .await?;
             Ok(serde_wasm_bindgen::to_value(&signature)?)
         };
-        Ok(future_to_promise(fut).into())
+        Ok(future_to_promise(fut).unchecked_into())
+    }
+
+    /// Drivers for the multi-party computation protocols.
+    ///
+    /// Methods return promises that resolve once the
+    /// session for the protocol has completed.
+    #[wasm_bindgen(js_name = "WebDriver")]
+    pub struct WebDriver;
+
+    #[wasm_bindgen(js_class = "WebDriver")]
+    impl WebDriver {
+        /// Distributed key generation.
+        ///
+        /// Participants are the hex-encoded public keys of
+        /// the participants when the session is created by
+        /// this client.
+        pub fn keygen(
+            options: JsSessionOptions,
+            participants: JsParticipants,
+        ) -> Result<JsKeySharePromise, JsError> {
+            keygen(options, participants)
+        }
+
+        /// Sign a hex-encoded message digest with a key share.
+        pub fn sign(
+            share: JsPrivateKey,
+            digest: String,
+            options: JsSessionOptions,
+            participants: JsParticipants,
+        ) -> Result<JsSignaturePromise, JsError> {
+            sign(options, participants, share, digest)
+        }
+    }
+
+    /// Serve requests to run protocols in a dedicated worker.
+    ///
+    /// Call this function from the worker script once the
+    /// module is initialized so the protocol computations do
+    /// not block the main thread; websockets are available
+    /// in workers so the client runs entirely in the worker.
+    ///
+    /// Post `{ id, method, args }` to the worker where the
+    /// method is `keygen` or `sign` and the arguments are the
+    /// arguments for the `WebDriver` method; the worker posts
+    /// `{ id, result }` or `{ id, error }` in reply.
+    #[wasm_bindgen(js_name = "serveWorker")]
+    pub fn serve_worker() -> Result<(), JsError> {
+        let scope: DedicatedWorkerGlobalScope = js_sys::global()
+            .dyn_into()
+            .map_err(|_| JsError::new("not a dedicated worker"))?;
+        let reply_scope = scope.clone();
+        let onmessage = Closure::<dyn FnMut(MessageEvent)>::new(
+            move |e: MessageEvent| {
+                let scope = reply_scope.clone();
+                spawn_local(async move {
+                    let data = e.data();
+                    let reply = Object::new();
+                    let id = Reflect::get(&data, &"id".into())
+                        .unwrap_or(JsValue::UNDEFINED);
+                    let _ = Reflect::set(&reply, &"id".into(), &id);
+                    let _ = match dispatch(&data).await {
+                        Ok(result) => Reflect::set(
+                            &reply,
+                            &"result".into(),
+                            &result,
+                        ),
+                        Err(error) => Reflect::set(
+                            &reply,
+                            &"error".into(),
+                            &error,
+                        ),
+                    };
+                    if let Err(e) = scope.post_message(&reply) {
+                        log::error!("worker reply failed: {:?}", e);
+                    }
+                });
+            },
+        );
+        scope.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        onmessage.forget();
+        Ok(())
+    }
+
+    /// Run the protocol for a request posted to a worker.
+    async fn dispatch(data: &JsValue) -> Result<JsValue, JsValue> {
+        let method = Reflect::get(data, &"method".into())?
+            .as_string()
+            .unwrap_or_default();
+        let args: Array = Reflect::get(data, &"args".into())?
+            .dyn_into()
+            .unwrap_or_else(|_| Array::new());
+        let promise = match method.as_str() {
+            "keygen" => WebDriver::keygen(
+                args.get(0).unchecked_into(),
+                args.get(1).unchecked_into(),
+            )
+            .map(JsValue::from),
+            "sign" => WebDriver::sign(
+                args.get(0).unchecked_into(),
+                args.get(1).as_string().unwrap_or_default(),
+                args.get(2).unchecked_into(),
+                args.get(3).unchecked_into(),
+            )
+            .map(JsValue::from),
+            _ => {
+                return Err(JsValue::from_str(&format!(
+                    "unknown method {}",
+                    method
+                )))
+            }
+        }?;
+        JsFuture::from(promise.unchecked_into::<Promise>()).await
     }
 
     /// Generate a PEM-encoded keypair.
@@ -84,6 +211,37 @@ mod bindings {
         Ok(serde_wasm_bindgen::to_value(&pem)?)
     }
 
+    /// Import a keypair from a hex-encoded private key.
+    ///
+    /// The public key is derived from the private key and
+    /// the keypair is returned PEM-encoded.
+    #[wasm_bindgen(js_name = "importKeypair")]
+    pub fn import_keypair(
+        private_key: String,
+        pattern: Option<String>,
+    ) -> Result<String, JsError> {
+        let pattern = pattern.unwrap_or_else(|| PATTERN.to_owned());
+        let private_key =
+            hex::decode(private_key).map_err(JsError::from)?;
+        let keypair = mpc_protocol::Keypair::from_private_key(
+            pattern.parse()?,
+            &private_key,
+        )?;
+        Ok(mpc_protocol::encode_keypair(&keypair))
+    }
+
+    /// Hex-encoded public key of a PEM-encoded keypair.
+    ///
+    /// Fails when the public key in the PEM does not belong
+    /// to the private key.
+    #[wasm_bindgen(js_name = "keypairPublicKey")]
+    pub fn keypair_public_key(
+        pem: String,
+    ) -> Result<String, JsError> {
+        let keypair = mpc_protocol::decode_keypair(pem)?;
+        Ok(hex::encode(keypair.public_key()))
+    }
+
     fn parse_participants(
         participants: JsValue,
     ) -> Result<Option<Vec<Vec<u8>>>, JsError> {
@@ -103,11 +261,9 @@ mod bindings {
         }
     }
 
-    fn parse_message(message: JsValue) -> Result<[u8; 32], JsError> {
-        let message: String =
-            serde_wasm_bindgen::from_value(message)?;
+    fn parse_message(message: &str) -> Result<[u8; 32], JsError> {
         let message: Vec<u8> =
-            hex::decode(&message).map_err(JsError::from)?;
+            hex::decode(message).map_err(JsError::from)?;
         let message: [u8; 32] =
             message.as_slice().try_into().map_err(JsError::from)?;
         Ok(message)
@@ -0,0 +1,363 @@
+//! IndexedDB storage for key shares and driver state.
+//!
+//! When a passphrase is given values are encrypted with
+//! AES-GCM using a key derived from the passphrase with
+//! PBKDF2; the salt is kept in a separate object store so
+//! it can not be replaced or deleted through the storage
+//! trait. The key of each record is authenticated with the
+//! value so values can not be swapped between records.
+use async_trait::async_trait;
+use js_sys::{Array, Object, Promise, Reflect, Uint8Array};
+use mpc_driver::{Error, Result, Storage};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    Crypto, CryptoKey, IdbDatabase, IdbFactory, IdbOpenDbRequest,
+    IdbRequest, IdbTransactionMode, IdbVersionChangeEvent, Window,
+    WorkerGlobalScope,
+};
+
+/// Name of the object store for values.
+const STORE: &str = "values";
+
+/// Name of the object store for the encryption settings.
+const META: &str = "meta";
+
+/// Key for the salt used to derive the encryption key.
+const SALT: &str = "salt";
+
+/// Size of the salt for key derivation.
+const SALT_SIZE: usize = 16;
+
+/// Size of the nonce for AES-GCM.
+const NONCE_SIZE: usize = 12;
+
+/// Iterations for PBKDF2.
+const ITERATIONS: u32 = 600_000;
+
+/// Storage backed by an IndexedDB database.
+pub struct IndexedDbStorage {
+    db: IdbDatabase,
+    crypto: Crypto,
+    key: Option<CryptoKey>,
+}
+
+impl IndexedDbStorage {
+    /// Open or create a database.
+    ///
+    /// Values are encrypted when a passphrase is given; the
+    /// same passphrase must be used to read the values.
+    pub async fn open(
+        name: &str,
+        passphrase: Option<&str>,
+    ) -> Result<Self> {
+        let (factory, crypto) = globals().map_err(storage_error)?;
+        let request =
+            factory.open_with_u32(name, 1).map_err(storage_error)?;
+        let upgrade =
+            Closure::<dyn FnMut(IdbVersionChangeEvent)>::new(
+                |e: IdbVersionChangeEvent| {
+                    let db = e
+                        .target()
+                        .and_then(|t| {
+                            t.dyn_into::<IdbOpenDbRequest>().ok()
+                        })
+                        .and_then(|r| r.result().ok())
+                        .and_then(|r| {
+                            r.dyn_into::<IdbDatabase>().ok()
+                        });
+                    if let Some(db) = db {
+                        let names = db.object_store_names();
+                        for store in [STORE, META] {
+                            if !names.contains(store) {
+                                let _ = db.create_object_store(store);
+                            }
+                        }
+                    }
+                },
+            );
+        request.set_onupgradeneeded(Some(
+            upgrade.as_ref().unchecked_ref(),
+        ));
+        let db: IdbDatabase = complete(&request)
+            .await
+            .and_then(|db| db.dyn_into())
+            .map_err(storage_error)?;
+        request.set_onupgradeneeded(None);
+
+        let mut storage = Self {
+            db,
+            crypto,
+            key: None,
+        };
+        if let Some(passphrase) = passphrase {
+            let salt = match storage.read(META, SALT).await? {
+                Some(salt) => salt,
+                None => {
+                    let mut salt = vec![0u8; SALT_SIZE];
+                    storage
+                        .crypto
+                        .get_random_values_with_u8_array(&mut salt)
+                        .map_err(storage_error)?;
+                    storage.write(META, SALT, &salt).await?;
+                    salt
+                }
+            };
+            storage.key = Some(
+                storage
+                    .derive_key(passphrase, &salt)
+                    .await
+                    .map_err(storage_error)?,
+            );
+        }
+        Ok(storage)
+    }
+
+    async fn read(
+        &self,
+        store: &str,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let store = self
+            .db
+            .transaction_with_str(store)
+            .and_then(|tx| tx.object_store(store))
+            .map_err(storage_error)?;
+        let request = store
+            .get(&JsValue::from_str(key))
+            .map_err(storage_error)?;
+        let value =
+            complete(&request).await.map_err(storage_error)?;
+        if value.is_undefined() {
+            return Ok(None);
+        }
+        Ok(Some(Uint8Array::new(&value).to_vec()))
+    }
+
+    async fn write(
+        &self,
+        store: &str,
+        key: &str,
+        value: &[u8],
+    ) -> Result<()> {
+        let store = self
+            .db
+            .transaction_with_str_and_mode(
+                store,
+                IdbTransactionMode::Readwrite,
+            )
+            .and_then(|tx| tx.object_store(store))
+            .map_err(storage_error)?;
+        let request = store
+            .put_with_key(
+                &Uint8Array::from(value),
+                &JsValue::from_str(key),
+            )
+            .map_err(storage_error)?;
+        complete(&request).await.map_err(storage_error)?;
+        Ok(())
+    }
+
+    async fn derive_key(
+        &self,
+        passphrase: &str,
+        salt: &[u8],
+    ) -> std::result::Result<CryptoKey, JsValue> {
+        let subtle = self.crypto.subtle();
+        let usages = Array::of1(&JsValue::from("deriveKey"));
+        let material = JsFuture::from(subtle.import_key_with_str(
+            "raw",
+            &Uint8Array::from(passphrase.as_bytes()),
+            "PBKDF2",
+            false,
+            &usages,
+        )?)
+        .await?;
+
+        let params = object(&[
+            ("name", "PBKDF2".into()),
+            ("hash", "SHA-256".into()),
+            ("iterations", ITERATIONS.into()),
+            ("salt", Uint8Array::from(salt).into()),
+        ])?;
+        let algorithm = object(&[
+            ("name", "AES-GCM".into()),
+            ("length", 256.into()),
+        ])?;
+        let usages = Array::of2(
+            &JsValue::from("encrypt"),
+            &JsValue::from("decrypt"),
+        );
+        let key = JsFuture::from(
+            subtle.derive_key_with_object_and_object(
+                &params,
+                material.unchecked_ref(),
+                &algorithm,
+                false,
+                &usages,
+            )?,
+        )
+        .await?;
+        Ok(key.unchecked_into())
+    }
+
+    async fn encrypt(
+        &self,
+        key: &CryptoKey,
+        record: &str,
+        value: &[u8],
+    ) -> std::result::Result<Vec<u8>, JsValue> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        self.crypto.get_random_values_with_u8_array(&mut nonce)?;
+        let params = object(&[
+            ("name", "AES-GCM".into()),
+            ("iv", Uint8Array::from(&nonce[..]).into()),
+            (
+                "additionalData",
+                Uint8Array::from(record.as_bytes()).into(),
+            ),
+        ])?;
+        let sealed = JsFuture::from(
+            self.crypto
+                .subtle()
+                .encrypt_with_object_and_buffer_source(
+                    &params,
+                    key,
+                    &Uint8Array::from(value),
+                )?,
+        )
+        .await?;
+        let mut output = nonce.to_vec();
+        output.extend(Uint8Array::new(&sealed).to_vec());
+        Ok(output)
+    }
+
+    async fn decrypt(
+        &self,
+        key: &CryptoKey,
+        record: &str,
+        value: &[u8],
+    ) -> std::result::Result<Vec<u8>, JsValue> {
+        if value.len() < NONCE_SIZE {
+            return Err(JsValue::from_str(
+                "encrypted value is too short",
+            ));
+        }
+        let (nonce, sealed) = value.split_at(NONCE_SIZE);
+        let params = object(&[
+            ("name", "AES-GCM".into()),
+            ("iv", Uint8Array::from(nonce).into()),
+            (
+                "additionalData",
+                Uint8Array::from(record.as_bytes()).into(),
+            ),
+        ])?;
+        let opened = JsFuture::from(
+            self.crypto
+                .subtle()
+                .decrypt_with_object_and_buffer_source(
+                    &params,
+                    key,
+                    &Uint8Array::from(sealed),
+                )?,
+        )
+        .await?;
+        Ok(Uint8Array::new(&opened).to_vec())
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for IndexedDbStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let value = match self.read(STORE, key).await? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match &self.key {
+            Some(secret) => Ok(Some(
+                self.decrypt(secret, key, &value)
+                    .await
+                    .map_err(storage_error)?,
+            )),
+            None => Ok(Some(value)),
+        }
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        let value = match &self.key {
+            Some(secret) => self
+                .encrypt(secret, key, &value)
+                .await
+                .map_err(storage_error)?,
+            None => value,
+        };
+        self.write(STORE, key, &value).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let store = self
+            .db
+            .transaction_with_str_and_mode(
+                STORE,
+                IdbTransactionMode::Readwrite,
+            )
+            .and_then(|tx| tx.object_store(STORE))
+            .map_err(storage_error)?;
+        let request = store
+            .delete(&JsValue::from_str(key))
+            .map_err(storage_error)?;
+        complete(&request).await.map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+/// IndexedDB factory and crypto for the window or worker.
+fn globals() -> std::result::Result<(IdbFactory, Crypto), JsValue> {
+    let global = js_sys::global();
+    let (factory, crypto) = if let Some(window) =
+        global.dyn_ref::<Window>()
+    {
+        (window.indexed_db()?, window.crypto()?)
+    } else if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>()
+    {
+        (worker.indexed_db()?, worker.crypto()?)
+    } else {
+        return Err(JsValue::from_str("no global scope"));
+    };
+    let factory = factory.ok_or_else(|| {
+        JsValue::from_str("indexeddb is not available")
+    })?;
+    Ok((factory, crypto))
+}
+
+/// Wait for a request to complete.
+async fn complete(
+    request: &IdbRequest,
+) -> std::result::Result<JsValue, JsValue> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    });
+    JsFuture::from(promise).await?;
+    request.set_onsuccess(None);
+    request.set_onerror(None);
+    request.result()
+}
+
+/// Create an object from properties.
+fn object(
+    properties: &[(&str, JsValue)],
+) -> std::result::Result<Object, JsValue> {
+    let object = Object::new();
+    for (name, value) in properties {
+        Reflect::set(&object, &JsValue::from_str(name), value)?;
+    }
+    Ok(object)
+}
+
+/// Convert a javascript error to a storage error.
+fn storage_error(e: JsValue) -> Error {
+    Error::Storage(
+        e.as_string().unwrap_or_else(|| format!("{:?}", e)),
+    )
+}
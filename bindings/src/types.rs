@@ -0,0 +1,122 @@
+//! TypeScript definitions for the values exchanged with
+//! javascript.
+//!
+//! Values are converted with `serde-wasm-bindgen` so the
+//! definitions mirror the serialized form of the types in
+//! the driver; byte arrays are arrays of numbers.
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TYPES: &'static str = r#"
+/** Multi-party computation protocol. */
+export type Protocol = "gg20" | "cggmp";
+
+/** Relay server for a session. */
+export interface ServerOptions {
+  /** URL of the server. */
+  serverUrl: string;
+  /** Hex-encoded public key of the server. */
+  serverPublicKey: string;
+  /** Noise protocol pattern. */
+  pattern?: string | null;
+}
+
+/** Parameters for key generation. */
+export interface Parameters {
+  /** Number of parties. */
+  parties: number;
+  /** Threshold for signing; `threshold + 1` parties sign. */
+  threshold: number;
+}
+
+/** Options used to drive a session to completion. */
+export interface SessionOptions {
+  /** Protocol for the session. */
+  protocol: Protocol;
+  /** PEM-encoded keypair of the participant. */
+  keypair: string;
+  /** Relay server for the session. */
+  server: ServerOptions;
+  /** Parameters for key generation. */
+  parameters: Parameters;
+}
+
+/** Hex-encoded public keys of the participants. */
+export type Participants = string[] | null | undefined;
+
+/**
+ * Private key share for a protocol.
+ *
+ * The contents are specific to the protocol and should be
+ * stored and passed back without modification.
+ */
+export interface PrivateKey {
+  gg20: unknown;
+}
+
+/** Key share generated by key generation. */
+export interface KeyShare {
+  /** Private key share. */
+  privateKey: PrivateKey;
+  /** Public key for the shared key. */
+  publicKey: number[];
+  /** Address for the public key. */
+  address: string;
+}
+
+/** ECDSA signature with a recovery identifier. */
+export interface SignatureRecid {
+  r: unknown;
+  s: unknown;
+  recid: number;
+}
+
+/** Signature generated by a protocol. */
+export interface Signature {
+  gg20: {
+    /** ECDSA signature. */
+    signature: SignatureRecid;
+    /** Public key for the signature. */
+    publicKey: number[];
+    /** Address for the public key. */
+    address: string;
+  };
+}
+
+/** Request posted to a worker serving protocol requests. */
+export type WorkerRequest =
+  | { id: unknown; method: "keygen"; args: [SessionOptions, Participants] }
+  | {
+      id: unknown;
+      method: "sign";
+      args: [PrivateKey, string, SessionOptions, Participants];
+    };
+
+/** Reply posted by a worker serving protocol requests. */
+export type WorkerResponse =
+  | { id: unknown; result: KeyShare | Signature }
+  | { id: unknown; error: unknown };
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    /// Options used to drive a session to completion.
+    #[wasm_bindgen(typescript_type = "SessionOptions")]
+    pub type JsSessionOptions;
+
+    /// Hex-encoded public keys of the participants.
+    #[wasm_bindgen(typescript_type = "Participants")]
+    pub type JsParticipants;
+
+    /// Private key share for a protocol.
+    #[wasm_bindgen(typescript_type = "PrivateKey")]
+    pub type JsPrivateKey;
+
+    /// Promise for a generated key share.
+    #[wasm_bindgen(typescript_type = "Promise<KeyShare>")]
+    pub type JsKeySharePromise;
+
+    /// Promise for a generated signature.
+    #[wasm_bindgen(typescript_type = "Promise<Signature>")]
+    pub type JsSignaturePromise;
+}
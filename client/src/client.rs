@@ -1,33 +1,360 @@
 #[doc(hidden)]
 macro_rules! client_impl {
     () => {
+        /// Snapshot of the metrics for this client.
+        pub fn metrics(&self) -> crate::ClientMetrics {
+            self.metrics.lock().unwrap().snapshot()
+        }
+
+        /// Subscribe to the events dispatched by the event loop.
+        ///
+        /// Only events dispatched after subscribing are
+        /// received; the event loop must be running for
+        /// events to be published.
+        pub fn subscribe(&self) -> crate::Subscription {
+            crate::Subscription::new(&self.events)
+        }
+
+        /// Watch the state of the connection to the server.
+        pub fn connection_state(
+            &self,
+        ) -> tokio::sync::watch::Receiver<crate::ConnectionState> {
+            self.state.subscribe()
+        }
+
+        /// Whether the event loop for this client is running.
+        ///
+        /// The event loop stops when the connection to the
+        /// server ends or is closed, and when the stream for
+        /// the event loop is dropped or the task polling the
+        /// stream panics.
+        pub fn is_event_loop_alive(&self) -> bool {
+            !self.outbound_tx.is_closed()
+        }
+
+        /// Error for a message that could not be sent because
+        /// the event loop has stopped.
+        fn disconnected(&self) -> Error {
+            let state = *self.state.borrow();
+            if state == crate::ConnectionState::Closed {
+                Error::Disconnected
+            } else {
+                Error::EventLoopTerminated
+            }
+        }
+
+        /// Reserve capacity in the outbound channel using the
+        /// backpressure policy for this client.
+        ///
+        /// Capacity is reserved before a message is encrypted
+        /// so that a full channel does not consume a nonce.
+        async fn reserve(
+            &self,
+        ) -> Result<mpsc::Permit<'_, InternalMessage>> {
+            match self.options.backpressure {
+                crate::Backpressure::Wait => self
+                    .outbound_tx
+                    .reserve()
+                    .await
+                    .map_err(|_| self.disconnected()),
+                crate::Backpressure::Error => {
+                    self.outbound_tx.try_reserve().map_err(|e| {
+                        match e {
+                            mpsc::error::TrySendError::Full(_) => {
+                                Error::ChannelFull
+                            }
+                            mpsc::error::TrySendError::Closed(_) => {
+                                self.disconnected()
+                            }
+                        }
+                    })
+                }
+            }
+        }
+
+        /// Wait until the rate limits allow a message to be
+        /// sent to a peer.
+        async fn throttle(&self, public_key: &[u8]) {
+            loop {
+                let delay =
+                    self.throttle.lock().await.acquire(public_key);
+                if let Some(delay) = delay {
+                    tracing::debug!(
+                        to = ?hex::encode(public_key),
+                        delay_ms = delay.as_millis() as u64,
+                        "send throttled",
+                    );
+                    crate::throttle::sleep(delay).await;
+                } else {
+                    break;
+                }
+            }
+        }
+
+        /// Handshake with a peer that is not connected and wait
+        /// for the channel when automatic handshakes are enabled.
+        async fn auto_connect(
+            &mut self,
+            peer_key: &[u8],
+        ) -> Result<()> {
+            let timeout = match self.options.auto_connect {
+                Some(timeout) => timeout,
+                None => return Ok(()),
+            };
+
+            // Wait for the channel before starting the handshake
+            // so the notification can not be missed
+            let (tx, rx) = oneshot::channel();
+            let connecting = {
+                let peers = self.peers.read().await;
+                let connecting = match peers.get(peer_key) {
+                    Some(ProtocolState::Transport(_)) => {
+                        return Ok(())
+                    }
+                    Some(_) => true,
+                    None => false,
+                };
+                self.metadata
+                    .write()
+                    .await
+                    .entry(peer_key.to_vec())
+                    .or_default()
+                    .connected
+                    .push(tx);
+                connecting
+            };
+
+            if !connecting {
+                tracing::debug!(
+                    to = ?hex::encode(peer_key),
+                    "handshake with peer on send",
+                );
+                match crate::NetworkTransport::connect_peer(
+                    self, peer_key,
+                )
+                .await
+                {
+                    Ok(_) | Err(Error::PeerAlreadyExists) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            match crate::timer::timeout(timeout, rx).await {
+                Some(Ok(())) => Ok(()),
+                Some(Err(_)) => {
+                    Err(Error::PeerNotFound(hex::encode(peer_key)))
+                }
+                None => Err(Error::PeerHandshakeTimeout(hex::encode(
+                    peer_key,
+                ))),
+            }
+        }
+
+        /// Dispatch a message sent to this client as an event
+        /// without relaying the message.
+        async fn loopback(
+            &self,
+            message: PeerMessage<'_>,
+        ) -> Result<()> {
+            let public_key = self.options.keypair.public_key();
+            let contents = message.payload.to_vec();
+            let event = match message.correlation {
+                Some(Correlation::Request(request_id)) => {
+                    crate::Event::PeerRequest {
+                        peer_key: public_key.to_vec(),
+                        request_id,
+                        message: contents,
+                        session_id: message.session_id,
+                    }
+                }
+                Some(Correlation::Response(request_id)) => {
+                    let sender = self
+                        .metadata
+                        .write()
+                        .await
+                        .get_mut(public_key)
+                        .and_then(|meta| {
+                            meta.requests.remove(&request_id)
+                        });
+                    if let Some(sender) = sender {
+                        let _ = sender.send(contents);
+                    }
+                    return Ok(());
+                }
+                None => crate::Event::message(
+                    public_key.to_vec(),
+                    message.encoding,
+                    contents,
+                    message.session_id,
+                )
+                .ok_or_else(|| {
+                    Error::UnexpectedMessage(format!(
+                        "loopback message has encoding {:?}",
+                        message.encoding,
+                    ))
+                })?,
+            };
+
+            let permit = self.reserve().await?;
+            permit.send(InternalMessage::Loopback(event));
+
+            // No server to acknowledge the message
+            if let Some(id) = message.ack {
+                let tx = self
+                    .metadata
+                    .write()
+                    .await
+                    .get_mut(public_key)
+                    .and_then(|meta| meta.acks.remove(&id));
+                if let Some(tx) = tx {
+                    let _ = tx.send(Ok(()));
+                }
+            }
+            Ok(())
+        }
+
         /// Relay a buffer to a peer over the noise protocol channel.
         ///
         /// The peers must have already performed the noise protocol
-        /// handshake.
+        /// handshake unless the message can be queued or automatic
+        /// handshakes are enabled.
         async fn relay(
             &mut self,
             public_key: impl AsRef<[u8]>,
-            payload: &[u8],
-            encoding: Encoding,
-            broadcast: bool,
-            session_id: Option<SessionId>,
+            message: PeerMessage<'_>,
         ) -> Result<()> {
+            let peer_key = public_key.as_ref();
+            if self.options.loopback
+                && peer_key == self.options.keypair.public_key()
+            {
+                return self.loopback(message).await;
+            }
+            self.auto_connect(peer_key).await?;
+            self.metrics.lock().unwrap().peer_sent(
+                &self.options,
+                peer_key,
+                message.session_id,
+                message.payload.len(),
+            )?;
+            self.throttle(peer_key).await;
+            let permit = self.reserve().await?;
+            let compression = self
+                .metadata
+                .read()
+                .await
+                .get(peer_key)
+                .map(|meta| meta.compression)
+                .unwrap_or(false);
+
             let mut peers = self.peers.write().await;
-            if let Some(peer) = peers.get_mut(public_key.as_ref()) {
-                let request = encrypt_peer_channel(
-                    public_key, peer, payload, encoding, broadcast,
-                    session_id,
-                )
-                .await?;
-                self.outbound_tx
-                    .send(InternalMessage::Request(request))
+            match peers.get_mut(peer_key) {
+                Some(peer @ ProtocolState::Transport(_)) => {
+                    // Messages sent over a failed data channel are
+                    // lost and the peer channel nonces no longer
+                    // match so the peer will be disconnected
+                    #[cfg(all(
+                        feature = "webrtc",
+                        not(all(
+                            target_arch = "wasm32",
+                            target_os = "unknown"
+                        ))
+                    ))]
+                    {
+                        use crate::direct::{send, FRAGMENT_SIZE};
+                        let channel = self
+                            .metadata
+                            .read()
+                            .await
+                            .get(peer_key)
+                            .and_then(|meta| meta.direct.as_ref())
+                            .and_then(|direct| direct.sender());
+                        if let Some(channel) = channel {
+                            drop(permit);
+                            let ack = message.ack;
+                            let size = self
+                                .options
+                                .fragment_size
+                                .unwrap_or(FRAGMENT_SIZE)
+                                .min(FRAGMENT_SIZE);
+                            let requests = seal_peer_requests(
+                                peer,
+                                peer_key,
+                                message,
+                                &self.options,
+                                Some(size),
+                                compression,
+                            )
+                            .await?;
+                            for request in requests {
+                                send(&channel, &request).await?;
+                            }
+                            let mut metadata =
+                                self.metadata.write().await;
+                            let meta = metadata
+                                .entry(peer_key.to_vec())
+                                .or_default();
+                            meta.touch();
+                            // No server to acknowledge the message
+                            if let Some(tx) = ack
+                                .and_then(|id| meta.acks.remove(&id))
+                            {
+                                let _ = tx.send(Ok(()));
+                            }
+                            return Ok(());
+                        }
+                    }
+
+                    let mut requests = seal_peer_requests(
+                        peer,
+                        peer_key,
+                        message,
+                        &self.options,
+                        self.options.fragment_size,
+                        compression,
+                    )
                     .await?;
-                Ok(())
-            } else {
-                Err(Error::PeerNotFound(hex::encode(
-                    public_key.as_ref().to_vec(),
-                )))
+                    // Fragments are sent together so they are
+                    // not interleaved with other messages
+                    let message = if requests.len() == 1 {
+                        InternalMessage::Request(requests.remove(0))
+                    } else {
+                        InternalMessage::Batch(requests)
+                    };
+                    permit.send(message);
+                    self.metadata
+                        .write()
+                        .await
+                        .entry(peer_key.to_vec())
+                        .or_default()
+                        .touch();
+                    Ok(())
+                }
+                peer => {
+                    if let Some(queue) = &self.options.offline_queue {
+                        let mut metadata =
+                            self.metadata.write().await;
+                        let meta = metadata
+                            .entry(peer_key.to_vec())
+                            .or_default();
+                        if meta.queue.len() >= queue.capacity {
+                            return Err(Error::OfflineQueueFull(
+                                hex::encode(peer_key),
+                            ));
+                        }
+                        tracing::debug!(
+                            to = ?hex::encode(peer_key),
+                            "queue message for offline peer",
+                        );
+                        meta.queue.push(Queued::new(message));
+                        Ok(())
+                    } else if peer.is_some() {
+                        Err(Error::NotTransportState)
+                    } else {
+                        Err(Error::PeerNotFound(hex::encode(
+                            peer_key,
+                        )))
+                    }
+                }
             }
         }
 
@@ -37,12 +364,16 @@ macro_rules! client_impl {
             &mut self,
             message: ServerMessage,
         ) -> Result<()> {
+            let permit = self.reserve().await?;
             let envelope = {
                 let mut server = self.server.write().await;
                 if let Some(server) = server.as_mut() {
                     let payload = encode(&message).await?;
                     let inner = encrypt_server_channel(
-                        server, &payload, false,
+                        server,
+                        &payload,
+                        false,
+                        self.options.rekey,
                     )
                     .await?;
                     Some(inner)
@@ -55,32 +386,125 @@ macro_rules! client_impl {
                 let request = RequestMessage::Opaque(
                     OpaqueMessage::ServerMessage(envelope),
                 );
-                self.outbound_tx
-                    .send(InternalMessage::Request(request))
-                    .await?;
+                permit.send(InternalMessage::Request(request));
                 Ok(())
             } else {
                 unreachable!()
             }
         }
 
+        /// Relay a message to each recipient.
+        ///
+        /// Failing to reach a recipient does not stop the
+        /// broadcast; errors that affect every recipient, such
+        /// as a closed connection, are returned.
         async fn relay_broadcast(
             &mut self,
             session_id: &SessionId,
             recipient_public_keys: &[Vec<u8>],
             payload: &[u8],
             encoding: Encoding,
+        ) -> Result<Deliveries> {
+            let mut deliveries = Deliveries::new();
+            for key in recipient_public_keys {
+                let result = self
+                    .relay(
+                        key,
+                        PeerMessage {
+                            payload,
+                            encoding,
+                            broadcast: true,
+                            session_id: Some(*session_id),
+                            correlation: None,
+                            ack: None,
+                            compress: self.compression,
+                        },
+                    )
+                    .await;
+                let delivery = match result {
+                    Ok(_) => Delivery::Sent,
+                    Err(Error::PeerNotFound(_)) => {
+                        Delivery::PeerNotFound
+                    }
+                    Err(
+                        e @ (Error::Protocol(_)
+                        | Error::Snow(_)
+                        | Error::NotTransportState),
+                    ) => Delivery::EncryptFailed(e.to_string()),
+                    Err(e) => return Err(e),
+                };
+                deliveries.insert(key.to_vec(), delivery);
+            }
+            Ok(deliveries)
+        }
+
+        async fn relay_ordered_broadcast(
+            &mut self,
+            session_id: &SessionId,
+            recipient_public_keys: &[Vec<u8>],
+            payload: &[u8],
+            encoding: Encoding,
         ) -> Result<()> {
             for key in recipient_public_keys {
-                self.relay(
-                    key,
-                    payload,
-                    encoding,
-                    true,
-                    Some(*session_id),
-                )
-                .await?;
+                self.throttle(key).await;
             }
+            let permit = self.reserve().await?;
+            let mut envelopes =
+                Vec::with_capacity(recipient_public_keys.len());
+            {
+                let mut peers = self.peers.write().await;
+                // Check every recipient before sealing so a
+                // failure does not advance the nonces of the
+                // channels for some of the recipients
+                for key in recipient_public_keys {
+                    match peers.get(key) {
+                        Some(ProtocolState::Transport(_)) => {}
+                        Some(_) => {
+                            return Err(Error::NotTransportState);
+                        }
+                        None => {
+                            return Err(Error::PeerNotFound(
+                                hex::encode(key),
+                            ));
+                        }
+                    }
+                }
+                {
+                    let mut metrics = self.metrics.lock().unwrap();
+                    for key in recipient_public_keys {
+                        metrics.peer_sent(
+                            &self.options,
+                            key,
+                            Some(*session_id),
+                            payload.len(),
+                        )?;
+                    }
+                }
+                for key in recipient_public_keys {
+                    let peer =
+                        peers.get_mut(key).ok_or_else(|| {
+                            Error::PeerNotFound(hex::encode(key))
+                        })?;
+                    let envelope = seal_peer_envelope(
+                        peer,
+                        payload,
+                        encoding,
+                        true,
+                        Some(*session_id),
+                        self.options.rekey,
+                    )
+                    .await?;
+                    envelopes.push((key.to_vec(), envelope));
+                }
+            }
+
+            let request = RequestMessage::Opaque(
+                OpaqueMessage::SessionBroadcast {
+                    session_id: *session_id,
+                    envelopes,
+                },
+            );
+            permit.send(InternalMessage::Request(request));
             Ok(())
         }
     };
@@ -98,6 +522,26 @@ macro_rules! client_transport_impl {
                 self.options.keypair.public_key()
             }
 
+            fn subscribe(&self) -> crate::Subscription {
+                <$kind>::subscribe(self)
+            }
+
+            fn connection_state(
+                &self,
+            ) -> tokio::sync::watch::Receiver<
+                crate::ConnectionState,
+            > {
+                <$kind>::connection_state(self)
+            }
+
+            fn is_compression_enabled(&self) -> bool {
+                self.compression
+            }
+
+            fn set_compression(&mut self, enabled: bool) {
+                self.compression = enabled;
+            }
+
             /// Perform initial handshake with the server.
             async fn connect(&mut self) -> Result<()> {
                 let request = {
@@ -120,27 +564,98 @@ macro_rules! client_transport_impl {
                     )
                 };
 
-                self.outbound_tx.send(InternalMessage::Request(request)).await?;
+                self.outbound_tx
+                    .send(InternalMessage::Request(request))
+                    .await
+                    .map_err(|_| self.disconnected())?;
+                self.state.send_replace(
+                    crate::ConnectionState::Handshaking,
+                );
 
                 Ok(())
             }
 
+            async fn connect_and_wait(&mut self) -> Result<()> {
+                use crate::ConnectionState;
+                let mut state = self.state.subscribe();
+                self.connect().await?;
+                let handshake = async {
+                    state
+                        .wait_for(|state| {
+                            matches!(
+                                state,
+                                ConnectionState::Connected
+                                    | ConnectionState::Closed
+                            )
+                        })
+                        .await
+                        .map(|state| {
+                            *state == ConnectionState::Connected
+                        })
+                };
+                match crate::timer::timeout(
+                    self.options.timeouts.server_handshake,
+                    handshake,
+                )
+                .await
+                {
+                    Some(Ok(true)) => Ok(()),
+                    Some(_) => Err(Error::Disconnected),
+                    None => Err(Error::ServerHandshakeTimeout),
+                }
+            }
+
             async fn is_connected(&self) -> bool {
                 let state = self.server.read().await;
                 matches!(&*state, Some(ProtocolState::Transport(_)))
             }
 
+            async fn peer_state(
+                &self,
+                public_key: &[u8],
+            ) -> PeerState {
+                let peers = self.peers.read().await;
+                peers
+                    .get(public_key)
+                    .map(PeerState::from)
+                    .unwrap_or(PeerState::Disconnected)
+            }
+
+            async fn list_peers(&self) -> Vec<PeerInfo> {
+                let peers = self.peers.read().await;
+                let metadata = self.metadata.read().await;
+                peers
+                    .iter()
+                    .map(|(public_key, state)| PeerInfo {
+                        public_key: public_key.clone(),
+                        state: state.into(),
+                        idle: metadata
+                            .get(public_key)
+                            .and_then(|meta| meta.last_active)
+                            .map(|last_active| last_active.elapsed()),
+                        direct: metadata
+                            .get(public_key)
+                            .map(|meta| meta.is_direct())
+                            .unwrap_or(false),
+                    })
+                    .collect()
+            }
+
             /// Handshake with a peer.
             ///
             /// Peer already exists error is returned if this
-            /// client is already connecting to the peer.
+            /// client is already connecting or connected to
+            /// the peer.
+            ///
+            /// An established channel is never replaced; call
+            /// `disconnect_peer()` first to handshake again.
             async fn connect_peer(
                 &mut self,
                 public_key: &[u8],
             ) -> Result<()> {
                 let mut peers = self.peers.write().await;
 
-                if peers.get(public_key.as_ref()).is_some() {
+                if peers.contains_key(public_key.as_ref()) {
                     return Err(Error::PeerAlreadyExists);
                 }
 
@@ -163,14 +678,18 @@ macro_rules! client_transport_impl {
 
                 let (len, payload) = match state {
                     ProtocolState::Handshake(initiator) => {
+                        // Advertise the capabilities of this client
                         let mut request = vec![0u8; 1024];
-                        let len =
-                            initiator.write_message(&[], &mut request)?;
+                        let len = initiator.write_message(
+                            crate::compression::capabilities(),
+                            &mut request,
+                        )?;
                         (len, request)
                     }
                     _ => return Err(Error::NotHandshakeState),
                 };
                 drop(peers);
+                self.metrics.lock().unwrap().handshake_initiated();
 
                 let request = RequestMessage::Transparent(
                     TransparentMessage::PeerHandshake {
@@ -179,8 +698,51 @@ macro_rules! client_transport_impl {
                     },
                 );
 
-                self.outbound_tx.send(InternalMessage::Request(request)).await?;
+                self.outbound_tx
+                    .send(InternalMessage::Request(request))
+                    .await
+                    .map_err(|_| self.disconnected())?;
+
+                Ok(())
+            }
+
+            /// Close the channel to a peer.
+            ///
+            /// An established channel is closed by the peer too
+            /// so both sides must handshake again to exchange
+            /// messages.
+            async fn disconnect_peer(
+                &mut self,
+                public_key: &[u8],
+            ) -> Result<()> {
+                let connected = matches!(
+                    self.peers.read().await.get(public_key),
+                    Some(ProtocolState::Transport(_))
+                );
+                if connected {
+                    self.relay(
+                        public_key,
+                        PeerMessage {
+                            payload: &[],
+                            encoding: Encoding::Close,
+                            broadcast: false,
+                            session_id: None,
+                            correlation: None,
+                            ack: None,
+                            compress: self.compression,
+                        },
+                    )
+                    .await?;
+                }
 
+                let removed =
+                    self.peers.write().await.remove(public_key);
+                self.metadata.write().await.remove(public_key);
+                if removed.is_none() {
+                    return Err(Error::PeerNotFound(hex::encode(
+                        public_key,
+                    )));
+                }
                 Ok(())
             }
 
@@ -196,10 +758,67 @@ macro_rules! client_transport_impl {
             {
                 self.relay(
                     public_key,
-                    &serde_json::to_vec(payload)?,
-                    Encoding::Json,
-                    false,
-                    session_id,
+                    PeerMessage {
+                        payload: &serde_json::to_vec(payload)?,
+                        encoding: Encoding::Json,
+                        broadcast: false,
+                        session_id,
+                        correlation: None,
+                        ack: None,
+                        compress: self.compression,
+                    },
+                )
+                .await
+            }
+
+            /// Send a CBOR message to a peer via the relay service.
+            async fn send_cbor<S>(
+                &mut self,
+                public_key: &[u8],
+                payload: &S,
+                session_id: Option<SessionId>,
+            ) -> Result<()>
+            where
+                S: Serialize + Send + Sync + ?Sized,
+            {
+                let mut buffer = Vec::new();
+                ciborium::ser::into_writer(payload, &mut buffer)?;
+                self.relay(
+                    public_key,
+                    PeerMessage {
+                        payload: &buffer,
+                        encoding: Encoding::Cbor,
+                        broadcast: false,
+                        session_id,
+                        correlation: None,
+                        ack: None,
+                        compress: self.compression,
+                    },
+                )
+                .await
+            }
+
+            /// Send a MessagePack message to a peer via the relay.
+            async fn send_msgpack<S>(
+                &mut self,
+                public_key: &[u8],
+                payload: &S,
+                session_id: Option<SessionId>,
+            ) -> Result<()>
+            where
+                S: Serialize + Send + Sync + ?Sized,
+            {
+                self.relay(
+                    public_key,
+                    PeerMessage {
+                        payload: &rmp_serde::to_vec_named(payload)?,
+                        encoding: Encoding::MessagePack,
+                        broadcast: false,
+                        session_id,
+                        correlation: None,
+                        ack: None,
+                        compress: self.compression,
+                    },
                 )
                 .await
             }
@@ -213,10 +832,171 @@ macro_rules! client_transport_impl {
             ) -> Result<()> {
                 self.relay(
                     public_key,
-                    &payload,
-                    Encoding::Blob,
-                    false,
-                    session_id,
+                    PeerMessage {
+                        payload: &payload,
+                        encoding: Encoding::Blob,
+                        broadcast: false,
+                        session_id,
+                        correlation: None,
+                        ack: None,
+                        compress: self.compression,
+                    },
+                )
+                .await
+            }
+
+            /// Send a binary message to a peer and wait for the
+            /// server to acknowledge it was relayed.
+            async fn send_blob_tracked(
+                &mut self,
+                public_key: &[u8],
+                payload: Vec<u8>,
+                session_id: Option<SessionId>,
+                timeout: Duration,
+            ) -> Result<()> {
+                let (tx, rx) = oneshot::channel();
+                let id = {
+                    let mut metadata = self.metadata.write().await;
+                    let meta = metadata
+                        .entry(public_key.to_vec())
+                        .or_default();
+                    let id = meta.next_request;
+                    meta.next_request =
+                        meta.next_request.wrapping_add(1);
+                    meta.acks.insert(id, tx);
+                    id
+                };
+
+                let result = self
+                    .relay(
+                        public_key,
+                        PeerMessage {
+                            payload: &payload,
+                            encoding: Encoding::Blob,
+                            broadcast: false,
+                            session_id,
+                            correlation: None,
+                            ack: Some(id),
+                            compress: self.compression,
+                        },
+                    )
+                    .await;
+                let delivered = crate::timer::timeout(timeout, rx);
+                let result = match result {
+                    Ok(_) => match delivered.await {
+                        Some(Ok(Ok(_))) => return Ok(()),
+                        Some(Ok(Err(reason))) => {
+                            Err(Error::Undelivered(id, reason))
+                        }
+                        // Sender is dropped when the peer disconnects
+                        Some(Err(_)) => Err(Error::Undelivered(
+                            id,
+                            "peer disconnected".to_string(),
+                        )),
+                        None => Err(Error::DeliveryTimeout(id)),
+                    },
+                    Err(e) => Err(e),
+                };
+
+                if let Some(meta) =
+                    self.metadata.write().await.get_mut(public_key)
+                {
+                    meta.acks.remove(&id);
+                }
+                result
+            }
+
+            /// Send a request to a peer and wait for the response.
+            async fn request_peer(
+                &mut self,
+                public_key: &[u8],
+                payload: &[u8],
+                session_id: Option<SessionId>,
+                timeout: Duration,
+            ) -> Result<Vec<u8>> {
+                let (tx, rx) = oneshot::channel();
+                let request_id = {
+                    let mut metadata = self.metadata.write().await;
+                    let meta = metadata
+                        .entry(public_key.to_vec())
+                        .or_default();
+                    let request_id = meta.next_request;
+                    meta.next_request =
+                        meta.next_request.wrapping_add(1);
+                    meta.requests.insert(request_id, tx);
+                    request_id
+                };
+
+                let started = crate::timer::now();
+                let result = self
+                    .relay(
+                        public_key,
+                        PeerMessage {
+                            payload,
+                            encoding: Encoding::Blob,
+                            broadcast: false,
+                            session_id,
+                            correlation: Some(Correlation::Request(
+                                request_id,
+                            )),
+                            ack: None,
+                            compress: self.compression,
+                        },
+                    )
+                    .await;
+                let response = crate::timer::timeout(timeout, rx);
+                let result = match result {
+                    Ok(_) => match response.await {
+                        Some(Ok(response)) => {
+                            if let Some(started) = started {
+                                let rtt = started.elapsed();
+                                self.metrics
+                                    .lock()
+                                    .unwrap()
+                                    .peer_rtt(public_key, rtt);
+                            }
+                            return Ok(response);
+                        }
+                        // Sender is dropped when the peer disconnects
+                        Some(Err(_)) => {
+                            Err(Error::RequestCancelled(request_id))
+                        }
+                        None => {
+                            Err(Error::RequestTimeout(request_id))
+                        }
+                    },
+                    Err(e) => Err(e),
+                };
+
+                if let Some(meta) =
+                    self.metadata.write().await.get_mut(public_key)
+                {
+                    meta.requests.remove(&request_id);
+                }
+                result
+            }
+
+            /// Reply to a request from a peer.
+            async fn reply_peer(
+                &mut self,
+                public_key: &[u8],
+                request_id: u64,
+                payload: &[u8],
+                session_id: Option<SessionId>,
+            ) -> Result<()> {
+                self.relay(
+                    public_key,
+                    PeerMessage {
+                        payload,
+                        encoding: Encoding::Blob,
+                        broadcast: false,
+                        session_id,
+                        correlation: Some(Correlation::Response(
+                            request_id,
+                        )),
+                        ack: None,
+                        compress: self.compression,
+                    },
                 )
                 .await
             }
@@ -242,12 +1022,14 @@ macro_rules! client_transport_impl {
             }
 
             /// Create a new session.
-            async fn new_session(
+            async fn new_session<R>(
                 &mut self,
-                participant_keys: Vec<Vec<u8>>,
-            ) -> Result<()> {
-                let session = SessionRequest { participant_keys };
-                let message = ServerMessage::NewSession(session);
+                request: R,
+            ) -> Result<()>
+            where
+                R: Into<SessionRequest> + Send,
+            {
+                let message = ServerMessage::NewSession(request.into());
                 self.request(message).await
             }
 
@@ -273,13 +1055,110 @@ macro_rules! client_transport_impl {
                 self.request(message).await
             }
 
+            /// Invite a participant to join a session.
+            async fn invite_participant(
+                &mut self,
+                session_id: &SessionId,
+                public_key: &[u8],
+            ) -> Result<()> {
+                let message = ServerMessage::InviteParticipant {
+                    session_id: *session_id,
+                    public_key: public_key.to_vec(),
+                };
+                self.request(message).await
+            }
+
+            /// Transfer ownership of a session.
+            async fn transfer_session(
+                &mut self,
+                session_id: &SessionId,
+                public_key: &[u8],
+            ) -> Result<()> {
+                let message = ServerMessage::TransferSession {
+                    session_id: *session_id,
+                    public_key: public_key.to_vec(),
+                };
+                self.request(message).await
+            }
+
+            /// Acknowledge that we have finished with a session.
+            async fn finish_session(
+                &mut self,
+                session_id: SessionId,
+            ) -> Result<()> {
+                let message =
+                    ServerMessage::FinishSession(session_id);
+                self.request(message).await
+            }
+
+            /// List the sessions we are a participant of.
+            async fn list_sessions(&mut self) -> Result<()> {
+                self.request(ServerMessage::ListSessions).await
+            }
+
+            /// Decline to join a session.
+            async fn decline_session(
+                &mut self,
+                session_id: SessionId,
+            ) -> Result<()> {
+                let message = ServerMessage::DeclineSession(session_id);
+                self.request(message).await
+            }
+
+            /// Rejoin a session after a disconnect.
+            async fn rejoin_session(
+                &mut self,
+                session_id: SessionId,
+            ) -> Result<()> {
+                let message = ServerMessage::RejoinSession(session_id);
+                self.request(message).await
+            }
+
+            /// Publish a value to the participants in a session.
+            async fn set_session_value(
+                &mut self,
+                session_id: SessionId,
+                key: &str,
+                value: Vec<u8>,
+            ) -> Result<()> {
+                let message = ServerMessage::SetSessionValue {
+                    session_id,
+                    key: key.to_string(),
+                    value,
+                };
+                self.request(message).await
+            }
+
+            /// Read a value published in a session.
+            async fn get_session_value(
+                &mut self,
+                session_id: SessionId,
+                key: &str,
+            ) -> Result<()> {
+                let message = ServerMessage::GetSessionValue {
+                    session_id,
+                    key: key.to_string(),
+                };
+                self.request(message).await
+            }
+
+            /// Send a heartbeat for a session.
+            async fn session_heartbeat(
+                &mut self,
+                session_id: SessionId,
+            ) -> Result<()> {
+                let message =
+                    ServerMessage::SessionHeartbeat(session_id);
+                self.request(message).await
+            }
+
             /// Broadcast a JSON message in the context of a session.
             async fn broadcast_json<S>(
                 &mut self,
                 session_id: &SessionId,
                 recipient_public_keys: &[Vec<u8>],
                 payload: &S,
-            ) -> Result<()>
+            ) -> Result<Deliveries>
             where
                 S: Serialize + Send + Sync + ?Sized,
             {
@@ -298,7 +1177,7 @@ macro_rules! client_transport_impl {
                 session_id: &SessionId,
                 recipient_public_keys: &[Vec<u8>],
                 payload: Vec<u8>,
-            ) -> Result<()> {
+            ) -> Result<Deliveries> {
                 self.relay_broadcast(
                     session_id,
                     recipient_public_keys,
@@ -308,8 +1187,48 @@ macro_rules! client_transport_impl {
                 .await
             }
 
+            /// Broadcast a JSON message in the context of a session
+            /// in the total order assigned by the server.
+            async fn ordered_broadcast_json<S>(
+                &mut self,
+                session_id: &SessionId,
+                recipient_public_keys: &[Vec<u8>],
+                payload: &S,
+            ) -> Result<()>
+            where
+                S: Serialize + Send + Sync + ?Sized,
+            {
+                self.relay_ordered_broadcast(
+                    session_id,
+                    recipient_public_keys,
+                    &serde_json::to_vec(payload)?,
+                    Encoding::Json,
+                )
+                .await
+            }
+
+            /// Broadcast a binary message in the context of a session
+            /// in the total order assigned by the server.
+            async fn ordered_broadcast_blob(
+                &mut self,
+                session_id: &SessionId,
+                recipient_public_keys: &[Vec<u8>],
+                payload: Vec<u8>,
+            ) -> Result<()> {
+                self.relay_ordered_broadcast(
+                    session_id,
+                    recipient_public_keys,
+                    &payload,
+                    Encoding::Blob,
+                )
+                .await
+            }
+
             async fn close(&self) -> Result<()> {
-                Ok(self.outbound_tx.send(InternalMessage::Close).await?)
+                self.outbound_tx
+                    .send(InternalMessage::Close)
+                    .await
+                    .map_err(|_| self.disconnected())
             }
         }
     }
@@ -0,0 +1,78 @@
+//! Compression of payloads sent to peers.
+//!
+//! Peers advertise that they accept compressed payloads
+//! in the noise handshake; compression is not supported
+//! by the web client.
+use crate::{Metadata, Result};
+
+/// Capability flag for zstd compressed payloads.
+const ZSTD: u8 = 0x01;
+
+/// Compression level for zstd.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+const LEVEL: i32 = 3;
+
+/// Capabilities sent in the payload of a peer handshake.
+pub(crate) fn capabilities() -> &'static [u8] {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        &[]
+    } else {
+        &[ZSTD]
+    }
+}
+
+/// Record the capabilities received from a peer in a
+/// handshake payload.
+///
+/// Payloads are only compressed for a peer when both
+/// clients support compression.
+pub(crate) async fn negotiate(
+    metadata: &Metadata,
+    public_key: &[u8],
+    payload: &[u8],
+) {
+    let local = capabilities().first().copied().unwrap_or_default();
+    let remote = payload.first().copied().unwrap_or_default();
+    let mut metadata = metadata.write().await;
+    let meta = metadata.entry(public_key.to_vec()).or_default();
+    meta.compression = local & remote & ZSTD != 0;
+    meta.touch();
+}
+
+/// Compress a payload.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) fn compress(payload: &[u8]) -> Result<Vec<u8>> {
+    Ok(zstd::bulk::compress(payload, LEVEL)?)
+}
+
+/// Compress a payload.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) fn compress(_payload: &[u8]) -> Result<Vec<u8>> {
+    Err(crate::Error::CompressionUnsupported)
+}
+
+/// Decompress a payload up to a maximum size.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) fn decompress(
+    payload: &[u8],
+    max_size: usize,
+) -> Result<Vec<u8>> {
+    use std::io::Read;
+    let mut contents = Vec::new();
+    zstd::stream::read::Decoder::new(payload)?
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut contents)?;
+    if contents.len() > max_size {
+        return Err(crate::Error::DecompressedSize(max_size as u64));
+    }
+    Ok(contents)
+}
+
+/// Decompress a payload up to a maximum size.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) fn decompress(
+    _payload: &[u8],
+    _max_size: usize,
+) -> Result<Vec<u8>> {
+    Err(crate::Error::CompressionUnsupported)
+}
@@ -0,0 +1,20 @@
+//! Connector for the TCP connections to a server.
+use async_trait::async_trait;
+use tokio::net::TcpStream;
+
+use crate::Result;
+
+/// Opens the TCP connection for the websocket to a server.
+///
+/// Implement this trait to resolve hosts with a custom
+/// resolver, bind to a network interface, prefer an address
+/// family or set socket options such as TCP keep-alive.
+#[async_trait]
+pub trait TcpConnector: Send + Sync {
+    /// Open a connection to a host and port.
+    async fn connect(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<TcpStream>;
+}
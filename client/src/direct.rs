@@ -0,0 +1,667 @@
+//! Direct connections between peers over WebRTC data channels.
+//!
+//! Once the noise protocol channel to a peer is established the
+//! peer with the lowest public key sends an offer; session
+//! descriptions and ICE candidates are exchanged over the
+//! encrypted peer channel so they are authenticated.
+//!
+//! The data channel carries the same sealed envelopes as the
+//! relay. When the channel opens a switch signal is relayed
+//! and subsequent messages are sent directly; the recipient
+//! holds direct messages until the switch is received so
+//! that envelopes are decrypted in order. If the connection
+//! can not be established before the timeout or is lost
+//! messages are relayed again.
+use bytes::Bytes;
+use mpc_protocol::{
+    decode, encode, hex, Encoding, OpaqueMessage, RequestMessage,
+    ResponseMessage,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use webrtc::{
+    api::{APIBuilder, API},
+    data_channel::{
+        data_channel_message::DataChannelMessage,
+        data_channel_state::RTCDataChannelState, RTCDataChannel,
+    },
+    ice_transport::{
+        ice_candidate::{RTCIceCandidate, RTCIceCandidateInit},
+        ice_server::RTCIceServer,
+    },
+    peer_connection::{
+        configuration::RTCConfiguration,
+        peer_connection_state::RTCPeerConnectionState,
+        sdp::session_description::RTCSessionDescription,
+        RTCPeerConnection,
+    },
+};
+
+use crate::{
+    event_loop::InternalMessage, seal_peer_requests, ClientOptions,
+    DirectOptions, Error, Metadata, PeerMessage, Peers, Result,
+};
+
+/// Label for the data channel.
+const LABEL: &str = "mpc";
+
+/// Maximum size of a payload fragment sent over a data channel.
+pub(crate) const FRAGMENT_SIZE: usize = 16 * 1024;
+
+/// Signal exchanged over the noise protocol channel.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Signal {
+    /// Offer from the peer that creates the data channel.
+    Offer { sdp: String },
+    /// Answer to an offer.
+    Answer { sdp: String },
+    /// ICE candidate for the connection.
+    Candidate { candidate: RTCIceCandidateInit },
+    /// Messages that follow are sent over the data channel.
+    Switch,
+}
+
+/// Direct connection to a peer.
+pub(crate) struct DirectChannel {
+    connection: Arc<RTCPeerConnection>,
+    channel: Option<Arc<RTCDataChannel>>,
+    /// Whether messages to the peer are sent directly.
+    sending: bool,
+    /// Whether the peer has switched to sending directly.
+    receiving: bool,
+    /// Direct messages received before the switch signal.
+    buffered: Vec<ResponseMessage>,
+}
+
+impl DirectChannel {
+    /// Data channel when messages are sent directly.
+    pub fn sender(&self) -> Option<Arc<RTCDataChannel>> {
+        if self.sending {
+            self.channel.clone()
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Debug for DirectChannel {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        f.debug_struct("DirectChannel")
+            .field("sending", &self.sending)
+            .field("receiving", &self.receiving)
+            .field("buffered", &self.buffered.len())
+            .finish()
+    }
+}
+
+impl Drop for DirectChannel {
+    fn drop(&mut self) {
+        let connection = Arc::clone(&self.connection);
+        tokio::spawn(async move {
+            let _ = connection.close().await;
+        });
+    }
+}
+
+/// Send a sealed request to a peer over a data channel.
+pub(crate) async fn send(
+    channel: &RTCDataChannel,
+    request: &RequestMessage,
+) -> Result<()> {
+    let buffer = encode(request).await?;
+    channel.send(&Bytes::from(buffer)).await?;
+    Ok(())
+}
+
+/// Command for the task that negotiates direct connections.
+enum Command {
+    /// Offer a connection to a peer.
+    Connect(Vec<u8>),
+    /// Handle a signal from a peer.
+    Signal(Vec<u8>, Signal),
+}
+
+/// Negotiates direct connections for an event loop.
+///
+/// Offers and answers are handled by a task so the event
+/// loop does not wait on the outbound channel it drains.
+pub(crate) struct Negotiator {
+    direct: Direct,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Negotiator {
+    /// Offer a direct connection to a peer once the noise
+    /// protocol channel is established.
+    pub fn connect(&self, peer_key: &[u8]) {
+        let _ =
+            self.commands.send(Command::Connect(peer_key.to_vec()));
+    }
+
+    /// Handle a signal from a peer.
+    ///
+    /// Returns the direct messages received before a switch
+    /// signal so they can be handled in order.
+    pub async fn handle_signal(
+        &self,
+        peer_key: &[u8],
+        payload: &[u8],
+    ) -> Result<Vec<ResponseMessage>> {
+        let signal: Signal = serde_json::from_slice(payload)?;
+        match signal {
+            Signal::Switch => {
+                Ok(self.direct.switched(peer_key).await)
+            }
+            signal => {
+                let _ = self
+                    .commands
+                    .send(Command::Signal(peer_key.to_vec(), signal));
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+/// Establishes direct connections for the event loop.
+#[derive(Clone)]
+pub(crate) struct Direct {
+    api: Arc<API>,
+    direct: DirectOptions,
+    options: Arc<ClientOptions>,
+    peers: Peers,
+    metadata: Metadata,
+    inbound_tx: mpsc::Sender<ResponseMessage>,
+    outbound_tx: mpsc::Sender<InternalMessage>,
+}
+
+impl Direct {
+    /// Create direct connections when enabled in the options.
+    pub fn new(
+        options: Arc<ClientOptions>,
+        peers: Peers,
+        metadata: Metadata,
+        inbound_tx: mpsc::Sender<ResponseMessage>,
+        outbound_tx: mpsc::Sender<InternalMessage>,
+    ) -> Option<Self> {
+        let direct = options.direct.clone()?;
+        Some(Self {
+            api: Arc::new(APIBuilder::new().build()),
+            direct,
+            options,
+            peers,
+            metadata,
+            inbound_tx,
+            outbound_tx,
+        })
+    }
+
+    /// Spawn the task that negotiates direct connections.
+    pub fn spawn(self) -> Negotiator {
+        let (commands, mut commands_rx) = mpsc::unbounded_channel();
+        let direct = self.clone();
+        tokio::spawn(async move {
+            while let Some(command) = commands_rx.recv().await {
+                let (peer_key, result) = match command {
+                    Command::Connect(peer_key) => {
+                        let result = direct.connect(&peer_key).await;
+                        (peer_key, result)
+                    }
+                    Command::Signal(peer_key, signal) => {
+                        let result =
+                            direct.negotiate(&peer_key, signal).await;
+                        (peer_key, result)
+                    }
+                };
+                if let Err(error) = result {
+                    tracing::warn!(
+                        peer = ?hex::encode(&peer_key),
+                        error = %error,
+                        "direct connection not negotiated",
+                    );
+                    direct.fallback(&peer_key).await;
+                }
+            }
+        });
+        Negotiator {
+            direct: self,
+            commands,
+        }
+    }
+
+    /// Offer a direct connection to a peer.
+    ///
+    /// Only the peer with the lowest public key sends an offer.
+    async fn connect(&self, peer_key: &[u8]) -> Result<()> {
+        if self.options.keypair.public_key() > peer_key {
+            return Ok(());
+        }
+
+        let connection = self.new_connection(peer_key).await?;
+        let channel =
+            connection.create_data_channel(LABEL, None).await?;
+        self.handle_channel(peer_key, Arc::clone(&channel));
+        self.insert(peer_key, &connection, Some(channel)).await;
+
+        let offer = connection.create_offer(None).await?;
+        self.signal(
+            peer_key,
+            &Signal::Offer {
+                sdp: offer.sdp.clone(),
+            },
+        )
+        .await?;
+        // Candidates are gathered after the offer is sent
+        connection.set_local_description(offer).await?;
+        Ok(())
+    }
+
+    /// Handle an offer, answer or candidate from a peer.
+    async fn negotiate(
+        &self,
+        peer_key: &[u8],
+        signal: Signal,
+    ) -> Result<()> {
+        match signal {
+            Signal::Offer { sdp } => {
+                let connection =
+                    self.new_connection(peer_key).await?;
+                let direct = self.clone();
+                let key = peer_key.to_vec();
+                connection.on_data_channel(Box::new(
+                    move |channel| {
+                        let direct = direct.clone();
+                        let key = key.clone();
+                        Box::pin(async move {
+                            direct.handle_channel(
+                                &key,
+                                Arc::clone(&channel),
+                            );
+                            if let Some(state) = direct
+                                .metadata
+                                .write()
+                                .await
+                                .get_mut(&key)
+                                .and_then(|meta| meta.direct.as_mut())
+                            {
+                                state.channel =
+                                    Some(Arc::clone(&channel));
+                            }
+                            // May have opened before the handlers
+                            // were registered
+                            if channel.ready_state()
+                                == RTCDataChannelState::Open
+                            {
+                                direct.switch(&key).await;
+                            }
+                        })
+                    },
+                ));
+                self.insert(peer_key, &connection, None).await;
+
+                connection
+                    .set_remote_description(
+                        RTCSessionDescription::offer(sdp)?,
+                    )
+                    .await?;
+                let answer = connection.create_answer(None).await?;
+                self.signal(
+                    peer_key,
+                    &Signal::Answer {
+                        sdp: answer.sdp.clone(),
+                    },
+                )
+                .await?;
+                connection.set_local_description(answer).await?;
+            }
+            Signal::Answer { sdp } => {
+                if let Some(connection) =
+                    self.connection(peer_key).await
+                {
+                    connection
+                        .set_remote_description(
+                            RTCSessionDescription::answer(sdp)?,
+                        )
+                        .await?;
+                }
+            }
+            Signal::Candidate { candidate } => {
+                if let Some(connection) =
+                    self.connection(peer_key).await
+                {
+                    connection.add_ice_candidate(candidate).await?;
+                }
+            }
+            Signal::Switch => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Handle a switch signal from a peer.
+    async fn switched(
+        &self,
+        peer_key: &[u8],
+    ) -> Vec<ResponseMessage> {
+        let mut metadata = self.metadata.write().await;
+        match metadata
+            .get_mut(peer_key)
+            .and_then(|meta| meta.direct.as_mut())
+        {
+            Some(state) => {
+                state.receiving = true;
+                std::mem::take(&mut state.buffered)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Create a peer connection that falls back to the relay
+    /// when the connection fails.
+    async fn new_connection(
+        &self,
+        peer_key: &[u8],
+    ) -> Result<Arc<RTCPeerConnection>> {
+        let config = RTCConfiguration {
+            ice_servers: vec![RTCIceServer {
+                urls: self.direct.ice_servers.clone(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let connection =
+            Arc::new(self.api.new_peer_connection(config).await?);
+
+        let direct = self.clone();
+        let key = peer_key.to_vec();
+        connection.on_ice_candidate(Box::new(
+            move |candidate: Option<RTCIceCandidate>| {
+                let direct = direct.clone();
+                let key = key.clone();
+                Box::pin(async move {
+                    let candidate = match candidate {
+                        Some(candidate) => candidate,
+                        None => return,
+                    };
+                    let result = match candidate.to_json() {
+                        Ok(candidate) => {
+                            direct
+                                .signal(
+                                    &key,
+                                    &Signal::Candidate { candidate },
+                                )
+                                .await
+                        }
+                        Err(e) => Err(e.into()),
+                    };
+                    if let Err(error) = result {
+                        tracing::warn!(
+                            to = ?hex::encode(&key),
+                            error = %error,
+                            "direct candidate not sent",
+                        );
+                    }
+                })
+            },
+        ));
+
+        let direct = self.clone();
+        let key = peer_key.to_vec();
+        connection.on_peer_connection_state_change(Box::new(
+            move |state: RTCPeerConnectionState| {
+                let direct = direct.clone();
+                let key = key.clone();
+                Box::pin(async move {
+                    if matches!(
+                        state,
+                        RTCPeerConnectionState::Failed
+                            | RTCPeerConnectionState::Closed
+                    ) {
+                        direct.fallback(&key).await;
+                    }
+                })
+            },
+        ));
+
+        let direct = self.clone();
+        let key = peer_key.to_vec();
+        let timeout = self.direct.timeout;
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            let mut metadata = direct.metadata.write().await;
+            if let Some(meta) = metadata.get_mut(&key) {
+                if meta.direct.as_ref().is_some_and(|d| !d.sending) {
+                    tracing::debug!(
+                        peer = ?hex::encode(&key),
+                        "direct connection timed out",
+                    );
+                    meta.direct = None;
+                }
+            }
+        });
+
+        Ok(connection)
+    }
+
+    /// Register the handlers for a data channel.
+    fn handle_channel(
+        &self,
+        peer_key: &[u8],
+        channel: Arc<RTCDataChannel>,
+    ) {
+        let direct = self.clone();
+        let key = peer_key.to_vec();
+        channel.on_open(Box::new(move || {
+            Box::pin(async move {
+                direct.switch(&key).await;
+            })
+        }));
+
+        let direct = self.clone();
+        let key = peer_key.to_vec();
+        channel.on_message(Box::new(
+            move |message: DataChannelMessage| {
+                let direct = direct.clone();
+                let key = key.clone();
+                Box::pin(async move {
+                    if let Err(error) =
+                        direct.receive(&key, message).await
+                    {
+                        tracing::warn!(
+                            from = ?hex::encode(&key),
+                            error = %error,
+                            "direct message dropped",
+                        );
+                    }
+                })
+            },
+        ));
+
+        let direct = self.clone();
+        let key = peer_key.to_vec();
+        channel.on_close(Box::new(move || {
+            let direct = direct.clone();
+            let key = key.clone();
+            Box::pin(async move {
+                direct.fallback(&key).await;
+            })
+        }));
+    }
+
+    /// Relay the switch signal then send messages directly.
+    async fn switch(&self, peer_key: &[u8]) {
+        if let Err(error) =
+            self.signal(peer_key, &Signal::Switch).await
+        {
+            tracing::warn!(
+                to = ?hex::encode(peer_key),
+                error = %error,
+                "direct switch not sent",
+            );
+        }
+    }
+
+    /// Handle a message received over a data channel.
+    async fn receive(
+        &self,
+        peer_key: &[u8],
+        message: DataChannelMessage,
+    ) -> Result<()> {
+        let request: RequestMessage = decode(&message.data).await?;
+        let response = match request {
+            RequestMessage::Opaque(
+                OpaqueMessage::PeerMessage {
+                    session_id,
+                    envelope,
+                    ..
+                }
+                | OpaqueMessage::TrackedPeerMessage {
+                    session_id,
+                    envelope,
+                    ..
+                },
+            ) => {
+                ResponseMessage::Opaque(OpaqueMessage::PeerMessage {
+                    public_key: peer_key.to_vec(),
+                    session_id,
+                    envelope,
+                })
+            }
+            _ => return Ok(()),
+        };
+
+        {
+            let mut metadata = self.metadata.write().await;
+            if let Some(state) = metadata
+                .get_mut(peer_key)
+                .and_then(|meta| meta.direct.as_mut())
+            {
+                if !state.receiving {
+                    state.buffered.push(response);
+                    return Ok(());
+                }
+            }
+        }
+        self.inbound_tx.send(response).await?;
+        Ok(())
+    }
+
+    /// Send messages to a peer over the relay again.
+    async fn fallback(&self, peer_key: &[u8]) {
+        let mut metadata = self.metadata.write().await;
+        if let Some(meta) = metadata.get_mut(peer_key) {
+            if meta.direct.take().is_some() {
+                tracing::debug!(
+                    peer = ?hex::encode(peer_key),
+                    "direct connection closed, using relay",
+                );
+            }
+        }
+    }
+
+    /// Peer connection for a peer.
+    async fn connection(
+        &self,
+        peer_key: &[u8],
+    ) -> Option<Arc<RTCPeerConnection>> {
+        self.metadata
+            .read()
+            .await
+            .get(peer_key)
+            .and_then(|meta| meta.direct.as_ref())
+            .map(|state| Arc::clone(&state.connection))
+    }
+
+    /// Store the direct connection for a peer.
+    async fn insert(
+        &self,
+        peer_key: &[u8],
+        connection: &Arc<RTCPeerConnection>,
+        channel: Option<Arc<RTCDataChannel>>,
+    ) {
+        self.metadata
+            .write()
+            .await
+            .entry(peer_key.to_vec())
+            .or_default()
+            .direct = Some(DirectChannel {
+            connection: Arc::clone(connection),
+            channel,
+            sending: false,
+            receiving: false,
+            buffered: Vec::new(),
+        });
+    }
+
+    /// Send a signal to a peer over the relay.
+    ///
+    /// Signals are not sent once messages to the peer are sent
+    /// directly as they would be received out of order.
+    async fn signal(
+        &self,
+        peer_key: &[u8],
+        signal: &Signal,
+    ) -> Result<()> {
+        let payload = serde_json::to_vec(signal)?;
+        let permit = self
+            .outbound_tx
+            .reserve()
+            .await
+            .map_err(|_| Error::Disconnected)?;
+
+        // Hold the peer channel so no message is sealed
+        // between the switch signal and the change of route
+        let mut peers = self.peers.write().await;
+        let peer = peers.get_mut(peer_key).ok_or_else(|| {
+            Error::PeerNotFound(hex::encode(peer_key))
+        })?;
+        let mut metadata = self.metadata.write().await;
+        let state = match metadata
+            .get_mut(peer_key)
+            .and_then(|meta| meta.direct.as_mut())
+        {
+            Some(state) if !state.sending => state,
+            _ => return Ok(()),
+        };
+        let switch = matches!(signal, Signal::Switch);
+        if switch && state.channel.is_none() {
+            return Ok(());
+        }
+
+        let mut requests = seal_peer_requests(
+            peer,
+            peer_key,
+            PeerMessage {
+                payload: &payload,
+                encoding: Encoding::Signal,
+                broadcast: false,
+                session_id: None,
+                correlation: None,
+                ack: None,
+                compress: false,
+            },
+            &self.options,
+            self.options.fragment_size,
+            false,
+        )
+        .await?;
+        let message = if requests.len() == 1 {
+            InternalMessage::Request(requests.remove(0))
+        } else {
+            InternalMessage::Batch(requests)
+        };
+        permit.send(message);
+
+        if switch {
+            tracing::debug!(
+                to = ?hex::encode(peer_key),
+                "direct connection open",
+            );
+            state.sending = true;
+        }
+        Ok(())
+    }
+}
@@ -24,13 +24,6 @@ pub enum Error {
     #[error("peer already exists")]
     PeerAlreadyExists,
 
-    /// Error generated attempting to handshake with a peer that
-    /// already exists.
-    #[error(
-        "peer already exists, maybe peers are racing to connect"
-    )]
-    PeerAlreadyExistsMaybeRace,
-
     /// Error generated when a peer could not be found.
     #[error(r#"peer "{0}" not found "#)]
     PeerNotFound(String),
@@ -45,11 +38,99 @@ pub enum Error {
     #[error("not transport protocol state")]
     NotTransportState,
 
+    /// Error generated when the session identifier bound to an
+    /// encrypted peer message does not match the session
+    /// identifier of the message.
+    #[error("peer message is not bound to the session")]
+    SessionBindingMismatch,
+
+    /// Error generated when an ordered broadcast is received
+    /// with a sequence number that is not after the last
+    /// ordered broadcast received in the session.
+    #[error("ordered broadcast {sequence} in session {session_id} is out of order")]
+    BroadcastOutOfOrder {
+        /// Session identifier.
+        session_id: mpc_protocol::SessionId,
+        /// Sequence number of the broadcast.
+        sequence: u64,
+    },
+
+    /// Error generated when a fragment of a payload is
+    /// received out of order.
+    #[error("fragment {0} received out of order")]
+    FragmentOutOfOrder(u32),
+
+    /// Error generated when the queue of messages for a
+    /// peer that is not connected is full.
+    #[error("offline queue for peer {0} is full")]
+    OfflineQueueFull(String),
+
+    /// Error generated when a peer does not respond to a
+    /// request before the timeout.
+    #[error("request {0} timed out")]
+    RequestTimeout(u64),
+
+    /// Error generated when a request is cancelled because
+    /// the channel to the peer was lost.
+    #[error("request {0} was cancelled")]
+    RequestCancelled(u64),
+
+    /// Error generated when the event loop receives a message
+    /// it does not expect.
+    #[error("unexpected message: {0}")]
+    UnexpectedMessage(String),
+
+    /// Error generated when a compressed payload is sent or
+    /// received on a platform that does not support compression.
+    #[error("compression is not supported")]
+    CompressionUnsupported,
+
+    /// Error generated when a compressed payload exceeds the
+    /// maximum size once decompressed.
+    #[error("decompressed payload exceeds {0} bytes")]
+    DecompressedSize(u64),
+
     /// Error generated when the wrong type of message is encountered
     /// during a peer to peer handshake.
     #[error("invalid peer handshake message")]
     InvalidPeerHandshakeMessage,
 
+    /// Error generated when the outbound channel is full and
+    /// the backpressure policy does not wait for capacity.
+    #[error("outbound channel is full")]
+    ChannelFull,
+
+    /// Error generated when sending after the event loop
+    /// has stopped because the connection to the server
+    /// ended or was closed.
+    #[error("disconnected from the server")]
+    Disconnected,
+
+    /// Error generated when sending after the event loop was
+    /// dropped or panicked before the connection ended.
+    #[error("event loop terminated")]
+    EventLoopTerminated,
+
+    /// Error generated when sending to a peer would exceed
+    /// the quota for the peer.
+    #[error("quota exceeded for peer {0}")]
+    PeerQuotaExceeded(String),
+
+    /// Error generated when sending in a session would
+    /// exceed the quota for the session.
+    #[error("quota exceeded for session {0}")]
+    SessionQuotaExceeded(mpc_protocol::SessionId),
+
+    /// Error generated when a frame received from the server
+    /// exceeds the maximum size.
+    #[error("frame of {0} bytes exceeds the maximum size")]
+    FrameTooLarge(usize),
+
+    /// Error generated when a payload reassembled from the
+    /// fragments sent by a peer exceeds the maximum size.
+    #[error("payload of {0} bytes exceeds the maximum size")]
+    PayloadTooLarge(usize),
+
     /// Error generated when the client fails to write to the websocket.
     #[error("web socket failed to send")]
     WebSocketSend,
@@ -69,6 +150,56 @@ pub enum Error {
     #[error("unknown javascript error (type conversion failed)")]
     JsError,
 
+    /// Error generated when a proxy refuses to open a tunnel
+    /// to the server.
+    #[error("proxy connect failed: {0}")]
+    ProxyConnect(String),
+
+    /// Error generated when the server URL does not have a
+    /// host to connect to.
+    #[error("server url {0} does not have a host")]
+    InvalidServerUrl(String),
+
+    /// Error generated when the connection to the server
+    /// is not opened before the timeout.
+    #[error("connect to the server timed out")]
+    ConnectTimeout,
+
+    /// Error generated when the server does not respond to
+    /// the handshake before the timeout.
+    #[error("server handshake timed out")]
+    ServerHandshakeTimeout,
+
+    /// Error generated when a peer does not respond to a
+    /// handshake before the timeout.
+    #[error(r#"handshake with peer "{0}" timed out"#)]
+    PeerHandshakeTimeout(String),
+
+    /// Error generated when the server does not acknowledge
+    /// a tracked message before the timeout.
+    #[error("message {0} was not acknowledged")]
+    DeliveryTimeout(u64),
+
+    /// Error generated when the server could not relay a
+    /// tracked message.
+    #[error("message {0} was not relayed: {1}")]
+    Undelivered(u64, String),
+
+    /// Error generated when a subscriber falls behind and
+    /// events are dropped.
+    #[error("subscription lagged, {0} events were dropped")]
+    SubscriptionLagged(u64),
+
+    /// Error generated when a message could not be
+    /// encrypted for a peer.
+    #[error(r#"encrypt for peer "{0}" failed: {1}"#)]
+    EncryptFailed(String, String),
+
+    /// Error generated when a header for the websocket
+    /// upgrade request is not valid.
+    #[error(r#"invalid header "{0}""#)]
+    InvalidHeader(String),
+
     /// Error generated when the native client fails to reunite
     /// the stream and sink.
     #[error("stream and sink reunite failed")]
@@ -90,11 +221,40 @@ pub enum Error {
     #[error(transparent)]
     Json(#[from] serde_json::Error),
 
+    /// Error generated serializing CBOR.
+    #[error(transparent)]
+    CborEncode(#[from] ciborium::ser::Error<std::io::Error>),
+
+    /// Error generated deserializing CBOR.
+    #[error(transparent)]
+    CborDecode(#[from] ciborium::de::Error<std::io::Error>),
+
+    /// Error generated serializing MessagePack.
+    #[error(transparent)]
+    MessagePackEncode(#[from] rmp_serde::encode::Error),
+
+    /// Error generated deserializing MessagePack.
+    #[error(transparent)]
+    MessagePackDecode(#[from] rmp_serde::decode::Error),
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    /// Error generated by the SOCKS proxy library.
+    #[error(transparent)]
+    Socks(#[from] tokio_socks::Error),
+
     #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
     /// Error generated by the client websocket library.
     #[error(transparent)]
     Websocket(#[from] tokio_tungstenite::tungstenite::Error),
 
+    #[cfg(all(
+        feature = "webrtc",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    /// Error generated by the WebRTC library.
+    #[error(transparent)]
+    WebRtc(#[from] webrtc::Error),
+
     /// Error generated sending a request over a channel.
     #[error(transparent)]
     RequestMpscSend(
@@ -108,6 +268,26 @@ pub enum Error {
     ),
 }
 
+impl Error {
+    /// Determine if this error is transient and the
+    /// operation may succeed if it is retried.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::NoReply
+            | Self::WebSocketSend
+            | Self::ChannelFull
+            | Self::Io(_) => true,
+            Self::ServerError(status, _) => status.is_server_error(),
+            #[cfg(not(all(
+                target_arch = "wasm32",
+                target_os = "unknown"
+            )))]
+            Self::Websocket(_) => true,
+            _ => false,
+        }
+    }
+}
+
 #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
 impl From<wasm_bindgen::JsValue> for Error {
     fn from(value: wasm_bindgen::JsValue) -> Self {
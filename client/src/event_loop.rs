@@ -1,25 +1,121 @@
+use async_stream::stream;
 use futures::{
-    sink::SinkExt,
-    stream::{BoxStream, Stream},
+    future::BoxFuture, select, stream::BoxStream, FutureExt,
+    StreamExt,
 };
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Instant,
+};
+use tokio::sync::{mpsc, watch};
 
 use mpc_protocol::{
-    channel::decrypt_server_channel, decode, hex, snow::Builder,
-    Encoding, HandshakeMessage, MeetingState, OpaqueMessage,
+    channel::decrypt_server_channel, decode, encode, hex,
+    snow::Builder, zlib, Correlation, Encoding, Fragment,
+    HandshakeMessage, Header, MeetingState, OpaqueMessage,
     ProtocolState, RequestMessage, ResponseMessage, SealedEnvelope,
     ServerMessage, SessionId, SessionState, TransparentMessage,
 };
 
-use super::{decrypt_peer_channel, Peers, Server};
-use crate::{ClientOptions, Error, Result};
+use super::{
+    compression, decrypt_peer_channel, forget_channel,
+    metrics::Metrics,
+    relay::{Frame, RelayTransport},
+    seal_peer_requests,
+    subscription::Events,
+    Delivered, Metadata, Peers, Server,
+};
+use crate::{
+    ClientOptions, Error, KeepAlive, Result, ServerEndpoint, Timeouts,
+};
 
 /// Stream of events emitted by an event loop.
 pub type EventStream = BoxStream<'static, Result<Event>>;
 
+/// Partial payloads being reassembled from fragments keyed
+/// by the public key of the sender with the index of the
+/// next fragment.
+pub(crate) type Fragments = HashMap<Vec<u8>, (u32, Vec<u8>)>;
+
+/// State for the messages received by an event loop.
+#[derive(Default)]
+pub(crate) struct Inbound {
+    /// Last ordered broadcast received in each session.
+    pub sequences: HashMap<SessionId, u64>,
+    /// Fragmented payloads received from each peer.
+    pub fragments: Fragments,
+    /// Events to dispatch after the current event.
+    pub pending: Vec<Event>,
+    /// Signals for direct connections keyed by the public
+    /// key of the sender.
+    pub signals: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Inbound {
+    /// Record the sequence number of an ordered broadcast.
+    ///
+    /// Ordered broadcasts must be delivered in the order
+    /// assigned by the server so a sequence number that is
+    /// not after the last one received is an error.
+    pub fn check_sequence(
+        &mut self,
+        session_id: SessionId,
+        sequence: u64,
+    ) -> Result<()> {
+        let last = self.sequences.entry(session_id).or_default();
+        if sequence <= *last {
+            return Err(Error::BroadcastOutOfOrder {
+                session_id,
+                sequence,
+            });
+        }
+        *last = sequence;
+        Ok(())
+    }
+
+    /// Add a fragment of a payload received from a peer.
+    ///
+    /// Returns the reassembled payload once the last fragment
+    /// is received. Fragments received out of order or that
+    /// exceed the maximum payload size discard the payload.
+    pub fn reassemble(
+        &mut self,
+        public_key: &[u8],
+        fragment: Fragment,
+        mut contents: Vec<u8>,
+        max_payload_size: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        let (next, buffer) =
+            self.fragments.entry(public_key.to_vec()).or_default();
+        if fragment.index == 0 {
+            *next = 0;
+            buffer.clear();
+        }
+        if fragment.index != *next {
+            self.fragments.remove(public_key);
+            return Err(Error::FragmentOutOfOrder(fragment.index));
+        }
+        // Bound the memory used to reassemble a payload
+        let size = buffer.len() + contents.len();
+        if size > max_payload_size {
+            self.fragments.remove(public_key);
+            return Err(Error::PayloadTooLarge(size));
+        }
+        buffer.append(&mut contents);
+        *next += 1;
+        if *next < fragment.count {
+            return Ok(None);
+        }
+        Ok(self
+            .fragments
+            .remove(public_key)
+            .map(|(_, buffer)| buffer))
+    }
+}
+
 /// Events dispatched by the event loop stream.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Event {
     /// Event dispatched when a handshake with the server
     /// is completed.
@@ -51,6 +147,24 @@ pub enum Event {
         /// Session identifier.
         session_id: Option<SessionId>,
     },
+    /// CBOR message received from a peer.
+    CborMessage {
+        /// Public key of the peer.
+        peer_key: Vec<u8>,
+        /// CBOR message.
+        message: CborMessage,
+        /// Session identifier.
+        session_id: Option<SessionId>,
+    },
+    /// MessagePack message received from a peer.
+    MessagePackMessage {
+        /// Public key of the peer.
+        peer_key: Vec<u8>,
+        /// MessagePack message.
+        message: MessagePackMessage,
+        /// Session identifier.
+        session_id: Option<SessionId>,
+    },
 
     /// Event dispatched when a meeting has been created.
     MeetingCreated(MeetingState),
@@ -80,20 +194,336 @@ pub enum Event {
 
     /// Event dispatched when a session timed out waiting
     /// for all the participants.
-    SessionTimeout(SessionId),
+    SessionTimeout {
+        /// Session identifier.
+        session_id: SessionId,
+        /// Public keys of the participants that did not connect.
+        missing: Vec<Vec<u8>>,
+    },
 
     /// Event dispatched when a session has been finished.
     ///
-    /// A session can only be finished when the session owner
-    /// explicitly closes the session.
+    /// A session is finished when the session owner closes
+    /// the session or when every participant has acknowledged
+    /// that they finished with the session.
     SessionFinished(SessionId),
 
+    /// Event dispatched to the other participants when
+    /// the session owner closes a session.
+    ///
+    /// If the participants were finishing the session then
+    /// not every participant acknowledged before the close.
+    SessionClosed(SessionId),
+
+    /// Event dispatched when this client has rejoined a
+    /// session after a disconnect.
+    ///
+    /// Peers should handshake again with the other
+    /// participants to restore their channels.
+    SessionRejoined(SessionState),
+
+    /// Event dispatched when a session has expired.
+    ///
+    /// The server has removed the session so any protocol
+    /// running in the session cannot complete.
+    SessionExpired(SessionId),
+
+    /// Event dispatched when a participant in a session
+    /// has disconnected from the server.
+    ///
+    /// The participant may rejoin the session so drivers
+    /// can decide whether to abort or wait.
+    SessionPeerLeft {
+        /// Session identifier.
+        session_id: SessionId,
+        /// Public key of the participant that disconnected.
+        peer_key: Vec<u8>,
+    },
+
+    /// Event dispatched when a participant has been
+    /// added to a session.
+    ///
+    /// The new participant is the last entry in the list
+    /// of participants and should handshake with all the
+    /// other participants.
+    ParticipantAdded(SessionState),
+
+    /// Event dispatched when ownership of a session has
+    /// been transferred to another participant.
+    SessionOwnerChanged {
+        /// Session identifier.
+        session_id: SessionId,
+        /// Public key of the new owner.
+        owner_key: Vec<u8>,
+    },
+
+    /// Event dispatched with the sessions the client
+    /// is a participant of.
+    SessionList(Vec<SessionState>),
+
+    /// Event dispatched when a participant declines
+    /// to join a session.
+    SessionDeclined {
+        /// Session identifier.
+        session_id: SessionId,
+        /// Public key of the participant that declined.
+        public_key: Vec<u8>,
+    },
+
+    /// Event dispatched when a participant in a session
+    /// has stopped sending heartbeats.
+    ///
+    /// The participant is still connected to the server
+    /// but is not responding.
+    SessionPeerTimeout {
+        /// Session identifier.
+        session_id: SessionId,
+        /// Public key of the unresponsive participant.
+        peer_key: Vec<u8>,
+    },
+
+    /// Event dispatched when a value is published in a
+    /// session or in reply to a request to read a value.
+    SessionValue {
+        /// Session identifier.
+        session_id: SessionId,
+        /// Key for the value.
+        key: String,
+        /// Value for the key if it exists.
+        value: Option<Vec<u8>>,
+    },
+
+    /// Request received from a peer.
+    ///
+    /// Reply to the request using the request identifier.
+    PeerRequest {
+        /// Public key of the peer.
+        peer_key: Vec<u8>,
+        /// Identifier for the request.
+        request_id: u64,
+        /// Message buffer.
+        message: Vec<u8>,
+        /// Session identifier.
+        session_id: Option<SessionId>,
+    },
+
+    /// Event dispatched when the channel to a peer is lost.
+    ///
+    /// Either the server reported that the peer left a
+    /// session or a message from the peer could not be
+    /// decrypted; a new handshake is required to send
+    /// messages to the peer.
+    PeerDisconnected {
+        /// Public key of the peer.
+        peer_key: Vec<u8>,
+    },
+
+    /// Event dispatched when a handshake started by a peer
+    /// is rejected by the peer policy.
+    PeerRejected {
+        /// Public key of the peer.
+        peer_key: Vec<u8>,
+    },
+
+    /// Event dispatched when the server did not respond
+    /// to a keep-alive ping before the deadline.
+    ///
+    /// The event loop closes the connection and the
+    /// stream ends after this event.
+    ConnectionLost,
+
+    /// Event dispatched when the connection to the server
+    /// ends without being closed by the client.
+    ///
+    /// The stream ends after this event and sending with
+    /// the client returns a disconnected error.
+    Disconnected {
+        /// Reason the connection ended.
+        cause: DisconnectCause,
+    },
+
+    /// Event dispatched when the client fails over to
+    /// another relay server.
+    ///
+    /// Channels to peers are closed and the event loop starts
+    /// the handshake with the new server; sessions must be
+    /// created or joined again once the server is connected.
+    ServerFailover {
+        /// URL of the server.
+        url: String,
+        /// Public key of the server.
+        server_key: Vec<u8>,
+    },
+
+    /// Event dispatched when the client reconnects to the
+    /// server after the connection ended.
+    ///
+    /// As with a failover channels to peers are closed and
+    /// sessions must be created or joined again once the
+    /// server is connected.
+    ServerReconnected {
+        /// Number of attempts to reconnect.
+        attempts: usize,
+    },
+
     /// Event dispatched when the socket is closed.
     Close,
 }
 
+impl Event {
+    /// Session identifier for this event.
+    pub fn session_id(&self) -> Option<SessionId> {
+        match self {
+            Self::BinaryMessage { session_id, .. }
+            | Self::JsonMessage { session_id, .. }
+            | Self::CborMessage { session_id, .. }
+            | Self::MessagePackMessage { session_id, .. }
+            | Self::PeerRequest { session_id, .. } => *session_id,
+            Self::SessionCreated(state)
+            | Self::SessionReady(state)
+            | Self::SessionActive(state)
+            | Self::SessionRejoined(state)
+            | Self::ParticipantAdded(state) => Some(state.session_id),
+            Self::SessionTimeout { session_id, .. }
+            | Self::SessionPeerLeft { session_id, .. }
+            | Self::SessionOwnerChanged { session_id, .. }
+            | Self::SessionDeclined { session_id, .. }
+            | Self::SessionPeerTimeout { session_id, .. }
+            | Self::SessionValue { session_id, .. }
+            | Self::SessionFinished(session_id)
+            | Self::SessionClosed(session_id)
+            | Self::SessionExpired(session_id) => Some(*session_id),
+            _ => None,
+        }
+    }
+
+    /// Event for a message received from a peer.
+    ///
+    /// Encodings that are not delivered as messages
+    /// do not have an event.
+    #[doc(hidden)]
+    pub fn message(
+        peer_key: Vec<u8>,
+        encoding: Encoding,
+        contents: Vec<u8>,
+        session_id: Option<SessionId>,
+    ) -> Option<Self> {
+        match encoding {
+            Encoding::Blob => Some(Self::BinaryMessage {
+                peer_key,
+                message: contents,
+                session_id,
+            }),
+            Encoding::Json => Some(Self::JsonMessage {
+                peer_key,
+                message: JsonMessage { contents },
+                session_id,
+            }),
+            Encoding::Cbor => Some(Self::CborMessage {
+                peer_key,
+                message: CborMessage { contents },
+                session_id,
+            }),
+            Encoding::MessagePack => Some(Self::MessagePackMessage {
+                peer_key,
+                message: MessagePackMessage { contents },
+                session_id,
+            }),
+            Encoding::Noop | Encoding::Close | Encoding::Signal => {
+                None
+            }
+        }
+    }
+
+    /// Size of the payload for a message from a peer.
+    pub(crate) fn message_len(&self) -> Option<usize> {
+        match self {
+            Self::BinaryMessage { message, .. }
+            | Self::PeerRequest { message, .. } => {
+                Some(message.len())
+            }
+            Self::JsonMessage { message, .. } => {
+                Some(message.contents.len())
+            }
+            Self::CborMessage { message, .. } => {
+                Some(message.contents.len())
+            }
+            Self::MessagePackMessage { message, .. } => {
+                Some(message.contents.len())
+            }
+            _ => None,
+        }
+    }
+
+    /// Public key of the peer for this event.
+    pub fn peer_key(&self) -> Option<&[u8]> {
+        match self {
+            Self::PeerConnected { peer_key }
+            | Self::BinaryMessage { peer_key, .. }
+            | Self::JsonMessage { peer_key, .. }
+            | Self::CborMessage { peer_key, .. }
+            | Self::MessagePackMessage { peer_key, .. }
+            | Self::PeerRequest { peer_key, .. }
+            | Self::SessionPeerLeft { peer_key, .. }
+            | Self::SessionPeerTimeout { peer_key, .. }
+            | Self::PeerDisconnected { peer_key }
+            | Self::PeerRejected { peer_key } => Some(peer_key),
+            Self::SessionDeclined { public_key, .. } => {
+                Some(public_key)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// State of the connection to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Transport is connected but the handshake with the
+    /// server has not started.
+    Connecting,
+    /// Waiting for the server to complete the handshake.
+    Handshaking,
+    /// Handshake with the server is complete.
+    Connected,
+    /// Connecting to the next server after the connection
+    /// to the server ended.
+    Reconnecting,
+    /// Connection to the server has ended.
+    Closed,
+}
+
+impl ConnectionState {
+    /// State after an event is dispatched.
+    fn after(event: &Event) -> Option<Self> {
+        match event {
+            Event::ServerConnected { .. } => Some(Self::Connected),
+            Event::ServerFailover { .. }
+            | Event::ServerReconnected { .. } => {
+                Some(Self::Handshaking)
+            }
+            Event::Close
+            | Event::ConnectionLost
+            | Event::Disconnected { .. } => Some(Self::Closed),
+            _ => None,
+        }
+    }
+}
+
+/// Publishes the state of the connection to the server.
+pub(crate) type ConnectionWatch = Arc<watch::Sender<ConnectionState>>;
+
+/// Reason the connection to the server ended.
+#[derive(Debug, Clone)]
+pub enum DisconnectCause {
+    /// Server closed the connection.
+    Closed,
+    /// Transport failed to receive from the server.
+    Error(String),
+}
+
 /// JSON message received from a peer.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct JsonMessage {
     contents: Vec<u8>,
 }
@@ -105,6 +535,51 @@ impl JsonMessage {
     ) -> Result<T> {
         Ok(serde_json::from_slice::<T>(&self.contents)?)
     }
+
+    /// Serialized bytes of this message.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.contents
+    }
+}
+
+/// CBOR message received from a peer.
+#[derive(Debug, Clone)]
+pub struct CborMessage {
+    contents: Vec<u8>,
+}
+
+impl CborMessage {
+    /// Deserialize this message.
+    pub fn deserialize<T: serde::de::DeserializeOwned>(
+        &self,
+    ) -> Result<T> {
+        Ok(ciborium::de::from_reader(self.contents.as_slice())?)
+    }
+
+    /// Serialized bytes of this message.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.contents
+    }
+}
+
+/// MessagePack message received from a peer.
+#[derive(Debug, Clone)]
+pub struct MessagePackMessage {
+    contents: Vec<u8>,
+}
+
+impl MessagePackMessage {
+    /// Deserialize this message.
+    pub fn deserialize<'a, T: serde::de::Deserialize<'a>>(
+        &'a self,
+    ) -> Result<T> {
+        Ok(rmp_serde::from_slice::<T>(&self.contents)?)
+    }
+
+    /// Serialized bytes of this message.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.contents
+    }
 }
 
 /// Internal message used to communicate between
@@ -114,42 +589,430 @@ impl JsonMessage {
 pub enum InternalMessage {
     /// Send a request.
     Request(RequestMessage),
+    /// Send several requests in order.
+    Batch(Vec<RequestMessage>),
+    /// Dispatch a message sent to this client.
+    Loopback(Event),
+    /// Reconnect to the server when the transport is closed
+    /// or when forced.
+    Reconnect {
+        /// Reconnect even when the transport is open.
+        force: bool,
+    },
     /// Close the connection.
     Close,
 }
 
+/// Tracks when the server was last heard from so that
+/// half-open connections are detected.
+pub(crate) struct Liveness {
+    keep_alive: Option<KeepAlive>,
+    deadline: Option<Instant>,
+    awaiting_pong: bool,
+}
+
+impl Liveness {
+    /// Create liveness tracking for the keep-alive settings.
+    pub(crate) fn new(keep_alive: Option<KeepAlive>) -> Self {
+        // No clock or websocket pings in the browser
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        let keep_alive = keep_alive.filter(|_| false);
+        let mut liveness = Self {
+            keep_alive,
+            deadline: None,
+            awaiting_pong: false,
+        };
+        liveness.received();
+        liveness
+    }
+
+    /// Record that a message was received from the server.
+    pub(crate) fn received(&mut self) {
+        if let Some(keep_alive) = &self.keep_alive {
+            self.awaiting_pong = false;
+            self.deadline =
+                Some(Instant::now() + keep_alive.interval);
+        }
+    }
+
+    /// Wait until the deadline for the next keep-alive check.
+    ///
+    /// Never completes when keep-alive is disabled.
+    pub(crate) async fn wait(&self) {
+        match self.deadline {
+            #[cfg(not(all(
+                target_arch = "wasm32",
+                target_os = "unknown"
+            )))]
+            Some(deadline) => {
+                tokio::time::sleep_until(deadline.into()).await
+            }
+            _ => futures::future::pending().await,
+        }
+    }
+
+    /// Check the connection after the deadline has passed.
+    ///
+    /// Returns true when a ping is already outstanding and
+    /// the connection is lost; otherwise the deadline is
+    /// moved to the ping timeout and a ping should be sent.
+    pub(crate) fn expired(&mut self) -> bool {
+        if self.awaiting_pong {
+            return true;
+        }
+        if let Some(keep_alive) = &self.keep_alive {
+            self.awaiting_pong = true;
+            self.deadline = Some(Instant::now() + keep_alive.timeout);
+        }
+        false
+    }
+}
+
+/// Deadlines for the handshakes started by the client so
+/// that a server or peer that never responds is detected.
+#[derive(Default)]
+pub(crate) struct Handshakes {
+    server: Option<Instant>,
+    peers: HashMap<Vec<u8>, Instant>,
+}
+
+impl Handshakes {
+    /// Record a handshake started by a request.
+    fn started(
+        &mut self,
+        request: &RequestMessage,
+        timeouts: &Timeouts,
+    ) {
+        // No clock in the browser
+        let now = match crate::timer::now() {
+            Some(now) => now,
+            None => return,
+        };
+        match request {
+            RequestMessage::Transparent(
+                TransparentMessage::ServerHandshake(
+                    HandshakeMessage::Initiator(..),
+                ),
+            ) => {
+                self.server = Some(now + timeouts.server_handshake);
+            }
+            RequestMessage::Transparent(
+                TransparentMessage::PeerHandshake {
+                    public_key,
+                    message: HandshakeMessage::Initiator(..),
+                },
+            ) => {
+                self.peers.insert(
+                    public_key.clone(),
+                    now + timeouts.peer_handshake,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Record a handshake completed by an event.
+    fn completed(&mut self, event: &Event) {
+        match event {
+            Event::ServerConnected { .. } => self.server = None,
+            Event::PeerConnected { peer_key } => {
+                self.peers.remove(peer_key);
+            }
+            _ => {}
+        }
+    }
+
+    /// Wait until the earliest handshake deadline.
+    ///
+    /// Never completes when there are no handshakes.
+    pub(crate) async fn wait(&self) {
+        let deadline = self
+            .server
+            .into_iter()
+            .chain(self.peers.values().copied())
+            .min();
+        match deadline {
+            #[cfg(not(all(
+                target_arch = "wasm32",
+                target_os = "unknown"
+            )))]
+            Some(deadline) => {
+                tokio::time::sleep_until(deadline.into()).await
+            }
+            _ => futures::future::pending().await,
+        }
+    }
+
+    /// Remove the handshakes whose deadline has passed.
+    ///
+    /// Returns whether the server handshake expired and the
+    /// public keys of the peers that did not respond.
+    fn expired(&mut self) -> (bool, Vec<Vec<u8>>) {
+        let now = Instant::now();
+        let server = self.server.is_some_and(|d| d <= now);
+        if server {
+            self.server = None;
+        }
+        let peers: Vec<Vec<u8>> = self
+            .peers
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(peer_key, _)| peer_key.clone())
+            .collect();
+        for peer_key in &peers {
+            self.peers.remove(peer_key);
+        }
+        (server, peers)
+    }
+}
+
 /// Event loop for a client.
-pub struct EventLoop<M, E, R, W>
-where
-    M: Send,
-    E: Send,
-    R: Stream<Item = std::result::Result<M, E>> + Unpin,
-    W: SinkExt<M> + Unpin,
-{
+pub struct EventLoop<T: RelayTransport> {
     pub(crate) options: Arc<ClientOptions>,
-    pub(crate) ws_reader: R,
-    pub(crate) ws_writer: W,
+    pub(crate) transport: T,
     pub(crate) inbound_tx: mpsc::Sender<ResponseMessage>,
     pub(crate) inbound_rx: mpsc::Receiver<ResponseMessage>,
     pub(crate) outbound_tx: mpsc::Sender<InternalMessage>,
     pub(crate) outbound_rx: mpsc::Receiver<InternalMessage>,
     pub(crate) server: Server,
     pub(crate) peers: Peers,
+    pub(crate) metadata: Metadata,
+    pub(crate) metrics: Metrics,
+    #[cfg(all(
+        feature = "webrtc",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    pub(crate) direct: Option<crate::direct::Direct>,
+    pub(crate) failover: Option<Failover<T>>,
+    pub(crate) handshakes: Handshakes,
+    pub(crate) events: Events,
+    pub(crate) state: ConnectionWatch,
+}
+
+/// Connects a transport to a server the client fails over to.
+pub(crate) type Connect<T> = Box<
+    dyn Fn(String) -> BoxFuture<'static, Result<T>> + Send + Sync,
+>;
+
+/// Servers to fail over to when the connection ends.
+pub(crate) struct Failover<T> {
+    /// URL and public key of the connected server.
+    pub server: (String, Vec<u8>),
+    /// Servers in order of priority.
+    pub endpoints: VecDeque<ServerEndpoint>,
+    /// Connect a transport to a server.
+    pub connect: Connect<T>,
 }
 
-impl<M, E, R, W> EventLoop<M, E, R, W>
-where
-    M: Send,
-    E: Send,
-    R: Stream<Item = std::result::Result<M, E>> + Unpin,
-    W: SinkExt<M> + Unpin,
-{
+impl<T: RelayTransport> EventLoop<T> {
+    /// Decode messages received from the server then send
+    /// to the messages channel.
+    async fn read_message(
+        incoming: Frame,
+        event_proxy: &mut mpsc::Sender<ResponseMessage>,
+        metrics: &Metrics,
+    ) -> Result<()> {
+        match incoming {
+            Frame::Message(buffer) => {
+                metrics.lock().unwrap().received(buffer.len());
+                let inflated = zlib::inflate(&buffer)?;
+                let response: ResponseMessage =
+                    decode(inflated).await?;
+                event_proxy.send(response).await?;
+            }
+            Frame::Pong => {
+                metrics.lock().unwrap().pong_received();
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a message to the server.
+    async fn send_message(
+        &mut self,
+        message: RequestMessage,
+    ) -> Result<()> {
+        self.handshakes.started(&message, &self.options.timeouts);
+        let encoded = encode(&message).await?;
+        let deflated = zlib::deflate(&encoded)?;
+        self.metrics.lock().unwrap().sent(deflated.len());
+        self.transport.send(deflated).await
+    }
+
+    /// Send a keep-alive ping to the server.
+    async fn send_ping(&mut self) -> Result<()> {
+        self.metrics.lock().unwrap().ping_sent();
+        self.transport.ping().await
+    }
+
+    /// Connect to the next server to fail over to.
+    ///
+    /// Once there are no more servers the client reconnects
+    /// to the last server; returns none when reconnecting
+    /// is disabled or every attempt failed.
+    async fn fail_over(
+        &mut self,
+        inbound: &mut Inbound,
+    ) -> Option<Event> {
+        let reconnect = self.options.reconnect.is_some();
+        if self.failover.as_ref().is_some_and(|failover| {
+            reconnect || !failover.endpoints.is_empty()
+        }) {
+            self.state.send_replace(ConnectionState::Reconnecting);
+        }
+        loop {
+            let (endpoint, url, result) = {
+                let failover = self.failover.as_mut()?;
+                let endpoint = match failover.endpoints.pop_front() {
+                    Some(endpoint) => endpoint,
+                    None => break,
+                };
+                let url = self.options.url(&endpoint.url);
+                let result = (failover.connect)(url.clone()).await;
+                (endpoint, url, result)
+            };
+            let result = match result {
+                Ok(transport) => {
+                    self.restart(
+                        transport,
+                        &endpoint.public_key,
+                        inbound,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => {
+                    tracing::info!(
+                        url = %endpoint.url,
+                        "failed over to server",
+                    );
+                    if let Some(failover) = self.failover.as_mut() {
+                        failover.server =
+                            (url, endpoint.public_key.clone());
+                    }
+                    return Some(Event::ServerFailover {
+                        url: endpoint.url,
+                        server_key: endpoint.public_key,
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        url = %endpoint.url,
+                        error = %e,
+                        "server failover failed",
+                    );
+                }
+            }
+        }
+        self.reconnect(inbound).await
+    }
+
+    /// Reconnect to the server with a delay before each
+    /// attempt.
+    ///
+    /// Returns none when reconnecting is disabled or every
+    /// attempt failed.
+    async fn reconnect(
+        &mut self,
+        inbound: &mut Inbound,
+    ) -> Option<Event> {
+        let reconnect = self.options.reconnect?;
+        for attempt in 0..reconnect.attempts {
+            crate::timer::sleep(reconnect.backoff(attempt)).await;
+            let (url, server_key, result) = {
+                let failover = self.failover.as_ref()?;
+                let (url, server_key) = failover.server.clone();
+                let result = (failover.connect)(url.clone()).await;
+                (url, server_key, result)
+            };
+            let result = match result {
+                Ok(transport) => {
+                    self.restart(transport, &server_key, inbound)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => {
+                    tracing::info!(
+                        url = %url,
+                        attempts = attempt + 1,
+                        "reconnected to server",
+                    );
+                    return Some(Event::ServerReconnected {
+                        attempts: attempt + 1,
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        url = %url,
+                        attempt = attempt + 1,
+                        error = %e,
+                        "reconnect failed",
+                    );
+                }
+            }
+        }
+        None
+    }
+
+    /// Replace the transport and start the handshake
+    /// with a new server.
+    async fn restart(
+        &mut self,
+        transport: T,
+        server_key: &[u8],
+        inbound: &mut Inbound,
+    ) -> Result<()> {
+        let mut initiator = Builder::new(self.options.params()?)
+            .local_private_key(self.options.keypair.private_key())
+            .remote_public_key(server_key)
+            .build_initiator()?;
+        let mut payload = vec![0u8; 1024];
+        let len = initiator.write_message(&[], &mut payload)?;
+
+        let _ = self.transport.close().await;
+        self.transport = transport;
+        *self.server.write().await =
+            Some(ProtocolState::Handshake(Box::new(initiator)));
+
+        // Channels to peers are not carried over
+        self.handshakes = Default::default();
+        {
+            let mut peers = self.peers.write().await;
+            let mut metadata = self.metadata.write().await;
+            for (peer_key, _) in peers.drain() {
+                forget_channel(&mut metadata, &peer_key);
+                inbound.fragments.remove(&peer_key);
+                inbound
+                    .pending
+                    .push(Event::PeerDisconnected { peer_key });
+            }
+        }
+        inbound.sequences.clear();
+
+        self.send_message(RequestMessage::Transparent(
+            TransparentMessage::ServerHandshake(
+                HandshakeMessage::Initiator(len, payload),
+            ),
+        ))
+        .await
+    }
+
+    async fn handle_close_message(mut self) -> Result<()> {
+        self.transport.close().await
+    }
+
     pub(crate) async fn handle_incoming_message(
         options: Arc<ClientOptions>,
         server: Server,
         peers: Peers,
+        metadata: Metadata,
         incoming: ResponseMessage,
         outbound_tx: mpsc::Sender<InternalMessage>,
+        inbound: &mut Inbound,
     ) -> Result<Option<Event>> {
         match incoming {
             ResponseMessage::Transparent(
@@ -171,6 +1034,7 @@ where
             ) => Ok(Self::peer_handshake_responder(
                 options,
                 peers,
+                metadata,
                 outbound_tx,
                 public_key,
                 len,
@@ -183,19 +1047,65 @@ where
                     public_key,
                 },
             ) => Ok(Some(
-                Self::peer_handshake_ack(peers, public_key, len, buf)
-                    .await?,
+                Self::peer_handshake_ack(
+                    peers, metadata, public_key, len, buf,
+                )
+                .await?,
             )),
+            ResponseMessage::Transparent(
+                TransparentMessage::Delivered { public_key, id },
+            ) => {
+                Self::acknowledge(&metadata, &public_key, id, Ok(()))
+                    .await;
+                Ok(None)
+            }
+            ResponseMessage::Transparent(
+                TransparentMessage::Undelivered {
+                    public_key,
+                    id,
+                    reason,
+                },
+            ) => {
+                Self::acknowledge(
+                    &metadata,
+                    &public_key,
+                    id,
+                    Err(reason),
+                )
+                .await;
+                Ok(None)
+            }
             ResponseMessage::Opaque(OpaqueMessage::PeerMessage {
                 public_key,
                 envelope,
                 session_id,
-            }) => Ok(Some(
+            }) => {
                 Self::handle_relayed_message(
-                    peers, public_key, envelope, session_id,
+                    &options, peers, metadata, inbound, public_key,
+                    envelope, session_id,
                 )
-                .await?,
-            )),
+                .await
+            }
+            ResponseMessage::Opaque(
+                OpaqueMessage::OrderedPeerMessage {
+                    public_key,
+                    session_id,
+                    sequence,
+                    envelope,
+                },
+            ) => {
+                inbound.check_sequence(session_id, sequence)?;
+                Self::handle_relayed_message(
+                    &options,
+                    peers,
+                    metadata,
+                    inbound,
+                    public_key,
+                    envelope,
+                    Some(session_id),
+                )
+                .await
+            }
             ResponseMessage::Opaque(
                 OpaqueMessage::ServerMessage(envelope),
             ) => {
@@ -210,20 +1120,163 @@ where
                                 decode(&contents).await?;
                             response
                         }
-                        _ => {
-                            panic!("unexpected encoding received from server")
+                        encoding => {
+                            return Err(Error::UnexpectedMessage(
+                                format!(
+                                    "server message encoding {:?}",
+                                    encoding,
+                                ),
+                            ));
                         }
                     };
-                    Ok(Self::handle_server_channel_message(message)
-                        .await?)
+                    let event =
+                        Self::handle_server_channel_message(message)
+                            .await?;
+                    if let Some(Event::SessionPeerLeft {
+                        peer_key,
+                        ..
+                    }) = &event
+                    {
+                        let mut peers = peers.write().await;
+                        if peers.remove(peer_key).is_some() {
+                            forget_channel(
+                                &mut *metadata.write().await,
+                                peer_key,
+                            );
+                            inbound.pending.push(
+                                Event::PeerDisconnected {
+                                    peer_key: peer_key.clone(),
+                                },
+                            );
+                        }
+                    }
+                    Ok(event)
                 } else {
-                    unreachable!()
+                    Err(Error::UnexpectedMessage(
+                        "server message before the server handshake"
+                            .to_string(),
+                    ))
                 }
             }
-            _ => {
-                panic!("unhandled message");
+            ResponseMessage::Transparent(
+                TransparentMessage::PeerHandshake {
+                    message,
+                    public_key,
+                },
+            ) => {
+                // Abandon a handshake with a misbehaving peer
+                let mut peers = peers.write().await;
+                if let Some(ProtocolState::Handshake(_)) =
+                    peers.get(&public_key)
+                {
+                    peers.remove(&public_key);
+                    inbound.pending.push(Event::PeerDisconnected {
+                        peer_key: public_key.clone(),
+                    });
+                }
+                Err(Error::UnexpectedMessage(format!(
+                    "peer handshake message type {} from {}",
+                    u8::from(&message),
+                    hex::encode(&public_key),
+                )))
+            }
+            ResponseMessage::Transparent(message) => {
+                Err(Error::UnexpectedMessage(format!(
+                    "transparent message type {}",
+                    u8::from(&message),
+                )))
+            }
+            ResponseMessage::Opaque(message) => {
+                Err(Error::UnexpectedMessage(format!(
+                    "opaque message type {}",
+                    u8::from(&message),
+                )))
+            }
+            ResponseMessage::Noop => Err(Error::UnexpectedMessage(
+                "empty message".to_string(),
+            )),
+        }
+    }
+
+    /// Notify the sender of a tracked message that the
+    /// server relayed the message or could not relay it.
+    async fn acknowledge(
+        metadata: &Metadata,
+        public_key: &[u8],
+        id: u64,
+        delivered: Delivered,
+    ) {
+        let mut metadata = metadata.write().await;
+        if let Some(tx) = metadata
+            .get_mut(public_key)
+            .and_then(|meta| meta.acks.remove(&id))
+        {
+            let _ = tx.send(delivered);
+        }
+    }
+
+    /// Wake the senders waiting for a channel to a peer.
+    async fn notify_connected(
+        metadata: &Metadata,
+        public_key: &[u8],
+    ) {
+        if let Some(meta) = metadata.write().await.get_mut(public_key)
+        {
+            for tx in meta.connected.drain(..) {
+                let _ = tx.send(());
+            }
+        }
+    }
+
+    /// Seal the messages queued for a peer once the channel
+    /// to the peer is established.
+    ///
+    /// Messages that have expired are discarded.
+    pub(crate) async fn flush_queue(
+        options: &ClientOptions,
+        peers: &Peers,
+        metadata: &Metadata,
+        public_key: &[u8],
+    ) -> Result<Vec<RequestMessage>> {
+        let mut peers = peers.write().await;
+        let mut metadata = metadata.write().await;
+        let (peer, meta) = match (
+            peers.get_mut(public_key),
+            metadata.get_mut(public_key),
+        ) {
+            (Some(peer), Some(meta)) => (peer, meta),
+            _ => return Ok(Vec::new()),
+        };
+
+        let ttl = options
+            .offline_queue
+            .map(|queue| queue.ttl)
+            .unwrap_or_default();
+        let mut requests = Vec::new();
+        for queued in std::mem::take(&mut meta.queue) {
+            if queued.is_expired(ttl) {
+                tracing::debug!(
+                    to = ?hex::encode(public_key),
+                    "drop expired message for peer",
+                );
+                continue;
             }
+            requests.append(
+                &mut seal_peer_requests(
+                    peer,
+                    public_key,
+                    queued.message(),
+                    options,
+                    options.fragment_size,
+                    meta.compression,
+                )
+                .await?,
+            );
+        }
+        if !requests.is_empty() {
+            meta.touch();
         }
+        Ok(requests)
     }
 
     /// Process an inner message from the server after
@@ -250,12 +1303,68 @@ where
             ServerMessage::SessionActive(response) => {
                 Ok(Some(Event::SessionActive(response)))
             }
-            ServerMessage::SessionTimeout(session_id) => {
-                Ok(Some(Event::SessionTimeout(session_id)))
-            }
+            ServerMessage::SessionTimeout {
+                session_id,
+                missing,
+            } => Ok(Some(Event::SessionTimeout {
+                session_id,
+                missing,
+            })),
             ServerMessage::SessionFinished(session_id) => {
                 Ok(Some(Event::SessionFinished(session_id)))
             }
+            ServerMessage::SessionClosed(session_id) => {
+                Ok(Some(Event::SessionClosed(session_id)))
+            }
+            ServerMessage::SessionRejoined(response) => {
+                Ok(Some(Event::SessionRejoined(response)))
+            }
+            ServerMessage::SessionExpired(session_id) => {
+                Ok(Some(Event::SessionExpired(session_id)))
+            }
+            ServerMessage::SessionPeerLeft {
+                session_id,
+                peer_key,
+            } => Ok(Some(Event::SessionPeerLeft {
+                session_id,
+                peer_key,
+            })),
+            ServerMessage::ParticipantAdded(response) => {
+                Ok(Some(Event::ParticipantAdded(response)))
+            }
+            ServerMessage::SessionOwnerChanged {
+                session_id,
+                owner_key,
+            } => Ok(Some(Event::SessionOwnerChanged {
+                session_id,
+                owner_key,
+            })),
+            ServerMessage::SessionList(sessions) => {
+                Ok(Some(Event::SessionList(sessions)))
+            }
+            ServerMessage::SessionDeclined {
+                session_id,
+                public_key,
+            } => Ok(Some(Event::SessionDeclined {
+                session_id,
+                public_key,
+            })),
+            ServerMessage::SessionPeerTimeout {
+                session_id,
+                peer_key,
+            } => Ok(Some(Event::SessionPeerTimeout {
+                session_id,
+                peer_key,
+            })),
+            ServerMessage::SessionValue {
+                session_id,
+                key,
+                value,
+            } => Ok(Some(Event::SessionValue {
+                session_id,
+                key,
+                value,
+            })),
             _ => Ok(None),
         }
     }
@@ -277,70 +1386,115 @@ where
             _ => return Err(Error::NotHandshakeState),
         };
 
+        // Key for the active server after a failover
+        let server_key = transport
+            .get_remote_static()
+            .map(|key| key.to_vec())
+            .unwrap_or_else(|| options.server_public_key.clone());
         *state = Some(ProtocolState::Transport(transport));
 
-        Ok(Event::ServerConnected {
-            server_key: options.server_public_key.clone(),
-        })
+        Ok(Event::ServerConnected { server_key })
     }
 
     async fn peer_handshake_responder(
         options: Arc<ClientOptions>,
         peers: Peers,
+        metadata: Metadata,
         outbound_tx: mpsc::Sender<InternalMessage>,
         public_key: impl AsRef<[u8]>,
         len: usize,
         buf: Vec<u8>,
     ) -> Result<Option<Event>> {
-        let mut peers = peers.write().await;
-
-        if peers.get(public_key.as_ref()).is_some() {
-            Err(Error::PeerAlreadyExistsMaybeRace)
-        } else {
-            tracing::debug!(
+        if !options.peer_policy.is_allowed(public_key.as_ref()) {
+            tracing::warn!(
                 from = ?hex::encode(public_key.as_ref()),
-                "peer handshake responder"
+                "peer handshake rejected by policy"
             );
+            return Ok(Some(Event::PeerRejected {
+                peer_key: public_key.as_ref().to_vec(),
+            }));
+        }
 
-            let builder = Builder::new(options.params()?);
-            let mut responder = builder
-                .local_private_key(options.keypair.private_key())
-                .remote_public_key(public_key.as_ref())
-                .build_responder()?;
+        let mut peers = peers.write().await;
 
-            let mut read_buf = vec![0u8; 1024];
+        // When both peers initiate a handshake at the same
+        // time the peer with the lowest public key remains
+        // the initiator and the other peer responds; an
+        // established channel is never replaced, it must be
+        // closed before the peers handshake again
+        match peers.get(public_key.as_ref()) {
+            Some(ProtocolState::Transport(_)) => {
+                tracing::warn!(
+                    from = ?hex::encode(public_key.as_ref()),
+                    "ignore peer handshake, channel established"
+                );
+                return Ok(None);
+            }
+            Some(ProtocolState::Handshake(_)) => {
+                if options.keypair.public_key()
+                    < public_key.as_ref()
+                {
+                    tracing::debug!(
+                        from = ?hex::encode(public_key.as_ref()),
+                        "ignore peer handshake, remain initiator"
+                    );
+                    return Ok(None);
+                }
+                peers.remove(public_key.as_ref());
+            }
+            None => {}
+        }
+
+        tracing::debug!(
+            from = ?hex::encode(public_key.as_ref()),
+            "peer handshake responder"
+        );
+
+        let builder = Builder::new(options.params()?);
+        let mut responder = builder
+            .local_private_key(options.keypair.private_key())
+            .remote_public_key(public_key.as_ref())
+            .build_responder()?;
+
+        let mut read_buf = vec![0u8; 1024];
+        let read_len =
             responder.read_message(&buf[..len], &mut read_buf)?;
+        compression::negotiate(
+            &metadata,
+            public_key.as_ref(),
+            &read_buf[..read_len],
+        )
+        .await;
 
-            let mut payload = vec![0u8; 1024];
-            let len = responder.write_message(&[], &mut payload)?;
+        let mut payload = vec![0u8; 1024];
+        let len = responder.write_message(
+            compression::capabilities(),
+            &mut payload,
+        )?;
 
-            let transport = responder.into_transport_mode()?;
-            peers.insert(
-                public_key.as_ref().to_vec(),
-                ProtocolState::Transport(transport),
-            );
+        let transport = responder.into_transport_mode()?;
+        peers.insert(
+            public_key.as_ref().to_vec(),
+            ProtocolState::Transport(transport),
+        );
 
-            let request = RequestMessage::Transparent(
-                TransparentMessage::PeerHandshake {
-                    public_key: public_key.as_ref().to_vec(),
-                    message: HandshakeMessage::Responder(
-                        len, payload,
-                    ),
-                },
-            );
+        let request = RequestMessage::Transparent(
+            TransparentMessage::PeerHandshake {
+                public_key: public_key.as_ref().to_vec(),
+                message: HandshakeMessage::Responder(len, payload),
+            },
+        );
 
-            outbound_tx
-                .send(InternalMessage::Request(request))
-                .await?;
+        outbound_tx.send(InternalMessage::Request(request)).await?;
 
-            Ok(Some(Event::PeerConnected {
-                peer_key: public_key.as_ref().to_vec(),
-            }))
-        }
+        Ok(Some(Event::PeerConnected {
+            peer_key: public_key.as_ref().to_vec(),
+        }))
     }
 
     async fn peer_handshake_ack(
         peers: Peers,
+        metadata: Metadata,
         public_key: impl AsRef<[u8]>,
         len: usize,
         buf: Vec<u8>,
@@ -364,7 +1518,14 @@ where
         let transport = match peer {
             ProtocolState::Handshake(mut initiator) => {
                 let mut read_buf = vec![0u8; 1024];
-                initiator.read_message(&buf[..len], &mut read_buf)?;
+                let read_len = initiator
+                    .read_message(&buf[..len], &mut read_buf)?;
+                compression::negotiate(
+                    &metadata,
+                    public_key.as_ref(),
+                    &read_buf[..read_len],
+                )
+                .await;
                 initiator.into_transport_mode()?
             }
             _ => return Err(Error::NotHandshakeState),
@@ -381,118 +1542,557 @@ where
     }
 
     async fn handle_relayed_message(
+        options: &ClientOptions,
         peers: Peers,
+        metadata: Metadata,
+        inbound: &mut Inbound,
         public_key: impl AsRef<[u8]>,
         envelope: SealedEnvelope,
         session_id: Option<SessionId>,
-    ) -> Result<Event> {
+    ) -> Result<Option<Event>> {
         let mut peers = peers.write().await;
         if let Some(peer) = peers.get_mut(public_key.as_ref()) {
-            let (encoding, contents) =
-                decrypt_peer_channel(peer, envelope).await?;
+            let result =
+                decrypt_peer_channel(peer, envelope, session_id)
+                    .await;
+            let (header, contents) = match result {
+                Ok(result) => result,
+                // Envelope did not authenticate with the
+                // channel keys so the channel is dead
+                Err(Error::Protocol(mpc_protocol::Error::Snow(
+                    e,
+                ))) => {
+                    tracing::warn!(
+                        from = ?hex::encode(public_key.as_ref()),
+                        error = %e,
+                        "peer channel decrypt failed",
+                    );
+                    peers.remove(public_key.as_ref());
+                    inbound.fragments.remove(public_key.as_ref());
+                    // Cancel requests waiting on the peer
+                    forget_channel(
+                        &mut *metadata.write().await,
+                        public_key.as_ref(),
+                    );
+                    return Ok(Some(Event::PeerDisconnected {
+                        peer_key: public_key.as_ref().to_vec(),
+                    }));
+                }
+                Err(e) => return Err(e),
+            };
+            metadata
+                .write()
+                .await
+                .entry(public_key.as_ref().to_vec())
+                .or_default()
+                .touch();
+
+            let Header {
+                encoding,
+                fragment,
+                compressed,
+                correlation,
+                ..
+            } = header;
+            let mut contents = match fragment {
+                Some(fragment) => match inbound.reassemble(
+                    public_key.as_ref(),
+                    fragment,
+                    contents,
+                    options.max_payload_size,
+                )? {
+                    Some(contents) => contents,
+                    None => return Ok(None),
+                },
+                None => contents,
+            };
+
+            if compressed {
+                contents = compression::decompress(
+                    &contents,
+                    options.max_payload_size,
+                )?;
+            }
+
+            match correlation {
+                Some(Correlation::Request(request_id)) => {
+                    return Ok(Some(Event::PeerRequest {
+                        peer_key: public_key.as_ref().to_vec(),
+                        request_id,
+                        message: contents,
+                        session_id,
+                    }));
+                }
+                Some(Correlation::Response(request_id)) => {
+                    let sender = metadata
+                        .write()
+                        .await
+                        .get_mut(public_key.as_ref())
+                        .and_then(|meta| {
+                            meta.requests.remove(&request_id)
+                        });
+                    if let Some(sender) = sender {
+                        let _ = sender.send(contents);
+                    } else {
+                        tracing::warn!(
+                            from = ?hex::encode(public_key.as_ref()),
+                            request_id = request_id,
+                            "drop response for unknown request",
+                        );
+                    }
+                    return Ok(None);
+                }
+                None => {}
+            }
+
             match encoding {
-                Encoding::Noop => unreachable!(),
-                Encoding::Blob => Ok(Event::BinaryMessage {
-                    peer_key: public_key.as_ref().to_vec(),
-                    message: contents,
-                    session_id,
-                }),
-                Encoding::Json => Ok(Event::JsonMessage {
-                    peer_key: public_key.as_ref().to_vec(),
-                    message: JsonMessage { contents },
+                Encoding::Noop => {
+                    // Close the channel to a misbehaving peer
+                    peers.remove(public_key.as_ref());
+                    forget_channel(
+                        &mut *metadata.write().await,
+                        public_key.as_ref(),
+                    );
+                    inbound.pending.push(Event::PeerDisconnected {
+                        peer_key: public_key.as_ref().to_vec(),
+                    });
+                    Err(Error::UnexpectedMessage(format!(
+                        "peer message from {} has no encoding",
+                        hex::encode(public_key.as_ref()),
+                    )))
+                }
+                Encoding::Close => {
+                    tracing::debug!(
+                        from = ?hex::encode(public_key.as_ref()),
+                        "peer closed channel",
+                    );
+                    peers.remove(public_key.as_ref());
+                    forget_channel(
+                        &mut *metadata.write().await,
+                        public_key.as_ref(),
+                    );
+                    inbound.fragments.remove(public_key.as_ref());
+                    Ok(Some(Event::PeerDisconnected {
+                        peer_key: public_key.as_ref().to_vec(),
+                    }))
+                }
+                Encoding::Signal => {
+                    inbound.signals.push((
+                        public_key.as_ref().to_vec(),
+                        contents,
+                    ));
+                    Ok(None)
+                }
+                encoding => Ok(Event::message(
+                    public_key.as_ref().to_vec(),
+                    encoding,
+                    contents,
                     session_id,
-                }),
+                )),
             }
         } else {
             Err(Error::PeerNotFound(hex::encode(public_key.as_ref())))
         }
     }
-}
 
-#[doc(hidden)]
-macro_rules! event_loop_run_impl {
-    () => {
-        /// Stream of events from the event loop.
-        pub fn run(mut self) -> EventStream {
-            let options = Arc::clone(&self.options);
-            let server = Arc::clone(&self.server);
-            let peers = Arc::clone(&self.peers);
-
-            let s = stream! {
-                loop {
-                    select!(
-                        message_in =
-                            self.ws_reader.next().fuse()
-                                => match message_in {
-                            Some(message) => {
-                                match message {
-                                    Ok(message) => {
-                                        if let Err(e) = Self::read_message(
-                                            message,
-                                            &mut self.inbound_tx,
-                                        ).await {
-                                            yield Err(e);
-                                        }
+    /// Stream of events from the event loop.
+    pub fn run(mut self) -> EventStream {
+        let options = Arc::clone(&self.options);
+        let server = Arc::clone(&self.server);
+        let peers = Arc::clone(&self.peers);
+        let metadata = Arc::clone(&self.metadata);
+        #[cfg(all(
+            feature = "webrtc",
+            not(all(target_arch = "wasm32", target_os = "unknown"))
+        ))]
+        let direct =
+            self.direct.take().map(crate::direct::Direct::spawn);
+        let events = self.events.clone();
+        let state = Arc::clone(&self.state);
+        let usage = Arc::clone(&self.metrics);
+
+        let s = stream! {
+            let mut inbound = Inbound::default();
+            let mut liveness = Liveness::new(options.keep_alive);
+            loop {
+                select!(
+                    message_in =
+                        self.transport.receive().fuse()
+                            => match message_in {
+                        Some(message) => {
+                            liveness.received();
+                            match message {
+                                Ok(message) => {
+                                    if let Err(e) = Self::read_message(
+                                        message,
+                                        &mut self.inbound_tx,
+                                        &self.metrics,
+                                    ).await {
+                                        yield Err(e);
                                     }
-                                    Err(e) => {
-                                        yield Err(e.into())
+                                }
+                                Err(e) => {
+                                    if let Some(event) = self.fail_over(&mut inbound).await {
+                                        liveness.received();
+                                        yield Ok(event);
+                                        for event in inbound.pending.drain(..) {
+                                            yield Ok(event);
+                                        }
+                                        continue;
                                     }
+                                    // Fail sends instead of waiting on
+                                    // a channel that is never drained
+                                    self.outbound_rx.close();
+                                    yield Ok(Event::Disconnected {
+                                        cause: DisconnectCause::Error(
+                                            e.to_string(),
+                                        ),
+                                    });
+                                    break;
                                 }
                             }
-                            _ => {}
-                        },
-                        message_out =
-                            self.outbound_rx.recv().fuse()
-                                => match message_out {
-                            Some(message) => {
-
-                                match message {
-                                    InternalMessage::Request(request) => {
+                        }
+                        None => {
+                            if let Some(event) = self.fail_over(&mut inbound).await {
+                                liveness.received();
+                                yield Ok(event);
+                                for event in inbound.pending.drain(..) {
+                                    yield Ok(event);
+                                }
+                                continue;
+                            }
+                            self.outbound_rx.close();
+                            yield Ok(Event::Disconnected {
+                                cause: DisconnectCause::Closed,
+                            });
+                            break;
+                        }
+                    },
+                    message_out =
+                        self.outbound_rx.recv().fuse()
+                            => match message_out {
+                        Some(message) => {
+
+                            match message {
+                                InternalMessage::Request(request) => {
+                                    if let Err(e) = self.send_message(request).await {
+                                        yield Err(e)
+                                    }
+                                }
+                                InternalMessage::Batch(requests) => {
+                                    for request in requests {
                                         if let Err(e) = self.send_message(request).await {
-                                            yield Err(e)
+                                            yield Err(e);
+                                            break;
                                         }
                                     }
-                                    InternalMessage::Close => {
-                                        if let Err(e) = self.handle_close_message().await {
-                                            yield Err(e)
+                                }
+                                InternalMessage::Loopback(event) => {
+                                    yield Ok(event);
+                                }
+                                InternalMessage::Reconnect { force } => {
+                                    if !force && self.transport.is_open() {
+                                        continue;
+                                    }
+                                    let previous = *self.state.borrow();
+                                    self.state.send_replace(ConnectionState::Reconnecting);
+                                    if let Some(event) = self.reconnect(&mut inbound).await {
+                                        liveness.received();
+                                        yield Ok(event);
+                                        for event in inbound.pending.drain(..) {
+                                            yield Ok(event);
                                         }
-                                        yield Ok(Event::Close);
-                                        break;
+                                    } else {
+                                        // Keep the current transport so a closed
+                                        // connection is still detected
+                                        self.state.send_replace(previous);
                                     }
                                 }
-
+                                InternalMessage::Close => {
+                                    if let Err(e) = self.handle_close_message().await {
+                                        yield Err(e)
+                                    }
+                                    yield Ok(Event::Close);
+                                    break;
+                                }
                             }
-                            _ => {}
-                        },
-                        event_message =
-                            self.inbound_rx.recv().fuse()
-                                => match event_message {
-                            Some(event_message) => {
-                                match Self::handle_incoming_message(
-                                    Arc::clone(&options),
-                                    Arc::clone(&server),
-                                    Arc::clone(&peers),
-                                    event_message,
-                                    self.outbound_tx.clone(),
-                                ).await {
-
-                                    Ok(Some(event)) => {
-                                        yield Ok(event);
+
+                        }
+                        _ => {}
+                    },
+                    event_message =
+                        self.inbound_rx.recv().fuse()
+                            => match event_message {
+                        Some(event_message) => {
+                            match Self::handle_incoming_message(
+                                Arc::clone(&options),
+                                Arc::clone(&server),
+                                Arc::clone(&peers),
+                                Arc::clone(&metadata),
+                                event_message,
+                                self.outbound_tx.clone(),
+                                &mut inbound,
+                            ).await {
+
+                                Ok(Some(event)) => {
+                                    self.handshakes.completed(&event);
+                                    if let Event::PeerConnected { peer_key } = &event {
+                                        self.metrics.lock().unwrap().handshake_completed();
+                                        #[cfg(all(
+                                            feature = "webrtc",
+                                            not(all(target_arch = "wasm32", target_os = "unknown"))
+                                        ))]
+                                        if let Some(direct) = &direct {
+                                            direct.connect(peer_key);
+                                        }
+                                        match Self::flush_queue(
+                                            &options,
+                                            &peers,
+                                            &metadata,
+                                            peer_key,
+                                        ).await {
+                                            Ok(requests) => {
+                                                for request in requests {
+                                                    if let Err(e) = self.send_message(request).await {
+                                                        yield Err(e);
+                                                        break;
+                                                    }
+                                                }
+                                            }
+                                            Err(e) => yield Err(e),
+                                        }
+                                        Self::notify_connected(&metadata, peer_key).await;
                                     }
-                                    Err(e) => {
-                                        yield Err(e)
+                                    yield Ok(event);
+                                }
+                                Err(e) => {
+                                    yield Err(e)
+                                }
+                                _ => {}
+                            }
+                            for event in inbound.pending.drain(..) {
+                                yield Ok(event);
+                            }
+
+                            for (peer_key, payload) in std::mem::take(&mut inbound.signals) {
+                                #[cfg(all(
+                                    feature = "webrtc",
+                                    not(all(target_arch = "wasm32", target_os = "unknown"))
+                                ))]
+                                if let Some(direct) = &direct {
+                                    // Direct messages that arrived before
+                                    // the switch follow the relayed messages
+                                    let buffered = match direct.handle_signal(&peer_key, &payload).await {
+                                        Ok(buffered) => buffered,
+                                        Err(e) => {
+                                            yield Err(e);
+                                            continue;
+                                        }
+                                    };
+                                    for message in buffered {
+                                        match Self::handle_incoming_message(
+                                            Arc::clone(&options),
+                                            Arc::clone(&server),
+                                            Arc::clone(&peers),
+                                            Arc::clone(&metadata),
+                                            message,
+                                            self.outbound_tx.clone(),
+                                            &mut inbound,
+                                        ).await {
+                                            Ok(Some(event)) => yield Ok(event),
+                                            Err(e) => yield Err(e),
+                                            _ => {}
+                                        }
+                                        for event in inbound.pending.drain(..) {
+                                            yield Ok(event);
+                                        }
                                     }
-                                    _ => {}
+                                    continue;
+                                }
+                                tracing::warn!(
+                                    from = ?hex::encode(&peer_key),
+                                    len = payload.len(),
+                                    "drop signal, direct connections disabled",
+                                );
+                            }
+                        }
+                        _ => {}
+                    },
+                    _ = self.handshakes.wait().fuse() => {
+                        let (server_expired, peer_keys) = self.handshakes.expired();
+                        let mut expired = Vec::new();
+                        {
+                            // Forget handshakes that were never answered
+                            // so the peer can be connected again
+                            let mut peers = peers.write().await;
+                            for peer_key in peer_keys {
+                                if let Some(ProtocolState::Handshake(_)) = peers.get(&peer_key) {
+                                    peers.remove(&peer_key);
+                                    expired.push(peer_key);
+                                }
+                            }
+                        }
+                        for peer_key in expired {
+                            yield Err(Error::PeerHandshakeTimeout(
+                                hex::encode(&peer_key),
+                            ));
+                        }
+                        if server_expired {
+                            tracing::warn!(
+                                "server did not respond to handshake",
+                            );
+                            if let Some(event) = self.fail_over(&mut inbound).await {
+                                liveness.received();
+                                yield Ok(event);
+                                for event in inbound.pending.drain(..) {
+                                    yield Ok(event);
+                                }
+                                continue;
+                            }
+                            self.outbound_rx.close();
+                            yield Err(Error::ServerHandshakeTimeout);
+                            yield Ok(Event::Disconnected {
+                                cause: DisconnectCause::Error(
+                                    Error::ServerHandshakeTimeout.to_string(),
+                                ),
+                            });
+                            break;
+                        }
+                    },
+                    _ = liveness.wait().fuse() => {
+                        if liveness.expired() {
+                            tracing::warn!(
+                                "server did not respond to ping",
+                            );
+                            if let Some(event) = self.fail_over(&mut inbound).await {
+                                liveness.received();
+                                yield Ok(event);
+                                for event in inbound.pending.drain(..) {
+                                    yield Ok(event);
                                 }
+                                continue;
                             }
-                            _ => {}
-                        },
+                            self.outbound_rx.close();
+                            yield Ok(Event::ConnectionLost);
+                            break;
+                        }
+                        if let Err(e) = self.send_ping().await {
+                            yield Err(e)
+                        }
+                    },
+                );
+            }
+        };
+        // Publish events to subscribers of the client
+        Box::pin(s.inspect(move |event| {
+            if let Ok(event) = event {
+                if let Some(next) = ConnectionState::after(event) {
+                    state.send_replace(next);
+                }
+                if let (Some(peer_key), Some(len)) =
+                    (event.peer_key(), event.message_len())
+                {
+                    usage.lock().unwrap().peer_received(
+                        peer_key,
+                        event.session_id(),
+                        len,
                     );
                 }
-            };
-            Box::pin(s)
-        }
+                if events.receiver_count() > 0 {
+                    let _ = events.send(event.clone());
+                }
+            }
+        }))
     }
 }
 
-pub(crate) use event_loop_run_impl;
+#[cfg(test)]
+mod tests {
+    use super::Inbound;
+    use crate::Error;
+    use anyhow::Result;
+    use mpc_protocol::{Fragment, SessionId};
+
+    fn fragment(index: u32, count: u32) -> Fragment {
+        Fragment { index, count }
+    }
+
+    #[test]
+    fn ordered_broadcast_sequence() -> Result<()> {
+        let mut inbound = Inbound::default();
+        let session_id = SessionId::new_v4();
+        inbound.check_sequence(session_id, 1)?;
+        inbound.check_sequence(session_id, 3)?;
+
+        // Replayed and reordered broadcasts
+        for sequence in [3, 2] {
+            assert!(matches!(
+                inbound.check_sequence(session_id, sequence),
+                Err(Error::BroadcastOutOfOrder { .. })
+            ));
+        }
+
+        // Sequences are scoped to the session
+        inbound.check_sequence(SessionId::new_v4(), 1)?;
+        inbound.check_sequence(session_id, 4)?;
+        Ok(())
+    }
+
+    #[test]
+    fn fragments_reassembled() -> Result<()> {
+        let mut inbound = Inbound::default();
+        let parts: [&[u8]; 3] = [b"one", b"two", b"three"];
+        let mut output = None;
+        for (index, part) in parts.iter().enumerate() {
+            output = inbound.reassemble(
+                &[1],
+                fragment(index as u32, 3),
+                part.to_vec(),
+                1024,
+            )?;
+            // Fragments from other peers are kept apart
+            inbound.reassemble(&[2], fragment(0, 2), vec![0], 1024)?;
+        }
+        assert_eq!(Some(b"onetwothree".to_vec()), output);
+        assert!(!inbound.fragments.contains_key([1u8].as_slice()));
+        Ok(())
+    }
+
+    #[test]
+    fn fragment_out_of_order() -> Result<()> {
+        let mut inbound = Inbound::default();
+        inbound.reassemble(&[1], fragment(0, 3), vec![0], 1024)?;
+        let result =
+            inbound.reassemble(&[1], fragment(2, 3), vec![2], 1024);
+        assert!(matches!(result, Err(Error::FragmentOutOfOrder(2))));
+        assert!(inbound.fragments.is_empty());
+
+        // A fragment without the start of the payload
+        let result =
+            inbound.reassemble(&[1], fragment(1, 3), vec![1], 1024);
+        assert!(matches!(result, Err(Error::FragmentOutOfOrder(1))));
+        Ok(())
+    }
+
+    #[test]
+    fn fragment_restarts_payload() -> Result<()> {
+        let mut inbound = Inbound::default();
+        inbound.reassemble(&[1], fragment(0, 2), vec![9], 1024)?;
+        inbound.reassemble(&[1], fragment(0, 2), vec![0], 1024)?;
+        let output =
+            inbound.reassemble(&[1], fragment(1, 2), vec![1], 1024)?;
+        assert_eq!(Some(vec![0, 1]), output);
+        Ok(())
+    }
+
+    #[test]
+    fn fragments_exceed_payload_size() -> Result<()> {
+        let mut inbound = Inbound::default();
+        inbound.reassemble(&[1], fragment(0, 3), vec![0; 6], 8)?;
+        let result =
+            inbound.reassemble(&[1], fragment(1, 3), vec![0; 6], 8);
+        assert!(matches!(result, Err(Error::PayloadTooLarge(12))));
+        assert!(inbound.fragments.is_empty());
+        Ok(())
+    }
+}
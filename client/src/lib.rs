@@ -10,38 +10,260 @@
 #![deny(missing_docs)]
 
 mod client;
+mod compression;
 mod error;
 mod event_loop;
+mod metrics;
+mod relay;
+mod sink;
+mod subscription;
+mod throttle;
+mod timer;
 mod transport;
 
 pub(crate) use client::{client_impl, client_transport_impl};
-pub use event_loop::{Event, EventStream, JsonMessage};
+pub use event_loop::{
+    CborMessage, ConnectionState, DisconnectCause, Event, EventStream,
+    JsonMessage, MessagePackMessage,
+};
+pub use metrics::{ClientMetrics, Quota, Usage};
+pub use relay::{Frame, RelayTransport};
+pub use sink::{EventStreamExt, PeerSink};
+pub use subscription::Subscription;
 pub use transport::{NetworkTransport, Transport};
 
+#[cfg(all(
+    feature = "webrtc",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+mod direct;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod connector;
+
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 mod native;
 
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod proxy;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod tcp;
+
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 pub use native::{
     NativeClient as Client, NativeEventLoop as EventLoop,
+    WebSocketTransport,
 };
 
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use tcp::TcpTransport;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use connector::TcpConnector;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use rustls;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod port;
+
 #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
 mod web;
 
 #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
-pub use web::{WebClient as Client, WebEventLoop as EventLoop};
+pub use port::PortTransport;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use web::{
+    WebClient as Client, WebEventLoop as EventLoop,
+    WebSocketTransport,
+};
 
 use mpc_protocol::{
-    hex, snow::params::NoiseParams, Chunk, Encoding, Keypair,
-    OpaqueMessage, ProtocolState, RequestMessage, SealedEnvelope,
-    SessionId, PATTERN,
+    hex, snow::params::NoiseParams, Correlation, Encoding, Fragment,
+    Header, Keypair, OpaqueMessage, ProtocolState, Rekey,
+    RequestMessage, SealedEnvelope, SessionId, PATTERN,
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::RwLock;
+use tokio::sync::{oneshot, RwLock};
 
 pub(crate) type Peers = Arc<RwLock<HashMap<Vec<u8>, ProtocolState>>>;
 pub(crate) type Server = Arc<RwLock<Option<ProtocolState>>>;
+pub(crate) type Metadata = Arc<RwLock<HashMap<Vec<u8>, PeerMeta>>>;
+
+/// Metadata for the channel to a peer.
+#[derive(Debug, Default)]
+pub(crate) struct PeerMeta {
+    /// Whether the peer accepts compressed payloads.
+    pub compression: bool,
+    /// When a message was last sent to or received from the peer.
+    pub last_active: Option<Instant>,
+    /// Identifier for the next request sent to the peer.
+    pub next_request: u64,
+    /// Requests sent to the peer waiting for a response.
+    pub requests: HashMap<u64, oneshot::Sender<Vec<u8>>>,
+    /// Tracked messages sent to the peer waiting for an
+    /// acknowledgement from the server.
+    pub acks: HashMap<u64, oneshot::Sender<Delivered>>,
+    /// Messages waiting for a channel to the peer.
+    pub queue: Vec<Queued>,
+    /// Senders waiting for a channel to the peer.
+    pub connected: Vec<oneshot::Sender<()>>,
+    #[cfg(all(
+        feature = "webrtc",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    /// Direct connection to the peer.
+    pub direct: Option<direct::DirectChannel>,
+}
+
+impl PeerMeta {
+    /// Record activity on the channel.
+    pub fn touch(&mut self) {
+        // No clock in the browser
+        if !cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            self.last_active = Some(Instant::now());
+        }
+    }
+
+    /// Whether messages to the peer are sent directly.
+    pub fn is_direct(&self) -> bool {
+        #[cfg(all(
+            feature = "webrtc",
+            not(all(target_arch = "wasm32", target_os = "unknown"))
+        ))]
+        {
+            self.direct
+                .as_ref()
+                .is_some_and(|direct| direct.sender().is_some())
+        }
+        #[cfg(not(all(
+            feature = "webrtc",
+            not(all(target_arch = "wasm32", target_os = "unknown"))
+        )))]
+        {
+            false
+        }
+    }
+}
+
+/// Message to relay to a peer.
+pub(crate) struct PeerMessage<'a> {
+    /// Payload for the message.
+    pub payload: &'a [u8],
+    /// Encoding for the payload.
+    pub encoding: Encoding,
+    /// Whether this is a broadcast message.
+    pub broadcast: bool,
+    /// Session identifier.
+    pub session_id: Option<SessionId>,
+    /// Correlation for a request or response.
+    pub correlation: Option<Correlation>,
+    /// Identifier for an acknowledgement from the server
+    /// once the message is relayed.
+    pub ack: Option<u64>,
+    /// Whether the payload may be compressed.
+    pub compress: bool,
+}
+
+/// Result of relaying a tracked message with the reason
+/// when the server did not relay the message.
+pub(crate) type Delivered = std::result::Result<(), String>;
+
+/// Forget the state of a closed channel to a peer.
+///
+/// Messages queued for the peer are kept with their
+/// acknowledgements, as are senders waiting for a channel,
+/// so they are delivered once a new channel is established.
+pub(crate) fn forget_channel(
+    metadata: &mut HashMap<Vec<u8>, PeerMeta>,
+    peer_key: &[u8],
+) {
+    if let Some(meta) = metadata.remove(peer_key) {
+        if meta.queue.is_empty() && meta.connected.is_empty() {
+            return;
+        }
+        let acks = meta
+            .acks
+            .into_iter()
+            .filter(|(id, _)| {
+                meta.queue
+                    .iter()
+                    .any(|queued| queued.ack == Some(*id))
+            })
+            .collect();
+        metadata.insert(
+            peer_key.to_vec(),
+            PeerMeta {
+                acks,
+                queue: meta.queue,
+                connected: meta.connected,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/// Message queued while there is no channel to a peer.
+#[derive(Debug)]
+pub(crate) struct Queued {
+    payload: Vec<u8>,
+    encoding: Encoding,
+    broadcast: bool,
+    session_id: Option<SessionId>,
+    correlation: Option<Correlation>,
+    ack: Option<u64>,
+    compress: bool,
+    queued_at: Option<Instant>,
+}
+
+impl Queued {
+    /// Queue a message.
+    pub fn new(message: PeerMessage<'_>) -> Self {
+        let mut queued_at = None;
+        // No clock in the browser
+        if !cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            queued_at = Some(Instant::now());
+        }
+        Self {
+            payload: message.payload.to_vec(),
+            encoding: message.encoding,
+            broadcast: message.broadcast,
+            session_id: message.session_id,
+            correlation: message.correlation,
+            ack: message.ack,
+            compress: message.compress,
+            queued_at,
+        }
+    }
+
+    /// Determine if the message has been queued for
+    /// longer than the time to live.
+    pub fn is_expired(&self, ttl: Duration) -> bool {
+        self.queued_at
+            .map(|queued_at| queued_at.elapsed() > ttl)
+            .unwrap_or(false)
+    }
+
+    /// Message to relay to the peer.
+    pub fn message(&self) -> PeerMessage<'_> {
+        PeerMessage {
+            payload: &self.payload,
+            encoding: self.encoding,
+            broadcast: self.broadcast,
+            session_id: self.session_id,
+            correlation: self.correlation,
+            ack: self.ack,
+            compress: self.compress,
+        }
+    }
+}
 
 /// Options used to create a new websocket client.
 pub struct ClientOptions {
@@ -54,9 +276,173 @@ pub struct ClientOptions {
     /// If no pattern is specified the default noise parameters
     /// pattern is used.
     pub pattern: Option<String>,
+    /// Keep-alive settings for the connection to the server.
+    ///
+    /// When no settings are specified the event loop does
+    /// not ping the server.
+    pub keep_alive: Option<KeepAlive>,
+    /// Timeouts for connecting to the server and for
+    /// handshakes.
+    pub timeouts: Timeouts,
+    /// Proxy used to connect to the server.
+    ///
+    /// The web client always uses the proxy settings
+    /// of the browser.
+    pub proxy: Option<Proxy>,
+    /// Connector for the TCP connection to the server.
+    ///
+    /// Not available for the web client; ignored when a
+    /// proxy is specified or the client is created with a
+    /// transport.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub tcp_connector: Option<Arc<dyn TcpConnector>>,
+    /// HTTP headers sent with the websocket upgrade request.
+    ///
+    /// Use this to authenticate with a reverse proxy in front
+    /// of the server; browsers do not allow custom headers so
+    /// the web client ignores these headers.
+    pub headers: Vec<(String, String)>,
+    /// Capacity of the channel for messages sent to the
+    /// event loop.
+    pub outbound_capacity: usize,
+    /// Capacity of the channel for messages received
+    /// from the server.
+    pub inbound_capacity: usize,
+    /// Maximum size of a frame received from the server.
+    ///
+    /// Larger frames are rejected before they are buffered
+    /// by the native transports; browsers buffer the frame
+    /// before it is rejected by the web client.
+    pub max_frame_size: usize,
+    /// Maximum size of a payload received from a peer once
+    /// fragments are reassembled and decompressed.
+    pub max_payload_size: usize,
+    /// Policy when the outbound channel is full.
+    pub backpressure: Backpressure,
+    /// Maximum size of a payload relayed in a single message.
+    ///
+    /// Larger payloads sent to a peer are split into
+    /// fragments that are reassembled by the recipient; when
+    /// no size is specified payloads are not fragmented
+    /// except by the web client which uses fragments of
+    /// one mebibyte.
+    pub fragment_size: Option<usize>,
+    /// Minimum size of a payload to compress before it
+    /// is encrypted.
+    ///
+    /// Payloads are only compressed for peers that accepted
+    /// compression during the handshake; when no threshold
+    /// is specified payloads are not compressed.
+    pub compression_threshold: Option<usize>,
+    /// Rate limit for messages sent to all peers.
+    ///
+    /// Rate limits are ignored by the web client.
+    pub global_rate_limit: Option<RateLimit>,
+    /// Rate limit for messages sent to each peer.
+    pub peer_rate_limit: Option<RateLimit>,
+    /// Queue for messages sent to peers that are not connected.
+    ///
+    /// Queued messages are sent once a handshake with the
+    /// peer completes; when no settings are specified
+    /// sending to a peer that is not connected is an error.
+    /// Ordered broadcasts are never queued. The queue is kept
+    /// when the channel to a peer is lost unless the channel
+    /// is closed using `disconnect_peer()`.
+    pub offline_queue: Option<OfflineQueue>,
+    /// Timeout for a handshake started when sending to a
+    /// peer that is not connected.
+    ///
+    /// The message is sent once the channel is established;
+    /// when no timeout is specified the peer must be connected
+    /// before sending unless the message can be queued.
+    pub auto_connect: Option<Duration>,
+    /// Dispatch messages sent to the public key of this
+    /// client as events instead of relaying them.
+    ///
+    /// When disabled sending to this client is a peer not
+    /// found error as there is no channel to this client.
+    pub loopback: bool,
+    /// Quota for the payloads sent to each peer.
+    ///
+    /// Sending a payload that exceeds the quota is an error;
+    /// when no quota is specified usage is only recorded.
+    pub peer_quota: Option<Quota>,
+    /// Quota for the payloads sent in each session.
+    pub session_quota: Option<Quota>,
+    /// Direct connections to peers.
+    ///
+    /// Requires the `webrtc` feature and is ignored by the
+    /// web client; when no settings are specified all
+    /// messages are relayed by the server.
+    pub direct: Option<DirectOptions>,
+    /// Servers to fail over to in order of priority.
+    ///
+    /// When the client can not connect to the server it was
+    /// created with, or the connection ends, the client
+    /// connects to the next server in the list. Ignored by
+    /// the web client and by clients created with a transport.
+    pub failover: Vec<ServerEndpoint>,
+    /// Reconnect to the server when the connection ends.
+    ///
+    /// Servers to fail over to are tried first; when no
+    /// settings are specified the connection is not
+    /// re-established. The web client also reconnects
+    /// when the page becomes visible or the browser comes
+    /// back online.
+    pub reconnect: Option<Reconnect>,
+    /// Thresholds for rekeying the channels to the server
+    /// and to peers.
+    ///
+    /// Messages sent by this client are encrypted with a new
+    /// key once a threshold is crossed; when no thresholds
+    /// are specified channels are never rekeyed.
+    pub rekey: Option<Rekey>,
+    /// Policy for handshakes started by peers.
+    ///
+    /// By default any peer that knows the public key of
+    /// this client may open a channel.
+    pub peer_policy: PeerPolicy,
 }
 
 impl ClientOptions {
+    /// Create client options with the default noise
+    /// parameters and keep-alive settings.
+    pub fn new(keypair: Keypair, server_public_key: Vec<u8>) -> Self {
+        Self {
+            keypair,
+            server_public_key,
+            pattern: None,
+            keep_alive: Some(Default::default()),
+            timeouts: Default::default(),
+            proxy: None,
+            #[cfg(not(all(
+                target_arch = "wasm32",
+                target_os = "unknown"
+            )))]
+            tcp_connector: None,
+            headers: Vec::new(),
+            outbound_capacity: CHANNEL_CAPACITY,
+            inbound_capacity: CHANNEL_CAPACITY,
+            max_frame_size: MAX_FRAME_SIZE,
+            max_payload_size: MAX_PAYLOAD_SIZE,
+            backpressure: Default::default(),
+            fragment_size: None,
+            compression_threshold: None,
+            global_rate_limit: None,
+            peer_rate_limit: None,
+            offline_queue: None,
+            auto_connect: None,
+            loopback: false,
+            peer_quota: None,
+            session_quota: None,
+            direct: None,
+            failover: Vec::new(),
+            reconnect: None,
+            rekey: None,
+            peer_policy: Default::default(),
+        }
+    }
+
     /// Build a connection URL for the given server.
     ///
     /// This method appends the public key query string
@@ -70,6 +456,14 @@ impl ClientOptions {
         )
     }
 
+    /// Add an authorization header with a bearer token.
+    pub fn bearer_token(&mut self, token: &str) {
+        self.headers.push((
+            "Authorization".to_string(),
+            format!("Bearer {}", token),
+        ));
+    }
+
     /// Parse noise parameters from the pattern.
     pub fn params(&self) -> Result<NoiseParams> {
         let pattern = self
@@ -81,39 +475,459 @@ impl ClientOptions {
     }
 }
 
+/// Keep-alive settings for the connection to the server.
+///
+/// The event loop sends a ping when nothing has been
+/// received from the server for the interval; if the
+/// server does not respond before the timeout the
+/// connection is considered lost.
+///
+/// Browsers do not expose websocket pings so these
+/// settings are ignored by the web client.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    /// Interval between pings.
+    pub interval: Duration,
+    /// Deadline for a response to a ping.
+    pub timeout: Duration,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Timeouts for connecting to the server and for the
+/// handshakes started by the client.
+///
+/// Browser timers can not be used by the web client so
+/// these timeouts are ignored by the web client.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// Deadline to open the connection to the server.
+    pub connect: Duration,
+    /// Deadline for the server to respond to the handshake.
+    pub server_handshake: Duration,
+    /// Deadline for a peer to respond to a handshake.
+    pub peer_handshake: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(10),
+            server_handshake: Duration::from_secs(10),
+            peer_handshake: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Policy for handshakes started by peers.
+///
+/// Denied public keys take precedence over allowed public
+/// keys; when there is no allowlist any peer that is not
+/// denied may connect.
+#[derive(Debug, Clone, Default)]
+pub struct PeerPolicy {
+    /// Public keys of the peers allowed to connect.
+    pub allow: Option<HashSet<Vec<u8>>>,
+    /// Public keys of the peers that may not connect.
+    pub deny: HashSet<Vec<u8>>,
+}
+
+impl PeerPolicy {
+    /// Determine if a peer may start a handshake.
+    pub fn is_allowed(&self, public_key: &[u8]) -> bool {
+        if self.deny.contains(public_key) {
+            return false;
+        }
+        self.allow
+            .as_ref()
+            .map(|allow| allow.contains(public_key))
+            .unwrap_or(true)
+    }
+}
+
+/// Policy for sending messages when the outbound
+/// channel is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for capacity in the channel.
+    #[default]
+    Wait,
+    /// Return an error so the caller can retry or shed load.
+    Error,
+}
+
+/// State of the channel to a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerState {
+    /// Handshake with the peer is in progress.
+    Handshaking,
+    /// Channel to the peer is established.
+    Connected,
+    /// There is no channel to the peer.
+    Disconnected,
+}
+
+impl From<&ProtocolState> for PeerState {
+    fn from(value: &ProtocolState) -> Self {
+        match value {
+            ProtocolState::Handshake(_) => PeerState::Handshaking,
+            ProtocolState::Transport(_) => PeerState::Connected,
+        }
+    }
+}
+
+/// Channel to a peer.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    /// Public key of the peer.
+    pub public_key: Vec<u8>,
+    /// State of the channel.
+    pub state: PeerState,
+    /// Time since a message was last sent to or received
+    /// from the peer.
+    ///
+    /// Not available for the web client.
+    pub idle: Option<Duration>,
+    /// Whether messages to the peer are sent over a
+    /// direct connection.
+    pub direct: bool,
+}
+
+/// Outcome of a broadcast for a recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    /// Message was sent or queued until the peer connects.
+    Sent,
+    /// There is no channel to the peer.
+    PeerNotFound,
+    /// Message could not be encrypted for the peer.
+    EncryptFailed(String),
+}
+
+impl Delivery {
+    /// Convert into a result for the recipient.
+    pub fn into_result(self, public_key: &[u8]) -> Result<()> {
+        match self {
+            Self::Sent => Ok(()),
+            Self::PeerNotFound => {
+                Err(Error::PeerNotFound(hex::encode(public_key)))
+            }
+            Self::EncryptFailed(reason) => Err(Error::EncryptFailed(
+                hex::encode(public_key),
+                reason,
+            )),
+        }
+    }
+}
+
+/// Outcome of a broadcast for each recipient keyed by
+/// public key.
+pub type Deliveries = HashMap<Vec<u8>, Delivery>;
+
+/// Token bucket rate limit for messages sent to peers.
+///
+/// Sends that exceed the limit are delayed until a token
+/// is available.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Number of messages allowed per second.
+    pub rate: u32,
+    /// Maximum number of messages sent in a burst.
+    pub burst: u32,
+}
+
+/// Settings for queueing messages to peers that are
+/// not connected.
+///
+/// Messages older than the time to live when the channel
+/// to the peer is established are discarded. Not enforced
+/// by the web client.
+#[derive(Debug, Clone, Copy)]
+pub struct OfflineQueue {
+    /// Maximum number of messages queued for each peer.
+    pub capacity: usize,
+    /// Time to live for a queued message.
+    pub ttl: Duration,
+}
+
+impl Default for OfflineQueue {
+    fn default() -> Self {
+        Self {
+            capacity: 64,
+            ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Relay server that a client can fail over to.
+#[derive(Debug, Clone)]
+pub struct ServerEndpoint {
+    /// URL of the server.
+    ///
+    /// The public key query string parameter is appended
+    /// when connecting.
+    pub url: String,
+    /// Public key of the server.
+    pub public_key: Vec<u8>,
+}
+
+/// Settings for reconnecting to the server.
+///
+/// The delay before each attempt doubles after an attempt
+/// fails until the maximum delay is reached.
+#[derive(Debug, Clone, Copy)]
+pub struct Reconnect {
+    /// Attempts to reconnect before the client disconnects.
+    pub attempts: usize,
+    /// Delay before the first attempt.
+    pub delay: Duration,
+    /// Maximum delay before an attempt.
+    pub max_delay: Duration,
+}
+
+impl Reconnect {
+    /// Delay before an attempt starting from zero.
+    pub(crate) fn backoff(&self, attempt: usize) -> Duration {
+        let factor = 2u32.saturating_pow(attempt as u32);
+        self.delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Settings for direct connections to peers over
+/// WebRTC data channels.
+///
+/// Connections are negotiated over the noise protocol
+/// channel once a handshake with a peer completes. Until
+/// a data channel is open, or when it can not be opened
+/// before the timeout, messages are relayed by the server.
+#[derive(Debug, Clone)]
+pub struct DirectOptions {
+    /// URLs for the STUN and TURN servers used to
+    /// gather ICE candidates.
+    pub ice_servers: Vec<String>,
+    /// Deadline for a data channel to open before
+    /// falling back to the relay.
+    pub timeout: Duration,
+}
+
+impl Default for DirectOptions {
+    fn default() -> Self {
+        Self {
+            ice_servers: vec![
+                "stun:stun.l.google.com:19302".to_string(),
+            ],
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Proxy used to connect to the server.
+#[derive(Debug, Clone)]
+pub enum Proxy {
+    /// HTTP proxy that supports the CONNECT method.
+    Http {
+        /// Host and port of the proxy.
+        address: String,
+        /// Username and password for basic authentication.
+        credentials: Option<(String, String)>,
+    },
+    /// SOCKS5 proxy.
+    Socks5 {
+        /// Host and port of the proxy.
+        address: String,
+        /// Username and password for authentication.
+        credentials: Option<(String, String)>,
+    },
+}
+
 pub use error::Error;
 
+/// Default capacity of the channels used by a client.
+const CHANNEL_CAPACITY: usize = 32;
+
+/// Default maximum size of a frame received from the server.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Default maximum size of a payload received from a peer.
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+/// Length of the session identifier bound to peer messages.
+const SESSION_BINDING: usize = 16;
+
 /// Result type for the client library.
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Encrypt a message to send to a peer.
+/// Compress and encrypt a message for a peer into
+/// relay requests.
+///
+/// The payload is compressed when it reaches the
+/// compression threshold, the peer accepts compressed
+/// payloads and compression was not disabled for the
+/// message.
 ///
 /// The protocol must be in transport mode.
-async fn encrypt_peer_channel(
-    public_key: impl AsRef<[u8]>,
+async fn seal_peer_requests(
+    peer: &mut ProtocolState,
+    public_key: &[u8],
+    message: PeerMessage<'_>,
+    options: &ClientOptions,
+    fragment_size: Option<usize>,
+    compression: bool,
+) -> Result<Vec<RequestMessage>> {
+    let compressed = compression
+        && message.compress
+        && options
+            .compression_threshold
+            .map(|threshold| message.payload.len() >= threshold)
+            .unwrap_or(false);
+    let payload = if compressed {
+        Cow::Owned(compression::compress(message.payload)?)
+    } else {
+        Cow::Borrowed(message.payload)
+    };
+
+    let header = Header {
+        encoding: message.encoding,
+        broadcast: message.broadcast,
+        compressed,
+        correlation: message.correlation,
+        ..Default::default()
+    };
+    let envelopes = seal_peer_fragments(
+        peer,
+        &payload,
+        header,
+        message.session_id,
+        fragment_size,
+        options.rekey,
+    )
+    .await?;
+    // The server acknowledges the last fragment of a
+    // tracked message
+    let last = envelopes.len().saturating_sub(1);
+    Ok(envelopes
+        .into_iter()
+        .enumerate()
+        .map(|(index, envelope)| match message.ack {
+            Some(id) if index == last => RequestMessage::Opaque(
+                OpaqueMessage::TrackedPeerMessage {
+                    id,
+                    public_key: public_key.to_vec(),
+                    session_id: message.session_id,
+                    envelope,
+                },
+            ),
+            _ => RequestMessage::Opaque(OpaqueMessage::PeerMessage {
+                public_key: public_key.to_vec(),
+                session_id: message.session_id,
+                envelope,
+            }),
+        })
+        .collect())
+}
+
+/// Encrypt a payload for a peer into one or more envelopes.
+///
+/// When a fragment size is given and the payload is larger
+/// it is split into fragments that are sealed in order.
+///
+/// The protocol must be in transport mode.
+async fn seal_peer_fragments(
+    peer: &mut ProtocolState,
+    payload: &[u8],
+    header: Header,
+    session_id: Option<SessionId>,
+    fragment_size: Option<usize>,
+    rekey: Option<Rekey>,
+) -> Result<Vec<SealedEnvelope>> {
+    let size = match fragment_size {
+        Some(size) if payload.len() > size => size.max(1),
+        _ => {
+            let envelope =
+                seal_peer(peer, header, payload, session_id, rekey)?;
+            return Ok(vec![envelope]);
+        }
+    };
+
+    let fragments: Vec<&[u8]> = payload.chunks(size).collect();
+    let count = fragments.len() as u32;
+    let mut envelopes = Vec::with_capacity(fragments.len());
+    for (index, fragment) in fragments.into_iter().enumerate() {
+        let header = Header {
+            fragment: Some(Fragment {
+                index: index as u32,
+                count,
+            }),
+            ..header
+        };
+        envelopes.push(seal_peer(
+            peer, header, fragment, session_id, rekey,
+        )?);
+    }
+    Ok(envelopes)
+}
+
+/// Encrypt a message for a peer into an envelope.
+///
+/// The protocol must be in transport mode.
+async fn seal_peer_envelope(
     peer: &mut ProtocolState,
     payload: &[u8],
     encoding: Encoding,
     broadcast: bool,
     session_id: Option<SessionId>,
-) -> Result<RequestMessage> {
+    rekey: Option<Rekey>,
+) -> Result<SealedEnvelope> {
+    let header = Header {
+        encoding,
+        broadcast,
+        ..Default::default()
+    };
+    seal_peer(peer, header, payload, session_id, rekey)
+}
+
+/// Encrypt a header and payload for a peer.
+///
+/// The session identifier (or the nil identifier when there
+/// is no session) is prepended to the payload before encryption
+/// so a message can not be accepted in another session.
+///
+/// When rekey thresholds are given the sending side is
+/// rekeyed once a threshold is crossed.
+fn seal_peer(
+    peer: &mut ProtocolState,
+    header: Header,
+    payload: &[u8],
+    session_id: Option<SessionId>,
+    rekey: Option<Rekey>,
+) -> Result<SealedEnvelope> {
     match peer {
         ProtocolState::Transport(transport) => {
-            let chunks = Chunk::split(payload, transport)?;
-            let envelope = SealedEnvelope {
-                encoding,
-                chunks,
-                broadcast,
-            };
-
-            let request =
-                RequestMessage::Opaque(OpaqueMessage::PeerMessage {
-                    public_key: public_key.as_ref().to_vec(),
-                    session_id,
-                    envelope,
-                });
-
-            Ok(request)
+            let binding = session_id.unwrap_or_default();
+            let mut contents =
+                Vec::with_capacity(SESSION_BINDING + payload.len());
+            contents.extend_from_slice(binding.as_bytes());
+            contents.extend_from_slice(payload);
+            Ok(SealedEnvelope::seal(
+                transport, header, &contents, rekey,
+            )?)
         }
         _ => Err(Error::NotTransportState),
     }
@@ -121,15 +935,26 @@ async fn encrypt_peer_channel(
 
 /// Decrypt a message received from a peer.
 ///
+/// The session identifier bound to the encrypted payload
+/// must match the session identifier of the envelope.
+///
 /// The protocol must be in transport mode.
 async fn decrypt_peer_channel(
     peer: &mut ProtocolState,
     envelope: SealedEnvelope,
-) -> Result<(Encoding, Vec<u8>)> {
+    session_id: Option<SessionId>,
+) -> Result<(Header, Vec<u8>)> {
     match peer {
         ProtocolState::Transport(transport) => {
-            let contents = Chunk::join(envelope.chunks, transport)?;
-            Ok((envelope.encoding, contents))
+            let (header, mut contents) = envelope.open(transport)?;
+            let binding = session_id.unwrap_or_default();
+            if contents.len() < SESSION_BINDING
+                || &contents[..SESSION_BINDING] != binding.as_bytes()
+            {
+                return Err(Error::SessionBindingMismatch);
+            }
+            contents.drain(..SESSION_BINDING);
+            Ok((header, contents))
         }
         _ => Err(Error::NotTransportState),
     }
@@ -0,0 +1,230 @@
+//! Metrics for the traffic of a client.
+use mpc_protocol::{hex, SessionId};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{ClientOptions, Error, Result};
+
+/// Metrics shared by a client and the event loop.
+pub(crate) type Metrics = Arc<Mutex<MetricsRecorder>>;
+
+/// Snapshot of the metrics for a client.
+#[derive(Debug, Clone, Default)]
+pub struct ClientMetrics {
+    /// Number of messages sent to the server.
+    pub messages_sent: u64,
+    /// Number of messages received from the server.
+    pub messages_received: u64,
+    /// Number of bytes sent to the server.
+    pub bytes_sent: u64,
+    /// Number of bytes received from the server.
+    pub bytes_received: u64,
+    /// Number of peer handshakes initiated by this client.
+    pub handshakes_initiated: u64,
+    /// Number of peer handshakes completed.
+    pub handshakes_completed: u64,
+    /// Round trip time to the server measured by
+    /// keep-alive pings.
+    ///
+    /// Not available for the web client.
+    pub server_rtt: Option<Duration>,
+    /// Round trip time to each peer estimated from the
+    /// responses to requests.
+    ///
+    /// Estimates include the time taken by the peer to
+    /// reply; not available for the web client.
+    pub peer_rtt: HashMap<Vec<u8>, Duration>,
+    /// Payloads exchanged with each peer.
+    pub peers: HashMap<Vec<u8>, Usage>,
+    /// Payloads exchanged in each session.
+    pub sessions: HashMap<SessionId, Usage>,
+}
+
+/// Payloads exchanged with a peer or in a session.
+///
+/// Sizes are the size of the payloads before compression
+/// and encryption.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    /// Number of messages sent.
+    pub messages_sent: u64,
+    /// Number of messages received.
+    pub messages_received: u64,
+    /// Number of bytes sent.
+    pub bytes_sent: u64,
+    /// Number of bytes received.
+    pub bytes_received: u64,
+}
+
+impl Usage {
+    fn sent(&mut self, bytes: u64) {
+        self.messages_sent += 1;
+        self.bytes_sent += bytes;
+    }
+
+    fn received(&mut self, bytes: u64) {
+        self.messages_received += 1;
+        self.bytes_received += bytes;
+    }
+}
+
+/// Limits on the payloads sent to a peer or in a session.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Quota {
+    /// Maximum number of messages.
+    pub messages: Option<u64>,
+    /// Maximum number of bytes.
+    pub bytes: Option<u64>,
+}
+
+impl Quota {
+    /// Whether a payload can be sent within this quota.
+    fn allows(&self, usage: Option<&Usage>, bytes: u64) -> bool {
+        let usage = usage.copied().unwrap_or_default();
+        let messages = match self.messages {
+            Some(limit) => usage.messages_sent < limit,
+            None => true,
+        };
+        let bytes = match self.bytes {
+            Some(limit) => usage.bytes_sent + bytes <= limit,
+            None => true,
+        };
+        messages && bytes
+    }
+}
+
+/// Records the metrics for a client.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsRecorder {
+    metrics: ClientMetrics,
+    ping_sent: Option<Instant>,
+}
+
+impl MetricsRecorder {
+    /// Record a message sent to the server.
+    pub fn sent(&mut self, bytes: usize) {
+        self.metrics.messages_sent += 1;
+        self.metrics.bytes_sent += bytes as u64;
+    }
+
+    /// Record a message received from the server.
+    pub fn received(&mut self, bytes: usize) {
+        self.metrics.messages_received += 1;
+        self.metrics.bytes_received += bytes as u64;
+    }
+
+    /// Record a handshake initiated with a peer.
+    pub fn handshake_initiated(&mut self) {
+        self.metrics.handshakes_initiated += 1;
+    }
+
+    /// Record a handshake completed with a peer.
+    pub fn handshake_completed(&mut self) {
+        self.metrics.handshakes_completed += 1;
+    }
+
+    /// Record a keep-alive ping sent to the server.
+    pub fn ping_sent(&mut self) {
+        self.ping_sent = Some(Instant::now());
+    }
+
+    /// Record a response to a keep-alive ping.
+    pub fn pong_received(&mut self) {
+        if let Some(sent) = self.ping_sent.take() {
+            self.metrics.server_rtt =
+                Some(smooth(self.metrics.server_rtt, sent.elapsed()));
+        }
+    }
+
+    /// Record the round trip time for a request to a peer.
+    pub fn peer_rtt(&mut self, public_key: &[u8], sample: Duration) {
+        let rtt = self.metrics.peer_rtt.get(public_key).copied();
+        self.metrics
+            .peer_rtt
+            .insert(public_key.to_vec(), smooth(rtt, sample));
+    }
+
+    /// Record a payload sent to a peer.
+    ///
+    /// Nothing is recorded when the payload exceeds a quota.
+    pub fn peer_sent(
+        &mut self,
+        options: &ClientOptions,
+        public_key: &[u8],
+        session_id: Option<SessionId>,
+        bytes: usize,
+    ) -> Result<()> {
+        let bytes = bytes as u64;
+        if let Some(quota) = &options.peer_quota {
+            if !quota
+                .allows(self.metrics.peers.get(public_key), bytes)
+            {
+                return Err(Error::PeerQuotaExceeded(hex::encode(
+                    public_key,
+                )));
+            }
+        }
+        if let (Some(quota), Some(session_id)) =
+            (&options.session_quota, &session_id)
+        {
+            if !quota
+                .allows(self.metrics.sessions.get(session_id), bytes)
+            {
+                return Err(Error::SessionQuotaExceeded(*session_id));
+            }
+        }
+
+        self.metrics
+            .peers
+            .entry(public_key.to_vec())
+            .or_default()
+            .sent(bytes);
+        if let Some(session_id) = session_id {
+            self.metrics
+                .sessions
+                .entry(session_id)
+                .or_default()
+                .sent(bytes);
+        }
+        Ok(())
+    }
+
+    /// Record a payload received from a peer.
+    pub fn peer_received(
+        &mut self,
+        public_key: &[u8],
+        session_id: Option<SessionId>,
+        bytes: usize,
+    ) {
+        let bytes = bytes as u64;
+        self.metrics
+            .peers
+            .entry(public_key.to_vec())
+            .or_default()
+            .received(bytes);
+        if let Some(session_id) = session_id {
+            self.metrics
+                .sessions
+                .entry(session_id)
+                .or_default()
+                .received(bytes);
+        }
+    }
+
+    /// Snapshot of the metrics.
+    pub fn snapshot(&self) -> ClientMetrics {
+        self.metrics.clone()
+    }
+}
+
+/// Smooth round trip time samples using the same
+/// weighting as TCP.
+fn smooth(rtt: Option<Duration>, sample: Duration) -> Duration {
+    match rtt {
+        Some(rtt) => (rtt * 7 + sample) / 8,
+        None => sample,
+    }
+}
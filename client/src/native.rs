@@ -1,51 +1,189 @@
-use async_stream::stream;
-use futures::{
-    select,
-    sink::SinkExt,
-    stream::{SplitSink, SplitStream},
-    FutureExt, StreamExt,
-};
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
 use serde::Serialize;
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
     net::TcpStream,
-    sync::{mpsc, RwLock},
+    sync::{broadcast, mpsc, oneshot, watch, Mutex, RwLock},
 };
 use tokio_tungstenite::{
-    connect_async, tungstenite::protocol::Message, MaybeTlsStream,
-    WebSocketStream,
+    client_async_tls_with_config, connect_async_tls_with_config,
+    tungstenite::{
+        self,
+        client::IntoClientRequest,
+        error::CapacityError,
+        http::header::{HeaderName, HeaderValue},
+        protocol::{Message, WebSocketConfig},
+    },
+    Connector, MaybeTlsStream, WebSocketStream,
 };
 
 use mpc_protocol::{
-    channel::encrypt_server_channel, decode, encode, hex,
-    http::StatusCode, snow::Builder, Encoding, HandshakeMessage,
+    channel::encrypt_server_channel, encode, hex, http::StatusCode,
+    snow::Builder, Correlation, Encoding, HandshakeMessage,
     MeetingId, OpaqueMessage, ProtocolState, RequestMessage,
     ResponseMessage, ServerMessage, SessionId, SessionRequest,
-    TransparentMessage, UserId, zlib,
+    TransparentMessage, UserId,
 };
 
 use super::{
-    encrypt_peer_channel,
     event_loop::{
-        event_loop_run_impl, EventLoop, EventStream, InternalMessage,
+        ConnectionState, ConnectionWatch, EventLoop, Failover,
+        InternalMessage,
     },
-    Peers, Server,
+    metrics::Metrics,
+    relay::{Frame, RelayTransport},
+    seal_peer_envelope, seal_peer_requests,
+    subscription::Events,
+    throttle::{RateLimiter, Throttle},
+    Metadata, PeerMessage, Peers, Queued, Server,
 };
 use crate::{
-    client_impl, client_transport_impl, ClientOptions, Error, Event,
-    Result,
+    client_impl, client_transport_impl, ClientOptions, Deliveries,
+    Delivery, Error, PeerInfo, PeerState, Result, ServerEndpoint,
 };
 
-type WsMessage = Message;
-type WsError = tokio_tungstenite::tungstenite::Error;
-type WsReadStream =
-    SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
-type WsWriteStream =
-    SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>;
+/// Event loop for the native client.
+///
+/// Uses the websocket transport unless the client was
+/// created with another transport.
+pub type NativeEventLoop<T = WebSocketTransport> = EventLoop<T>;
+
+/// Websocket connection to a relay server.
+pub struct WebSocketTransport {
+    websocket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl WebSocketTransport {
+    /// Connect to a relay server.
+    ///
+    /// When no TLS configuration is given the webpki roots
+    /// are used for secure connections.
+    pub async fn connect(
+        server: &str,
+        options: &ClientOptions,
+        tls: Option<Arc<rustls::ClientConfig>>,
+    ) -> Result<Self> {
+        let connector = tls.map(Connector::Rustls);
+        let mut request = server.into_client_request()?;
+        for (name, value) in &options.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::InvalidHeader(name.clone()))?;
+            let value =
+                HeaderValue::from_str(value).map_err(|_| {
+                    Error::InvalidHeader(name.to_string())
+                })?;
+            request.headers_mut().append(name, value);
+        }
+
+        // Reject oversized frames before they are buffered
+        let config = WebSocketConfig {
+            max_frame_size: Some(options.max_frame_size),
+            max_message_size: Some(options.max_frame_size),
+            ..Default::default()
+        };
+
+        let connect = async {
+            let result = if let Some(proxy) = &options.proxy {
+                let tunnel =
+                    crate::proxy::connect(proxy, request.uri())
+                        .await?;
+                client_async_tls_with_config(
+                    request,
+                    tunnel,
+                    Some(config),
+                    connector,
+                )
+                .await?
+            } else if let Some(tcp) = &options.tcp_connector {
+                let (host, port) =
+                    crate::proxy::endpoint(request.uri())
+                        .ok_or_else(|| {
+                            Error::InvalidServerUrl(
+                                request.uri().to_string(),
+                            )
+                        })?;
+                let stream = tcp.connect(host, port).await?;
+                client_async_tls_with_config(
+                    request,
+                    stream,
+                    Some(config),
+                    connector,
+                )
+                .await?
+            } else {
+                connect_async_tls_with_config(
+                    request,
+                    Some(config),
+                    false,
+                    connector,
+                )
+                .await?
+            };
+            Ok::<_, Error>(result)
+        };
+        let (websocket, response) =
+            crate::timer::timeout(options.timeouts.connect, connect)
+                .await
+                .ok_or(Error::ConnectTimeout)??;
+
+        if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return Err(Error::ConnectError(
+                response.status(),
+                response.status().to_string(),
+            ));
+        }
 
-/// Event loop for the websocket client.
-pub type NativeEventLoop =
-    EventLoop<WsMessage, WsError, WsReadStream, WsWriteStream>;
+        Ok(Self { websocket })
+    }
+}
+
+#[async_trait]
+impl RelayTransport for WebSocketTransport {
+    async fn send(&mut self, message: Vec<u8>) -> Result<()> {
+        self.websocket
+            .send(Message::Binary(message))
+            .await
+            .map_err(|_| Error::WebSocketSend)?;
+        self.websocket
+            .flush()
+            .await
+            .map_err(|_| Error::WebSocketSend)
+    }
+
+    async fn receive(&mut self) -> Option<Result<Frame>> {
+        loop {
+            match self.websocket.next().await? {
+                Ok(Message::Binary(buffer)) => {
+                    return Some(Ok(Frame::Message(buffer)))
+                }
+                Ok(Message::Pong(_)) => return Some(Ok(Frame::Pong)),
+                // Pings are answered by the websocket library
+                Ok(_) => {}
+                Err(tungstenite::Error::Capacity(
+                    CapacityError::MessageTooLong { size, .. },
+                )) => return Some(Err(Error::FrameTooLarge(size))),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        self.websocket
+            .send(Message::Ping(Vec::new()))
+            .await
+            .map_err(|_| Error::WebSocketSend)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.websocket.close(None).await?;
+        Ok(())
+    }
+}
 
 /// Relay service websocket client.
 #[derive(Clone)]
@@ -54,6 +192,12 @@ pub struct NativeClient {
     outbound_tx: mpsc::Sender<InternalMessage>,
     server: Server,
     peers: Peers,
+    metadata: Metadata,
+    throttle: Throttle,
+    metrics: Metrics,
+    events: Events,
+    state: ConnectionWatch,
+    compression: bool,
 }
 
 impl NativeClient {
@@ -62,27 +206,107 @@ impl NativeClient {
         server: &str,
         options: ClientOptions,
     ) -> Result<(Self, NativeEventLoop)> {
-        let (stream, response) = connect_async(server).await?;
+        Self::new_with_tls(server, options, None).await
+    }
 
-        if response.status() != StatusCode::SWITCHING_PROTOCOLS {
-            return Err(Error::ConnectError(
-                response.status(),
-                response.status().to_string(),
-            ));
+    /// Create a new native client with a TLS configuration.
+    ///
+    /// Use this to trust custom root certificates or to
+    /// authenticate to the server with a client certificate;
+    /// when no configuration is given the webpki roots are
+    /// used for secure connections.
+    ///
+    /// When the connection to the server fails the servers
+    /// to fail over to are tried in order.
+    pub async fn new_with_tls(
+        server: &str,
+        options: ClientOptions,
+        tls: Option<Arc<rustls::ClientConfig>>,
+    ) -> Result<(Self, NativeEventLoop)> {
+        let mut endpoints: VecDeque<ServerEndpoint> =
+            options.failover.iter().cloned().collect();
+        let mut result = WebSocketTransport::connect(
+            server,
+            &options,
+            tls.clone(),
+        )
+        .await
+        .map(|transport| {
+            (
+                transport,
+                server.to_string(),
+                options.server_public_key.clone(),
+            )
+        });
+        while let Err(e) = &result {
+            let endpoint = match endpoints.pop_front() {
+                Some(endpoint) => endpoint,
+                None => break,
+            };
+            tracing::warn!(
+                next = %endpoint.url,
+                error = %e,
+                "connect failed, trying next server",
+            );
+            let url = options.url(&endpoint.url);
+            result = WebSocketTransport::connect(
+                &url,
+                &options,
+                tls.clone(),
+            )
+            .await
+            .map(|transport| (transport, url, endpoint.public_key));
         }
+        let (transport, url, server_key) = result?;
 
-        let (ws_writer, ws_reader) = stream.split();
+        let (client, mut event_loop) = Self::new_with_server_key(
+            transport,
+            options,
+            &server_key,
+        )?;
+        let options = Arc::clone(&event_loop.options);
+        event_loop.failover = Some(Failover {
+            server: (url, server_key),
+            endpoints,
+            connect: Box::new(move |url| {
+                let options = Arc::clone(&options);
+                let tls = tls.clone();
+                Box::pin(async move {
+                    WebSocketTransport::connect(&url, &options, tls)
+                        .await
+                })
+            }),
+        });
+        Ok((client, event_loop))
+    }
+
+    /// Create a new native client using a transport that
+    /// is already connected to the server.
+    pub fn new_with_transport<T: RelayTransport>(
+        transport: T,
+        options: ClientOptions,
+    ) -> Result<(Self, NativeEventLoop<T>)> {
+        let server_key = options.server_public_key.clone();
+        Self::new_with_server_key(transport, options, &server_key)
+    }
 
+    fn new_with_server_key<T: RelayTransport>(
+        transport: T,
+        options: ClientOptions,
+        server_key: &[u8],
+    ) -> Result<(Self, NativeEventLoop<T>)> {
         let builder = Builder::new(options.params()?);
         let handshake = builder
             .local_private_key(options.keypair.private_key())
-            .remote_public_key(&options.server_public_key)
+            .remote_public_key(server_key)
             .build_initiator()?;
 
         // Channel for writing outbound messages to send
         // to the server
         let (outbound_tx, outbound_rx) =
-            mpsc::channel::<InternalMessage>(32);
+            mpsc::channel::<InternalMessage>(
+                options.outbound_capacity,
+            );
 
         // State for the server transport
         let server = Arc::new(RwLock::new(Some(
@@ -90,28 +314,59 @@ impl NativeClient {
         )));
 
         let peers = Arc::new(RwLock::new(Default::default()));
+        let metadata = Arc::new(RwLock::new(Default::default()));
+        let throttle =
+            Arc::new(Mutex::new(RateLimiter::new(&options)));
+        let metrics: Metrics = Default::default();
+        let (events, _) =
+            broadcast::channel(options.inbound_capacity);
         let options = Arc::new(options);
+        let state =
+            Arc::new(watch::Sender::new(ConnectionState::Connecting));
         let client = Self {
             options: Arc::clone(&options),
             outbound_tx: outbound_tx.clone(),
             server: Arc::clone(&server),
             peers: Arc::clone(&peers),
+            metadata: Arc::clone(&metadata),
+            throttle,
+            metrics: Arc::clone(&metrics),
+            events: events.clone(),
+            state: Arc::clone(&state),
+            compression: true,
         };
 
         // Decoded socket messages are sent over this channel
-        let (inbound_tx, inbound_rx) =
-            mpsc::channel::<ResponseMessage>(32);
+        let (inbound_tx, inbound_rx) = mpsc::channel::<ResponseMessage>(
+            options.inbound_capacity,
+        );
+
+        #[cfg(feature = "webrtc")]
+        let direct = crate::direct::Direct::new(
+            Arc::clone(&options),
+            Arc::clone(&peers),
+            Arc::clone(&metadata),
+            inbound_tx.clone(),
+            outbound_tx.clone(),
+        );
 
         let event_loop = EventLoop {
             options,
-            ws_reader,
-            ws_writer,
+            transport,
             inbound_tx,
             inbound_rx,
             outbound_tx,
             outbound_rx,
             server,
             peers,
+            metadata,
+            metrics,
+            #[cfg(feature = "webrtc")]
+            direct,
+            failover: None,
+            handshakes: Default::default(),
+            events,
+            state,
         };
 
         Ok((client, event_loop))
@@ -121,51 +376,3 @@ impl NativeClient {
 }
 
 client_transport_impl!(NativeClient);
-
-impl EventLoop<WsMessage, WsError, WsReadStream, WsWriteStream> {
-    /// Receive and decode socket messages then send to
-    /// the messages channel.
-    pub(crate) async fn read_message(
-        incoming: Message,
-        event_proxy: &mut mpsc::Sender<ResponseMessage>,
-    ) -> Result<()> {
-        if let Message::Binary(buffer) = incoming {
-            let inflated = zlib::inflate(&buffer)?;
-            let response: ResponseMessage = decode(inflated).await?;
-            event_proxy.send(response).await?;
-        }
-        Ok(())
-    }
-
-    /// Send a message to the socket and flush the stream.
-    pub(crate) async fn send_message(
-        &mut self,
-        message: RequestMessage,
-    ) -> Result<()> {
-        let encoded = encode(&message).await?;
-        let deflated = zlib::deflate(&encoded)?;
-        let message = Message::Binary(deflated);
-
-        self.ws_writer
-            .send(message)
-            .await
-            .map_err(|_| Error::WebSocketSend)?;
-        self.ws_writer
-            .flush()
-            .await
-            .map_err(|_| Error::WebSocketSend)
-    }
-
-    async fn handle_close_message(self) -> Result<()> {
-        let mut websocket: WebSocketStream<
-            MaybeTlsStream<TcpStream>,
-        > = self
-            .ws_reader
-            .reunite(self.ws_writer)
-            .map_err(|_| Error::StreamReunite)?;
-        websocket.close(None).await?;
-        Ok(())
-    }
-
-    event_loop_run_impl!();
-}
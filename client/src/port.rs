@@ -0,0 +1,184 @@
+//! Transport over a browser extension message port.
+//!
+//! The websocket to the relay server is owned by another
+//! context of the extension, such as a content script or an
+//! offscreen document, which forwards frames between the
+//! socket and the port so the client can run in the
+//! background service worker.
+//!
+//! Extension messaging serializes messages as JSON so frames
+//! are posted as arrays of bytes; the other context posts
+//! each frame received from the server in the same form.
+use async_trait::async_trait;
+use js_sys::{Array, Function, Reflect, Uint8Array};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::sync::mpsc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+
+use crate::{
+    relay::{Frame, RelayTransport},
+    ClientOptions, Error, Result,
+};
+
+/// Connection to a relay server over an extension port.
+///
+/// Browsers do not expose a way to ping the server through
+/// the port so keep-alive should be disabled.
+pub struct PortTransport {
+    port: JsValue,
+    reader: mpsc::Receiver<Result<Vec<u8>>>,
+    max_frame_size: usize,
+    open: Arc<AtomicBool>,
+    on_message: Closure<dyn FnMut(JsValue)>,
+    on_disconnect: Closure<dyn FnMut()>,
+}
+
+impl PortTransport {
+    /// Create a transport for a connected port.
+    ///
+    /// The port is a `chrome.runtime.Port` or an object with
+    /// the same interface.
+    pub fn new(
+        port: JsValue,
+        options: &ClientOptions,
+    ) -> Result<Self> {
+        let (msg_tx, reader) =
+            mpsc::channel(options.inbound_capacity);
+        let open = Arc::new(AtomicBool::new(true));
+
+        let sender = msg_tx.clone();
+        let on_message =
+            Closure::<dyn FnMut(_)>::new(move |message: JsValue| {
+                if !Array::is_array(&message) {
+                    log::warn!("unknown port message: {:?}", message);
+                    return;
+                }
+                let buffer = Uint8Array::new(&message).to_vec();
+                let sender = sender.clone();
+                spawn_local(async move {
+                    if sender.send(Ok(buffer)).await.is_err() {
+                        log::error!(
+                            "message dropped, event loop closed"
+                        );
+                    }
+                });
+            });
+
+        let disconnected = Arc::clone(&open);
+        let on_disconnect = Closure::<dyn FnMut()>::new(move || {
+            disconnected.store(false, Ordering::SeqCst);
+            // Queued after messages already received
+            let sender = msg_tx.clone();
+            spawn_local(async move {
+                let _ = sender.send(Err(Error::Disconnected)).await;
+            });
+        });
+
+        listen(&port, "onMessage", "addListener", &on_message)?;
+        listen(&port, "onDisconnect", "addListener", &on_disconnect)?;
+
+        Ok(Self {
+            port,
+            reader,
+            max_frame_size: options.max_frame_size,
+            open,
+            on_message,
+            on_disconnect,
+        })
+    }
+
+    /// Remove the listeners from the port.
+    fn detach(&self) {
+        let _ = listen(
+            &self.port,
+            "onMessage",
+            "removeListener",
+            &self.on_message,
+        );
+        let _ = listen(
+            &self.port,
+            "onDisconnect",
+            "removeListener",
+            &self.on_disconnect,
+        );
+    }
+}
+
+impl Drop for PortTransport {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+#[async_trait]
+impl RelayTransport for PortTransport {
+    async fn send(&mut self, message: Vec<u8>) -> Result<()> {
+        let frame = Array::from(&Uint8Array::from(&message[..]));
+        call(&self.port, "postMessage", &Array::of1(&frame))?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Option<Result<Frame>> {
+        let max_frame_size = self.max_frame_size;
+        self.reader.recv().await.and_then(|message| {
+            let buffer = match message {
+                Ok(buffer) => buffer,
+                // Port was disconnected
+                Err(Error::Disconnected) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            if buffer.len() > max_frame_size {
+                return Some(Err(Error::FrameTooLarge(buffer.len())));
+            }
+            Some(Ok(Frame::Message(buffer)))
+        })
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.detach();
+        self.open.store(false, Ordering::SeqCst);
+        call(&self.port, "disconnect", &Array::new())?;
+        Ok(())
+    }
+}
+
+// See the note for the websocket transport; the port is
+// only used from the single thread of the browser.
+unsafe impl Send for PortTransport {}
+
+/// Call a method of an object.
+fn call(
+    target: &JsValue,
+    name: &str,
+    args: &Array,
+) -> Result<JsValue> {
+    let method: Function =
+        Reflect::get(target, &JsValue::from(name))?
+            .dyn_into()
+            .map_err(|_| Error::JsError)?;
+    Ok(method.apply(target, args)?)
+}
+
+/// Add or remove a listener for an event of the port.
+fn listen<T: ?Sized>(
+    port: &JsValue,
+    event: &str,
+    method: &str,
+    listener: &Closure<T>,
+) -> Result<()> {
+    let event = Reflect::get(port, &JsValue::from(event))?;
+    call(&event, method, &Array::of1(listener.as_ref()))?;
+    Ok(())
+}
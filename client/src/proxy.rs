@@ -0,0 +1,112 @@
+//! Open connections to the server through a proxy.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mpc_protocol::http::Uri;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_socks::tcp::Socks5Stream;
+
+use crate::{Error, Proxy, Result};
+
+/// Maximum size of the response headers from an HTTP proxy.
+const MAX_RESPONSE_SIZE: usize = 8192;
+
+/// Host and port for a server URI.
+pub(crate) fn endpoint(uri: &Uri) -> Option<(&str, u16)> {
+    let host = uri.host()?;
+    let port = uri.port_u16().unwrap_or_else(|| {
+        if uri.scheme_str() == Some("wss") {
+            443
+        } else {
+            80
+        }
+    });
+    Some((host, port))
+}
+
+/// Open a tunnel to the host for the server URI through a proxy.
+pub(crate) async fn connect(
+    proxy: &Proxy,
+    uri: &Uri,
+) -> Result<TcpStream> {
+    let (host, port) = endpoint(uri).ok_or_else(|| {
+        Error::ProxyConnect(format!("no host in {}", uri))
+    })?;
+
+    match proxy {
+        Proxy::Http {
+            address,
+            credentials,
+        } => {
+            let stream = TcpStream::connect(address).await?;
+            http_connect(stream, host, port, credentials.as_ref())
+                .await
+        }
+        Proxy::Socks5 {
+            address,
+            credentials,
+        } => {
+            let stream = if let Some((username, password)) =
+                credentials
+            {
+                Socks5Stream::connect_with_password(
+                    address.as_str(),
+                    (host, port),
+                    username,
+                    password,
+                )
+                .await?
+            } else {
+                Socks5Stream::connect(address.as_str(), (host, port))
+                    .await?
+            };
+            Ok(stream.into_inner())
+        }
+    }
+}
+
+/// Request a tunnel from an HTTP proxy using the CONNECT method.
+async fn http_connect(
+    mut stream: TcpStream,
+    host: &str,
+    port: u16,
+    credentials: Option<&(String, String)>,
+) -> Result<TcpStream> {
+    let mut request = format!(
+        "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n"
+    );
+    if let Some((username, password)) = credentials {
+        let token = STANDARD.encode(format!("{username}:{password}"));
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {token}\r\n"
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read byte by byte so no data after the headers is consumed
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_RESPONSE_SIZE {
+            return Err(Error::ProxyConnect(
+                "response headers too large".to_string(),
+            ));
+        }
+        if stream.read(&mut byte).await? == 0 {
+            return Err(Error::ProxyConnect(
+                "connection closed by proxy".to_string(),
+            ));
+        }
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    let code = status.split_whitespace().nth(1);
+    if code != Some("200") {
+        return Err(Error::ProxyConnect(status.to_string()));
+    }
+    Ok(stream)
+}
@@ -0,0 +1,52 @@
+//! Transports for the connection to a relay server.
+use async_trait::async_trait;
+
+use crate::Result;
+
+/// Frame received from a relay transport.
+#[derive(Debug)]
+pub enum Frame {
+    /// Encoded message from the server.
+    Message(Vec<u8>),
+    /// Response to a keep-alive ping.
+    Pong,
+}
+
+/// Connection to a relay server.
+///
+/// A transport only carries encoded messages in order; the
+/// noise protocol channels and sessions are layered on top
+/// by the event loop so alternative transports do not need
+/// to know about them.
+///
+/// The event loop polls `receive()` concurrently with other
+/// work and drops the future when other work completes first
+/// so implementations must not lose a frame when the future
+/// is dropped.
+#[async_trait]
+pub trait RelayTransport: Send + 'static {
+    /// Send an encoded message to the server.
+    async fn send(&mut self, message: Vec<u8>) -> Result<()>;
+
+    /// Receive the next frame from the server.
+    ///
+    /// Returns none when the connection is closed.
+    async fn receive(&mut self) -> Option<Result<Frame>>;
+
+    /// Send a keep-alive ping to the server.
+    ///
+    /// Transports that can not ping the server should
+    /// do nothing and disable keep-alive in the client options.
+    async fn ping(&mut self) -> Result<()>;
+
+    /// Whether the connection is open.
+    ///
+    /// Transports that can not detect a closed connection
+    /// until receiving should return true.
+    fn is_open(&self) -> bool {
+        true
+    }
+
+    /// Close the connection.
+    async fn close(&mut self) -> Result<()>;
+}
@@ -0,0 +1,165 @@
+//! Sink and stream adapters so clients compose with
+//! the combinators in the futures crate.
+//!
+//! Binary messages received from an event loop can be
+//! forwarded to a [PeerSink] to relay them to peers.
+use futures::{
+    future::{self, BoxFuture},
+    stream::BoxStream,
+    FutureExt, Sink, Stream, StreamExt,
+};
+use mpc_protocol::SessionId;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{Error, Event, JsonMessage, NetworkTransport, Result};
+
+/// Sends binary messages to peers.
+///
+/// Items are the public key of the peer and the payload;
+/// each message is sent before the next message is
+/// accepted so messages are sent in order.
+pub struct PeerSink<T> {
+    transport: T,
+    session_id: Option<SessionId>,
+    pending: Option<BoxFuture<'static, Result<()>>>,
+}
+
+impl<T> PeerSink<T>
+where
+    T: NetworkTransport + Clone + Send + Sync + 'static,
+{
+    /// Create a sink that sends messages using a transport.
+    ///
+    /// Messages are sent in the context of the session
+    /// when a session identifier is given.
+    pub fn new(transport: T, session_id: Option<SessionId>) -> Self {
+        Self {
+            transport,
+            session_id,
+            pending: None,
+        }
+    }
+
+    /// Wait for the message being sent.
+    fn poll_pending(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        match self.pending.as_mut() {
+            Some(pending) => {
+                let result = futures::ready!(pending.poll_unpin(cx));
+                self.pending = None;
+                Poll::Ready(result)
+            }
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl<T> Sink<(Vec<u8>, Vec<u8>)> for PeerSink<T>
+where
+    T: NetworkTransport + Clone + Send + Sync + Unpin + 'static,
+{
+    type Error = Error;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(
+        self: Pin<&mut Self>,
+        item: (Vec<u8>, Vec<u8>),
+    ) -> Result<()> {
+        let this = self.get_mut();
+        let (public_key, payload) = item;
+        let mut transport = this.transport.clone();
+        let session_id = this.session_id;
+        this.pending = Some(Box::pin(async move {
+            transport
+                .send_blob(&public_key, payload, session_id)
+                .await
+        }));
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+/// Typed streams of the events emitted by an event loop.
+///
+/// Errors are always yielded so they are not hidden by
+/// the filter.
+pub trait EventStreamExt:
+    Stream<Item = Result<Event>> + Send + Sized + 'static
+{
+    /// Binary messages with the public key of the sender.
+    fn binary_messages(
+        self,
+    ) -> BoxStream<'static, Result<(Vec<u8>, Vec<u8>)>> {
+        self.filter_map(|event| {
+            future::ready(match event {
+                Ok(Event::BinaryMessage {
+                    peer_key,
+                    message,
+                    ..
+                }) => Some(Ok((peer_key, message))),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+        })
+        .boxed()
+    }
+
+    /// JSON messages with the public key of the sender.
+    fn json_messages(
+        self,
+    ) -> BoxStream<'static, Result<(Vec<u8>, JsonMessage)>> {
+        self.filter_map(|event| {
+            future::ready(match event {
+                Ok(Event::JsonMessage {
+                    peer_key, message, ..
+                }) => Some(Ok((peer_key, message))),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+        })
+        .boxed()
+    }
+
+    /// Public keys of peers as they connect.
+    fn peer_connections(self) -> BoxStream<'static, Result<Vec<u8>>> {
+        self.filter_map(|event| {
+            future::ready(match event {
+                Ok(Event::PeerConnected { peer_key }) => {
+                    Some(Ok(peer_key))
+                }
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+        })
+        .boxed()
+    }
+}
+
+impl<S> EventStreamExt for S where
+    S: Stream<Item = Result<Event>> + Send + Sized + 'static
+{
+}
@@ -0,0 +1,129 @@
+//! Subscriptions to the events emitted by an event loop.
+//!
+//! Events are published to every subscriber so several
+//! components can consume events without sharing the
+//! stream returned when the event loop is run.
+use futures::stream::{self, BoxStream, StreamExt};
+use mpc_protocol::SessionId;
+use tokio::sync::broadcast;
+
+use crate::{Error, Event, Result};
+
+/// Sender for the events published to subscribers.
+pub(crate) type Events = broadcast::Sender<Event>;
+
+/// Predicate for the events yielded by a subscription.
+type Filter = Box<dyn Fn(&Event) -> bool + Send + Sync>;
+
+/// Subscription to the events of a client.
+///
+/// Every filter must match for an event to be yielded. When
+/// a subscriber falls behind by more than the capacity of
+/// the channel the stream yields a lagged error and
+/// continues with the oldest retained event.
+pub struct Subscription {
+    receiver: broadcast::Receiver<Event>,
+    filters: Vec<Filter>,
+}
+
+impl Subscription {
+    /// Create a subscription.
+    pub(crate) fn new(events: &Events) -> Self {
+        Self {
+            receiver: events.subscribe(),
+            filters: Vec::new(),
+        }
+    }
+
+    /// Only yield events that match a predicate.
+    pub fn filter(
+        mut self,
+        predicate: impl Fn(&Event) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.filters.push(Box::new(predicate));
+        self
+    }
+
+    /// Only yield events for a session.
+    pub fn session(self, session_id: SessionId) -> Self {
+        self.filter(move |event| {
+            event.session_id() == Some(session_id)
+        })
+    }
+
+    /// Only yield events for a peer.
+    pub fn peer(self, peer_key: impl Into<Vec<u8>>) -> Self {
+        let peer_key = peer_key.into();
+        self.filter(move |event| {
+            event.peer_key() == Some(peer_key.as_slice())
+        })
+    }
+
+    /// Only yield events when a session is ready.
+    pub fn session_ready(self) -> Self {
+        self.filter(|event| matches!(event, Event::SessionReady(_)))
+    }
+
+    /// Only yield messages received from peers.
+    pub fn messages(self) -> Self {
+        self.filter(|event| {
+            matches!(
+                event,
+                Event::BinaryMessage { .. }
+                    | Event::JsonMessage { .. }
+                    | Event::CborMessage { .. }
+                    | Event::MessagePackMessage { .. }
+                    | Event::PeerRequest { .. }
+            )
+        })
+    }
+
+    /// Stream of the events for this subscription.
+    ///
+    /// The stream ends after the event loop dispatches an
+    /// event that ends the connection, even when the event
+    /// is not yielded by the filters.
+    pub fn into_stream(self) -> BoxStream<'static, Result<Event>> {
+        stream::unfold(Some(self), |subscription| async move {
+            let mut subscription = subscription?;
+            loop {
+                match subscription.receiver.recv().await {
+                    Ok(event) => {
+                        let ended = matches!(
+                            event,
+                            Event::Close
+                                | Event::ConnectionLost
+                                | Event::Disconnected { .. }
+                        );
+                        if subscription.matches(&event) {
+                            let next = if ended {
+                                None
+                            } else {
+                                Some(subscription)
+                            };
+                            return Some((Ok(event), next));
+                        } else if ended {
+                            return None;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(
+                        skipped,
+                    )) => {
+                        return Some((
+                            Err(Error::SubscriptionLagged(skipped)),
+                            Some(subscription),
+                        ));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return None
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        self.filters.iter().all(|filter| filter(event))
+    }
+}
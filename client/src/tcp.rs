@@ -0,0 +1,152 @@
+//! Plain TCP transport for environments that can not
+//! use websockets.
+//!
+//! Each frame is a big-endian `u32` length followed by the
+//! same compressed message carried in a websocket binary
+//! message; an empty frame is a keep-alive ping or pong.
+//! The first frame sent by the client is its public key.
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream, ToSocketAddrs,
+    },
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{
+    relay::{Frame, RelayTransport},
+    ClientOptions, Error, Result,
+};
+
+/// Maximum size of a frame sent, must match the server.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Length-prefixed TCP connection to a relay server.
+///
+/// The connection is not encrypted with TLS; messages are
+/// still encrypted by the noise protocol channel to the
+/// server.
+pub struct TcpTransport {
+    writer: OwnedWriteHalf,
+    reader: mpsc::Receiver<Result<Frame>>,
+    task: JoinHandle<()>,
+}
+
+impl TcpTransport {
+    /// Connect to the TCP listener of a relay server.
+    pub async fn connect(
+        addr: impl ToSocketAddrs,
+        options: &ClientOptions,
+    ) -> Result<Self> {
+        let stream = crate::timer::timeout(
+            options.timeouts.connect,
+            TcpStream::connect(addr),
+        )
+        .await
+        .ok_or(Error::ConnectTimeout)??;
+        stream.set_nodelay(true)?;
+        let (reader, mut writer) = stream.into_split();
+        write_frame(&mut writer, options.keypair.public_key())
+            .await?;
+
+        // Frames are read by a task so receiving is cancel safe
+        let (frames_tx, frames_rx) =
+            mpsc::channel(options.inbound_capacity);
+        let task = tokio::spawn(read_frames(
+            reader,
+            frames_tx,
+            options.max_frame_size,
+        ));
+        Ok(Self {
+            writer,
+            reader: frames_rx,
+            task,
+        })
+    }
+}
+
+#[async_trait]
+impl RelayTransport for TcpTransport {
+    async fn send(&mut self, message: Vec<u8>) -> Result<()> {
+        write_frame(&mut self.writer, &message).await
+    }
+
+    async fn receive(&mut self) -> Option<Result<Frame>> {
+        self.reader.recv().await
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        write_frame(&mut self.writer, &[]).await
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.writer.shutdown().await?;
+        self.task.abort();
+        Ok(())
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn write_frame(
+    writer: &mut OwnedWriteHalf,
+    buffer: &[u8],
+) -> Result<()> {
+    if buffer.len() > MAX_FRAME_SIZE {
+        return Err(Error::FrameTooLarge(buffer.len()));
+    }
+    writer.write_u32(buffer.len() as u32).await?;
+    writer.write_all(buffer).await?;
+    Ok(())
+}
+
+async fn read_frame(
+    reader: &mut OwnedReadHalf,
+    max_frame_size: usize,
+) -> Result<Option<Frame>> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if len == 0 {
+        return Ok(Some(Frame::Pong));
+    }
+    if len > max_frame_size {
+        return Err(Error::FrameTooLarge(len));
+    }
+    let mut buffer = vec![0; len];
+    reader.read_exact(&mut buffer).await?;
+    Ok(Some(Frame::Message(buffer)))
+}
+
+async fn read_frames(
+    mut reader: OwnedReadHalf,
+    frames: mpsc::Sender<Result<Frame>>,
+    max_frame_size: usize,
+) {
+    loop {
+        match read_frame(&mut reader, max_frame_size).await {
+            Ok(Some(frame)) => {
+                if frames.send(Ok(frame)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                let _ = frames.send(Err(e)).await;
+                break;
+            }
+        }
+    }
+}
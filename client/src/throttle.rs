@@ -0,0 +1,122 @@
+//! Token bucket rate limiting for messages sent to peers.
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+use crate::{ClientOptions, RateLimit};
+
+/// Rate limiter shared by the clones of a client.
+pub(crate) type Throttle = Arc<Mutex<RateLimiter>>;
+
+/// Token bucket.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Add the tokens accrued since the last update.
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate as f64)
+            .min(limit.burst as f64);
+        self.updated = now;
+    }
+
+    /// Time until a token is available.
+    fn wait(&self, limit: &RateLimit) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.rate.max(1) as f64,
+            )
+        }
+    }
+}
+
+/// Limits the rate of messages sent to all peers and
+/// to each peer.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    global: Option<(RateLimit, Bucket)>,
+    peer_limit: Option<RateLimit>,
+    peers: HashMap<Vec<u8>, Bucket>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter for the client options.
+    pub fn new(options: &ClientOptions) -> Self {
+        // No clock in the browser
+        if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            return Default::default();
+        }
+        Self {
+            global: options
+                .global_rate_limit
+                .map(|limit| (limit, Bucket::new(&limit))),
+            peer_limit: options.peer_rate_limit,
+            peers: Default::default(),
+        }
+    }
+
+    /// Take a token to send a message to a peer.
+    ///
+    /// When a token is not available the delay before
+    /// trying again is returned and no token is taken.
+    pub fn acquire(&mut self, public_key: &[u8]) -> Option<Duration> {
+        if self.global.is_none() && self.peer_limit.is_none() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let mut delay = Duration::ZERO;
+
+        if let Some((limit, bucket)) = &mut self.global {
+            bucket.refill(limit, now);
+            delay = delay.max(bucket.wait(limit));
+        }
+
+        if let Some(limit) = &self.peer_limit {
+            let bucket = self
+                .peers
+                .entry(public_key.to_vec())
+                .or_insert_with(|| Bucket::new(limit));
+            bucket.refill(limit, now);
+            delay = delay.max(bucket.wait(limit));
+        }
+
+        if !delay.is_zero() {
+            return Some(delay);
+        }
+
+        if let Some((_, bucket)) = &mut self.global {
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = self.peers.get_mut(public_key) {
+            bucket.tokens -= 1.0;
+        }
+        None
+    }
+}
+
+/// Wait for a delay imposed by the rate limiter.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) async fn sleep(delay: Duration) {
+    tokio::time::sleep(delay).await
+}
+
+/// Wait for a delay imposed by the rate limiter.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) async fn sleep(_delay: Duration) {}
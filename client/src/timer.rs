@@ -0,0 +1,73 @@
+//! Clock and timeouts for futures.
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+/// Current time.
+///
+/// Returns none in the browser where there is no clock.
+pub(crate) fn now() -> Option<Instant> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+/// Wait for a future to complete within a timeout.
+///
+/// Returns none if the future did not complete in time.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) async fn timeout<F: Future>(
+    delay: Duration,
+    future: F,
+) -> Option<F::Output> {
+    tokio::time::timeout(delay, future).await.ok()
+}
+
+/// Wait for a future to complete.
+///
+/// Browser timers cannot be awaited from a future that must
+/// be `Send` so the timeout is not enforced.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) async fn timeout<F: Future>(
+    _delay: Duration,
+    future: F,
+) -> Option<F::Output> {
+    Some(future.await)
+}
+
+/// Wait for a delay.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) async fn sleep(delay: Duration) {
+    tokio::time::sleep(delay).await
+}
+
+/// Wait for a delay.
+///
+/// The browser timer resolves a channel so that the future
+/// does not hold a javascript value and remains `Send`.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) async fn sleep(delay: Duration) {
+    use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let callback = Closure::once_into_js(move || {
+        let _ = tx.send(());
+    });
+    let global = js_sys::global();
+    let set_timeout =
+        js_sys::Reflect::get(&global, &JsValue::from("setTimeout"))
+            .ok()
+            .and_then(|f| f.dyn_into::<js_sys::Function>().ok());
+    match set_timeout {
+        Some(set_timeout) => {
+            let millis = JsValue::from(delay.as_millis() as f64);
+            if set_timeout.call2(&global, &callback, &millis).is_ok()
+            {
+                let _ = rx.await;
+            }
+        }
+        None => log::warn!("timers are not available"),
+    }
+}
@@ -1,8 +1,12 @@
-use crate::{Client, ClientOptions, EventLoop, Result};
+use crate::{
+    Client, ClientOptions, ConnectionState, Deliveries, EventLoop,
+    PeerInfo, PeerState, Result, Subscription,
+};
 use async_trait::async_trait;
-use mpc_protocol::{MeetingId, SessionId, UserId};
+use mpc_protocol::{MeetingId, SessionId, SessionRequest, UserId};
 use serde::Serialize;
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Duration};
+use tokio::sync::watch;
 
 /// Enumeration of available transports.
 #[derive(Clone)]
@@ -27,18 +31,68 @@ impl NetworkTransport for Transport {
         }
     }
 
+    fn subscribe(&self) -> Subscription {
+        match self {
+            Transport::Relay(client) => client.subscribe(),
+        }
+    }
+
+    fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        match self {
+            Transport::Relay(client) => client.connection_state(),
+        }
+    }
+
+    fn is_compression_enabled(&self) -> bool {
+        match self {
+            Transport::Relay(client) => {
+                client.is_compression_enabled()
+            }
+        }
+    }
+
+    fn set_compression(&mut self, enabled: bool) {
+        match self {
+            Transport::Relay(client) => {
+                client.set_compression(enabled)
+            }
+        }
+    }
+
     async fn connect(&mut self) -> Result<()> {
         match self {
             Transport::Relay(client) => client.connect().await,
         }
     }
 
+    async fn connect_and_wait(&mut self) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client.connect_and_wait().await
+            }
+        }
+    }
+
     async fn is_connected(&self) -> bool {
         match self {
             Transport::Relay(client) => client.is_connected().await,
         }
     }
 
+    async fn peer_state(&self, public_key: &[u8]) -> PeerState {
+        match self {
+            Transport::Relay(client) => {
+                client.peer_state(public_key).await
+            }
+        }
+    }
+
+    async fn list_peers(&self) -> Vec<PeerInfo> {
+        match self {
+            Transport::Relay(client) => client.list_peers().await,
+        }
+    }
+
     async fn connect_peer(
         &mut self,
         public_key: &[u8],
@@ -50,6 +104,17 @@ impl NetworkTransport for Transport {
         }
     }
 
+    async fn disconnect_peer(
+        &mut self,
+        public_key: &[u8],
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client.disconnect_peer(public_key).await
+            }
+        }
+    }
+
     async fn send_json<S>(
         &mut self,
         public_key: &[u8],
@@ -68,6 +133,42 @@ impl NetworkTransport for Transport {
         }
     }
 
+    async fn send_cbor<S>(
+        &mut self,
+        public_key: &[u8],
+        payload: &S,
+        session_id: Option<SessionId>,
+    ) -> Result<()>
+    where
+        S: Serialize + Send + Sync + ?Sized,
+    {
+        match self {
+            Transport::Relay(client) => {
+                client
+                    .send_cbor(public_key, payload, session_id)
+                    .await
+            }
+        }
+    }
+
+    async fn send_msgpack<S>(
+        &mut self,
+        public_key: &[u8],
+        payload: &S,
+        session_id: Option<SessionId>,
+    ) -> Result<()>
+    where
+        S: Serialize + Send + Sync + ?Sized,
+    {
+        match self {
+            Transport::Relay(client) => {
+                client
+                    .send_msgpack(public_key, payload, session_id)
+                    .await
+            }
+        }
+    }
+
     async fn send_blob(
         &mut self,
         public_key: &[u8],
@@ -83,6 +184,60 @@ impl NetworkTransport for Transport {
         }
     }
 
+    async fn send_blob_tracked(
+        &mut self,
+        public_key: &[u8],
+        payload: Vec<u8>,
+        session_id: Option<SessionId>,
+        timeout: Duration,
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client
+                    .send_blob_tracked(
+                        public_key, payload, session_id, timeout,
+                    )
+                    .await
+            }
+        }
+    }
+
+    async fn request_peer(
+        &mut self,
+        public_key: &[u8],
+        payload: &[u8],
+        session_id: Option<SessionId>,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        match self {
+            Transport::Relay(client) => {
+                client
+                    .request_peer(
+                        public_key, payload, session_id, timeout,
+                    )
+                    .await
+            }
+        }
+    }
+
+    async fn reply_peer(
+        &mut self,
+        public_key: &[u8],
+        request_id: u64,
+        payload: &[u8],
+        session_id: Option<SessionId>,
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client
+                    .reply_peer(
+                        public_key, request_id, payload, session_id,
+                    )
+                    .await
+            }
+        }
+    }
+
     async fn new_meeting(
         &mut self,
         owner_id: UserId,
@@ -107,13 +262,13 @@ impl NetworkTransport for Transport {
         }
     }
 
-    async fn new_session(
-        &mut self,
-        participant_keys: Vec<Vec<u8>>,
-    ) -> Result<()> {
+    async fn new_session<R>(&mut self, request: R) -> Result<()>
+    where
+        R: Into<SessionRequest> + Send,
+    {
         match self {
             Transport::Relay(client) => {
-                client.new_session(participant_keys).await
+                client.new_session(request).await
             }
         }
     }
@@ -141,12 +296,113 @@ impl NetworkTransport for Transport {
         }
     }
 
+    async fn invite_participant(
+        &mut self,
+        session_id: &SessionId,
+        public_key: &[u8],
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client
+                    .invite_participant(session_id, public_key)
+                    .await
+            }
+        }
+    }
+
+    async fn transfer_session(
+        &mut self,
+        session_id: &SessionId,
+        public_key: &[u8],
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client.transfer_session(session_id, public_key).await
+            }
+        }
+    }
+
+    async fn finish_session(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client.finish_session(session_id).await
+            }
+        }
+    }
+
+    async fn list_sessions(&mut self) -> Result<()> {
+        match self {
+            Transport::Relay(client) => client.list_sessions().await,
+        }
+    }
+
+    async fn decline_session(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client.decline_session(session_id).await
+            }
+        }
+    }
+
+    async fn rejoin_session(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client.rejoin_session(session_id).await
+            }
+        }
+    }
+
+    async fn set_session_value(
+        &mut self,
+        session_id: SessionId,
+        key: &str,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client.set_session_value(session_id, key, value).await
+            }
+        }
+    }
+
+    async fn get_session_value(
+        &mut self,
+        session_id: SessionId,
+        key: &str,
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client.get_session_value(session_id, key).await
+            }
+        }
+    }
+
+    async fn session_heartbeat(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client.session_heartbeat(session_id).await
+            }
+        }
+    }
+
     async fn broadcast_json<S>(
         &mut self,
         session_id: &SessionId,
         recipient_public_keys: &[Vec<u8>],
         payload: &S,
-    ) -> Result<()>
+    ) -> Result<Deliveries>
     where
         S: Serialize + Send + Sync + ?Sized,
     {
@@ -168,7 +424,7 @@ impl NetworkTransport for Transport {
         session_id: &SessionId,
         recipient_public_keys: &[Vec<u8>],
         payload: Vec<u8>,
-    ) -> Result<()> {
+    ) -> Result<Deliveries> {
         match self {
             Transport::Relay(client) => {
                 client
@@ -182,6 +438,47 @@ impl NetworkTransport for Transport {
         }
     }
 
+    async fn ordered_broadcast_json<S>(
+        &mut self,
+        session_id: &SessionId,
+        recipient_public_keys: &[Vec<u8>],
+        payload: &S,
+    ) -> Result<()>
+    where
+        S: Serialize + Send + Sync + ?Sized,
+    {
+        match self {
+            Transport::Relay(client) => {
+                client
+                    .ordered_broadcast_json(
+                        session_id,
+                        recipient_public_keys,
+                        payload,
+                    )
+                    .await
+            }
+        }
+    }
+
+    async fn ordered_broadcast_blob(
+        &mut self,
+        session_id: &SessionId,
+        recipient_public_keys: &[Vec<u8>],
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        match self {
+            Transport::Relay(client) => {
+                client
+                    .ordered_broadcast_blob(
+                        session_id,
+                        recipient_public_keys,
+                        payload,
+                    )
+                    .await
+            }
+        }
+    }
+
     async fn close(&self) -> Result<()> {
         match self {
             Transport::Relay(client) => client.close().await,
@@ -202,25 +499,75 @@ impl Transport {
 }
 
 /// Trait for network clients.
+///
+/// Implemented by the native and web clients so protocol
+/// code written against this trait runs on both targets.
 #[async_trait]
 pub trait NetworkTransport {
     /// Public key for this client.
     fn public_key(&self) -> &[u8];
 
+    /// Subscribe to the events dispatched by the event loop.
+    fn subscribe(&self) -> Subscription;
+
+    /// Watch the state of the connection to the server.
+    fn connection_state(&self) -> watch::Receiver<ConnectionState>;
+
+    /// Whether compression of the payloads sent by this
+    /// transport is enabled.
+    fn is_compression_enabled(&self) -> bool;
+
+    /// Enable or disable compression of the payloads sent
+    /// by this transport.
+    ///
+    /// Compression is enabled by default and only applies
+    /// when a compression threshold is configured; the
+    /// setting is not shared with clones of the transport.
+    fn set_compression(&mut self, enabled: bool);
+
     /// Perform initial handshake with the server.
     async fn connect(&mut self) -> Result<()>;
 
+    /// Perform initial handshake with the server and wait
+    /// until the channel to the server is established.
+    ///
+    /// The event loop must be running for the handshake to
+    /// complete; a timeout error is returned when the server
+    /// does not complete the handshake in time.
+    async fn connect_and_wait(&mut self) -> Result<()>;
+
     /// Determine if this client has completed a
     /// server handshake.
     async fn is_connected(&self) -> bool;
 
+    /// State of the channel to a peer.
+    ///
+    /// A peer is disconnected when there is no channel,
+    /// when the server reports that the peer left a session
+    /// or when a message from the peer could not be decrypted.
+    async fn peer_state(&self, public_key: &[u8]) -> PeerState;
+
+    /// Channels to peers that are established or in the
+    /// process of a handshake.
+    async fn list_peers(&self) -> Vec<PeerInfo>;
+
     /// Handshake with a peer.
     ///
     /// Peer already exists error is returned if this
-    /// client is already connecting to the peer.
+    /// client is already connecting or connected to the peer.
     async fn connect_peer(&mut self, public_key: &[u8])
         -> Result<()>;
 
+    /// Close the channel to a peer.
+    ///
+    /// When the channel is established the peer is notified
+    /// and receives a peer disconnected event; a new handshake
+    /// is required to send messages to the peer.
+    async fn disconnect_peer(
+        &mut self,
+        public_key: &[u8],
+    ) -> Result<()>;
+
     /// Send a JSON message to a peer.
     async fn send_json<S>(
         &mut self,
@@ -231,6 +578,29 @@ pub trait NetworkTransport {
     where
         S: Serialize + Send + Sync + ?Sized;
 
+    /// Send a CBOR message to a peer.
+    async fn send_cbor<S>(
+        &mut self,
+        public_key: &[u8],
+        payload: &S,
+        session_id: Option<SessionId>,
+    ) -> Result<()>
+    where
+        S: Serialize + Send + Sync + ?Sized;
+
+    /// Send a MessagePack message to a peer.
+    ///
+    /// Structs are encoded as maps so that fields may be
+    /// added without breaking older peers.
+    async fn send_msgpack<S>(
+        &mut self,
+        public_key: &[u8],
+        payload: &S,
+        session_id: Option<SessionId>,
+    ) -> Result<()>
+    where
+        S: Serialize + Send + Sync + ?Sized;
+
     /// Send a binary message to a peer.
     async fn send_blob(
         &mut self,
@@ -239,6 +609,48 @@ pub trait NetworkTransport {
         session_id: Option<SessionId>,
     ) -> Result<()>;
 
+    /// Send a binary message to a peer and wait for the
+    /// server to acknowledge that it relayed the message.
+    ///
+    /// Messages queued for an offline peer are acknowledged
+    /// once they are relayed and messages sent over a direct
+    /// connection once they are sent. The event loop must be
+    /// running to receive the acknowledgement; timeouts are
+    /// not enforced in the browser.
+    async fn send_blob_tracked(
+        &mut self,
+        public_key: &[u8],
+        payload: Vec<u8>,
+        session_id: Option<SessionId>,
+        timeout: Duration,
+    ) -> Result<()>;
+
+    /// Send a request to a peer and wait for the response.
+    ///
+    /// The peer receives a peer request event and should
+    /// call `reply_peer()` with the request identifier.
+    ///
+    /// The event loop must be running to receive the response;
+    /// if the peer does not reply before the timeout a request
+    /// timeout error is returned. Timeouts are not enforced in
+    /// the browser.
+    async fn request_peer(
+        &mut self,
+        public_key: &[u8],
+        payload: &[u8],
+        session_id: Option<SessionId>,
+        timeout: Duration,
+    ) -> Result<Vec<u8>>;
+
+    /// Reply to a request from a peer.
+    async fn reply_peer(
+        &mut self,
+        public_key: &[u8],
+        request_id: u64,
+        payload: &[u8],
+        session_id: Option<SessionId>,
+    ) -> Result<()>;
+
     /// Create a new meeting point.
     async fn new_meeting(
         &mut self,
@@ -257,10 +669,12 @@ pub trait NetworkTransport {
     ///
     /// Do not include the public key of the initiator for the new
     /// session; it is automatically included as the session owner.
-    async fn new_session(
-        &mut self,
-        participant_keys: Vec<Vec<u8>>,
-    ) -> Result<()>;
+    ///
+    /// The request may be the public keys of the participants
+    /// or a session request that assigns roles to participants.
+    async fn new_session<R>(&mut self, request: R) -> Result<()>
+    where
+        R: Into<SessionRequest> + Send;
 
     /// Register a peer connection in a session.
     async fn register_connection(
@@ -275,22 +689,145 @@ pub trait NetworkTransport {
         session_id: SessionId,
     ) -> Result<()>;
 
+    /// Invite a participant to join a session.
+    ///
+    /// Only the session owner may invite participants.
+    async fn invite_participant(
+        &mut self,
+        session_id: &SessionId,
+        public_key: &[u8],
+    ) -> Result<()>;
+
+    /// Transfer ownership of a session to another participant.
+    ///
+    /// Only the session owner may transfer ownership; party
+    /// numbers are not affected by the transfer.
+    async fn transfer_session(
+        &mut self,
+        session_id: &SessionId,
+        public_key: &[u8],
+    ) -> Result<()>;
+
+    /// Acknowledge that we have finished with a session.
+    ///
+    /// Once every participant has acknowledged the server
+    /// closes the session and notifies all the participants
+    /// that the session is finished; the owner may close the
+    /// session early if some participants do not acknowledge.
+    async fn finish_session(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<()>;
+
+    /// List the sessions the client is a participant of.
+    ///
+    /// The server replies with a session list event.
+    async fn list_sessions(&mut self) -> Result<()>;
+
+    /// Decline to join a session.
+    ///
+    /// The other participants are notified so the
+    /// initiator can close the session or regroup.
+    async fn decline_session(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<()>;
+
+    /// Rejoin a session after a disconnect.
+    ///
+    /// The session must still exist on the server and the
+    /// client must rejoin within the server's grace period.
+    ///
+    /// Slots are bound to the public key that joined the
+    /// session so a participant that restarts can rejoin its
+    /// slot if it persisted its key pair.
+    async fn rejoin_session(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<()>;
+
+    /// Publish a value to the participants in a session.
+    ///
+    /// The value is stored by the server for the lifetime of
+    /// the session and every participant receives a session
+    /// value event; use this to coordinate parameters before
+    /// running a protocol.
+    async fn set_session_value(
+        &mut self,
+        session_id: SessionId,
+        key: &str,
+        value: Vec<u8>,
+    ) -> Result<()>;
+
+    /// Read a value published in a session.
+    ///
+    /// The server replies with a session value event; the
+    /// value is none if it has not been published.
+    async fn get_session_value(
+        &mut self,
+        session_id: SessionId,
+        key: &str,
+    ) -> Result<()>;
+
+    /// Send a heartbeat for a session.
+    ///
+    /// Once a participant has sent a heartbeat the server
+    /// notifies the other participants when no heartbeat is
+    /// received within the server's heartbeat timeout, so a
+    /// peer that is connected but hung can be detected.
+    async fn session_heartbeat(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<()>;
+
     /// Broadcast a JSON message in the context of a session.
+    ///
+    /// Returns the outcome for each recipient; a recipient
+    /// that can not be reached does not stop the broadcast.
     async fn broadcast_json<S>(
         &mut self,
         session_id: &SessionId,
         recipient_public_keys: &[Vec<u8>],
         payload: &S,
-    ) -> Result<()>
+    ) -> Result<Deliveries>
     where
         S: Serialize + Send + Sync + ?Sized;
 
     /// Broadcast a binary message in the context of a session.
+    ///
+    /// Returns the outcome for each recipient; a recipient
+    /// that can not be reached does not stop the broadcast.
     async fn broadcast_blob(
         &mut self,
         session_id: &SessionId,
         recipient_public_keys: &[Vec<u8>],
         payload: Vec<u8>,
+    ) -> Result<Deliveries>;
+
+    /// Broadcast a JSON message in the context of a session
+    /// in the total order assigned by the server.
+    ///
+    /// Every participant receives the ordered broadcasts in
+    /// a session in the same order.
+    async fn ordered_broadcast_json<S>(
+        &mut self,
+        session_id: &SessionId,
+        recipient_public_keys: &[Vec<u8>],
+        payload: &S,
+    ) -> Result<()>
+    where
+        S: Serialize + Send + Sync + ?Sized;
+
+    /// Broadcast a binary message in the context of a session
+    /// in the total order assigned by the server.
+    ///
+    /// Every participant receives the ordered broadcasts in
+    /// a session in the same order.
+    async fn ordered_broadcast_blob(
+        &mut self,
+        session_id: &SessionId,
+        recipient_public_keys: &[Vec<u8>],
+        payload: Vec<u8>,
     ) -> Result<()>;
 
     /// Close the socket connection.
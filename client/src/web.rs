@@ -1,134 +1,131 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
-use web_sys::{ErrorEvent, MessageEvent, WebSocket};
-
-use async_stream::stream;
-use futures::{
-    select, stream::BoxStream, FutureExt, Sink, SinkExt, StreamExt,
+use web_sys::{
+    CloseEvent, ErrorEvent, EventTarget, MessageEvent,
+    VisibilityState, WebSocket,
 };
+
+use async_trait::async_trait;
+use futures::Future;
 use serde::Serialize;
-use std::{collections::HashSet, pin::Pin, sync::Arc};
-use tokio::sync::{mpsc, RwLock};
+use std::{
+    collections::HashSet,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::{
+    broadcast, mpsc, oneshot, watch, Mutex, RwLock,
+};
 
 use mpc_protocol::{
-    channel::encrypt_server_channel, decode, encode, hex,
-    snow::Builder, Encoding, HandshakeMessage, MeetingId,
-    OpaqueMessage, ProtocolState, RequestMessage, ResponseMessage,
-    ServerMessage, SessionId, SessionRequest, TransparentMessage,
-    UserId, zlib,
+    channel::encrypt_server_channel, encode, hex,
+    snow::Builder, Correlation, Encoding, HandshakeMessage,
+    MeetingId, OpaqueMessage, ProtocolState, RequestMessage,
+    ResponseMessage, ServerMessage, SessionId, SessionRequest,
+    TransparentMessage, UserId,
 };
 
 use crate::{
-    client_impl, client_transport_impl, encrypt_peer_channel,
+    client_impl, client_transport_impl,
     event_loop::{
-        event_loop_run_impl, EventLoop, EventStream, InternalMessage,
+        ConnectionState, ConnectionWatch, EventLoop, Failover,
+        InternalMessage,
     },
-    ClientOptions, Error, Event, Peers, Result, Server,
+    metrics::Metrics,
+    relay::{Frame, RelayTransport},
+    seal_peer_envelope, seal_peer_requests,
+    subscription::Events,
+    throttle::{RateLimiter, Throttle},
+    ClientOptions, Deliveries, Delivery, Error, Metadata, PeerInfo,
+    PeerMessage, PeerState, Peers, Queued, Result, Server,
 };
 
-type WsMessage = Vec<u8>;
-type WsError = Error;
-type WsReadStream = BoxStream<'static, Result<Vec<u8>>>;
-type WsWriteStream = Pin<Box<WebSocketSink>>;
+/// Size of the fragments for payloads relayed by the web
+/// client when no fragment size is specified.
+///
+/// Browsers struggle with very large binary frames so
+/// large payloads are always fragmented.
+const DEFAULT_FRAGMENT_SIZE: usize = 1024 * 1024;
 
 /// Event loop for the web client.
-pub type WebEventLoop =
-    EventLoop<WsMessage, WsError, WsReadStream, WsWriteStream>;
+///
+/// Messages received by the websocket listeners are
+/// bridged into the same event stream as the native client
+/// so functions that wait for sessions and drivers work
+/// with either client.
+pub type WebEventLoop<T = WebSocketTransport> = EventLoop<T>;
 
 /// Client for the web platform.
 #[derive(Clone)]
 pub struct WebClient {
-    //ws: WebSocket,
     options: Arc<ClientOptions>,
     outbound_tx: mpsc::Sender<InternalMessage>,
     server: Server,
     peers: Peers,
-    ptr: *mut mpsc::Sender<Result<Vec<u8>>>,
+    metadata: Metadata,
+    throttle: Throttle,
+    metrics: Metrics,
+    events: Events,
+    state: ConnectionWatch,
+    compression: bool,
 }
 
 impl WebClient {
     /// Create a new web client.
+    ///
+    /// When reconnect settings are given the client
+    /// reconnects to the server when the connection ends,
+    /// when the page becomes visible after the socket was
+    /// closed and when the browser comes back online.
     pub async fn new(
         server: &str,
         options: ClientOptions,
     ) -> Result<(WebClient, WebEventLoop)> {
-        let ws = WebSocket::new(server)?;
-        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
-
-        let (ws_msg_tx, mut ws_msg_rx) = mpsc::channel(32);
-        let msg_tx = Box::new(ws_msg_tx);
-
-        let ptr = Box::into_raw(msg_tx);
-        unsafe {
-            let msg_proxy = &*(ptr as *const _)
-                as &'static mpsc::Sender<Result<Vec<u8>>>;
-
-            let onmessage_callback = Closure::<dyn FnMut(_)>::new(
-                move |e: MessageEvent| {
-                    spawn_local(async move {
-                        if let Ok(buf) =
-                            e.data().dyn_into::<js_sys::ArrayBuffer>()
-                        {
-                            let array = js_sys::Uint8Array::new(&buf);
-                            let buffer = array.to_vec();
-
-                            if let Err(e) =
-                                msg_proxy.send(Ok(buffer)).await
-                            {
-                                if let mpsc::error::SendError(Ok(
-                                    buffer,
-                                )) = e
-                                {
-                                    let message: ResponseMessage =
-                                        decode(&buffer)
-                                            .await
-                                            .unwrap();
-                                    log::error!(
-                                        "send error {:#?}",
-                                        message
-                                    );
-                                }
-                            }
-                        } else {
-                            log::warn!(
-                                "unknown message event: {:?}",
-                                e.data()
-                            );
-                        }
-                    });
-                },
-            );
-            ws.set_onmessage(Some(
-                onmessage_callback.as_ref().unchecked_ref(),
-            ));
-            onmessage_callback.forget();
+        let transport =
+            WebSocketTransport::connect(server, &options).await?;
+        let endpoint =
+            (server.to_string(), options.server_public_key.clone());
+        let (client, mut event_loop) =
+            Self::new_with_transport(transport, options)?;
+
+        let options = Arc::clone(&event_loop.options);
+        if options.reconnect.is_some() {
+            watch_page(client.outbound_tx.downgrade())?;
         }
-
-        let onerror_callback =
-            Closure::<dyn FnMut(_)>::new(move |e: ErrorEvent| {
-                log::error!("error event: {:?}", e.as_string());
-            });
-        ws.set_onerror(Some(
-            onerror_callback.as_ref().unchecked_ref(),
-        ));
-        onerror_callback.forget();
-
-        let (open_tx, mut open_rx) = mpsc::channel(1);
-
-        let onopen_callback = Closure::once(move || {
-            spawn_local(async move {
-                open_tx.send(()).await.unwrap();
-            });
+        event_loop.failover = Some(Failover {
+            server: endpoint,
+            endpoints: Default::default(),
+            connect: Box::new(move |url| {
+                let options = Arc::clone(&options);
+                Box::pin(async move {
+                    WebSocketTransport::connect(&url, &options).await
+                })
+            }),
         });
-        ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+        Ok((client, event_loop))
+    }
 
-        let _ = open_rx.recv().await;
-        drop(open_rx);
+    /// Create a new web client using a transport that
+    /// is already connected to the server.
+    ///
+    /// The client does not reconnect as the transport can
+    /// not be opened again.
+    pub fn new_with_transport<T: RelayTransport>(
+        transport: T,
+        mut options: ClientOptions,
+    ) -> Result<(WebClient, WebEventLoop<T>)> {
+        if options.fragment_size.is_none() {
+            options.fragment_size = Some(DEFAULT_FRAGMENT_SIZE);
+        }
 
         // Channel for writing outbound messages to send
         // to the server
         let (outbound_tx, outbound_rx) =
-            mpsc::channel::<InternalMessage>(32);
+            mpsc::channel::<InternalMessage>(
+                options.outbound_capacity,
+            );
 
         let builder = Builder::new(options.params()?);
         let handshake = builder
@@ -142,153 +139,279 @@ impl WebClient {
         )));
 
         let peers = Arc::new(RwLock::new(Default::default()));
+        let metadata = Arc::new(RwLock::new(Default::default()));
+        let throttle =
+            Arc::new(Mutex::new(RateLimiter::new(&options)));
+        let metrics: Metrics = Default::default();
+        let (events, _) =
+            broadcast::channel(options.inbound_capacity);
         let options = Arc::new(options);
+        let state =
+            Arc::new(watch::Sender::new(ConnectionState::Connecting));
 
         let client = WebClient {
-            //ws: ws.clone(),
             options: Arc::clone(&options),
             outbound_tx: outbound_tx.clone(),
             server: Arc::clone(&server),
             peers: Arc::clone(&peers),
-            ptr,
+            metadata: Arc::clone(&metadata),
+            throttle,
+            metrics: Arc::clone(&metrics),
+            events: events.clone(),
+            state: Arc::clone(&state),
+            compression: true,
         };
 
-        // Proxy stream from the websocket message event closure
-        // to the event loop
-        let ws_reader = Box::pin(stream! {
-            while let Some(message) = ws_msg_rx.recv().await {
-                yield message;
-            }
-        });
-
-        let ws_writer = Box::pin(WebSocketSink { ws });
-
         // Decoded socket messages are sent over this channel
-        let (inbound_tx, inbound_rx) =
-            mpsc::channel::<ResponseMessage>(32);
+        let (inbound_tx, inbound_rx) = mpsc::channel::<ResponseMessage>(
+            options.inbound_capacity,
+        );
 
-        let event_loop: WebEventLoop = EventLoop {
+        let event_loop: WebEventLoop<T> = EventLoop {
             options,
-            ws_reader,
-            ws_writer,
+            transport,
             inbound_tx,
             inbound_rx,
             outbound_tx,
             outbound_rx,
             server,
             peers,
+            metadata,
+            metrics,
+            failover: None,
+            handshakes: Default::default(),
+            events,
+            state,
         };
 
         Ok((client, event_loop))
     }
 
     client_impl!();
-
-    /*
-        self.ws_writer.ws.set_onopen(None);
-        self.ws_writer.ws.set_onmessage(None);
-        self.ws_writer.ws.set_onerror(None);
-
-        self.ws_writer.ws.close()?;
-    */
 }
 
 client_transport_impl!(WebClient);
 
-impl Drop for WebClient {
-    fn drop(&mut self) {
-        //log::info!("drop called for {:p}", self);
-        unsafe {
-            std::ptr::drop_in_place(self.ptr);
+/// Reconnect when the page becomes visible or the browser
+/// comes back online.
+///
+/// The listeners live as long as the page but only hold a
+/// weak reference to the event loop.
+fn watch_page(
+    outbound: mpsc::WeakSender<InternalMessage>,
+) -> Result<()> {
+    let global = js_sys::global();
+
+    let sender = outbound.clone();
+    let online = Closure::<dyn FnMut()>::new(move || {
+        // Sockets opened before the network changed are
+        // unlikely to recover
+        if let Some(tx) = sender.upgrade() {
+            let _ = tx
+                .try_send(InternalMessage::Reconnect { force: true });
         }
+    });
+    global
+        .unchecked_ref::<EventTarget>()
+        .add_event_listener_with_callback(
+            "online",
+            online.as_ref().unchecked_ref(),
+        )?;
+    online.forget();
+
+    let document = global
+        .dyn_ref::<web_sys::Window>()
+        .and_then(|window| window.document());
+    if let Some(document) = document {
+        let page = document.clone();
+        let visible = Closure::<dyn FnMut()>::new(move || {
+            if page.visibility_state() != VisibilityState::Visible {
+                return;
+            }
+            if let Some(tx) = outbound.upgrade() {
+                let _ = tx.try_send(InternalMessage::Reconnect {
+                    force: false,
+                });
+            }
+        });
+        document.add_event_listener_with_callback(
+            "visibilitychange",
+            visible.as_ref().unchecked_ref(),
+        )?;
+        visible.forget();
     }
+    Ok(())
 }
 
-unsafe impl Send for WebClient {}
-unsafe impl Sync for WebClient {}
-
-impl EventLoop<WsMessage, WsError, WsReadStream, WsWriteStream> {
-    /// Receive and decode socket messages then send to
-    /// the messages channel.
-    pub(crate) async fn read_message(
-        incoming: WsMessage,
-        event_proxy: &mut mpsc::Sender<ResponseMessage>,
-    ) -> Result<()> {
-        let inflated = zlib::inflate(&incoming)?;
-        let response: ResponseMessage = decode(&inflated).await?;
-        event_proxy.send(response).await?;
-        Ok(())
-    }
+/// Websocket connection to a relay server in the browser.
+pub struct WebSocketTransport {
+    ws: WebSocket,
+    reader: mpsc::Receiver<Result<Vec<u8>>>,
+    max_frame_size: usize,
+    _callbacks: Callbacks,
+}
 
-    /// Send a message to the socket and flush the stream.
-    pub(crate) async fn send_message(
-        &mut self,
-        message: RequestMessage,
-    ) -> Result<()> {
-        let encoded = encode(&message).await?;
-        let deflated = zlib::deflate(&encoded)?;
-        self.ws_writer
-            .send(deflated)
-            .await
-            .map_err(|_| Error::WebSocketSend)?;
-        Ok(self
-            .ws_writer
-            .flush()
-            .await
-            .map_err(|_| Error::WebSocketSend)?)
+/// Event listeners for a websocket.
+///
+/// Listeners are freed with the transport so they are
+/// removed from the socket first.
+struct Callbacks {
+    _message: Closure<dyn FnMut(MessageEvent)>,
+    _error: Closure<dyn FnMut(ErrorEvent)>,
+    _open: Closure<dyn FnMut()>,
+    _close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+impl WebSocketTransport {
+    /// Open a connection to a server.
+    ///
+    /// Messages from the socket event listeners are proxied
+    /// to the event loop; once the socket is closed the
+    /// transport stops receiving.
+    pub fn connect(
+        server: &str,
+        options: &ClientOptions,
+    ) -> impl Future<Output = Result<Self>> + Send {
+        LocalFuture(Box::pin(Self::open(
+            server.to_string(),
+            options.inbound_capacity,
+            options.max_frame_size,
+        )))
     }
 
-    async fn handle_close_message(self) -> Result<()> {
-        // Remove event listener closures
-        self.ws_writer.ws.set_onopen(None);
-        self.ws_writer.ws.set_onmessage(None);
-        self.ws_writer.ws.set_onerror(None);
+    async fn open(
+        server: String,
+        capacity: usize,
+        max_frame_size: usize,
+    ) -> Result<Self> {
+        let ws = WebSocket::new(&server)?;
+        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
 
-        // Close the socket connection
-        self.ws_writer.ws.close()?;
+        let (msg_tx, reader) = mpsc::channel(capacity);
+        let (open_tx, mut open_rx) = mpsc::channel(1);
 
-        Ok(())
+        let sender = msg_tx.clone();
+        let message = Closure::<dyn FnMut(_)>::new(
+            move |e: MessageEvent| {
+                if let Ok(buf) =
+                    e.data().dyn_into::<js_sys::ArrayBuffer>()
+                {
+                    let buffer =
+                        js_sys::Uint8Array::new(&buf).to_vec();
+                    let sender = sender.clone();
+                    spawn_local(async move {
+                        if sender.send(Ok(buffer)).await.is_err() {
+                            log::error!(
+                                "message dropped, event loop closed"
+                            );
+                        }
+                    });
+                } else {
+                    log::warn!(
+                        "unknown message event: {:?}",
+                        e.data()
+                    );
+                }
+            },
+        );
+        ws.set_onmessage(Some(message.as_ref().unchecked_ref()));
+
+        let error =
+            Closure::<dyn FnMut(_)>::new(move |e: ErrorEvent| {
+                log::error!("error event: {:?}", e.as_string());
+            });
+        ws.set_onerror(Some(error.as_ref().unchecked_ref()));
+
+        let opened = open_tx.clone();
+        let open = Closure::<dyn FnMut()>::new(move || {
+            let _ = opened.try_send(Ok(()));
+        });
+        ws.set_onopen(Some(open.as_ref().unchecked_ref()));
+
+        let close =
+            Closure::<dyn FnMut(_)>::new(move |_: CloseEvent| {
+                let _ = open_tx.try_send(Err(Error::Disconnected));
+                // Queued after messages already received
+                let sender = msg_tx.clone();
+                spawn_local(async move {
+                    let _ =
+                        sender.send(Err(Error::Disconnected)).await;
+                });
+            });
+        ws.set_onclose(Some(close.as_ref().unchecked_ref()));
+
+        let transport = Self {
+            ws,
+            reader,
+            max_frame_size,
+            _callbacks: Callbacks {
+                _message: message,
+                _error: error,
+                _open: open,
+                _close: close,
+            },
+        };
+
+        match open_rx.recv().await {
+            Some(Ok(_)) => Ok(transport),
+            Some(Err(e)) => Err(e),
+            None => Err(Error::Disconnected),
+        }
     }
 
-    event_loop_run_impl!();
+    /// Remove the event listeners from the socket.
+    fn detach(&self) {
+        self.ws.set_onopen(None);
+        self.ws.set_onmessage(None);
+        self.ws.set_onerror(None);
+        self.ws.set_onclose(None);
+    }
 }
 
-use core::task::{Context, Poll};
-
-#[doc(hidden)]
-pub struct WebSocketSink {
-    ws: WebSocket,
+impl Drop for WebSocketTransport {
+    fn drop(&mut self) {
+        self.detach();
+        let _ = self.ws.close();
+    }
 }
 
-impl Sink<Vec<u8>> for WebSocketSink {
-    type Error = Error;
+#[async_trait]
+impl RelayTransport for WebSocketTransport {
+    async fn send(&mut self, message: Vec<u8>) -> Result<()> {
+        self.ws.send_with_u8_array(&message)?;
+        Ok(())
+    }
 
-    fn poll_ready(
-        self: Pin<&mut Self>,
-        _: &mut Context<'_>,
-    ) -> Poll<Result<()>> {
-        Poll::Ready(Ok(()))
+    async fn receive(&mut self) -> Option<Result<Frame>> {
+        let max_frame_size = self.max_frame_size;
+        self.reader.recv().await.and_then(|message| {
+            let buffer = match message {
+                Ok(buffer) => buffer,
+                // Socket was closed
+                Err(Error::Disconnected) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            if buffer.len() > max_frame_size {
+                return Some(Err(Error::FrameTooLarge(buffer.len())));
+            }
+            Some(Ok(Frame::Message(buffer)))
+        })
     }
 
-    fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> Result<()> {
-        unsafe { self.get_unchecked_mut() }
-            .ws
-            .send_with_u8_array(&item)?;
+    /// Browsers do not expose websocket pings so
+    /// keep-alive is disabled for the web client.
+    async fn ping(&mut self) -> Result<()> {
         Ok(())
     }
 
-    fn poll_flush(
-        self: Pin<&mut Self>,
-        _: &mut Context<'_>,
-    ) -> Poll<Result<()>> {
-        Poll::Ready(Ok(()))
+    fn is_open(&self) -> bool {
+        self.ws.ready_state() == WebSocket::OPEN
     }
 
-    fn poll_close(
-        self: Pin<&mut Self>,
-        _: &mut Context<'_>,
-    ) -> Poll<Result<()>> {
-        Poll::Ready(Ok(()))
+    async fn close(&mut self) -> Result<()> {
+        self.detach();
+        self.ws.close()?;
+        Ok(())
     }
 }
 
@@ -300,4 +423,23 @@ impl Sink<Vec<u8>> for WebSocketSink {
 // We know that the webassembly client should only
 // ever run in a single threaded context so we can
 // implement `Send` to appease the compiler.
-unsafe impl Send for WebSocketSink {}
+unsafe impl Send for WebSocketTransport {}
+
+/// Future that is only polled on the single thread of
+/// the browser.
+struct LocalFuture<F>(Pin<Box<F>>);
+
+impl<F: Future> Future for LocalFuture<F> {
+    type Output = F::Output;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+// Futures that hold javascript values are not `Send`;
+// see the note for the transport above.
+unsafe impl<F> Send for LocalFuture<F> {}
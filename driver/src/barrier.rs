@@ -0,0 +1,332 @@
+//! Synchronization barrier for session participants.
+use async_trait::async_trait;
+use mpc_client::{Event, NetworkTransport, Transport};
+use mpc_protocol::{hex, SessionState};
+use serde::de::IgnoredAny;
+use std::collections::HashSet;
+
+use crate::{
+    bridge::delivered, message::BridgeMessage, Driver, Error,
+    Progress, Result,
+};
+
+/// Waits until all session participants have signaled
+/// readiness for the next phase of a flow.
+///
+/// Run a barrier using `wait_for_driver()`; each participant
+/// must use the same barrier name. The output is the list
+/// of events that were received whilst waiting for the barrier
+/// but were not barrier signals, for example round messages from
+/// participants that have already passed the barrier. Callers
+/// should pass these events to the driver for the next phase.
+///
+/// Barrier signals received whilst a protocol was running are
+/// yielded by the protocol driver as deferred events which
+/// should be given to the barrier using `with_events()`.
+///
+/// Observers are not assigned a party number so the barrier
+/// does not wait for them to signal.
+pub struct Barrier {
+    transport: Transport,
+    signals: Signals,
+}
+
+impl Barrier {
+    /// Create a new barrier.
+    pub fn new(
+        transport: Transport,
+        session: SessionState,
+        name: impl Into<String>,
+    ) -> Self {
+        let signals =
+            Signals::new(session, transport.public_key(), name);
+        Self { transport, signals }
+    }
+
+    /// Events deferred by the driver for the previous phase.
+    pub fn with_events(
+        mut self,
+        events: impl IntoIterator<Item = Event>,
+    ) -> Self {
+        for event in events {
+            self.signals.accept(event);
+        }
+        self
+    }
+}
+
+/// Barrier signals received from the session participants.
+struct Signals {
+    session: SessionState,
+    name: String,
+    /// Parties that must signal in party number order.
+    expected: Vec<Vec<u8>>,
+    signaled: HashSet<Vec<u8>>,
+    deferred: Vec<Event>,
+}
+
+impl Signals {
+    fn new(
+        session: SessionState,
+        own_key: &[u8],
+        name: impl Into<String>,
+    ) -> Self {
+        let expected = session
+            .parties()
+            .into_iter()
+            .filter(|k| *k != own_key)
+            .map(|k| k.to_vec())
+            .collect();
+        Self {
+            session,
+            name: name.into(),
+            expected,
+            signaled: HashSet::new(),
+            deferred: Vec::new(),
+        }
+    }
+
+    /// Parties that have not signaled yet.
+    fn waiting_on(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.expected.iter().filter(|k| !self.signaled.contains(*k))
+    }
+
+    fn is_complete(&self) -> bool {
+        self.waiting_on().next().is_none()
+    }
+
+    /// Record a barrier signal or defer the event.
+    fn accept(&mut self, event: Event) {
+        let signal = if let Event::JsonMessage {
+            peer_key,
+            message,
+            session_id: Some(session_id),
+        } = &event
+        {
+            if session_id == &self.session.session_id {
+                if !self.session.is_participant(peer_key) {
+                    tracing::warn!(
+                        from = ?hex::encode(peer_key),
+                        "drop message from non-participant",
+                    );
+                    return;
+                }
+
+                match message
+                    .deserialize::<BridgeMessage<IgnoredAny>>()
+                {
+                    Ok(BridgeMessage::Barrier(name))
+                        if name == self.name =>
+                    {
+                        Some(peer_key.clone())
+                    }
+                    _ => None,
+                }
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        if let Some(peer_key) = signal {
+            self.signaled.insert(peer_key);
+        } else {
+            self.deferred.push(event);
+        }
+    }
+}
+
+#[async_trait]
+impl Driver for Barrier {
+    type Error = Error;
+    type Output = Vec<Event>;
+
+    async fn handle_event(
+        &mut self,
+        event: Event,
+    ) -> Result<Option<Self::Output>> {
+        if let Event::SessionExpired(session_id) = &event {
+            if session_id == &self.signals.session.session_id {
+                return Err(Error::SessionExpired(*session_id));
+            }
+        }
+
+        if let Event::ConnectionLost | Event::Disconnected { .. } =
+            &event
+        {
+            return Err(Error::ConnectionLost);
+        }
+
+        self.signals.accept(event);
+        Ok(self.take_output())
+    }
+
+    async fn execute(&mut self) -> Result<()> {
+        let recipients = self
+            .signals
+            .session
+            .recipients(self.transport.public_key());
+        let message: BridgeMessage<()> =
+            BridgeMessage::Barrier(self.signals.name.clone());
+        self.transport
+            .broadcast_json(
+                &self.signals.session.session_id,
+                recipients.as_slice(),
+                &message,
+            )
+            .await
+            .and_then(delivered)?;
+        Ok(())
+    }
+
+    fn take_output(&mut self) -> Option<Self::Output> {
+        if self.signals.is_complete() {
+            Some(std::mem::take(&mut self.signals.deferred))
+        } else {
+            None
+        }
+    }
+
+    fn progress(&self) -> Progress {
+        let signals = &self.signals;
+        let waiting_on = signals
+            .waiting_on()
+            .filter_map(|k| signals.session.party_number(k))
+            .collect();
+        Progress {
+            round: None,
+            waiting_on,
+        }
+    }
+}
+
+impl From<Barrier> for Transport {
+    fn from(value: Barrier) -> Self {
+        value.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Signals;
+    use crate::message::BridgeMessage;
+    use mpc_client::Event;
+    use mpc_protocol::{
+        Encoding, SessionId, SessionRole, SessionState,
+    };
+
+    fn session() -> SessionState {
+        SessionState {
+            session_id: SessionId::new_v4(),
+            all_participants: vec![vec![1], vec![2], vec![3]],
+            ..Default::default()
+        }
+    }
+
+    fn signal(
+        session_id: SessionId,
+        peer_key: Vec<u8>,
+        name: &str,
+    ) -> Event {
+        let message: BridgeMessage<()> =
+            BridgeMessage::Barrier(name.to_owned());
+        Event::message(
+            peer_key,
+            Encoding::Json,
+            serde_json::to_vec(&message).unwrap(),
+            Some(session_id),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn barrier_complete() {
+        let session = session();
+        let session_id = session.session_id;
+        let mut signals = Signals::new(session, &[1], "keygen");
+
+        signals.accept(signal(session_id, vec![2], "keygen"));
+        assert!(!signals.is_complete());
+
+        // Signal repeated by the same participant
+        signals.accept(signal(session_id, vec![2], "keygen"));
+        assert!(!signals.is_complete());
+
+        signals.accept(signal(session_id, vec![3], "keygen"));
+        assert!(signals.is_complete());
+        assert!(signals.deferred.is_empty());
+    }
+
+    #[test]
+    fn barrier_ignores_observers() {
+        let session = SessionState {
+            session_id: SessionId::new_v4(),
+            all_participants: vec![vec![1], vec![2], vec![3]],
+            roles: vec![
+                SessionRole::Signer,
+                SessionRole::Observer,
+                SessionRole::Signer,
+            ],
+            ..Default::default()
+        };
+        let session_id = session.session_id;
+        let mut signals = Signals::new(session, &[1], "keygen");
+        let waiting_on: Vec<_> = signals.waiting_on().collect();
+        assert_eq!(vec![[3u8].as_slice()], waiting_on);
+
+        signals.accept(signal(session_id, vec![3], "keygen"));
+        assert!(signals.is_complete());
+        assert_eq!(0, signals.waiting_on().count());
+    }
+
+    #[test]
+    fn barrier_empty_session() {
+        let signals =
+            Signals::new(SessionState::default(), &[1], "keygen");
+        assert!(signals.is_complete());
+    }
+
+    #[test]
+    fn barrier_defers_other_signals() {
+        let session = session();
+        let session_id = session.session_id;
+        let mut signals = Signals::new(session, &[1], "keygen");
+
+        // Different barrier name
+        signals.accept(signal(session_id, vec![2], "sign"));
+        // Different session
+        signals.accept(signal(
+            SessionId::new_v4(),
+            vec![3],
+            "keygen",
+        ));
+        // Message without a session identifier
+        signals.accept(
+            Event::message(
+                vec![3],
+                Encoding::Json,
+                b"null".to_vec(),
+                None,
+            )
+            .unwrap(),
+        );
+
+        assert!(signals.signaled.is_empty());
+        assert_eq!(3, signals.deferred.len());
+        assert!(!signals.is_complete());
+    }
+
+    #[test]
+    fn barrier_drops_non_participant() {
+        let session = session();
+        let session_id = session.session_id;
+        let mut signals = Signals::new(session, &[1], "keygen");
+
+        signals.accept(signal(session_id, vec![4], "keygen"));
+        signals.accept(signal(session_id, vec![5], "keygen"));
+        assert!(signals.signaled.is_empty());
+        assert!(signals.deferred.is_empty());
+        assert!(!signals.is_complete());
+    }
+}
@@ -0,0 +1,207 @@
+//! Type erased protocol drivers.
+use async_trait::async_trait;
+use mpc_client::{Event, Transport};
+use serde::Serialize;
+use serde_json::Value;
+use web_time::Instant;
+
+use crate::{Driver, DriverEvent, Error, Progress, Result};
+
+/// Driver with the output erased to a JSON value.
+///
+/// Allows drivers for different protocols to be held in
+/// a single collection and driven uniformly.
+pub struct BoxedDriver {
+    inner: Box<dyn ErasedDriver>,
+}
+
+impl BoxedDriver {
+    /// Create a boxed driver.
+    pub fn new<D>(driver: D) -> Self
+    where
+        D: Driver + Into<Transport> + Send + 'static,
+        D::Output: Serialize,
+        Error: From<D::Error>,
+    {
+        Self {
+            inner: Box::new(Erased(driver)),
+        }
+    }
+}
+
+#[async_trait]
+impl Driver for BoxedDriver {
+    type Error = Error;
+    type Output = Value;
+
+    async fn handle_event(
+        &mut self,
+        event: Event,
+    ) -> Result<Option<Self::Output>> {
+        self.inner.handle_event(event).await
+    }
+
+    async fn execute(&mut self) -> Result<()> {
+        self.inner.execute().await
+    }
+
+    fn progress(&self) -> Progress {
+        self.inner.progress()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline()
+    }
+
+    fn watchdog(&self) -> Option<Instant> {
+        self.inner.watchdog()
+    }
+
+    fn heartbeat(&self) -> Option<Instant> {
+        self.inner.heartbeat()
+    }
+
+    async fn send_heartbeat(&mut self) -> Result<()> {
+        self.inner.send_heartbeat().await
+    }
+
+    fn take_events(
+        &mut self,
+    ) -> Vec<DriverEvent<Self::Output, Self::Error>> {
+        self.inner.take_events()
+    }
+
+    fn take_output(&mut self) -> Option<Self::Output> {
+        self.inner.take_output()
+    }
+}
+
+impl From<BoxedDriver> for Transport {
+    fn from(value: BoxedDriver) -> Self {
+        value.inner.into_transport()
+    }
+}
+
+/// Object safe view of a driver.
+#[async_trait]
+trait ErasedDriver: Send {
+    async fn handle_event(
+        &mut self,
+        event: Event,
+    ) -> Result<Option<Value>>;
+    async fn execute(&mut self) -> Result<()>;
+    fn progress(&self) -> Progress;
+    fn deadline(&self) -> Option<Instant>;
+    fn watchdog(&self) -> Option<Instant>;
+    fn heartbeat(&self) -> Option<Instant>;
+    async fn send_heartbeat(&mut self) -> Result<()>;
+    fn take_events(&mut self) -> Vec<DriverEvent<Value, Error>>;
+    fn take_output(&mut self) -> Option<Value>;
+    fn into_transport(self: Box<Self>) -> Transport;
+}
+
+struct Erased<D>(D);
+
+#[async_trait]
+impl<D> ErasedDriver for Erased<D>
+where
+    D: Driver + Into<Transport> + Send + 'static,
+    D::Output: Serialize,
+    Error: From<D::Error>,
+{
+    async fn handle_event(
+        &mut self,
+        event: Event,
+    ) -> Result<Option<Value>> {
+        match self.0.handle_event(event).await? {
+            Some(output) => Ok(Some(serde_json::to_value(output)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn execute(&mut self) -> Result<()> {
+        Ok(self.0.execute().await?)
+    }
+
+    fn progress(&self) -> Progress {
+        self.0.progress()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.0.deadline()
+    }
+
+    fn watchdog(&self) -> Option<Instant> {
+        self.0.watchdog()
+    }
+
+    fn heartbeat(&self) -> Option<Instant> {
+        self.0.heartbeat()
+    }
+
+    async fn send_heartbeat(&mut self) -> Result<()> {
+        Ok(self.0.send_heartbeat().await?)
+    }
+
+    fn take_events(&mut self) -> Vec<DriverEvent<Value, Error>> {
+        self.0
+            .take_events()
+            .into_iter()
+            .map(|event| match event {
+                DriverEvent::RoundStarted(round) => {
+                    DriverEvent::RoundStarted(round)
+                }
+                DriverEvent::MessageReceived { round, party } => {
+                    DriverEvent::MessageReceived { round, party }
+                }
+                DriverEvent::RoundCompleted(round) => {
+                    DriverEvent::RoundCompleted(round)
+                }
+                DriverEvent::Stalled { round, waiting_on } => {
+                    DriverEvent::Stalled { round, waiting_on }
+                }
+                DriverEvent::MessageDropped { peer_key } => {
+                    DriverEvent::MessageDropped { peer_key }
+                }
+                DriverEvent::PeerLeft(party) => {
+                    DriverEvent::PeerLeft(party)
+                }
+                DriverEvent::PeerUnresponsive(party) => {
+                    DriverEvent::PeerUnresponsive(party)
+                }
+                DriverEvent::Intermediate(output) => {
+                    DriverEvent::Intermediate(output)
+                }
+                DriverEvent::Deferred(event) => {
+                    DriverEvent::Deferred(event)
+                }
+                DriverEvent::Completed { transport, output } => {
+                    match serde_json::to_value(output) {
+                        Ok(output) => DriverEvent::Completed {
+                            transport,
+                            output,
+                        },
+                        Err(e) => DriverEvent::Failed(e.into()),
+                    }
+                }
+                DriverEvent::Failed(e) => {
+                    DriverEvent::Failed(e.into())
+                }
+            })
+            .collect()
+    }
+
+    fn take_output(&mut self) -> Option<Value> {
+        match serde_json::to_value(self.0.take_output()?) {
+            Ok(output) => Some(output),
+            Err(e) => {
+                tracing::error!(error = %e, "serialize output");
+                None
+            }
+        }
+    }
+
+    fn into_transport(self: Box<Self>) -> Transport {
+        self.0.into()
+    }
+}
@@ -1,8 +1,73 @@
 use futures::{select, FutureExt, StreamExt};
-use mpc_client::{Event, EventStream, NetworkTransport, Transport};
-use mpc_protocol::{SessionId, SessionState};
+use mpc_client::{
+    Deliveries, Event, EventStream, NetworkTransport, Transport,
+};
+use mpc_protocol::{
+    hex, PartyNumber, RoundNumber, SessionId, SessionState,
+};
+use serde_json::value::RawValue;
+use std::collections::{HashMap, HashSet};
+use web_time::Instant;
 
-use crate::{Driver, Error, ProtocolDriver, Round, RoundBuffer};
+use crate::{
+    chunk::ChunkBuffer,
+    echo::{Echo, EchoBroadcast},
+    identity::Signed,
+    message::BridgeMessage,
+    padding,
+    round::Sequence,
+    timer,
+    transcript::{self, Transcript},
+    Decision, Driver, DriverEvent, DriverMetrics, DriverOptions,
+    Error, ErrorContext, Progress, ProtocolDriver, Round,
+    RoundBuffer, RoundEncoding, RoundMessage, WaitOptions,
+};
+
+/// Maximum number of progress events kept until they
+/// are taken.
+const MAX_EVENTS: usize = 1024;
+
+/// Round message retained for retransmission.
+#[derive(Debug)]
+struct Sent {
+    /// Round number.
+    round: RoundNumber,
+    /// Public key of the receiver for peer to peer messages.
+    receiver: Option<Vec<u8>>,
+    /// Encoded bridge message.
+    payload: Vec<u8>,
+}
+
+/// Round messages retained for retransmission.
+#[derive(Debug, Default)]
+struct SentMessages(Vec<Sent>);
+
+impl SentMessages {
+    /// Retain messages after they were sent.
+    fn extend(&mut self, messages: Vec<Sent>) {
+        self.0.extend(messages);
+    }
+
+    /// Messages for a peer starting at a round.
+    fn for_peer<'a>(
+        &'a self,
+        peer_key: &'a [u8],
+        round: RoundNumber,
+    ) -> impl Iterator<Item = &'a Sent> + 'a {
+        self.0.iter().filter(move |m| {
+            m.round >= round
+                && m.receiver
+                    .as_ref()
+                    .map(|r| r.as_slice() == peer_key)
+                    .unwrap_or(true)
+        })
+    }
+
+    /// Discard the messages for rounds before a round.
+    fn prune(&mut self, round: RoundNumber) {
+        self.0.retain(|m| m.round >= round);
+    }
+}
 
 /// Connects a network transport with a protocol driver.
 pub(crate) struct Bridge<D: ProtocolDriver> {
@@ -10,123 +75,1366 @@ pub(crate) struct Bridge<D: ProtocolDriver> {
     pub(crate) buffer: RoundBuffer<D::Incoming>,
     pub(crate) driver: Option<D>,
     pub(crate) session: SessionState,
+    pub(crate) options: DriverOptions,
+    transcript: Transcript,
+    echo: EchoBroadcast,
+    chunks: ChunkBuffer,
+    sequence: Sequence,
+    /// Round messages we sent for retransmission on request.
+    ///
+    /// Only the messages for the last completed round and
+    /// the current round are kept.
+    sent: SentMessages,
+    pub(crate) metrics: DriverMetrics,
+    /// Events recorded until they are taken; when the
+    /// events are not taken the oldest progress events are
+    /// dropped.
+    pub(crate) events: Vec<DriverEvent<D::Output, D::Error>>,
+    /// Time by which the protocol must complete.
+    pub(crate) deadline: Option<Instant>,
+    /// Time the last protocol message was received.
+    last_activity: Option<Instant>,
+    /// Time the last session heartbeat was sent.
+    last_heartbeat: Option<Instant>,
+    /// Round we are waiting to complete.
+    round: Option<RoundNumber>,
+    /// Public keys of the peers that sent messages for each round.
+    received: HashMap<RoundNumber, HashSet<Vec<u8>>>,
+    aborted: bool,
+    /// Whether the last error was a local protocol failure
+    /// that must be reported to the session.
+    failed: bool,
 }
 
 impl<D: ProtocolDriver> Bridge<D> {
+    /// Create a new bridge.
+    pub fn new(
+        transport: Transport,
+        driver: D,
+        buffer: RoundBuffer<D::Incoming>,
+        session: SessionState,
+    ) -> Self {
+        let transcript = Transcript::new(&session.session_id);
+        Self {
+            transport,
+            buffer,
+            driver: Some(driver),
+            session,
+            options: Default::default(),
+            transcript,
+            echo: Default::default(),
+            chunks: Default::default(),
+            sequence: Default::default(),
+            sent: Default::default(),
+            metrics: Default::default(),
+            events: Vec::new(),
+            deadline: None,
+            last_activity: None,
+            last_heartbeat: None,
+            round: None,
+            received: HashMap::new(),
+            aborted: false,
+            failed: false,
+        }
+    }
+
     /// Handle event from the client event loop stream.
+    ///
+    /// If the protocol fails locally the other session
+    /// participants are notified that the protocol was aborted.
     pub async fn handle_event(
         &mut self,
         event: Event,
     ) -> Result<Option<D::Output>, D::Error> {
-        if let Event::JsonMessage {
-            message,
-            session_id,
-            ..
-        } = event
-        {
-            if let Some(session_id) = &session_id {
-                if session_id != &self.session.session_id {
-                    return Err(
-                        Box::new(Error::SessionIdMismatch).into()
+        match self.process_event(event).await {
+            Err(e) => {
+                if std::mem::take(&mut self.failed) {
+                    self.abort(&e).await;
+                }
+                Err(e)
+            }
+            result => result,
+        }
+    }
+
+    async fn process_event(
+        &mut self,
+        event: Event,
+    ) -> Result<Option<D::Output>, D::Error> {
+        // Retained so barrier signals can be deferred
+        let mut json = None;
+        let (peer_key, session_id, bytes, encoding) = match event {
+            Event::JsonMessage {
+                peer_key,
+                message,
+                session_id,
+            } => {
+                let bytes = message.as_bytes().to_vec();
+                json = Some(message);
+                (peer_key, session_id, bytes, RoundEncoding::Json)
+            }
+            Event::BinaryMessage {
+                peer_key,
+                message,
+                session_id,
+            } => {
+                // Round messages are only sent as binary
+                // messages for binary encodings or padding
+                if !self.options.encoding.is_binary()
+                    && self.options.padding.is_none()
+                {
+                    return self.drop_message(
+                        &peer_key,
+                        &"binary message with JSON encoding",
                     );
                 }
-            } else {
-                return Err(Box::new(Error::SessionIdRequired).into());
+                let message = if self.options.padding.is_some() {
+                    match padding::unpad(message) {
+                        Ok(message) => message,
+                        Err(e) => {
+                            return self.drop_message(&peer_key, &e)
+                        }
+                    }
+                } else {
+                    message
+                };
+                (peer_key, session_id, message, self.options.encoding)
+            }
+            Event::PeerConnected { peer_key } => {
+                return self.handle_peer_connected(&peer_key).await;
+            }
+            Event::SessionExpired(session_id)
+                if session_id == self.session.session_id =>
+            {
+                return Err(Box::new(Error::SessionExpired(
+                    session_id,
+                ))
+                .into());
+            }
+            Event::SessionPeerLeft {
+                session_id,
+                peer_key,
+            } if session_id == self.session.session_id => {
+                return self.handle_peer_left(&peer_key);
             }
+            Event::ConnectionLost | Event::Disconnected { .. } => {
+                return Err(Box::new(Error::ConnectionLost).into());
+            }
+            Event::SessionPeerTimeout {
+                session_id,
+                peer_key,
+            } if session_id == self.session.session_id => {
+                if let Some(party) =
+                    self.session.party_number(&peer_key)
+                {
+                    tracing::warn!(
+                        party = %party,
+                        "participant is unresponsive",
+                    );
+                    self.record(DriverEvent::PeerUnresponsive(party));
+                }
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        };
 
-            let message: D::Outgoing = message.deserialize()?;
-            let round_number = message.round_number();
-            let incoming: D::Incoming = message.into();
-            self.buffer.add_message(round_number, incoming);
+        if let Some(session_id) = &session_id {
+            if session_id != &self.session.session_id {
+                return Err(Box::new(Error::SessionIdMismatch).into());
+            }
+        } else {
+            return Err(Box::new(Error::SessionIdRequired).into());
+        }
+
+        if !self.session.is_participant(&peer_key) {
+            tracing::warn!(
+                from = ?hex::encode(&peer_key),
+                "drop message from non-participant",
+            );
+            self.record(DriverEvent::MessageDropped { peer_key });
+            return Ok(None);
+        }
 
-            if self.buffer.is_ready(round_number) {
-                let messages = self.buffer.take(round_number);
-                for message in messages {
-                    self.driver
-                        .as_mut()
-                        .unwrap()
-                        .handle_incoming(message)?;
+        let message: BridgeMessage<D::Outgoing> =
+            match encoding.decode(&bytes) {
+                Ok(message) => message,
+                Err(e) => return self.drop_message(&peer_key, &e),
+            };
+        match message {
+            BridgeMessage::Round(message) => {
+                return self
+                    .handle_round_message(
+                        &peer_key, &bytes, message, None,
+                    )
+                    .await;
+            }
+            BridgeMessage::Signed(signed) => {
+                return self
+                    .handle_signed(&peer_key, signed, encoding)
+                    .await;
+            }
+            BridgeMessage::Abort(reason) => {
+                let party = match self.session.party_number(&peer_key)
+                {
+                    Some(party) => party,
+                    None => {
+                        return self
+                            .drop_message(&peer_key, &"not a signer")
+                    }
+                };
+                // Do not echo the abort back to the session
+                self.aborted = true;
+                return Err(Box::new(Error::RemoteAbort {
+                    party: party.get(),
+                    reason,
+                })
+                .into());
+            }
+            BridgeMessage::Echo(echo) => {
+                return self.handle_echo(&peer_key, echo).await;
+            }
+            BridgeMessage::Chunk(chunk) => {
+                let size = match self.options.max_message_size {
+                    Some(size) => size,
+                    None => {
+                        return self.drop_message(
+                            &peer_key,
+                            &"chunked messages are disabled",
+                        )
+                    }
+                };
+                let bytes =
+                    match self.chunks.receive(&peer_key, chunk, size)
+                    {
+                        Ok(bytes) => bytes,
+                        Err(e) => {
+                            return self.drop_message(&peer_key, &e)
+                        }
+                    };
+                if let Some(bytes) = bytes {
+                    let message: BridgeMessage<D::Outgoing> =
+                        match encoding.decode(&bytes) {
+                            Ok(message) => message,
+                            Err(e) => {
+                                return self
+                                    .drop_message(&peer_key, &e)
+                            }
+                        };
+                    return match message {
+                        BridgeMessage::Round(message) => {
+                            self.handle_round_message(
+                                &peer_key, &bytes, message, None,
+                            )
+                            .await
+                        }
+                        BridgeMessage::Signed(signed) => {
+                            self.handle_signed(
+                                &peer_key, signed, encoding,
+                            )
+                            .await
+                        }
+                        _ => self.drop_message(
+                            &peer_key,
+                            &Error::InvalidChunk(
+                                "chunks must contain a round message"
+                                    .to_string(),
+                            ),
+                        ),
+                    };
+                }
+            }
+            BridgeMessage::Retransmit(round) => {
+                if self.session.party_number(&peer_key).is_none() {
+                    return self
+                        .drop_message(&peer_key, &"not a signer");
+                }
+                self.retransmit(&peer_key, round).await?;
+            }
+            BridgeMessage::Barrier(_) => {
+                // Participant completed the protocol and is
+                // waiting for the next barrier
+                tracing::debug!(
+                    from = ?hex::encode(&peer_key),
+                    "defer barrier signal",
+                );
+                if let Some(message) = json {
+                    self.record(DriverEvent::Deferred(
+                        Event::JsonMessage {
+                            peer_key,
+                            message,
+                            session_id,
+                        },
+                    ));
                 }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Request retransmission of the messages for the current
+    /// round when the channel to a participant is re-established.
+    async fn handle_peer_connected(
+        &mut self,
+        peer_key: &[u8],
+    ) -> Result<Option<D::Output>, D::Error> {
+        if self.session.party_number(peer_key).is_none() {
+            return Ok(None);
+        }
+        let round = match self.round {
+            Some(round) => round,
+            None => return Ok(None),
+        };
+        tracing::debug!(
+            peer = ?hex::encode(peer_key),
+            round = round.get(),
+            "request retransmission",
+        );
+        let message: BridgeMessage<()> =
+            BridgeMessage::Retransmit(round);
+        let payload = self
+            .options
+            .encoding
+            .encode(&message)
+            .map_err(Box::new)?;
+        self.send_encoded(&[peer_key.to_vec()], true, payload)
+            .await?;
+        Ok(None)
+    }
+
+    fn handle_peer_left(
+        &mut self,
+        peer_key: &[u8],
+    ) -> Result<Option<D::Output>, D::Error> {
+        let party = match self.session.party_number(peer_key) {
+            Some(party) => party,
+            None => return Ok(None),
+        };
+        if self.options.abort_on_peer_left {
+            return Err(Box::new(Error::PeerLeft(party.get())).into());
+        }
+        self.record(DriverEvent::PeerLeft(party));
+        Ok(None)
+    }
+
+    /// Send the round messages for a peer starting at a round.
+    async fn retransmit(
+        &mut self,
+        peer_key: &[u8],
+        round: RoundNumber,
+    ) -> Result<(), D::Error> {
+        let sent = std::mem::take(&mut self.sent);
+        let mut result = Ok(());
+        for message in sent.for_peer(peer_key, round) {
+            result = self.send_sent(message, Some(peer_key)).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        self.sent = sent;
+        result
+    }
+
+    /// Verify a signed round message and handle the
+    /// round message it contains.
+    async fn handle_signed(
+        &mut self,
+        peer_key: &[u8],
+        signed: Signed,
+        encoding: RoundEncoding,
+    ) -> Result<Option<D::Output>, D::Error> {
+        let party = self
+            .session
+            .party_number(peer_key)
+            .map(|p| p.get())
+            .unwrap_or_default();
+        // All participants must agree on whether round
+        // messages are signed
+        let identity = match &self.options.identity {
+            Some(identity) => identity,
+            None => {
+                return Err(Box::new(Error::UnexpectedSignature(
+                    party,
+                ))
+                .into())
+            }
+        };
+        if identity
+            .verify(
+                &self.session.session_id,
+                peer_key,
+                &signed.payload,
+                &signed.signature,
+            )
+            .is_err()
+        {
+            return Err(
+                Box::new(Error::SignatureRejected(party)).into()
+            );
+        }
+
+        let message: BridgeMessage<D::Outgoing> =
+            match encoding.decode(&signed.payload) {
+                Ok(message) => message,
+                Err(e) => return self.drop_message(peer_key, &e),
+            };
+        if let BridgeMessage::Round(message) = message {
+            self.handle_round_message(
+                peer_key,
+                &signed.payload,
+                message,
+                Some(&signed.signature),
+            )
+            .await
+        } else {
+            tracing::debug!(
+                from = ?hex::encode(peer_key),
+                "ignore signed message that is not a round message",
+            );
+            Ok(None)
+        }
+    }
+
+    async fn handle_round_message(
+        &mut self,
+        peer_key: &[u8],
+        bytes: &[u8],
+        message: D::Outgoing,
+        signature: Option<&[u8]>,
+    ) -> Result<Option<D::Output>, D::Error> {
+        if self.session.party_number(peer_key).is_none() {
+            return self.drop_message(peer_key, &"not a signer");
+        }
+        self.validate_sender(peer_key, &message)?;
+        if self.options.identity.is_some() && signature.is_none() {
+            return Err(Box::new(Error::MissingSignature(
+                message.sender().get(),
+            ))
+            .into());
+        }
+        match self.buffer.check_sequence(
+            *message.sender(),
+            message.is_broadcast(),
+            message.sequence(),
+        ) {
+            Ok(true) => {}
+            Ok(false) => {
+                // Duplicate from a retransmission
+                tracing::debug!(
+                    round = message.round_number().get(),
+                    party = message.sender().get(),
+                    "ignore duplicate message",
+                );
+                return Ok(None);
+            }
+            Err(expected) => {
+                return Err(Box::new(Error::SequenceGap {
+                    party: message.sender().get(),
+                    expected,
+                    actual: message.sequence(),
+                })
+                .into());
+            }
+        }
+
+        let round_number = message.round_number();
+        let sender = *message.sender();
+        self.metrics.received(round_number, bytes.len());
+        self.record(DriverEvent::MessageReceived {
+            round: round_number,
+            party: sender,
+        });
 
-                // For single round drivers we mustn't call proceed again
-                if self.buffer.len() == 1 {
-                    let result =
-                        self.driver.take().unwrap().finish()?;
-                    return Ok(Some(result));
+        let view = RoundMessage {
+            round: round_number,
+            sender,
+            receiver: message.receiver().copied(),
+            payload: bytes,
+            signature,
+        };
+        for interceptor in &self.options.interceptors {
+            match interceptor.on_incoming(&view) {
+                Decision::Accept => {}
+                Decision::Drop => {
+                    tracing::debug!(
+                        round = round_number.get(),
+                        party = sender.get(),
+                        "drop intercepted message",
+                    );
+                    return Ok(None);
                 }
+                Decision::Reject(reason) => {
+                    return Err(Box::new(Error::MessageRejected {
+                        round: round_number.get(),
+                        party: sender.get(),
+                        reason,
+                    })
+                    .into());
+                }
+            }
+        }
+
+        let is_broadcast = message.is_broadcast();
+        self.buffer.accept_sequence(sender, is_broadcast);
+        self.last_activity = Some(Instant::now());
+        self.received
+            .entry(round_number)
+            .or_default()
+            .insert(peer_key.to_vec());
+
+        if is_broadcast {
+            self.transcript.record(round_number, sender, bytes);
+        }
+        self.transcript.claim(
+            round_number,
+            sender,
+            *message.transcript(),
+        );
+
+        let incoming: D::Incoming = message.into();
+        self.buffer.add_message(round_number, sender, incoming);
+
+        if self.options.echo_broadcast && is_broadcast {
+            let digest = transcript::hash(bytes);
+            self.echo.receive(round_number, sender, digest);
+            self.send_echo(
+                peer_key,
+                Echo {
+                    round: round_number,
+                    sender,
+                    digest,
+                },
+            )
+            .await?;
+        }
+
+        self.try_complete_round(round_number).await
+    }
 
-                let messages =
-                    self.driver.as_mut().unwrap().proceed()?;
-                self.dispatch_round_messages(messages).await?;
+    async fn handle_echo(
+        &mut self,
+        peer_key: &[u8],
+        echo: Echo,
+    ) -> Result<Option<D::Output>, D::Error> {
+        let party = match self.session.party_number(peer_key) {
+            Some(party) => party,
+            None => {
+                return self.drop_message(peer_key, &"not a signer")
+            }
+        };
+        let round_number = echo.round;
+        self.echo.echo(party, echo);
+        self.try_complete_round(round_number).await
+    }
+
+    /// Send an echo for a broadcast message to all parties
+    /// except the sender of the message.
+    ///
+    /// Observers do not take part in echo broadcast.
+    async fn send_echo(
+        &mut self,
+        peer_key: &[u8],
+        echo: Echo,
+    ) -> Result<(), D::Error> {
+        let own_key = self.transport.public_key();
+        let recipients: Vec<Vec<u8>> = self
+            .session
+            .parties()
+            .into_iter()
+            .filter(|k| *k != own_key && *k != peer_key)
+            .map(|k| k.to_vec())
+            .collect();
+        if recipients.is_empty() {
+            return Ok(());
+        }
+        let message: BridgeMessage<()> = BridgeMessage::Echo(echo);
+        self.transport
+            .broadcast_json(
+                &self.session.session_id,
+                recipients.as_slice(),
+                &message,
+            )
+            .await
+            .and_then(delivered)?;
+        Ok(())
+    }
 
-                if round_number.get() as usize == self.buffer.len() {
-                    let result =
-                        self.driver.take().unwrap().finish()?;
-                    return Ok(Some(result));
+    /// Complete a round if all messages (and echoes when enabled)
+    /// for the round have been received.
+    async fn try_complete_round(
+        &mut self,
+        round_number: RoundNumber,
+    ) -> Result<Option<D::Output>, D::Error> {
+        if self.options.echo_broadcast {
+            let expected_echoes =
+                self.session.parties().len().saturating_sub(2);
+            match self.echo.verify(round_number, expected_echoes) {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(mismatch) => {
+                    return Err(self.failure(Error::EchoMismatch {
+                        round: round_number.get(),
+                        sender: mismatch.sender.get(),
+                        peer: mismatch.peer.get(),
+                    }));
                 }
             }
         }
 
+        if self.buffer.is_ready(round_number) {
+            self.echo.clear(round_number);
+            return self.complete_round(round_number).await;
+        }
+
+        Ok(None)
+    }
+
+    /// Handle the messages for a round once all messages
+    /// for the round have been received.
+    async fn complete_round(
+        &mut self,
+        round_number: RoundNumber,
+    ) -> Result<Option<D::Output>, D::Error> {
+        if let Err(party) = self.transcript.verify(round_number) {
+            return Err(self.failure(Error::TranscriptMismatch {
+                round: round_number.get(),
+                party: party.get(),
+            }));
+        }
+        self.transcript.commit(round_number);
+
+        self.record(DriverEvent::RoundCompleted(round_number));
+        let metrics = self.metrics.complete(round_number);
+        for interceptor in &self.options.interceptors {
+            interceptor.on_round_complete(round_number, metrics);
+        }
+
+        self.received.remove(&round_number);
+        self.round = round_number.checked_add(1);
+        // Peers still waiting on this round may request our
+        // messages for it but earlier rounds are complete
+        // for every participant
+        self.sent.prune(round_number);
+        self.chunks.complete(round_number);
+
+        let messages = self.buffer.take(round_number);
+        for (sender, message) in messages {
+            self.driver
+                .as_mut()
+                .unwrap()
+                .handle_incoming(message)
+                .map_err(|e| {
+                    self.protocol_error(
+                        Some(round_number),
+                        Some(sender),
+                        e,
+                    )
+                })?;
+        }
+        self.take_intermediate();
+
+        // For single round drivers we mustn't call proceed again
+        if self.buffer.len() == 1 {
+            return self.finish(round_number).map(Some);
+        }
+
+        if self.driver.as_ref().unwrap().wants_to_proceed() {
+            let messages = self
+                .driver
+                .as_mut()
+                .unwrap()
+                .proceed()
+                .map_err(|e| {
+                    self.protocol_error(Some(round_number), None, e)
+                })?;
+            self.take_intermediate();
+            self.dispatch_round_messages(messages).await?;
+        }
+
+        if round_number.get() as usize == self.buffer.len() {
+            return self.finish(round_number).map(Some);
+        }
+
         Ok(None)
     }
 
+    /// Notify the session participants that the protocol
+    /// was aborted and close the session if we are the owner.
+    ///
+    /// Failures are logged as the original error is
+    /// returned to the caller.
+    async fn abort(&mut self, error: &D::Error) {
+        if self.aborted {
+            return;
+        }
+        self.aborted = true;
+
+        let session_id = self.session.session_id;
+        let own_key = self.transport.public_key().to_vec();
+        let recipients = self.session.recipients(&own_key);
+        let message: BridgeMessage<()> =
+            BridgeMessage::Abort(error.to_string());
+        if let Err(e) = self
+            .transport
+            .broadcast_json(
+                &session_id,
+                recipients.as_slice(),
+                &message,
+            )
+            .await
+        {
+            tracing::warn!(error = %e, "failed to notify abort");
+        }
+
+        let is_owner = self
+            .session
+            .all_participants
+            .first()
+            .map(|k| k == &own_key)
+            .unwrap_or(false);
+        if is_owner {
+            if let Err(e) =
+                self.transport.close_session(session_id).await
+            {
+                tracing::warn!(
+                    error = %e,
+                    "failed to close aborted session",
+                );
+            }
+        }
+    }
+
+    /// Resume the protocol after the client rejoined the session.
+    ///
+    /// Rejoining the session re-establishes the channels to the
+    /// other participants and once each channel is connected
+    /// the messages for the current round are retransmitted.
+    pub async fn resume(
+        &mut self,
+        transport: Transport,
+    ) -> Result<(), D::Error> {
+        self.transport = transport;
+        self.last_activity = Some(Instant::now());
+        Ok(())
+    }
+
     /// Start running the protocol.
     pub async fn execute(&mut self) -> Result<(), D::Error> {
-        let messages = self.driver.as_mut().unwrap().proceed()?;
+        match self.start().await {
+            Err(e) => {
+                if std::mem::take(&mut self.failed) {
+                    self.abort(&e).await;
+                }
+                Err(e)
+            }
+            result => result,
+        }
+    }
+
+    async fn start(&mut self) -> Result<(), D::Error> {
+        self.deadline = self
+            .options
+            .timeout
+            .map(|timeout| Instant::now() + timeout);
+        self.round = RoundNumber::new(1);
+        self.last_activity = Some(Instant::now());
+
+        if self.options.heartbeat_interval.is_some() {
+            self.send_heartbeat().await?;
+        }
+
+        if !self.driver.as_ref().unwrap().wants_to_proceed() {
+            return Ok(());
+        }
+
+        let messages = self
+            .driver
+            .as_mut()
+            .unwrap()
+            .proceed()
+            .map_err(|e| self.protocol_error(None, None, e))?;
+        self.take_intermediate();
         self.dispatch_round_messages(messages).await?;
         Ok(())
     }
 
+    /// Progress of the protocol.
+    pub fn progress(&self) -> Progress {
+        let waiting_on = if let Some(round) = &self.round {
+            let own_key = self.transport.public_key();
+            let received = self.received.get(round);
+            self.session
+                .all_participants
+                .iter()
+                .filter(|k| k.as_slice() != own_key)
+                .filter(|k| {
+                    received.map(|r| !r.contains(*k)).unwrap_or(true)
+                })
+                .filter_map(|k| self.session.party_number(k))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Progress {
+            round: self.round,
+            waiting_on,
+        }
+    }
+
+    /// Time after which the protocol is considered stalled.
+    pub fn watchdog(&self) -> Option<Instant> {
+        match (self.options.stall_interval, self.last_activity) {
+            (Some(interval), Some(last_activity)) => {
+                Some(last_activity + interval)
+            }
+            _ => None,
+        }
+    }
+
+    /// Time the next session heartbeat is due.
+    pub fn heartbeat(&self) -> Option<Instant> {
+        match (self.options.heartbeat_interval, self.last_heartbeat) {
+            (Some(interval), Some(last_heartbeat)) => {
+                Some(last_heartbeat + interval)
+            }
+            _ => None,
+        }
+    }
+
+    /// Send a session heartbeat to the server.
+    pub async fn send_heartbeat(&mut self) -> Result<(), D::Error> {
+        self.last_heartbeat = Some(Instant::now());
+        self.transport
+            .session_heartbeat(self.session.session_id)
+            .await?;
+        Ok(())
+    }
+
+    /// Record the intermediate outputs of the protocol
+    /// driver as events.
+    fn take_intermediate(&mut self) {
+        let outputs = self
+            .driver
+            .as_mut()
+            .map(|driver| driver.take_intermediate())
+            .unwrap_or_default();
+        for output in outputs {
+            self.record(DriverEvent::Intermediate(output));
+        }
+    }
+
+    /// Record an event.
+    ///
+    /// Once the limit is reached the oldest progress event
+    /// is dropped; intermediate outputs and deferred events
+    /// are always kept.
+    fn record(&mut self, event: DriverEvent<D::Output, D::Error>) {
+        if self.events.len() >= MAX_EVENTS {
+            let oldest = self.events.iter().position(|event| {
+                !matches!(
+                    event,
+                    DriverEvent::Intermediate(_)
+                        | DriverEvent::Deferred(_)
+                )
+            });
+            if let Some(index) = oldest {
+                self.events.remove(index);
+            }
+        }
+        self.events.push(event);
+    }
+
+    /// Complete the protocol and get the output.
+    fn finish(
+        &mut self,
+        round_number: RoundNumber,
+    ) -> Result<D::Output, D::Error> {
+        self.driver.take().unwrap().finish().map_err(|e| {
+            self.protocol_error(Some(round_number), None, e)
+        })
+    }
+
+    /// Wrap an error generated by the protocol driver
+    /// with the context of the failure.
+    fn protocol_error(
+        &mut self,
+        round: Option<RoundNumber>,
+        remote: Option<PartyNumber>,
+        error: D::Error,
+    ) -> D::Error {
+        let context = ErrorContext {
+            protocol: D::PROTOCOL,
+            round,
+            party: self
+                .session
+                .party_number(self.transport.public_key()),
+            remote,
+        };
+        tracing::error!(
+            protocol = context.protocol,
+            round = ?context.round,
+            party = ?context.party,
+            remote = ?context.remote,
+            error = %error,
+            "protocol error",
+        );
+        self.failure(Error::Protocol {
+            context,
+            source: Box::new(error),
+        })
+    }
+
+    /// Record a local protocol failure so the session is
+    /// notified that the protocol was aborted.
+    fn failure(&mut self, error: Error) -> D::Error {
+        self.failed = true;
+        Box::new(error).into()
+    }
+
+    /// Drop a message that can not be handled, for example
+    /// a message that does not decode or a message from a
+    /// participant that is not a signer.
+    fn drop_message(
+        &mut self,
+        peer_key: &[u8],
+        reason: &dyn std::fmt::Display,
+    ) -> Result<Option<D::Output>, D::Error> {
+        tracing::warn!(
+            from = ?hex::encode(peer_key),
+            reason = %reason,
+            "drop message",
+        );
+        self.record(DriverEvent::MessageDropped {
+            peer_key: peer_key.to_vec(),
+        });
+        Ok(None)
+    }
+
+    /// Ensure a message was sent by a session participant
+    /// using the party number assigned to that participant.
+    fn validate_sender(
+        &mut self,
+        peer_key: &[u8],
+        message: &D::Outgoing,
+    ) -> Result<(), D::Error> {
+        let party_number =
+            self.session.party_number(peer_key).ok_or_else(|| {
+                Box::new(Error::UnknownSender(hex::encode(peer_key)))
+            })?;
+
+        let claimed = *message.sender();
+        if claimed != party_number {
+            return Err(Box::new(Error::SenderMismatch {
+                peer_key: hex::encode(peer_key),
+                expected: party_number.get(),
+                actual: claimed.get(),
+            })
+            .into());
+        }
+
+        // Peer to peer messages must be addressed to us
+        if let Some(receiver) = message.receiver() {
+            let own_number = self
+                .session
+                .party_number(self.transport.public_key());
+            if own_number.as_ref() != Some(receiver) {
+                return Err(Box::new(Error::NotRecipient {
+                    round: message.round_number().get(),
+                    party: claimed.get(),
+                    receiver: receiver.get(),
+                })
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
     async fn dispatch_round_messages(
         &mut self,
         mut messages: Vec<D::Outgoing>,
     ) -> Result<(), D::Error> {
-        let is_broadcast = messages.len() == 1
-            && messages.get(0).as_ref().unwrap().is_broadcast();
+        // Broadcast and peer to peer messages may be mixed
+        // in a round so each message is dispatched individually
+        let mut outgoing = Vec::with_capacity(messages.len());
+        for message in messages.iter_mut() {
+            message.set_transcript(*self.transcript.digest());
+            message.set_sequence(
+                self.sequence.next(message.receiver().copied()),
+            );
+            let bytes = self
+                .options
+                .encoding
+                .encode(&BridgeMessage::Round(&*message))
+                .map_err(Box::new)?;
+            if message.is_broadcast() {
+                self.transcript.record(
+                    message.round_number(),
+                    *message.sender(),
+                    &bytes,
+                );
+            }
 
-        if is_broadcast {
-            let message = messages.remove(0);
-            let recipients =
-                self.session.recipients(self.transport.public_key());
-
-            self.transport
-                .broadcast_json(
-                    &self.session.session_id,
-                    recipients.as_slice(),
-                    &message,
-                )
-                .await?;
+            let round_number = message.round_number();
+            let is_started = self
+                .metrics
+                .rounds
+                .get(&round_number)
+                .map(|r| r.messages_sent > 0)
+                .unwrap_or(false);
+            if !is_started {
+                self.record(DriverEvent::RoundStarted(round_number));
+            }
+            self.metrics.sent(round_number, bytes.len());
+
+            let signature =
+                self.options.identity.as_ref().map(|identity| {
+                    identity.sign(&self.session.session_id, &bytes)
+                });
+
+            let view = RoundMessage {
+                round: message.round_number(),
+                sender: *message.sender(),
+                receiver: message.receiver().copied(),
+                payload: &bytes,
+                signature: signature.as_deref(),
+            };
+            for interceptor in &self.options.interceptors {
+                interceptor.on_outgoing(&view);
+            }
+
+            let receiver =
+                if let Some(party_number) = message.receiver() {
+                    let peer_key = self
+                        .session
+                        .peer_key(*party_number)
+                        .ok_or_else(|| {
+                            Box::new(Error::UnknownReceiver(
+                                party_number.get(),
+                            ))
+                        })?;
+                    Some(peer_key.to_vec())
+                } else {
+                    None
+                };
+
+            let payload = if let Some(signature) = signature {
+                let signed: BridgeMessage<()> =
+                    BridgeMessage::Signed(Signed {
+                        payload: bytes,
+                        signature,
+                    });
+                self.options
+                    .encoding
+                    .encode(&signed)
+                    .map_err(Box::new)?
+            } else {
+                bytes
+            };
+
+            outgoing.push(Sent {
+                round: round_number,
+                receiver,
+                payload,
+            });
+        }
+
+        for message in &outgoing {
+            self.send_sent(message, None).await?;
+        }
+        self.sent.extend(outgoing);
+        Ok(())
+    }
+
+    /// Send a round message to the intended recipients or
+    /// only to the given peer.
+    async fn send_sent(
+        &mut self,
+        message: &Sent,
+        peer_key: Option<&[u8]>,
+    ) -> Result<(), D::Error> {
+        let recipients = match (&message.receiver, peer_key) {
+            (Some(receiver), _) => vec![receiver.clone()],
+            (None, Some(peer_key)) => vec![peer_key.to_vec()],
+            (None, None) => {
+                self.session.recipients(self.transport.public_key())
+            }
+        };
+        self.send_message(
+            &recipients,
+            message.receiver.is_some(),
+            message.round,
+            message.payload.clone(),
+        )
+        .await
+    }
+
+    /// Send an encoded bridge message.
+    ///
+    /// Messages larger than the maximum message size
+    /// are split into chunks.
+    async fn send_message(
+        &mut self,
+        recipients: &[Vec<u8>],
+        is_peer: bool,
+        round: RoundNumber,
+        payload: Vec<u8>,
+    ) -> Result<(), D::Error> {
+        if let Some(size) = self.options.max_message_size {
+            if payload.len() > size {
+                let chunks = self
+                    .chunks
+                    .split(
+                        &payload,
+                        round,
+                        size,
+                        &self.options.encoding,
+                    )
+                    .map_err(Box::new)?;
+                for chunk in chunks {
+                    let chunk: BridgeMessage<()> =
+                        BridgeMessage::Chunk(chunk);
+                    let payload = self
+                        .options
+                        .encoding
+                        .encode(&chunk)
+                        .map_err(Box::new)?;
+                    self.send_encoded(recipients, is_peer, payload)
+                        .await?;
+                }
+                return Ok(());
+            }
+        }
+
+        self.send_encoded(recipients, is_peer, payload).await
+    }
+
+    /// Send an encoded bridge message to the recipients.
+    ///
+    /// JSON messages are sent as JSON whilst binary encodings
+    /// and padded messages are sent as binary messages;
+    /// padded messages are never compressed.
+    async fn send_encoded(
+        &mut self,
+        recipients: &[Vec<u8>],
+        is_peer: bool,
+        mut payload: Vec<u8>,
+    ) -> Result<(), D::Error> {
+        let mut is_binary = self.options.encoding.is_binary();
+        if let Some(bucket) = self.options.padding {
+            payload =
+                padding::pad(payload, bucket).map_err(Box::new)?;
+            is_binary = true;
+        }
+
+        // Compressing a padded message would reveal the
+        // length of the message through the compressed size;
+        // the setting only applies to the transport owned by
+        // this bridge and is restored after sending
+        let padded = self.options.padding.is_some();
+        let compression = self.transport.is_compression_enabled();
+        if padded {
+            self.transport.set_compression(false);
+        }
+        let result = self
+            .send_retried(recipients, is_peer, is_binary, &payload)
+            .await;
+        if padded {
+            self.transport.set_compression(compression);
+        }
+        result
+    }
+
+    /// Send a message to the recipients retrying transient
+    /// failures.
+    ///
+    /// When retries are enabled each recipient is sent the
+    /// message individually so that transient failures are
+    /// retried for the recipient without duplicating the
+    /// message for other recipients; otherwise broadcasts are
+    /// sent to all the recipients at once. Ordered broadcasts
+    /// are always sent to all the recipients at once so the
+    /// server assigns a single sequence number.
+    async fn send_retried(
+        &mut self,
+        recipients: &[Vec<u8>],
+        is_peer: bool,
+        is_binary: bool,
+        payload: &[u8],
+    ) -> Result<(), D::Error> {
+        let is_retried = self.options.retry.max_attempts > 1;
+        let size = if !is_peer
+            && (self.options.ordered_broadcast || !is_retried)
+        {
+            recipients.len().max(1)
         } else {
-            for message in messages {
-                let party_number = message.receiver().unwrap();
-                let peer_key =
-                    self.session.peer_key(*party_number).unwrap();
+            1
+        };
+        for recipient in recipients.chunks(size) {
+            let mut attempt = 1;
+            loop {
+                let result = self
+                    .send_to(recipient, is_peer, is_binary, payload)
+                    .await;
+                match result {
+                    Err(e)
+                        if e.is_transient()
+                            && attempt
+                                < self.options.retry.max_attempts =>
+                    {
+                        let delay = self.options.retry.delay(attempt);
+                        tracing::warn!(
+                            attempt = attempt,
+                            delay = ?delay,
+                            error = %e,
+                            "retry send",
+                        );
+                        timer::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    result => {
+                        result?;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send an encoded bridge message to the recipients.
+    ///
+    /// Peer to peer messages have a single recipient; the
+    /// recipients are a slice so they can be used for broadcasts.
+    async fn send_to(
+        &mut self,
+        recipient: &[Vec<u8>],
+        is_peer: bool,
+        is_binary: bool,
+        payload: &[u8],
+    ) -> mpc_client::Result<()> {
+        let session_id = self.session.session_id;
+        match (is_binary, is_peer) {
+            (false, true) => {
+                let message: &RawValue =
+                    serde_json::from_slice(payload)?;
                 self.transport
                     .send_json(
-                        peer_key,
-                        &message,
-                        Some(self.session.session_id),
+                        &recipient[0],
+                        message,
+                        Some(session_id),
+                    )
+                    .await
+            }
+            (false, false) => {
+                let message: &RawValue =
+                    serde_json::from_slice(payload)?;
+                if self.options.ordered_broadcast {
+                    self.transport
+                        .ordered_broadcast_json(
+                            &session_id,
+                            recipient,
+                            message,
+                        )
+                        .await
+                } else {
+                    self.transport
+                        .broadcast_json(
+                            &session_id,
+                            recipient,
+                            message,
+                        )
+                        .await
+                        .and_then(delivered)
+                }
+            }
+            (true, true) => {
+                self.transport
+                    .send_blob(
+                        &recipient[0],
+                        payload.to_vec(),
+                        Some(session_id),
                     )
-                    .await?;
+                    .await
             }
+            (true, false) if self.options.ordered_broadcast => {
+                self.transport
+                    .ordered_broadcast_blob(
+                        &session_id,
+                        recipient,
+                        payload.to_vec(),
+                    )
+                    .await
+            }
+            (true, false) => self
+                .transport
+                .broadcast_blob(
+                    &session_id,
+                    recipient,
+                    payload.to_vec(),
+                )
+                .await
+                .and_then(delivered),
         }
-        Ok(())
     }
 }
 
+/// Error for the first recipient a broadcast did not reach.
+pub(crate) fn delivered(
+    deliveries: Deliveries,
+) -> mpc_client::Result<()> {
+    deliveries
+        .into_iter()
+        .try_for_each(|(key, delivery)| delivery.into_result(&key))
+}
+
 /// Wait for a driver to complete.
+///
+/// If the driver has a deadline and it expires before the
+/// protocol completes a timeout error is returned that
+/// includes the progress of the protocol.
 pub async fn wait_for_driver<D>(
     stream: &mut EventStream,
     mut driver: D,
 ) -> Result<(Transport, D::Output), D::Error>
 where
     D: Driver + Into<Transport>,
+    D::Error: From<Box<Error>>,
 {
     driver.execute().await?;
+    if let Some(output) = driver.take_output() {
+        return Ok((driver.into(), output));
+    }
+    let output =
+        wait_for_driver_with(stream, &mut driver, Default::default())
+            .await?;
+    Ok((driver.into(), output))
+}
+
+/// Wait for a driver to complete with a timeout and
+/// cancellation token.
+///
+/// Unlike `wait_for_driver()` the driver must already have
+/// been started by calling `execute()`; the driver is borrowed
+/// so that its state is retained and the wait may be retried
+/// when it times out or is cancelled.
+pub async fn wait_for_driver_with<D>(
+    stream: &mut EventStream,
+    driver: &mut D,
+    options: WaitOptions,
+) -> Result<D::Output, D::Error>
+where
+    D: Driver,
+    D::Error: From<Box<Error>>,
+{
+    let wait_deadline = options.deadline();
 
-    #[allow(unused_assignments)]
-    let mut output: Option<D::Output> = None;
     loop {
+        let deadline = match (driver.deadline(), wait_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let heartbeat = driver.heartbeat();
         select! {
             event = stream.next().fuse() => {
                 match event {
@@ -134,16 +1442,25 @@ where
                         let event = event?;
                         if let Some(result) =
                             driver.handle_event(event).await? {
-                            output = Some(result);
-                            break;
+                            return Ok(result);
                         }
                     }
                     _ => {}
                 }
             },
+            _ = timer::sleep_until(deadline).fuse() => {
+                return Err(
+                    Box::new(Error::Timeout(driver.progress())).into()
+                );
+            },
+            _ = timer::sleep_until(heartbeat).fuse() => {
+                driver.send_heartbeat().await?;
+            },
+            _ = options.cancelled().fuse() => {
+                return Err(Box::new(Error::Cancelled).into());
+            },
         }
     }
-    Ok((driver.into(), output.take().unwrap()))
 }
 
 /// Wait for a close event.
@@ -154,19 +1471,37 @@ where
 pub async fn wait_for_close(
     stream: &mut EventStream,
 ) -> crate::Result<()> {
+    wait_for_close_with(stream, Default::default()).await
+}
+
+/// Wait for a close event with a timeout and cancellation token.
+pub async fn wait_for_close_with(
+    stream: &mut EventStream,
+    options: WaitOptions,
+) -> crate::Result<()> {
+    let deadline = options.deadline();
     loop {
         select! {
             event = stream.next().fuse() => {
                 match event {
                     Some(event) => {
                         let event = event?;
-                        if let Event::Close = event {
+                        if let Event::Close
+                        | Event::ConnectionLost
+                        | Event::Disconnected { .. } = event
+                        {
                             break;
                         }
                     }
                     _ => {}
                 }
             },
+            _ = timer::sleep_until(deadline).fuse() => {
+                return Err(Error::WaitTimeout);
+            },
+            _ = options.cancelled().fuse() => {
+                return Err(Error::Cancelled);
+            },
         }
     }
     Ok(())
@@ -177,22 +1512,126 @@ pub async fn wait_for_session_finish(
     stream: &mut EventStream,
     session_id: SessionId,
 ) -> crate::Result<()> {
+    wait_for_session_finish_with(
+        stream,
+        session_id,
+        Default::default(),
+    )
+    .await
+}
+
+/// Wait for a session finish event with a timeout and
+/// cancellation token.
+///
+/// Participants should call `finish_session()` on the
+/// transport once their output is saved; the session is
+/// finished when every participant has acknowledged. If the
+/// wait times out the session owner can call `close_session()`
+/// in which case the other participants receive an
+/// [Error::SessionClosed] error.
+pub async fn wait_for_session_finish_with(
+    stream: &mut EventStream,
+    session_id: SessionId,
+    options: WaitOptions,
+) -> crate::Result<()> {
+    let deadline = options.deadline();
     loop {
         select! {
             event = stream.next().fuse() => {
                 match event {
                     Some(event) => {
                         let event = event?;
-                        if let Event::SessionFinished(id)= event {
-                            if session_id == id {
+                        match event {
+                            Event::SessionFinished(id)
+                                if session_id == id =>
+                            {
                                 break;
                             }
+                            Event::SessionClosed(id)
+                                if session_id == id =>
+                            {
+                                return Err(Error::SessionClosed(id));
+                            }
+                            Event::ConnectionLost
+                            | Event::Disconnected { .. } => {
+                                return Err(Error::ConnectionLost);
+                            }
+                            _ => {}
                         }
                     }
                     _ => {}
                 }
             },
+            _ = timer::sleep_until(deadline).fuse() => {
+                return Err(Error::WaitTimeout);
+            },
+            _ = options.cancelled().fuse() => {
+                return Err(Error::Cancelled);
+            },
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Sent, SentMessages};
+    use crate::number;
+
+    fn message(
+        value: u16,
+        receiver: Option<Vec<u8>>,
+        payload: u8,
+    ) -> Sent {
+        Sent {
+            round: number(value),
+            receiver,
+            payload: vec![payload],
+        }
+    }
+
+    fn payloads(
+        sent: &SentMessages,
+        peer_key: &[u8],
+        from: u16,
+    ) -> Vec<u8> {
+        sent.for_peer(peer_key, number(from))
+            .map(|m| m.payload[0])
+            .collect()
+    }
+
+    #[test]
+    fn retransmit_only_own_messages() {
+        let mut sent = SentMessages::default();
+        sent.extend(vec![
+            message(1, None, 1),
+            message(1, Some(vec![2]), 2),
+            message(1, Some(vec![3]), 3),
+            message(2, None, 4),
+        ]);
+
+        // Peer to peer messages for other peers are never
+        // retransmitted to a peer that requests them
+        assert_eq!(vec![1, 2, 4], payloads(&sent, &[2], 1));
+        assert_eq!(vec![1, 3, 4], payloads(&sent, &[3], 1));
+        assert_eq!(vec![1, 4], payloads(&sent, &[4], 1));
+        assert_eq!(vec![4], payloads(&sent, &[2], 2));
+        assert!(payloads(&sent, &[2], 3).is_empty());
+    }
+
+    #[test]
+    fn retransmit_pruned_rounds() {
+        let mut sent = SentMessages::default();
+        sent.extend(vec![
+            message(1, None, 1),
+            message(2, None, 2),
+            message(3, None, 3),
+        ]);
+
+        sent.prune(number(2));
+        assert_eq!(vec![2, 3], payloads(&sent, &[2], 1));
+
+        sent.prune(number(3));
+        assert_eq!(vec![3], payloads(&sent, &[2], 1));
+    }
+}
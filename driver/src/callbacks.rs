@@ -0,0 +1,204 @@
+//! Typed callbacks for session events.
+use async_trait::async_trait;
+use mpc_client::{Event, Transport};
+use mpc_protocol::{SessionId, SessionState};
+
+use crate::{Result, SessionEventHandler, SessionStatus};
+
+/// Typed callbacks for session events.
+///
+/// Every callback has a default implementation that does
+/// nothing so implementations only override the events
+/// they need to handle.
+#[async_trait]
+pub trait SessionCallbacks: Send {
+    /// Called when all the participants in a session are
+    /// connected to the server.
+    async fn on_ready(
+        &mut self,
+        _session: &SessionState,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the session handler has connected to
+    /// all the other participants in a session.
+    async fn on_active(
+        &mut self,
+        _session: &SessionState,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when a peer channel is connected.
+    async fn on_peer_connected(
+        &mut self,
+        _peer_key: &[u8],
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when a session is finished.
+    async fn on_finished(
+        &mut self,
+        _session_id: &SessionId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the status of the session changes.
+    async fn on_status(
+        &mut self,
+        _status: SessionStatus,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when a message is received from a peer.
+    async fn on_message(
+        &mut self,
+        _peer_key: &[u8],
+        _session_id: Option<&SessionId>,
+        _message: &[u8],
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Session event handler that invokes callbacks before
+/// delegating events to another handler.
+///
+/// Use this to observe the progress of a session
+/// handshake without routing the events manually.
+pub struct WithCallbacks<S, C> {
+    handler: S,
+    callbacks: C,
+}
+
+impl<S, C> WithCallbacks<S, C> {
+    /// Create a new handler with callbacks.
+    pub fn new(handler: S, callbacks: C) -> Self {
+        Self { handler, callbacks }
+    }
+
+    /// Callbacks for this handler.
+    pub fn callbacks(&self) -> &C {
+        &self.callbacks
+    }
+
+    /// Split into the handler and callbacks.
+    pub fn into_inner(self) -> (S, C) {
+        (self.handler, self.callbacks)
+    }
+}
+
+#[async_trait]
+impl<S, C> SessionEventHandler for WithCallbacks<S, C>
+where
+    S: SessionEventHandler + Send,
+    C: SessionCallbacks,
+{
+    async fn handle_event(
+        &mut self,
+        event: Event,
+    ) -> Result<Option<SessionState>> {
+        match &event {
+            Event::SessionReady(session) => {
+                self.callbacks.on_ready(session).await?;
+            }
+            Event::PeerConnected { peer_key } => {
+                self.callbacks.on_peer_connected(peer_key).await?;
+            }
+            Event::SessionFinished(session_id) => {
+                self.callbacks.on_finished(session_id).await?;
+            }
+            Event::JsonMessage {
+                peer_key,
+                message,
+                session_id,
+            } => {
+                self.callbacks
+                    .on_message(
+                        peer_key,
+                        session_id.as_ref(),
+                        message.as_bytes(),
+                    )
+                    .await?;
+            }
+            Event::BinaryMessage {
+                peer_key,
+                message,
+                session_id,
+            } => {
+                self.callbacks
+                    .on_message(
+                        peer_key,
+                        session_id.as_ref(),
+                        message,
+                    )
+                    .await?;
+            }
+            Event::CborMessage {
+                peer_key,
+                message,
+                session_id,
+            } => {
+                self.callbacks
+                    .on_message(
+                        peer_key,
+                        session_id.as_ref(),
+                        message.as_bytes(),
+                    )
+                    .await?;
+            }
+            Event::MessagePackMessage {
+                peer_key,
+                message,
+                session_id,
+            } => {
+                self.callbacks
+                    .on_message(
+                        peer_key,
+                        session_id.as_ref(),
+                        message.as_bytes(),
+                    )
+                    .await?;
+            }
+            _ => {}
+        }
+
+        let status = self.handler.status();
+        let result = self.handler.handle_event(event).await;
+        if self.handler.status() != status {
+            self.callbacks.on_status(self.handler.status()).await?;
+        }
+
+        let session = result?;
+        if let Some(session) = &session {
+            self.callbacks.on_active(session).await?;
+        }
+        Ok(session)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        let status = self.handler.status();
+        let result = self.handler.close().await;
+        if self.handler.status() != status {
+            self.callbacks.on_status(self.handler.status()).await?;
+        }
+        result
+    }
+
+    fn status(&self) -> SessionStatus {
+        self.handler.status()
+    }
+}
+
+impl<S, C> From<WithCallbacks<S, C>> for Transport
+where
+    S: Into<Transport>,
+{
+    fn from(value: WithCallbacks<S, C>) -> Self {
+        value.handler.into()
+    }
+}
@@ -0,0 +1,421 @@
+//! Split large round messages into chunks.
+use mpc_protocol::{hex, RoundNumber};
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{collections::HashMap, fmt};
+
+use crate::{message::BridgeMessage, Error, Result, RoundEncoding};
+
+/// Maximum size of a message reassembled from chunks.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Maximum number of partially received messages per peer.
+const MAX_PARTIALS: usize = 4;
+
+/// Allowance for length prefixes of the chunk data that
+/// grow with the length of the data.
+const LENGTH_PREFIX: usize = 8;
+
+/// Part of an encoded round message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Chunk {
+    /// Identifier of the message for the sender.
+    pub id: u32,
+    /// Round of the message.
+    pub round: RoundNumber,
+    /// Index of this chunk.
+    pub index: u16,
+    /// Total number of chunks for the message.
+    pub total: u16,
+    /// Bytes for this chunk.
+    ///
+    /// Hex encoded for JSON and raw bytes for the
+    /// binary encodings.
+    #[serde(with = "data")]
+    pub data: Vec<u8>,
+}
+
+/// Partially received message.
+#[derive(Debug)]
+struct Partial {
+    round: RoundNumber,
+    chunks: Vec<Option<Vec<u8>>>,
+    remaining: usize,
+}
+
+/// Splits outgoing messages and reassembles incoming chunks.
+#[derive(Debug, Default)]
+pub(crate) struct ChunkBuffer {
+    next_id: u32,
+    partials: HashMap<(Vec<u8>, u32), Partial>,
+}
+
+impl ChunkBuffer {
+    /// Split a message into chunks that are at most size
+    /// bytes once encoded.
+    pub fn split(
+        &mut self,
+        message: &[u8],
+        round: RoundNumber,
+        size: usize,
+        encoding: &RoundEncoding,
+    ) -> Result<Vec<Chunk>> {
+        let capacity = Self::capacity(round, size, encoding)?;
+        let total = u16::try_from(message.len().div_ceil(capacity))
+            .map_err(|_| {
+            Error::InvalidChunk(format!(
+                "message of {} bytes needs too many chunks",
+                message.len(),
+            ))
+        })?;
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        Ok(message
+            .chunks(capacity)
+            .enumerate()
+            .map(|(index, data)| Chunk {
+                id,
+                round,
+                index: index as u16,
+                total,
+                data: data.to_vec(),
+            })
+            .collect())
+    }
+
+    /// Number of message bytes that fit in a chunk of
+    /// size bytes once encoded.
+    fn capacity(
+        round: RoundNumber,
+        size: usize,
+        encoding: &RoundEncoding,
+    ) -> Result<usize> {
+        let empty: BridgeMessage<()> = BridgeMessage::Chunk(Chunk {
+            id: u32::MAX,
+            round,
+            index: u16::MAX,
+            total: u16::MAX,
+            data: Vec::new(),
+        });
+        let overhead = encoding.encode(&empty)?.len() + LENGTH_PREFIX;
+        // Hex encoding doubles the size of the data
+        let expansion = if encoding.is_binary() { 1 } else { 2 };
+        match size.saturating_sub(overhead) / expansion {
+            0 => Err(Error::InvalidChunk(format!(
+                "message size {} is too small for a chunk",
+                size,
+            ))),
+            capacity => Ok(capacity),
+        }
+    }
+
+    /// Add a chunk received from a peer.
+    ///
+    /// Chunks larger than size bytes are rejected and the
+    /// reassembled message is limited to a multiple of the
+    /// size; each peer may only have a few messages
+    /// partially received at a time.
+    ///
+    /// Once all the chunks for a message have been
+    /// received the reassembled message is returned.
+    pub fn receive(
+        &mut self,
+        peer_key: &[u8],
+        chunk: Chunk,
+        size: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        if chunk.index >= chunk.total {
+            return Err(Error::InvalidChunk(format!(
+                "chunk index {} is out of bounds for {} chunks",
+                chunk.index, chunk.total,
+            )));
+        }
+
+        if chunk.data.len() > size {
+            return Err(Error::InvalidChunk(format!(
+                "chunk of {} bytes exceeds {} bytes",
+                chunk.data.len(),
+                size,
+            )));
+        }
+
+        let limit = (chunk.total as usize).saturating_mul(size);
+        if limit > MAX_MESSAGE_SIZE {
+            return Err(Error::InvalidChunk(format!(
+                "message of {} chunks exceeds {} bytes",
+                chunk.total, MAX_MESSAGE_SIZE,
+            )));
+        }
+
+        let key = (peer_key.to_vec(), chunk.id);
+        if !self.partials.contains_key(&key) {
+            let pending = self
+                .partials
+                .keys()
+                .filter(|(key, _)| key.as_slice() == peer_key)
+                .count();
+            if pending >= MAX_PARTIALS {
+                return Err(Error::InvalidChunk(format!(
+                    "too many partial messages from peer {}",
+                    hex::encode(peer_key),
+                )));
+            }
+        }
+
+        let partial = self
+            .partials
+            .entry(key.clone())
+            .or_insert_with(|| Partial {
+                round: chunk.round,
+                chunks: vec![None; chunk.total as usize],
+                remaining: chunk.total as usize,
+            });
+
+        if partial.chunks.len() != chunk.total as usize
+            || partial.round != chunk.round
+        {
+            return Err(Error::InvalidChunk(format!(
+                "chunk total {} for round {} does not match \
+                 {} for round {}",
+                chunk.total,
+                chunk.round,
+                partial.chunks.len(),
+                partial.round,
+            )));
+        }
+
+        let slot = &mut partial.chunks[chunk.index as usize];
+        if slot.is_some() {
+            return Err(Error::InvalidChunk(format!(
+                "duplicate chunk index {}",
+                chunk.index,
+            )));
+        }
+        *slot = Some(chunk.data);
+        partial.remaining -= 1;
+
+        if partial.remaining > 0 {
+            return Ok(None);
+        }
+
+        let partial = self.partials.remove(&key).unwrap();
+        Ok(Some(
+            partial.chunks.into_iter().flatten().flatten().collect(),
+        ))
+    }
+
+    /// Discard partial messages for a completed round and
+    /// the rounds before it.
+    pub fn complete(&mut self, round: RoundNumber) {
+        self.partials.retain(|_, partial| partial.round > round);
+    }
+}
+
+/// Serialize chunk data as hex for human readable encodings
+/// and as bytes otherwise.
+mod data {
+    use super::*;
+
+    pub fn serialize<S>(
+        data: &[u8],
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            hex::serde::serialize(data, serializer)
+        } else {
+            serializer.serialize_bytes(data)
+        }
+    }
+
+    pub fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> std::result::Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            hex::serde::deserialize(deserializer)
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("chunk bytes")
+        }
+
+        fn visit_bytes<E: de::Error>(
+            self,
+            v: &[u8],
+        ) -> std::result::Result<Self::Value, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(
+            self,
+            v: Vec<u8>,
+        ) -> std::result::Result<Self::Value, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> std::result::Result<Self::Value, A::Error> {
+            let mut bytes = Vec::new();
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chunk, ChunkBuffer, MAX_PARTIALS};
+    use crate::{
+        message::BridgeMessage, number, Error, RoundEncoding,
+    };
+
+    const SIZE: usize = 256;
+
+    fn chunk(id: u32, index: u16, total: u16) -> Chunk {
+        Chunk {
+            id,
+            round: number(1),
+            index,
+            total,
+            data: vec![0; 8],
+        }
+    }
+
+    #[test]
+    fn chunks_fit_after_encoding() -> crate::Result<()> {
+        let message: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+        for encoding in [
+            RoundEncoding::Json,
+            RoundEncoding::Bincode,
+            RoundEncoding::Cbor,
+        ] {
+            let mut sender = ChunkBuffer::default();
+            let mut receiver = ChunkBuffer::default();
+            let mut chunks =
+                sender.split(&message, number(1), SIZE, &encoding)?;
+            assert!(chunks.len() > 1);
+            chunks.reverse();
+
+            let mut output = None;
+            for chunk in chunks {
+                let encoded: BridgeMessage<()> =
+                    BridgeMessage::Chunk(chunk);
+                let encoded = encoding.encode(&encoded)?;
+                assert!(encoded.len() <= SIZE);
+                let chunk = match encoding.decode(&encoded)? {
+                    BridgeMessage::<()>::Chunk(chunk) => chunk,
+                    _ => unreachable!(),
+                };
+                output = receiver.receive(&[1], chunk, SIZE)?;
+            }
+            assert_eq!(Some(message.clone()), output);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_size_too_small() {
+        let mut buffer = ChunkBuffer::default();
+        let result = buffer.split(
+            b"message",
+            number(1),
+            8,
+            &RoundEncoding::Json,
+        );
+        assert!(matches!(result, Err(Error::InvalidChunk(_))));
+    }
+
+    #[test]
+    fn chunk_oversized_rejected() {
+        let mut buffer = ChunkBuffer::default();
+        let mut oversized = chunk(0, 0, 2);
+        oversized.data = vec![0; SIZE + 1];
+        let result = buffer.receive(&[1], oversized, SIZE);
+        assert!(matches!(result, Err(Error::InvalidChunk(_))));
+    }
+
+    #[test]
+    fn chunk_total_exceeds_maximum() {
+        let mut buffer = ChunkBuffer::default();
+        let result =
+            buffer.receive(&[1], chunk(0, 0, u16::MAX), 4096);
+        assert!(matches!(result, Err(Error::InvalidChunk(_))));
+    }
+
+    #[test]
+    fn chunk_index_out_of_bounds() {
+        let mut buffer = ChunkBuffer::default();
+        let result = buffer.receive(&[1], chunk(0, 2, 2), SIZE);
+        assert!(matches!(result, Err(Error::InvalidChunk(_))));
+    }
+
+    #[test]
+    fn chunk_duplicate_rejected() -> crate::Result<()> {
+        let mut buffer = ChunkBuffer::default();
+        assert!(buffer
+            .receive(&[1], chunk(0, 0, 2), SIZE)?
+            .is_none());
+        let result = buffer.receive(&[1], chunk(0, 0, 2), SIZE);
+        assert!(matches!(result, Err(Error::InvalidChunk(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_inconsistent_rejected() -> crate::Result<()> {
+        let mut buffer = ChunkBuffer::default();
+        assert!(buffer
+            .receive(&[1], chunk(0, 0, 3), SIZE)?
+            .is_none());
+
+        let result = buffer.receive(&[1], chunk(0, 1, 4), SIZE);
+        assert!(matches!(result, Err(Error::InvalidChunk(_))));
+
+        let mut other_round = chunk(0, 1, 3);
+        other_round.round = number(2);
+        let result = buffer.receive(&[1], other_round, SIZE);
+        assert!(matches!(result, Err(Error::InvalidChunk(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_partials_limited() -> crate::Result<()> {
+        let mut buffer = ChunkBuffer::default();
+        for id in 0..MAX_PARTIALS as u32 {
+            buffer.receive(&[1], chunk(id, 0, 2), SIZE)?;
+        }
+        let id = MAX_PARTIALS as u32;
+        let result = buffer.receive(&[1], chunk(id, 0, 2), SIZE);
+        assert!(matches!(result, Err(Error::InvalidChunk(_))));
+
+        // Other peers are not affected
+        assert!(buffer
+            .receive(&[2], chunk(id, 0, 2), SIZE)?
+            .is_none());
+
+        // Partials are evicted when the round completes
+        buffer.complete(number(1));
+        assert!(buffer
+            .receive(&[1], chunk(id, 0, 2), SIZE)?
+            .is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! Run multiple drivers concurrently over a single client.
+use futures::StreamExt;
+use mpc_client::{Event, EventStream};
+use mpc_protocol::{hex, SessionId};
+use std::collections::HashMap;
+
+use crate::Driver;
+
+/// Result of a driver completed by a dispatcher.
+pub type Dispatched<D> = (
+    SessionId,
+    std::result::Result<<D as Driver>::Output, <D as Driver>::Error>,
+);
+
+/// Routes session messages to drivers by session identifier.
+///
+/// Each driver should be created with a clone of the same
+/// transport so that several protocols can run concurrently
+/// over a single client without cross-talk.
+pub struct Dispatcher<D: Driver> {
+    drivers: HashMap<SessionId, D>,
+}
+
+impl<D: Driver + Send> Dispatcher<D> {
+    /// Create a new dispatcher.
+    pub fn new() -> Self {
+        Self {
+            drivers: HashMap::new(),
+        }
+    }
+
+    /// Number of running drivers.
+    pub fn len(&self) -> usize {
+        self.drivers.len()
+    }
+
+    /// Determine if there are no running drivers.
+    pub fn is_empty(&self) -> bool {
+        self.drivers.is_empty()
+    }
+
+    /// Start running a driver for a session.
+    pub async fn insert(
+        &mut self,
+        session_id: SessionId,
+        mut driver: D,
+    ) -> std::result::Result<(), D::Error> {
+        driver.execute().await?;
+        self.drivers.insert(session_id, driver);
+        Ok(())
+    }
+
+    /// Route an event to the driver for the session.
+    ///
+    /// When a driver completes or fails it is removed and
+    /// the result is returned with the session identifier.
+    pub async fn handle_event(
+        &mut self,
+        event: Event,
+    ) -> Option<Dispatched<D>> {
+        let session_id = match &event {
+            Event::JsonMessage {
+                session_id,
+                peer_key,
+                ..
+            }
+            | Event::BinaryMessage {
+                session_id,
+                peer_key,
+                ..
+            } => {
+                if let Some(session_id) = session_id {
+                    *session_id
+                } else {
+                    tracing::debug!(
+                        from = ?hex::encode(peer_key),
+                        "dispatcher ignored message without session",
+                    );
+                    return None;
+                }
+            }
+            _ => return None,
+        };
+
+        let driver =
+            if let Some(driver) = self.drivers.get_mut(&session_id) {
+                driver
+            } else {
+                tracing::debug!(
+                    session_id = %session_id,
+                    "dispatcher ignored message for unknown session",
+                );
+                return None;
+            };
+
+        match driver.handle_event(event).await {
+            Ok(Some(output)) => {
+                self.drivers.remove(&session_id);
+                Some((session_id, Ok(output)))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.drivers.remove(&session_id);
+                Some((session_id, Err(e)))
+            }
+        }
+    }
+}
+
+impl<D: Driver + Send> Default for Dispatcher<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wait for all the drivers in a dispatcher to complete.
+///
+/// Results are returned in the order the drivers completed.
+pub async fn wait_for_dispatcher<D>(
+    stream: &mut EventStream,
+    dispatcher: &mut Dispatcher<D>,
+) -> std::result::Result<Vec<Dispatched<D>>, D::Error>
+where
+    D: Driver + Send,
+{
+    let mut results = Vec::new();
+    while !dispatcher.is_empty() {
+        match stream.next().await {
+            Some(event) => {
+                let event = event?;
+                if let Some(result) =
+                    dispatcher.handle_event(event).await
+                {
+                    results.push(result);
+                }
+            }
+            None => break,
+        }
+    }
+    Ok(results)
+}
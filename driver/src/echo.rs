@@ -0,0 +1,206 @@
+//! Echo broadcast to detect equivocation by a sender.
+use mpc_protocol::{PartyNumber, RoundNumber};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::transcript::TranscriptHash;
+
+/// Confirms the hash of a broadcast message received
+/// from a sender to the other participants.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Echo {
+    /// Round number.
+    pub round: RoundNumber,
+    /// Party number of the sender of the broadcast message.
+    pub sender: PartyNumber,
+    /// Hash of the broadcast message.
+    pub digest: TranscriptHash,
+}
+
+/// Mismatch between a broadcast message we received and
+/// the hash echoed by another participant.
+///
+/// Either the sender equivocated or the peer lied about the
+/// message it received so blame is assigned to both.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) struct EchoMismatch {
+    /// Party number of the sender of the broadcast message.
+    pub sender: PartyNumber,
+    /// Party number of the participant that sent the echo.
+    pub peer: PartyNumber,
+}
+
+/// Tracks the echoes for broadcast messages.
+///
+/// Relayed broadcasts are delivered as individual messages
+/// to each recipient so a malicious sender could send different
+/// messages to different recipients; receivers cross-confirm
+/// the hash of each broadcast message before it is delivered
+/// to the protocol driver.
+#[derive(Debug, Default)]
+pub(crate) struct EchoBroadcast {
+    /// Hashes of the broadcast messages we received.
+    received: HashMap<(RoundNumber, PartyNumber), TranscriptHash>,
+    /// Hashes echoed by other participants keyed by party number.
+    echoes: HashMap<
+        (RoundNumber, PartyNumber),
+        HashMap<PartyNumber, TranscriptHash>,
+    >,
+    /// Highest round that has been cleared.
+    closed: Option<RoundNumber>,
+}
+
+impl EchoBroadcast {
+    /// Record the hash of a broadcast message we received.
+    pub fn receive(
+        &mut self,
+        round: RoundNumber,
+        sender: PartyNumber,
+        digest: TranscriptHash,
+    ) {
+        if self.is_closed(round) {
+            return;
+        }
+        self.received.insert((round, sender), digest);
+    }
+
+    /// Record an echo from another participant.
+    ///
+    /// Echoes for rounds that have already been cleared
+    /// are ignored.
+    pub fn echo(&mut self, peer: PartyNumber, echo: Echo) {
+        if self.is_closed(echo.round) {
+            return;
+        }
+        self.echoes
+            .entry((echo.round, echo.sender))
+            .or_default()
+            .insert(peer, echo.digest);
+    }
+
+    /// Determine if a round has already been cleared.
+    fn is_closed(&self, round: RoundNumber) -> bool {
+        self.closed.map(|closed| round <= closed).unwrap_or(false)
+    }
+
+    /// Verify the echoes for a round.
+    ///
+    /// Returns `Ok(true)` when every broadcast message in the round
+    /// has been confirmed by the expected number of echoes or the
+    /// sender and peer when an echo does not match the message
+    /// we received.
+    pub fn verify(
+        &self,
+        round: RoundNumber,
+        expected_echoes: usize,
+    ) -> std::result::Result<bool, EchoMismatch> {
+        let mut complete = true;
+        for ((r, sender), digest) in &self.received {
+            if r != &round {
+                continue;
+            }
+            let echoes = self.echoes.get(&(*r, *sender));
+            let amount = echoes.map(|e| e.len()).unwrap_or(0);
+            if let Some(echoes) = echoes {
+                if let Some(peer) = echoes
+                    .iter()
+                    .find(|(_, d)| *d != digest)
+                    .map(|(peer, _)| *peer)
+                {
+                    return Err(EchoMismatch {
+                        sender: *sender,
+                        peer,
+                    });
+                }
+            }
+            if amount < expected_echoes {
+                complete = false;
+            }
+        }
+        Ok(complete)
+    }
+
+    /// Remove the state for a completed round.
+    ///
+    /// Any state for earlier rounds is also removed and later
+    /// echoes for these rounds are ignored.
+    pub fn clear(&mut self, round: RoundNumber) {
+        if !self.is_closed(round) {
+            self.closed = Some(round);
+        }
+        self.received.retain(|(r, _), _| r > &round);
+        self.echoes.retain(|(r, _), _| r > &round);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Echo, EchoBroadcast, EchoMismatch};
+    use crate::{number, transcript::hash};
+
+    fn echo(sender: u16, message: &[u8]) -> Echo {
+        Echo {
+            round: number(1),
+            sender: number(sender),
+            digest: hash(message),
+        }
+    }
+
+    #[test]
+    fn echo_confirmed() {
+        let mut echoes = EchoBroadcast::default();
+        echoes.receive(number(1), number(1), hash(b"message"));
+        assert_eq!(Ok(false), echoes.verify(number(1), 2));
+
+        echoes.echo(number(2), echo(1, b"message"));
+        assert_eq!(Ok(false), echoes.verify(number(1), 2));
+
+        echoes.echo(number(3), echo(1, b"message"));
+        assert_eq!(Ok(true), echoes.verify(number(1), 2));
+    }
+
+    #[test]
+    fn echo_equivocation_detected() {
+        let mut echoes = EchoBroadcast::default();
+        echoes.receive(number(1), number(1), hash(b"message"));
+        echoes.echo(number(2), echo(1, b"message"));
+        echoes.echo(number(3), echo(1, b"equivocation"));
+        assert_eq!(
+            Err(EchoMismatch {
+                sender: number(1),
+                peer: number(3),
+            }),
+            echoes.verify(number(1), 2)
+        );
+    }
+
+    #[test]
+    fn echo_repeated_by_peer_counted_once() {
+        let mut echoes = EchoBroadcast::default();
+        echoes.receive(number(1), number(1), hash(b"message"));
+        echoes.echo(number(2), echo(1, b"message"));
+        echoes.echo(number(2), echo(1, b"message"));
+        assert_eq!(Ok(false), echoes.verify(number(1), 2));
+    }
+
+    #[test]
+    fn echo_cleared_for_round() {
+        let mut echoes = EchoBroadcast::default();
+        echoes.receive(number(1), number(1), hash(b"message"));
+        echoes.echo(number(2), echo(1, b"equivocation"));
+        echoes.clear(number(1));
+        assert_eq!(Ok(true), echoes.verify(number(1), 2));
+    }
+
+    #[test]
+    fn echo_ignored_for_closed_round() {
+        let mut echoes = EchoBroadcast::default();
+        echoes.receive(number(1), number(1), hash(b"message"));
+        echoes.clear(number(1));
+        echoes.receive(number(1), number(1), hash(b"message"));
+        echoes.echo(number(2), echo(1, b"equivocation"));
+        assert!(echoes.received.is_empty());
+        assert!(echoes.echoes.is_empty());
+        assert_eq!(Ok(true), echoes.verify(number(1), 2));
+    }
+}
@@ -0,0 +1,53 @@
+//! Encodings for round messages.
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Result;
+
+/// Encoding used to serialize round messages.
+///
+/// All participants in a session must use the same encoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundEncoding {
+    /// Encode round messages as JSON.
+    #[default]
+    Json,
+    /// Encode round messages using bincode.
+    Bincode,
+    /// Encode round messages as CBOR.
+    Cbor,
+}
+
+impl RoundEncoding {
+    /// Determine if this encoding is sent as binary messages.
+    pub fn is_binary(&self) -> bool {
+        !matches!(self, Self::Json)
+    }
+
+    /// Encode a value.
+    pub(crate) fn encode<T: Serialize + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec(value)?,
+            Self::Bincode => bincode::serialize(value)?,
+            Self::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::ser::into_writer(value, &mut buffer)?;
+                buffer
+            }
+        })
+    }
+
+    /// Decode a value.
+    pub(crate) fn decode<T: DeserializeOwned>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<T> {
+        Ok(match self {
+            Self::Json => serde_json::from_slice(bytes)?,
+            Self::Bincode => bincode::deserialize(bytes)?,
+            Self::Cbor => ciborium::de::from_reader(bytes)?,
+        })
+    }
+}
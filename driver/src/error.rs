@@ -1,5 +1,40 @@
+use mpc_protocol::{PartyNumber, RoundNumber, SessionId};
+use std::fmt;
 use thiserror::Error;
 
+use crate::Progress;
+
+/// Context for an error generated while driving a protocol.
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    /// Name of the protocol.
+    pub protocol: &'static str,
+    /// Round number when known.
+    pub round: Option<RoundNumber>,
+    /// Party number of the local participant.
+    pub party: Option<PartyNumber>,
+    /// Party number of the remote participant when known.
+    pub remote: Option<PartyNumber>,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn optional(value: Option<PartyNumber>) -> String {
+            value
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "-".to_string())
+        }
+        write!(
+            f,
+            "protocol={} round={} party={} remote={}",
+            self.protocol,
+            optional(self.round),
+            optional(self.party),
+            optional(self.remote),
+        )
+    }
+}
+
 /// Errors generated by the driver.
 #[derive(Debug, Error)]
 pub enum Error {
@@ -12,6 +47,217 @@ pub enum Error {
     #[error("session identifier required")]
     SessionIdRequired,
 
+    /// Error generated when a session times out waiting
+    /// for participants to connect.
+    #[error(
+        "session {session_id} timed out waiting for {} participant(s)",
+        .missing.len()
+    )]
+    SessionTimeout {
+        /// Session identifier.
+        session_id: SessionId,
+        /// Public keys of the participants that did not connect.
+        missing: Vec<Vec<u8>>,
+    },
+
+    /// Error generated when the server stops responding to
+    /// keep-alive pings or the connection to the server ends.
+    #[error("connection to the server was lost")]
+    ConnectionLost,
+
+    /// Error generated when the server expires a session.
+    #[error("session {0} has expired")]
+    SessionExpired(SessionId),
+
+    /// Error generated when the session owner closes a session
+    /// before every participant acknowledged finishing it.
+    #[error(
+        "session {0} was closed before all participants finished"
+    )]
+    SessionClosed(SessionId),
+
+    /// Error generated when the join policy of a participant
+    /// rejects a session.
+    #[error("session {0} was rejected by the join policy")]
+    SessionRejected(SessionId),
+
+    /// Error generated when a session identifier was not
+    /// derived from the participants and the expected nonce.
+    #[error("session {0} was not derived from the nonce")]
+    SessionNotDerived(SessionId),
+
+    /// Error generated when a participant declines to join
+    /// a session.
+    #[error("participant {public_key} declined to join session {session_id}")]
+    SessionDeclined {
+        /// Session identifier.
+        session_id: SessionId,
+        /// Public key of the participant that declined.
+        public_key: String,
+    },
+
+    /// Error generated when a participant disconnects from
+    /// a session and the driver is configured to abort.
+    #[error("party {0} left the session")]
+    PeerLeft(u16),
+
+    /// Error generated when a message is received from a public
+    /// key that is not a participant in the session.
+    #[error("message sender {0} is not a session participant")]
+    UnknownSender(String),
+
+    /// Error generated when the party number claimed by a message
+    /// does not match the party number of the sender.
+    #[error("message from {peer_key} claims party number {actual} but expected {expected}")]
+    SenderMismatch {
+        /// Public key of the peer that sent the message.
+        peer_key: String,
+        /// Party number expected for the sender.
+        expected: u16,
+        /// Party number claimed by the message.
+        actual: u16,
+    },
+
+    /// Error generated when a peer to peer message is
+    /// addressed to a party number that is not in the session.
+    #[error("receiver party number {0} is not in the session")]
+    UnknownReceiver(u16),
+
+    /// Error generated when a peer to peer message is
+    /// addressed to another party.
+    #[error("message in round {round} from party {party} is addressed to party {receiver}")]
+    NotRecipient {
+        /// Round number.
+        round: u16,
+        /// Party number of the sender.
+        party: u16,
+        /// Party number of the receiver.
+        receiver: u16,
+    },
+
+    /// Error generated when a message from a party is out of
+    /// sequence because messages were reordered or dropped.
+    #[error("message from party {party} has sequence number {actual} but expected {expected}")]
+    SequenceGap {
+        /// Party number of the sender.
+        party: u16,
+        /// Sequence number expected for the message.
+        expected: u32,
+        /// Sequence number of the message.
+        actual: u32,
+    },
+
+    /// Error generated when an interceptor rejects a message.
+    #[error("message in round {round} from party {party} rejected: {reason}")]
+    MessageRejected {
+        /// Round number.
+        round: u16,
+        /// Party number of the sender.
+        party: u16,
+        /// Reason given by the interceptor.
+        reason: String,
+    },
+
+    /// Error generated when the transcript hash claimed by a
+    /// message does not match the local transcript.
+    #[error(
+        "transcript mismatch in round {round} from party {party}"
+    )]
+    TranscriptMismatch {
+        /// Round number.
+        round: u16,
+        /// Party number of the sender.
+        party: u16,
+    },
+
+    /// Error generated by a protocol driver.
+    #[error("{context}: {source}")]
+    Protocol {
+        /// Context for the error.
+        context: ErrorContext,
+        /// Error generated by the protocol driver.
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// Error generated when the echo for a broadcast message
+    /// does not match the message we received; either the sender
+    /// equivocated or the peer lied about the message it received.
+    #[error(
+        "echo mismatch between party {sender} and party {peer} in round {round}"
+    )]
+    EchoMismatch {
+        /// Round number.
+        round: u16,
+        /// Party number of the sender of the broadcast message.
+        sender: u16,
+        /// Party number of the participant that sent the echo.
+        peer: u16,
+    },
+
+    /// Error generated when another participant aborts the protocol.
+    #[error("party {party} aborted the protocol: {reason}")]
+    RemoteAbort {
+        /// Party number of the participant that aborted.
+        party: u16,
+        /// Reason given for the abort.
+        reason: String,
+    },
+
+    /// Error generated when a participant does not have
+    /// an identity key.
+    #[error("no identity key for participant {0}")]
+    UnknownIdentity(String),
+
+    /// Error generated when a round message signature is invalid.
+    #[error("invalid signature")]
+    InvalidSignature,
+
+    /// Error generated when a round message from a party
+    /// is not signed but identity keys are required.
+    #[error("message from party {0} is not signed")]
+    MissingSignature(u16),
+
+    /// Error generated when a round message from a party
+    /// is signed but identity keys are not configured.
+    #[error(
+        "message from party {0} is signed without identity keys"
+    )]
+    UnexpectedSignature(u16),
+
+    /// Error generated when the signature for a round
+    /// message from a party fails verification.
+    #[error("signature for message from party {0} was rejected")]
+    SignatureRejected(u16),
+
+    /// Error generated when a chunk of a round message is invalid.
+    #[error("invalid message chunk: {0}")]
+    InvalidChunk(String),
+
+    /// Error generated when a padded round message is invalid.
+    #[error("invalid message padding: {0}")]
+    InvalidPadding(String),
+
+    /// Error generated when the deadline for a driver expires.
+    #[error("driver timed out ({0})")]
+    Timeout(Progress),
+
+    /// Error generated when the timeout for a wait expires.
+    #[error("timed out waiting for event")]
+    WaitTimeout,
+
+    /// Error generated when a wait is cancelled.
+    #[error("wait was cancelled")]
+    Cancelled,
+
+    /// Error generated when a recording ends before the
+    /// protocol is completed.
+    #[error("recording ended before the protocol completed")]
+    ReplayIncomplete,
+
+    /// Error generated by a storage backend.
+    #[error("storage error: {0}")]
+    Storage(String),
+
     #[cfg(feature = "gg20")]
     /// GG20 driver errors.
     #[error(transparent)]
@@ -20,6 +266,32 @@ pub enum Error {
     /// Client library errors.
     #[error(transparent)]
     Client(#[from] mpc_client::Error),
+
+    /// Error generated by input/output.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// Error generated serializing or deserializing JSON.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    /// Error generated serializing or deserializing bincode.
+    #[error(transparent)]
+    Bincode(#[from] Box<bincode::ErrorKind>),
+
+    /// Error generated serializing CBOR.
+    #[error(transparent)]
+    CborEncode(#[from] ciborium::ser::Error<std::io::Error>),
+
+    /// Error generated deserializing CBOR.
+    #[error(transparent)]
+    CborDecode(#[from] ciborium::de::Error<std::io::Error>),
+}
+
+impl From<Box<Error>> for Error {
+    fn from(value: Box<Error>) -> Self {
+        *value
+    }
 }
 
 #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
@@ -0,0 +1,182 @@
+//! Progress events for a driver.
+use async_stream::stream;
+use futures::{select, FutureExt, Stream, StreamExt};
+use mpc_client::{Event, EventStream, Transport};
+use mpc_protocol::{PartyNumber, RoundNumber};
+
+use crate::{timer, Driver, Error};
+
+/// Output yielded by a protocol before it is completed.
+#[derive(Debug, Clone)]
+pub struct Intermediate {
+    /// Name of the output.
+    pub name: String,
+    /// Value of the output.
+    pub value: serde_json::Value,
+}
+
+/// Event yielded whilst driving a protocol to completion.
+#[derive(Debug)]
+pub enum DriverEvent<O, E> {
+    /// Messages for a round have been sent.
+    RoundStarted(RoundNumber),
+    /// Message received for a round.
+    MessageReceived {
+        /// Round number.
+        round: RoundNumber,
+        /// Party number of the sender.
+        party: PartyNumber,
+    },
+    /// All the messages for a round have been received.
+    RoundCompleted(RoundNumber),
+    /// No protocol message has been received for the
+    /// stall interval of the driver.
+    Stalled {
+        /// Round the driver is waiting to complete.
+        round: Option<RoundNumber>,
+        /// Session party numbers of the participants that
+        /// have not sent a message for the round.
+        waiting_on: Vec<PartyNumber>,
+    },
+    /// Message tagged with the session identifier was
+    /// received from a peer that is not a participant
+    /// and has been dropped.
+    MessageDropped {
+        /// Public key of the peer.
+        peer_key: Vec<u8>,
+    },
+    /// Participant disconnected from the session; the
+    /// participant may rejoin before the protocol times out.
+    PeerLeft(PartyNumber),
+    /// Participant is connected but has stopped sending
+    /// session heartbeats.
+    PeerUnresponsive(PartyNumber),
+    /// Intermediate output produced whilst the protocol
+    /// continues, for example a presignature.
+    Intermediate(Intermediate),
+    /// Event for the next phase of a flow received whilst
+    /// the protocol was running, for example a barrier signal
+    /// from a participant that has already completed the
+    /// protocol; pass it to the next barrier.
+    Deferred(Event),
+    /// Protocol completed.
+    Completed {
+        /// Transport released by the driver.
+        transport: Transport,
+        /// Output of the protocol.
+        output: O,
+    },
+    /// Protocol failed.
+    Failed(E),
+}
+
+/// Reason the drive loop was woken.
+enum Wake {
+    Event(Option<mpc_client::Result<Event>>),
+    Deadline,
+    Stalled,
+    Heartbeat,
+}
+
+/// Drive a protocol to completion yielding progress events.
+///
+/// The stream ends after a completed or failed event; if the
+/// deadline for the driver expires a failed event is yielded
+/// with a timeout error.
+///
+/// When the driver has a stall interval a stalled event is
+/// yielded each time no protocol message is received for
+/// the interval.
+pub fn drive<'a, D>(
+    stream: &'a mut EventStream,
+    mut driver: D,
+) -> impl Stream<Item = DriverEvent<D::Output, D::Error>> + 'a
+where
+    D: Driver + Into<Transport> + Send + 'a,
+    D::Output: 'a,
+    D::Error: From<Box<Error>> + 'a,
+{
+    stream! {
+        if let Err(e) = driver.execute().await {
+            yield DriverEvent::Failed(e);
+            return;
+        }
+        for event in driver.take_events() {
+            yield event;
+        }
+        if let Some(output) = driver.take_output() {
+            yield DriverEvent::Completed {
+                transport: driver.into(),
+                output,
+            };
+            return;
+        }
+
+        // Watchdog instant that has already been reported
+        let mut stalled = None;
+        loop {
+            let deadline = driver.deadline();
+            let watchdog =
+                driver.watchdog().filter(|w| Some(*w) != stalled);
+            let heartbeat = driver.heartbeat();
+            let wake = select! {
+                event = stream.next().fuse() => Wake::Event(event),
+                _ = timer::sleep_until(deadline).fuse() => Wake::Deadline,
+                _ = timer::sleep_until(watchdog).fuse() => Wake::Stalled,
+                _ = timer::sleep_until(heartbeat).fuse() => Wake::Heartbeat,
+            };
+            let event = match wake {
+                Wake::Event(Some(Ok(event))) => event,
+                Wake::Event(Some(Err(e))) => {
+                    yield DriverEvent::Failed(e.into());
+                    return;
+                }
+                Wake::Event(None) => return,
+                Wake::Deadline => {
+                    let error = Error::Timeout(driver.progress());
+                    yield DriverEvent::Failed(Box::new(error).into());
+                    return;
+                }
+                Wake::Stalled => {
+                    stalled = watchdog;
+                    let progress = driver.progress();
+                    tracing::warn!(
+                        progress = %progress,
+                        "driver stalled",
+                    );
+                    yield DriverEvent::Stalled {
+                        round: progress.round,
+                        waiting_on: progress.waiting_on,
+                    };
+                    continue;
+                }
+                Wake::Heartbeat => {
+                    if let Err(e) = driver.send_heartbeat().await {
+                        yield DriverEvent::Failed(e);
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            match driver.handle_event(event).await {
+                Ok(result) => {
+                    for event in driver.take_events() {
+                        yield event;
+                    }
+                    if let Some(output) = result {
+                        yield DriverEvent::Completed {
+                            transport: driver.into(),
+                            output,
+                        };
+                        return;
+                    }
+                }
+                Err(e) => {
+                    yield DriverEvent::Failed(e);
+                    return;
+                }
+            }
+        }
+    }
+}
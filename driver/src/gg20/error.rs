@@ -16,6 +16,11 @@ pub enum Error {
     #[error("local key index not found in list of participants")]
     LocalKeyNotParticipant,
 
+    /// Error generated when the session participants are not
+    /// in the order of the local key indices of the signers.
+    #[error("party {0} is not in the order of the signers")]
+    SignerOrder(u16),
+
     /// Signature verification failed.
     #[error("failed to verify generated signature")]
     VerifySignature,
@@ -1,7 +1,6 @@
 //! Key generation for GG20.
-use async_trait::async_trait;
-use mpc_client::{Event, NetworkTransport, Transport};
-use mpc_protocol::{hex, Parameters, SessionState};
+use mpc_client::{NetworkTransport, Transport};
+use mpc_protocol::{hex, Parameters, PartyNumber, SessionState};
 use round_based::{Msg, StateMachine};
 
 use super::{Error, Result};
@@ -10,7 +9,7 @@ use crate::{
     gg_2020::state_machine::keygen::{
         Keygen, LocalKey, ProtocolMessage,
     },
-    Bridge, Driver, ProtocolDriver, RoundBuffer, RoundMsg,
+    Bridge, ProtocolDriver, RoundBuffer, RoundMsg,
 };
 
 /// Key share.
@@ -41,42 +40,36 @@ impl KeyGenDriver {
 
         let driver =
             KeygenDriver::new(parameters, party_number.into())?;
-        let bridge = Bridge {
-            transport,
-            driver: Some(driver),
-            buffer,
-            session,
-        };
+        let bridge = Bridge::new(transport, driver, buffer, session);
         Ok(Self { bridge })
     }
-}
 
-#[async_trait]
-impl Driver for KeyGenDriver {
-    type Error = Error;
-    type Output = LocalKey<Secp256k1>;
-
-    async fn handle_event(
-        &mut self,
-        event: Event,
-    ) -> Result<Option<Self::Output>> {
-        self.bridge.handle_event(event).await
-    }
-
-    async fn execute(&mut self) -> Result<()> {
-        self.bridge.execute().await
+    /// Replay the incoming messages in a recording.
+    ///
+    /// Used to reproduce key generation failures offline.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn replay(
+        parameters: Parameters,
+        party_number: PartyNumber,
+        recording: &crate::Recording,
+        encoding: crate::RoundEncoding,
+    ) -> Result<KeyShare> {
+        let buffer =
+            RoundBuffer::new_fixed(4, parameters.parties - 1);
+        let driver =
+            KeygenDriver::new(parameters, party_number.into())?;
+        crate::recorder::replay(driver, buffer, recording, encoding)
     }
 }
 
-impl From<KeyGenDriver> for Transport {
-    fn from(value: KeyGenDriver) -> Self {
-        value.bridge.transport
-    }
-}
+bridge_driver!(KeyGenDriver, LocalKey<Secp256k1>);
 
 /// GG20 keygen driver.
 struct KeygenDriver {
     inner: Keygen,
+    /// Messages queued by the state machine when it
+    /// proceeds automatically whilst handling incoming messages.
+    pending: Vec<RoundMsg<ProtocolMessage>>,
 }
 
 impl KeygenDriver {
@@ -91,6 +84,7 @@ impl KeygenDriver {
                 parameters.threshold,
                 parameters.parties,
             )?,
+            pending: vec![],
         })
     }
 }
@@ -101,19 +95,38 @@ impl ProtocolDriver for KeygenDriver {
     type Outgoing = RoundMsg<ProtocolMessage>;
     type Output = LocalKey<Secp256k1>;
 
+    const PROTOCOL: &'static str = "gg20-keygen";
+
     fn handle_incoming(
         &mut self,
         message: Self::Incoming,
     ) -> Result<()> {
         self.inner.handle_incoming(message)?;
+        let messages: Vec<_> =
+            self.inner.message_queue().drain(..).collect();
+        if !messages.is_empty() {
+            let round = self.inner.current_round();
+            self.pending
+                .append(&mut RoundMsg::from_round(round, messages));
+        }
         Ok(())
     }
 
+    fn wants_to_proceed(&self) -> bool {
+        self.inner.wants_to_proceed() || !self.pending.is_empty()
+    }
+
     fn proceed(&mut self) -> Result<Vec<Self::Outgoing>> {
-        self.inner.proceed()?;
-        let messages = self.inner.message_queue().drain(..).collect();
-        let round = self.inner.current_round();
-        Ok(RoundMsg::from_round(round, messages))
+        let mut outgoing: Vec<_> = self.pending.drain(..).collect();
+        if self.inner.wants_to_proceed() {
+            self.inner.proceed()?;
+            let messages =
+                self.inner.message_queue().drain(..).collect();
+            let round = self.inner.current_round();
+            outgoing
+                .append(&mut RoundMsg::from_round(round, messages));
+        }
+        Ok(outgoing)
     }
 
     fn finish(mut self) -> Result<Self::Output> {
@@ -1,5 +1,92 @@
 //! Driver for the GG2020 protocol.
 
+/// Implement the options, metrics and resume accessors and
+/// the driver trait for a type wrapping a bridge.
+macro_rules! bridge_driver {
+    ($name:ident, $output:ty) => {
+        impl $name {
+            /// Set the options for this driver.
+            pub fn with_options(
+                mut self,
+                options: $crate::DriverOptions,
+            ) -> Self {
+                self.bridge.options = options;
+                self
+            }
+
+            /// Metrics collected whilst driving the protocol.
+            pub fn metrics(&self) -> &$crate::DriverMetrics {
+                &self.bridge.metrics
+            }
+
+            /// Resume the protocol using a transport that
+            /// has rejoined the session.
+            ///
+            /// Once the channels to the other participants are
+            /// re-established the messages for the current round
+            /// are exchanged again so the protocol can continue.
+            pub async fn resume(
+                &mut self,
+                transport: mpc_client::Transport,
+            ) -> $crate::gg20::Result<()> {
+                self.bridge.resume(transport).await
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl $crate::Driver for $name {
+            type Error = $crate::gg20::Error;
+            type Output = $output;
+
+            async fn handle_event(
+                &mut self,
+                event: mpc_client::Event,
+            ) -> $crate::gg20::Result<Option<Self::Output>> {
+                self.bridge.handle_event(event).await
+            }
+
+            async fn execute(&mut self) -> $crate::gg20::Result<()> {
+                self.bridge.execute().await
+            }
+
+            fn progress(&self) -> $crate::Progress {
+                self.bridge.progress()
+            }
+
+            fn deadline(&self) -> Option<web_time::Instant> {
+                self.bridge.deadline
+            }
+
+            fn watchdog(&self) -> Option<web_time::Instant> {
+                self.bridge.watchdog()
+            }
+
+            fn heartbeat(&self) -> Option<web_time::Instant> {
+                self.bridge.heartbeat()
+            }
+
+            async fn send_heartbeat(
+                &mut self,
+            ) -> $crate::gg20::Result<()> {
+                self.bridge.send_heartbeat().await
+            }
+
+            fn take_events(
+                &mut self,
+            ) -> Vec<$crate::DriverEvent<Self::Output, Self::Error>>
+            {
+                std::mem::take(&mut self.bridge.events)
+            }
+        }
+
+        impl From<$name> for mpc_client::Transport {
+            fn from(value: $name) -> Self {
+                value.bridge.transport
+            }
+        }
+    };
+}
+
 mod error;
 mod keygen;
 mod sign;
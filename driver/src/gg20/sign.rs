@@ -1,6 +1,5 @@
 //! GG20 message signing.
-use async_trait::async_trait;
-use mpc_client::{Event, NetworkTransport, Transport};
+use mpc_client::{NetworkTransport, Transport};
 use mpc_protocol::{hex, Parameters, PartyNumber, SessionState};
 use round_based::{Msg, StateMachine};
 use serde::{Deserialize, Serialize};
@@ -22,7 +21,7 @@ use crate::{
             },
         },
     },
-    Bridge, Driver, ProtocolDriver, RoundBuffer, RoundMsg,
+    Bridge, Intermediate, ProtocolDriver, RoundBuffer, RoundMsg,
 };
 
 type Message = Msg<<OfflineStage as StateMachine>::MessageBody>;
@@ -69,38 +68,12 @@ impl ParticipantDriver {
             party_number.into(),
             local_key_index.into(),
         );
-        let bridge = Bridge {
-            transport,
-            driver: Some(driver),
-            buffer,
-            session,
-        };
+        let bridge = Bridge::new(transport, driver, buffer, session);
         Ok(Self { bridge })
     }
 }
 
-#[async_trait]
-impl Driver for ParticipantDriver {
-    type Error = Error;
-    type Output = Vec<u16>;
-
-    async fn handle_event(
-        &mut self,
-        event: Event,
-    ) -> Result<Option<Self::Output>> {
-        self.bridge.handle_event(event).await
-    }
-
-    async fn execute(&mut self) -> Result<()> {
-        self.bridge.execute().await
-    }
-}
-
-impl From<ParticipantDriver> for Transport {
-    fn from(value: ParticipantDriver) -> Self {
-        value.bridge.transport
-    }
-}
+bridge_driver!(ParticipantDriver, Vec<u16>);
 
 /// GG20 presign generator.
 pub struct PreSignDriver {
@@ -109,6 +82,10 @@ pub struct PreSignDriver {
 
 impl PreSignDriver {
     /// Create a new GG20 key generator.
+    ///
+    /// The session participants must be in the order of
+    /// their local key indices so the party numbers of the
+    /// offline stage are the party numbers in the session.
     pub fn new(
         transport: Transport,
         parameters: Parameters,
@@ -123,45 +100,35 @@ impl PreSignDriver {
             .map(|pos| pos + 1)
             .ok_or_else(|| Error::LocalKeyNotParticipant)?
             as u16;
+        let party_number = session
+            .party_number(transport.public_key())
+            .ok_or_else(|| {
+                Error::NotSessionParticipant(hex::encode(
+                    transport.public_key(),
+                ))
+            })?;
+        if party_number.get() != party_index
+            || session.parties().len() != participants.len()
+        {
+            return Err(Error::SignerOrder(party_number.get()));
+        }
         let driver = SignOfflineDriver::new(
             party_index,
             participants,
             local_key,
         )?;
-        let bridge = Bridge {
-            transport,
-            driver: Some(driver),
-            buffer,
-            session,
-        };
+        let bridge = Bridge::new(transport, driver, buffer, session);
         Ok(Self { bridge })
     }
 }
 
-#[async_trait]
-impl Driver for PreSignDriver {
-    type Error = Error;
-    type Output = CompletedOfflineStage;
-
-    async fn handle_event(
-        &mut self,
-        event: Event,
-    ) -> Result<Option<Self::Output>> {
-        self.bridge.handle_event(event).await
-    }
-
-    async fn execute(&mut self) -> Result<()> {
-        self.bridge.execute().await
-    }
-}
-
-impl From<PreSignDriver> for Transport {
-    fn from(value: PreSignDriver) -> Self {
-        value.bridge.transport
-    }
-}
+bridge_driver!(PreSignDriver, CompletedOfflineStage);
 
 /// GG20 signature generator.
+///
+/// The local partial signature is yielded as an
+/// intermediate output named `partialSignature` once it
+/// has been sent to the other signers.
 pub struct SignatureDriver {
     bridge: Bridge<SignOnlineDriver>,
 }
@@ -191,38 +158,12 @@ impl SignatureDriver {
             message,
         )?;
 
-        let bridge = Bridge {
-            transport,
-            driver: Some(driver),
-            buffer,
-            session,
-        };
+        let bridge = Bridge::new(transport, driver, buffer, session);
         Ok(Self { bridge })
     }
 }
 
-#[async_trait]
-impl Driver for SignatureDriver {
-    type Error = Error;
-    type Output = Signature;
-
-    async fn handle_event(
-        &mut self,
-        event: Event,
-    ) -> Result<Option<Self::Output>> {
-        self.bridge.handle_event(event).await
-    }
-
-    async fn execute(&mut self) -> Result<()> {
-        self.bridge.execute().await
-    }
-}
-
-impl From<SignatureDriver> for Transport {
-    fn from(value: SignatureDriver) -> Self {
-        value.bridge.transport
-    }
-}
+bridge_driver!(SignatureDriver, Signature);
 
 /// GG20 local key participant number exchange.
 ///
@@ -235,6 +176,7 @@ struct ParticipantProtocolDriver {
     party_number: u16,
     participants: Vec<u16>,
     local_key_index: u16,
+    sent: bool,
 }
 
 impl ParticipantProtocolDriver {
@@ -244,6 +186,7 @@ impl ParticipantProtocolDriver {
             party_number,
             participants: vec![local_key_index],
             local_key_index,
+            sent: false,
         }
     }
 }
@@ -254,6 +197,8 @@ impl ProtocolDriver for ParticipantProtocolDriver {
     type Outgoing = RoundMsg<u16>;
     type Output = Vec<u16>;
 
+    const PROTOCOL: &'static str = "gg20-participant";
+
     fn handle_incoming(
         &mut self,
         message: Self::Incoming,
@@ -262,12 +207,17 @@ impl ProtocolDriver for ParticipantProtocolDriver {
         Ok(())
     }
 
+    fn wants_to_proceed(&self) -> bool {
+        !self.sent
+    }
+
     fn proceed(&mut self) -> Result<Vec<Self::Outgoing>> {
         let messages = vec![Msg {
             sender: self.party_number,
             receiver: None,
             body: self.local_key_index,
         }];
+        self.sent = true;
         Ok(RoundMsg::from_round(1, messages))
     }
 
@@ -280,6 +230,9 @@ impl ProtocolDriver for ParticipantProtocolDriver {
 /// Drive the offline signing stage.
 struct SignOfflineDriver {
     inner: OfflineStage,
+    /// Messages queued by the state machine when it
+    /// proceeds automatically whilst handling incoming messages.
+    pending: Vec<RoundMsg<OfflineProtocolMessage>>,
 }
 
 impl SignOfflineDriver {
@@ -291,6 +244,7 @@ impl SignOfflineDriver {
     ) -> Result<SignOfflineDriver> {
         Ok(SignOfflineDriver {
             inner: OfflineStage::new(index, participants, local_key)?,
+            pending: vec![],
         })
     }
 }
@@ -301,19 +255,38 @@ impl ProtocolDriver for SignOfflineDriver {
     type Outgoing = RoundMsg<OfflineProtocolMessage>;
     type Output = CompletedOfflineStage;
 
+    const PROTOCOL: &'static str = "gg20-sign-offline";
+
     fn handle_incoming(
         &mut self,
         message: Self::Incoming,
     ) -> Result<()> {
         self.inner.handle_incoming(message)?;
+        let messages: Vec<_> =
+            self.inner.message_queue().drain(..).collect();
+        if !messages.is_empty() {
+            let round = self.inner.current_round();
+            self.pending
+                .append(&mut RoundMsg::from_round(round, messages));
+        }
         Ok(())
     }
 
+    fn wants_to_proceed(&self) -> bool {
+        self.inner.wants_to_proceed() || !self.pending.is_empty()
+    }
+
     fn proceed(&mut self) -> Result<Vec<Self::Outgoing>> {
-        self.inner.proceed()?;
-        let messages = self.inner.message_queue().drain(..).collect();
-        let round = self.inner.current_round();
-        Ok(RoundMsg::from_round(round, messages))
+        let mut outgoing: Vec<_> = self.pending.drain(..).collect();
+        if self.inner.wants_to_proceed() {
+            self.inner.proceed()?;
+            let messages =
+                self.inner.message_queue().drain(..).collect();
+            let round = self.inner.current_round();
+            outgoing
+                .append(&mut RoundMsg::from_round(round, messages));
+        }
+        Ok(outgoing)
     }
 
     fn finish(mut self) -> Result<Self::Output> {
@@ -329,6 +302,9 @@ struct SignOnlineDriver {
     partial: PartialSignature,
     sign: SignManual,
     partials: Vec<PartialSignature>,
+    sent: bool,
+    /// Intermediate outputs not yet taken by the bridge.
+    intermediate: Vec<Intermediate>,
 }
 
 impl SignOnlineDriver {
@@ -349,6 +325,8 @@ impl SignOnlineDriver {
             partial,
             data,
             partials: vec![],
+            sent: false,
+            intermediate: vec![],
         })
     }
 }
@@ -359,6 +337,8 @@ impl ProtocolDriver for SignOnlineDriver {
     type Outgoing = RoundMsg<PartialSignature>;
     type Output = Signature;
 
+    const PROTOCOL: &'static str = "gg20-sign-online";
+
     fn handle_incoming(
         &mut self,
         message: Self::Incoming,
@@ -367,15 +347,32 @@ impl ProtocolDriver for SignOnlineDriver {
         Ok(())
     }
 
+    fn wants_to_proceed(&self) -> bool {
+        !self.sent
+    }
+
     fn proceed(&mut self) -> Result<Vec<Self::Outgoing>> {
         let messages = vec![Msg {
             sender: self.party_number,
             receiver: None,
             body: self.partial.clone(),
         }];
+        self.sent = true;
+        // Yield the partial signature so it can be recorded
+        // before the other partial signatures are received
+        let value = serde_json::to_value(&self.partial)
+            .map_err(|e| Box::new(crate::Error::from(e)))?;
+        self.intermediate.push(Intermediate {
+            name: "partialSignature".to_string(),
+            value,
+        });
         Ok(RoundMsg::from_round(1, messages))
     }
 
+    fn take_intermediate(&mut self) -> Vec<Intermediate> {
+        std::mem::take(&mut self.intermediate)
+    }
+
     fn finish(self) -> Result<Self::Output> {
         let signature = self.sign.clone().complete(&self.partials)?;
         verify(&signature, &self.public_key, &self.data)
@@ -0,0 +1,192 @@
+//! Identity keys for signing round messages.
+use ed25519_dalek::{
+    Signature, Signer, SigningKey, Verifier, VerifyingKey,
+};
+use mpc_protocol::{hex, SessionId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{Error, Result};
+
+/// Round message signed with the identity key of the sender.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Signed {
+    /// Encoded round message.
+    #[serde(with = "hex::serde")]
+    pub payload: Vec<u8>,
+    /// Signature over the session identifier and payload.
+    #[serde(with = "hex::serde")]
+    pub signature: Vec<u8>,
+}
+
+/// Long-term identity keys used to sign round messages.
+///
+/// Signatures bind each round message to the session so that
+/// the messages of a misbehaving participant can be proven
+/// to third parties after an identifiable abort.
+#[derive(Clone)]
+pub struct IdentityKeys {
+    /// Key used to sign our round messages.
+    pub signing_key: SigningKey,
+    /// Verifying keys of the other participants keyed by
+    /// the transport public key of each participant.
+    pub verifying_keys: HashMap<Vec<u8>, VerifyingKey>,
+}
+
+impl IdentityKeys {
+    /// Sign a round message.
+    pub(crate) fn sign(
+        &self,
+        session_id: &SessionId,
+        message: &[u8],
+    ) -> Vec<u8> {
+        self.signing_key
+            .sign(&signed_bytes(session_id, message))
+            .to_bytes()
+            .to_vec()
+    }
+
+    /// Verify a round message sent by a participant.
+    pub(crate) fn verify(
+        &self,
+        session_id: &SessionId,
+        peer_key: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        let verifying_key =
+            self.verifying_keys.get(peer_key).ok_or_else(|| {
+                Error::UnknownIdentity(hex::encode(peer_key))
+            })?;
+        verify_signature(
+            verifying_key,
+            session_id,
+            message,
+            signature,
+        )
+    }
+}
+
+/// Verify the signature for a round message.
+///
+/// Use this to check the signature of a recorded round
+/// message against the identity key of the sender.
+pub fn verify_signature(
+    verifying_key: &VerifyingKey,
+    session_id: &SessionId,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let signature = Signature::from_slice(signature)
+        .map_err(|_| Error::InvalidSignature)?;
+    verifying_key
+        .verify(&signed_bytes(session_id, message), &signature)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+/// Bytes covered by the signature for a round message.
+fn signed_bytes(session_id: &SessionId, message: &[u8]) -> Vec<u8> {
+    let mut bytes = session_id.as_bytes().to_vec();
+    bytes.extend_from_slice(message);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdentityKeys;
+    use crate::Error;
+    use ed25519_dalek::SigningKey;
+    use mpc_protocol::SessionId;
+    use std::collections::HashMap;
+
+    /// Identity keys for the receiver that trust the
+    /// sender key for peer 1.
+    fn identities() -> (IdentityKeys, IdentityKeys) {
+        let sender = SigningKey::from_bytes(&[1; 32]);
+        let receiver = SigningKey::from_bytes(&[2; 32]);
+        let mut verifying_keys = HashMap::new();
+        verifying_keys.insert(vec![1], sender.verifying_key());
+        (
+            IdentityKeys {
+                signing_key: sender,
+                verifying_keys: HashMap::new(),
+            },
+            IdentityKeys {
+                signing_key: receiver,
+                verifying_keys,
+            },
+        )
+    }
+
+    #[test]
+    fn identity_sign_verify() -> crate::Result<()> {
+        let (sender, receiver) = identities();
+        let session_id = SessionId::new_v4();
+        let signature = sender.sign(&session_id, b"message");
+        receiver.verify(&session_id, &[1], b"message", &signature)
+    }
+
+    #[test]
+    fn identity_signature_bound_to_session() {
+        let (sender, receiver) = identities();
+        let signature = sender.sign(&SessionId::new_v4(), b"message");
+        let result = receiver.verify(
+            &SessionId::new_v4(),
+            &[1],
+            b"message",
+            &signature,
+        );
+        assert!(matches!(result, Err(Error::InvalidSignature)));
+    }
+
+    #[test]
+    fn identity_tampered_message() {
+        let (sender, receiver) = identities();
+        let session_id = SessionId::new_v4();
+        let signature = sender.sign(&session_id, b"message");
+        let result = receiver.verify(
+            &session_id,
+            &[1],
+            b"tampered",
+            &signature,
+        );
+        assert!(matches!(result, Err(Error::InvalidSignature)));
+    }
+
+    #[test]
+    fn identity_impersonation() {
+        let (_, receiver) = identities();
+        let session_id = SessionId::new_v4();
+        // Signed by a key that is not trusted for the peer
+        let signature = receiver.sign(&session_id, b"message");
+        let result = receiver.verify(
+            &session_id,
+            &[1],
+            b"message",
+            &signature,
+        );
+        assert!(matches!(result, Err(Error::InvalidSignature)));
+
+        let result = receiver.verify(
+            &session_id,
+            &[3],
+            b"message",
+            &signature,
+        );
+        assert!(matches!(result, Err(Error::UnknownIdentity(_))));
+    }
+
+    #[test]
+    fn identity_malformed_signature() {
+        let (sender, receiver) = identities();
+        let session_id = SessionId::new_v4();
+        let signature = sender.sign(&session_id, b"message");
+        let result = receiver.verify(
+            &session_id,
+            &[1],
+            b"message",
+            &signature[..32],
+        );
+        assert!(matches!(result, Err(Error::InvalidSignature)));
+    }
+}
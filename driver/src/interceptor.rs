@@ -0,0 +1,55 @@
+//! Hooks for inspecting protocol messages.
+use mpc_protocol::{PartyNumber, RoundNumber};
+
+use crate::RoundMetrics;
+
+/// Decision made by an interceptor for an incoming message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Accept the message.
+    Accept,
+    /// Ignore the message.
+    Drop,
+    /// Reject the message and fail the protocol.
+    Reject(String),
+}
+
+/// View of a round message passed to interceptors.
+#[derive(Debug)]
+pub struct RoundMessage<'a> {
+    /// Round number.
+    pub round: RoundNumber,
+    /// Party number of the sender.
+    pub sender: PartyNumber,
+    /// Party number of the receiver for peer to peer messages.
+    pub receiver: Option<PartyNumber>,
+    /// Serialized message.
+    pub payload: &'a [u8],
+    /// Signature for the message when identity keys are used.
+    pub signature: Option<&'a [u8]>,
+}
+
+/// Inspects the round messages sent and received by a driver.
+///
+/// Interceptors are called in the order they were registered;
+/// for incoming messages the first decision that does not
+/// accept the message wins.
+pub trait Interceptor: Send + Sync {
+    /// Called before an outgoing message is sent.
+    fn on_outgoing(&self, _message: &RoundMessage<'_>) {}
+
+    /// Called when an incoming message is received before
+    /// it is buffered for the round.
+    fn on_incoming(&self, _message: &RoundMessage<'_>) -> Decision {
+        Decision::Accept
+    }
+
+    /// Called when all the messages for a round have been
+    /// received and the round is completed.
+    fn on_round_complete(
+        &self,
+        _round: RoundNumber,
+        _metrics: &RoundMetrics,
+    ) {
+    }
+}
@@ -4,27 +4,72 @@
 use async_trait::async_trait;
 use mpc_client::{Client, ClientOptions, Event, EventLoop};
 
+mod barrier;
+mod boxed;
 mod bridge;
+mod callbacks;
+mod chunk;
+mod dispatcher;
+mod echo;
+mod encoding;
 mod error;
+mod event;
+mod identity;
+mod interceptor;
+mod message;
+mod metrics;
+mod observer;
+mod options;
+mod padding;
+mod progress;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod recorder;
 mod round;
 mod session;
+mod storage;
+mod timer;
+mod transcript;
 mod types;
 
+pub use barrier::Barrier;
+pub use boxed::BoxedDriver;
 pub(crate) use bridge::Bridge;
 pub use bridge::{
-    wait_for_close, wait_for_driver, wait_for_session_finish,
+    wait_for_close, wait_for_close_with, wait_for_driver,
+    wait_for_driver_with, wait_for_session_finish,
+    wait_for_session_finish_with,
 };
-pub use error::Error;
+pub use callbacks::{SessionCallbacks, WithCallbacks};
+pub use dispatcher::{wait_for_dispatcher, Dispatched, Dispatcher};
+pub use encoding::RoundEncoding;
+pub use error::{Error, ErrorContext};
+pub use event::{drive, DriverEvent, Intermediate};
+pub use identity::{verify_signature, IdentityKeys};
+pub use interceptor::{Decision, Interceptor, RoundMessage};
+pub use metrics::{DriverMetrics, RoundMetrics};
+pub use observer::{Observed, Observer};
+pub use options::{DriverOptions, RetryPolicy, WaitOptions};
+pub use progress::Progress;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use recorder::{Direction, RecordedMessage, Recorder, Recording};
 pub(crate) use round::{Round, RoundBuffer, RoundMsg};
 pub use session::{
-    wait_for_session, SessionEventHandler, SessionHandler,
-    SessionInitiator, SessionParticipant,
+    wait_for_session, wait_for_session_with, JoinPolicy,
+    SessionEventHandler, SessionHandler, SessionInitiator,
+    SessionParticipant, SessionRouter, SessionStatus,
 };
+pub use storage::{MemoryStorage, Storage};
 pub use types::*;
 
 /// Result type for the driver library.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Round or party number for tests.
+#[cfg(test)]
+pub(crate) fn number(value: u16) -> std::num::NonZeroU16 {
+    std::num::NonZeroU16::new(value).unwrap()
+}
+
 #[cfg(feature = "gg20")]
 pub mod gg20;
 
@@ -56,13 +101,66 @@ pub trait Driver {
     async fn execute(
         &mut self,
     ) -> std::result::Result<(), Self::Error>;
+
+    /// Progress of the protocol.
+    fn progress(&self) -> Progress {
+        Default::default()
+    }
+
+    /// Time by which the protocol must complete.
+    ///
+    /// In webassembly the deadline is scheduled with a
+    /// browser timer.
+    fn deadline(&self) -> Option<web_time::Instant> {
+        None
+    }
+
+    /// Time after which the driver is considered stalled
+    /// unless a protocol message is received.
+    fn watchdog(&self) -> Option<web_time::Instant> {
+        None
+    }
+
+    /// Time the next session heartbeat is due.
+    fn heartbeat(&self) -> Option<web_time::Instant> {
+        None
+    }
+
+    /// Send a session heartbeat to the server.
+    async fn send_heartbeat(
+        &mut self,
+    ) -> std::result::Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Take the progress events recorded since the last call.
+    ///
+    /// Drivers may bound the number of events kept whilst
+    /// they are not taken by dropping the oldest ones.
+    fn take_events(
+        &mut self,
+    ) -> Vec<DriverEvent<Self::Output, Self::Error>> {
+        Vec::new()
+    }
+
+    /// Take the output of a driver that completed when it
+    /// was started without handling another event.
+    ///
+    /// For example a barrier that every other participant
+    /// signaled before it was executed.
+    fn take_output(&mut self) -> Option<Self::Output> {
+        None
+    }
 }
 
 /// Trait for implementations that drive
 /// protocol to completion.
 pub(crate) trait ProtocolDriver {
     /// Error type for results.
-    type Error: std::fmt::Debug
+    type Error: std::error::Error
+        + Send
+        + Sync
+        + 'static
         + From<mpc_client::Error>
         + From<Box<crate::Error>>;
     /// Incoming message type.
@@ -72,22 +170,31 @@ pub(crate) trait ProtocolDriver {
     /// Output when the protocol is completed.
     type Output;
 
+    /// Name of the protocol used in error context.
+    const PROTOCOL: &'static str;
+
     /// Handle an incoming message.
     fn handle_incoming(
         &mut self,
         message: Self::Incoming,
     ) -> std::result::Result<(), Self::Error>;
 
-    /*
     /// Determine if the protocol wants to proceed.
     fn wants_to_proceed(&self) -> bool;
-    */
 
     /// Proceed to the next round.
     fn proceed(
         &mut self,
     ) -> std::result::Result<Vec<Self::Outgoing>, Self::Error>;
 
+    /// Take the intermediate outputs produced since the last call.
+    ///
+    /// Called after incoming messages are handled and after
+    /// proceeding to the next round.
+    fn take_intermediate(&mut self) -> Vec<Intermediate> {
+        Vec::new()
+    }
+
     /// Complete the protocol and get the output.
     fn finish(self)
         -> std::result::Result<Self::Output, Self::Error>;
@@ -148,11 +255,11 @@ pub(crate) async fn new_client(
     options: SessionOptions,
 ) -> Result<(Client, EventLoop)> {
     let server_url = options.server.server_url;
-    let options = ClientOptions {
-        keypair: options.keypair,
-        server_public_key: options.server.server_public_key,
-        pattern: options.server.pattern,
-    };
-    let url = options.url(&server_url);
-    Ok(Client::new(&url, options).await?)
+    let mut client_options = ClientOptions::new(
+        options.keypair,
+        options.server.server_public_key,
+    );
+    client_options.pattern = options.server.pattern;
+    let url = client_options.url(&server_url);
+    Ok(Client::new(&url, client_options).await?)
 }
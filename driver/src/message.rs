@@ -0,0 +1,26 @@
+//! Messages exchanged between bridges.
+use mpc_protocol::RoundNumber;
+use serde::{Deserialize, Serialize};
+
+use crate::{chunk::Chunk, echo::Echo, identity::Signed};
+
+/// Message sent between the bridges of session participants.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BridgeMessage<O> {
+    /// Round message for the protocol driver.
+    Round(O),
+    /// Notify participants that a party aborted the protocol.
+    Abort(String),
+    /// Signal that a party reached a named barrier.
+    Barrier(String),
+    /// Confirm the hash of a broadcast message.
+    Echo(Echo),
+    /// Part of a round message that was split into chunks.
+    Chunk(Chunk),
+    /// Round message signed with an identity key.
+    Signed(Signed),
+    /// Request retransmission of round messages starting
+    /// at a round after a channel was re-established.
+    Retransmit(RoundNumber),
+}
@@ -0,0 +1,85 @@
+//! Metrics collected whilst driving a protocol.
+use mpc_protocol::RoundNumber;
+use std::{collections::BTreeMap, time::Duration};
+use web_time::Instant;
+
+/// Metrics for a protocol round.
+#[derive(Debug, Clone, Default)]
+pub struct RoundMetrics {
+    /// Time the first message for the round was sent or received.
+    pub started: Option<Instant>,
+    /// Time taken to complete the round.
+    pub duration: Option<Duration>,
+    /// Number of messages sent.
+    pub messages_sent: usize,
+    /// Number of messages received.
+    pub messages_received: usize,
+    /// Number of bytes sent.
+    pub bytes_sent: usize,
+    /// Number of bytes received.
+    pub bytes_received: usize,
+}
+
+impl RoundMetrics {
+    fn touch(&mut self) {
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+    }
+}
+
+/// Metrics collected by a driver.
+#[derive(Debug, Clone, Default)]
+pub struct DriverMetrics {
+    /// Metrics for each round.
+    pub rounds: BTreeMap<RoundNumber, RoundMetrics>,
+}
+
+impl DriverMetrics {
+    /// Total time taken by the completed rounds.
+    pub fn duration(&self) -> Duration {
+        self.rounds.values().filter_map(|r| r.duration).sum()
+    }
+
+    /// Total number of bytes sent.
+    pub fn bytes_sent(&self) -> usize {
+        self.rounds.values().map(|r| r.bytes_sent).sum()
+    }
+
+    /// Total number of bytes received.
+    pub fn bytes_received(&self) -> usize {
+        self.rounds.values().map(|r| r.bytes_received).sum()
+    }
+
+    /// Record a message sent in a round.
+    pub(crate) fn sent(&mut self, round: RoundNumber, bytes: usize) {
+        let metrics = self.rounds.entry(round).or_default();
+        metrics.touch();
+        metrics.messages_sent += 1;
+        metrics.bytes_sent += bytes;
+    }
+
+    /// Record a message received in a round.
+    pub(crate) fn received(
+        &mut self,
+        round: RoundNumber,
+        bytes: usize,
+    ) {
+        let metrics = self.rounds.entry(round).or_default();
+        metrics.touch();
+        metrics.messages_received += 1;
+        metrics.bytes_received += bytes;
+    }
+
+    /// Mark a round as completed.
+    pub(crate) fn complete(
+        &mut self,
+        round: RoundNumber,
+    ) -> &RoundMetrics {
+        let metrics = self.rounds.entry(round).or_default();
+        metrics.touch();
+        metrics.duration =
+            metrics.started.map(|started| started.elapsed());
+        metrics
+    }
+}
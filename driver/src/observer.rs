@@ -0,0 +1,234 @@
+//! Observe the broadcast messages of a session.
+use mpc_client::Event;
+use mpc_protocol::{hex, PartyNumber, RoundNumber, SessionState};
+
+use crate::{
+    chunk::ChunkBuffer, identity::Signed, message::BridgeMessage,
+    padding, round::RoundHeader, DriverOptions, Error, Result,
+    RoundEncoding,
+};
+
+/// Broadcast round message received by an observer.
+#[derive(Debug, Clone)]
+pub struct Observed {
+    /// Round number.
+    pub round: RoundNumber,
+    /// Party number of the sender.
+    pub sender: PartyNumber,
+    /// Encoded round message.
+    pub payload: Vec<u8>,
+}
+
+/// Receives the broadcast messages for a session without
+/// taking part in the protocol.
+///
+/// The public key of an observer must be assigned the
+/// observer role when the session is created so the other
+/// participants do not wait on it to complete a round.
+///
+/// The options must match the encoding, padding and identity
+/// settings of the participants.
+pub struct Observer {
+    session: SessionState,
+    options: DriverOptions,
+    chunks: ChunkBuffer,
+}
+
+impl Observer {
+    /// Create a new observer.
+    pub fn new(
+        session: SessionState,
+        options: DriverOptions,
+    ) -> Self {
+        Self {
+            session,
+            options,
+            chunks: Default::default(),
+        }
+    }
+
+    /// Session state.
+    pub fn session(&self) -> &SessionState {
+        &self.session
+    }
+
+    /// Handle an event from the client event loop stream.
+    ///
+    /// Returns the broadcast round messages; other events
+    /// are ignored.
+    pub fn handle_event(
+        &mut self,
+        event: Event,
+    ) -> Result<Option<Observed>> {
+        let (peer_key, session_id, bytes, encoding) = match event {
+            Event::JsonMessage {
+                peer_key,
+                message,
+                session_id,
+            } => (
+                peer_key,
+                session_id,
+                message.as_bytes().to_vec(),
+                RoundEncoding::Json,
+            ),
+            Event::BinaryMessage {
+                peer_key,
+                message,
+                session_id,
+            } => {
+                let message = if self.options.padding.is_some() {
+                    padding::unpad(message)?
+                } else {
+                    message
+                };
+                (peer_key, session_id, message, self.options.encoding)
+            }
+            Event::SessionExpired(session_id)
+                if session_id == self.session.session_id =>
+            {
+                return Err(Error::SessionExpired(session_id));
+            }
+            _ => return Ok(None),
+        };
+
+        match session_id {
+            Some(session_id)
+                if session_id == self.session.session_id => {}
+            Some(_) => return Err(Error::SessionIdMismatch),
+            None => return Err(Error::SessionIdRequired),
+        }
+
+        if !self.session.is_participant(&peer_key) {
+            tracing::warn!(
+                from = ?hex::encode(&peer_key),
+                "drop message from non-participant",
+            );
+            return Ok(None);
+        }
+
+        self.handle_message(&peer_key, bytes, encoding, true)
+    }
+
+    fn handle_message(
+        &mut self,
+        peer_key: &[u8],
+        bytes: Vec<u8>,
+        encoding: RoundEncoding,
+        allow_chunks: bool,
+    ) -> Result<Option<Observed>> {
+        let message: BridgeMessage<RoundHeader> =
+            encoding.decode(&bytes)?;
+        match message {
+            BridgeMessage::Round(header) => {
+                if self.options.identity.is_some() {
+                    return Err(Error::MissingSignature(
+                        header.sender.get(),
+                    ));
+                }
+                self.observe(peer_key, header, bytes)
+            }
+            BridgeMessage::Signed(signed) => {
+                self.handle_signed(peer_key, signed, encoding)
+            }
+            BridgeMessage::Chunk(chunk) if allow_chunks => {
+                let size = self.options.max_message_size.ok_or_else(
+                    || {
+                        Error::InvalidChunk(
+                            "chunked messages are disabled"
+                                .to_string(),
+                        )
+                    },
+                )?;
+                match self.chunks.receive(peer_key, chunk, size)? {
+                    Some(bytes) => self.handle_message(
+                        peer_key, bytes, encoding, false,
+                    ),
+                    None => Ok(None),
+                }
+            }
+            BridgeMessage::Chunk(_) => Err(Error::InvalidChunk(
+                "chunks must contain a round message".to_string(),
+            )),
+            BridgeMessage::Abort(reason) => {
+                let party = self.party_number(peer_key)?;
+                Err(Error::RemoteAbort {
+                    party: party.get(),
+                    reason,
+                })
+            }
+            BridgeMessage::Echo(_)
+            | BridgeMessage::Retransmit(_)
+            | BridgeMessage::Barrier(_) => Ok(None),
+        }
+    }
+
+    /// Verify a signed round message.
+    fn handle_signed(
+        &self,
+        peer_key: &[u8],
+        signed: Signed,
+        encoding: RoundEncoding,
+    ) -> Result<Option<Observed>> {
+        let identity = match &self.options.identity {
+            Some(identity) => identity,
+            None => {
+                let party = self.party_number(peer_key)?;
+                return Err(Error::UnexpectedSignature(party.get()));
+            }
+        };
+        if identity
+            .verify(
+                &self.session.session_id,
+                peer_key,
+                &signed.payload,
+                &signed.signature,
+            )
+            .is_err()
+        {
+            let party = self.party_number(peer_key)?;
+            return Err(Error::SignatureRejected(party.get()));
+        }
+
+        let message: BridgeMessage<RoundHeader> =
+            encoding.decode(&signed.payload)?;
+        match message {
+            BridgeMessage::Round(header) => {
+                self.observe(peer_key, header, signed.payload)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn observe(
+        &self,
+        peer_key: &[u8],
+        header: RoundHeader,
+        payload: Vec<u8>,
+    ) -> Result<Option<Observed>> {
+        let party = self.party_number(peer_key)?;
+        if header.sender != party {
+            return Err(Error::SenderMismatch {
+                peer_key: hex::encode(peer_key),
+                expected: party.get(),
+                actual: header.sender.get(),
+            });
+        }
+
+        // Peer to peer messages are not addressed to observers
+        if header.receiver.is_some() {
+            return Ok(None);
+        }
+
+        Ok(Some(Observed {
+            round: header.round,
+            sender: header.sender,
+            payload,
+        }))
+    }
+
+    fn party_number(&self, peer_key: &[u8]) -> Result<PartyNumber> {
+        self.session.party_number(peer_key).ok_or_else(|| {
+            Error::UnknownSender(hex::encode(peer_key))
+        })
+    }
+}
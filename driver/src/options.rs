@@ -0,0 +1,125 @@
+//! Options for protocol drivers.
+use std::{sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use web_time::Instant;
+
+use crate::{IdentityKeys, Interceptor, RoundEncoding};
+
+/// Options for a protocol driver.
+#[derive(Clone, Default)]
+pub struct DriverOptions {
+    /// Interceptors for inspecting round messages.
+    pub interceptors: Vec<Arc<dyn Interceptor>>,
+    /// Cross-confirm broadcast messages with the other
+    /// participants before they are delivered to the driver.
+    ///
+    /// All participants in a session must use the same setting.
+    pub echo_broadcast: bool,
+    /// Relay broadcast messages in a total order assigned
+    /// by the server so every participant receives the
+    /// broadcasts in a session in the same order.
+    ///
+    /// All participants in a session must use the same setting.
+    pub ordered_broadcast: bool,
+    /// Encoding for round messages.
+    pub encoding: RoundEncoding,
+    /// Split encoded round messages larger than this
+    /// number of bytes into chunks.
+    ///
+    /// Chunks received from other participants are rejected
+    /// when this is not set or a chunk is larger; all
+    /// participants in a session must use the same setting.
+    pub max_message_size: Option<usize>,
+    /// Pad encoded round messages to a multiple of this
+    /// number of bytes so message lengths do not reveal
+    /// the protocol round or the role of a party.
+    ///
+    /// Padded messages are always sent as binary messages
+    /// without compression so the compressed size does not
+    /// reveal the length; all participants in a session must
+    /// use the same setting.
+    pub padding: Option<usize>,
+    /// Maximum time allowed for the protocol to complete
+    /// once execution has started.
+    pub timeout: Option<Duration>,
+    /// Report the driver as stalled when no protocol message
+    /// is received for this interval.
+    pub stall_interval: Option<Duration>,
+    /// Sign round messages with an identity key and require
+    /// valid signatures on the messages of all participants.
+    pub identity: Option<IdentityKeys>,
+    /// Policy for retrying transient failures when sending
+    /// round messages.
+    pub retry: RetryPolicy,
+    /// Fail immediately when a participant disconnects from
+    /// the session instead of waiting for them to rejoin.
+    pub abort_on_peer_left: bool,
+    /// Send a session heartbeat to the server at this
+    /// interval while the protocol is running.
+    ///
+    /// Must be shorter than the heartbeat timeout of the
+    /// server for the other participants to detect when
+    /// this participant stops responding.
+    pub heartbeat_interval: Option<Duration>,
+}
+
+/// Policy for retrying failed sends.
+///
+/// The delay between attempts doubles after each
+/// attempt up to the maximum delay.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts for each send; a value
+    /// of one disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_delay: Duration,
+    /// Maximum delay between attempts.
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Options for waiting on a driver or event.
+#[derive(Debug, Clone, Default)]
+pub struct WaitOptions {
+    /// Maximum time to wait.
+    pub timeout: Option<Duration>,
+    /// Token used to cancel the wait.
+    pub cancel: Option<CancellationToken>,
+}
+
+impl WaitOptions {
+    /// Deadline for the wait relative to now.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| Instant::now() + timeout)
+    }
+
+    /// Wait until the token is cancelled.
+    ///
+    /// When there is no token the future never completes.
+    pub(crate) async fn cancelled(&self) {
+        match &self.cancel {
+            Some(token) => token.cancelled().await,
+            None => std::future::pending().await,
+        }
+    }
+}
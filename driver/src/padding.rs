@@ -0,0 +1,50 @@
+//! Pad round messages to fixed size buckets.
+use crate::{Error, Result};
+
+/// Size of the length prefix for a padded message.
+const LENGTH_PREFIX: usize = 4;
+
+/// Pad a message to a multiple of the bucket size.
+///
+/// The message is prefixed with its length so that
+/// the padding can be removed by the receiver.
+pub(crate) fn pad(
+    message: Vec<u8>,
+    bucket: usize,
+) -> Result<Vec<u8>> {
+    let length = u32::try_from(message.len()).map_err(|_| {
+        Error::InvalidPadding(
+            "message is too large to pad".to_string(),
+        )
+    })?;
+    let bucket = bucket.max(1);
+    let size =
+        (message.len() + LENGTH_PREFIX).div_ceil(bucket) * bucket;
+    let mut padded = Vec::with_capacity(size);
+    padded.extend_from_slice(&length.to_be_bytes());
+    padded.extend_from_slice(&message);
+    padded.resize(size, 0);
+    Ok(padded)
+}
+
+/// Remove the padding from a message.
+pub(crate) fn unpad(mut message: Vec<u8>) -> Result<Vec<u8>> {
+    if message.len() < LENGTH_PREFIX {
+        return Err(Error::InvalidPadding(
+            "message is too short".to_string(),
+        ));
+    }
+    let mut prefix = [0u8; LENGTH_PREFIX];
+    prefix.copy_from_slice(&message[..LENGTH_PREFIX]);
+    let length = u32::from_be_bytes(prefix) as usize;
+    if LENGTH_PREFIX + length > message.len() {
+        return Err(Error::InvalidPadding(format!(
+            "length {} exceeds message size {}",
+            length,
+            message.len() - LENGTH_PREFIX,
+        )));
+    }
+    message.truncate(LENGTH_PREFIX + length);
+    message.drain(..LENGTH_PREFIX);
+    Ok(message)
+}
@@ -0,0 +1,30 @@
+//! Progress of a protocol driver.
+use mpc_protocol::{PartyNumber, RoundNumber};
+use std::fmt;
+
+/// Progress of a protocol at a point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Round the driver is waiting to complete; `None` if
+    /// the protocol has not started.
+    pub round: Option<RoundNumber>,
+    /// Session party numbers of the participants that have
+    /// not sent a message for the round.
+    pub waiting_on: Vec<PartyNumber>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let round = self
+            .round
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let waiting_on = self
+            .waiting_on
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        write!(f, "round={} waiting_on=[{}]", round, waiting_on)
+    }
+}
@@ -0,0 +1,193 @@
+//! Record round messages and replay them to a protocol driver.
+use mpc_protocol::{hex, PartyNumber, RoundNumber};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    message::BridgeMessage, Decision, Error, Interceptor,
+    ProtocolDriver, Result, Round, RoundBuffer, RoundEncoding,
+    RoundMessage,
+};
+
+/// Direction of a recorded message.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Message received from a peer.
+    Incoming,
+    /// Message sent to a peer.
+    Outgoing,
+}
+
+/// Round message captured by a recorder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    /// Direction of the message.
+    pub direction: Direction,
+    /// Milliseconds since the UNIX epoch.
+    pub timestamp: u64,
+    /// Round number.
+    pub round: RoundNumber,
+    /// Party number of the sender.
+    pub sender: PartyNumber,
+    /// Party number of the receiver for peer to peer messages.
+    pub receiver: Option<PartyNumber>,
+    /// Serialized message.
+    #[serde(with = "hex::serde")]
+    pub payload: Vec<u8>,
+    /// Hex encoded signature for the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Interceptor that appends every round message to a file.
+///
+/// Each message is written as a line of JSON so a recording
+/// of a failed protocol is readable up to the point of failure.
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Create a recorder that appends to the file at path.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(
+        &self,
+        direction: Direction,
+        message: &RoundMessage<'_>,
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let record = RecordedMessage {
+            direction,
+            timestamp,
+            round: message.round,
+            sender: message.sender,
+            receiver: message.receiver,
+            payload: message.payload.to_vec(),
+            signature: message.signature.map(hex::encode),
+        };
+        let result = serde_json::to_vec(&record)
+            .map_err(Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                let mut file = self.file.lock().unwrap();
+                file.write_all(&line)?;
+                Ok(())
+            });
+        if let Err(error) = result {
+            tracing::warn!(error = %error, "failed to record message");
+        }
+    }
+}
+
+impl Interceptor for Recorder {
+    fn on_outgoing(&self, message: &RoundMessage<'_>) {
+        self.write(Direction::Outgoing, message);
+    }
+
+    fn on_incoming(&self, message: &RoundMessage<'_>) -> Decision {
+        self.write(Direction::Incoming, message);
+        Decision::Accept
+    }
+}
+
+/// Round messages loaded from a recorder file.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    /// Recorded messages in the order they were captured.
+    pub messages: Vec<RecordedMessage>,
+}
+
+impl Recording {
+    /// Load a recording from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut messages = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            messages.push(serde_json::from_str(&line)?);
+        }
+        Ok(Self { messages })
+    }
+
+    /// Iterator of the incoming messages.
+    pub fn incoming(&self) -> impl Iterator<Item = &RecordedMessage> {
+        self.messages
+            .iter()
+            .filter(|m| m.direction == Direction::Incoming)
+    }
+}
+
+/// Drive a protocol using the incoming messages in a recording.
+///
+/// Outgoing messages are discarded; the result is only
+/// reproducible when the protocol driver is deterministic
+/// for the recorded inputs.
+///
+/// The encoding must match the encoding used by the driver
+/// that made the recording.
+pub(crate) fn replay<D: ProtocolDriver>(
+    mut driver: D,
+    mut buffer: RoundBuffer<D::Incoming>,
+    recording: &Recording,
+    encoding: RoundEncoding,
+) -> std::result::Result<D::Output, D::Error> {
+    if driver.wants_to_proceed() {
+        driver.proceed()?;
+    }
+
+    for record in recording.incoming() {
+        let message: BridgeMessage<D::Outgoing> =
+            encoding.decode(&record.payload).map_err(Box::new)?;
+        let message = match message {
+            BridgeMessage::Round(message) => message,
+            _ => continue,
+        };
+        let round_number = message.round_number();
+        let sender = *message.sender();
+        buffer.add_message(round_number, sender, message.into());
+
+        if buffer.is_ready(round_number) {
+            for (_, message) in buffer.take(round_number) {
+                driver.handle_incoming(message)?;
+            }
+
+            if buffer.len() == 1 {
+                return driver.finish();
+            }
+
+            if driver.wants_to_proceed() {
+                driver.proceed()?;
+            }
+
+            if round_number.get() as usize == buffer.len() {
+                return driver.finish();
+            }
+        }
+    }
+
+    Err(Box::new(Error::ReplayIncomplete).into())
+}
@@ -1,5 +1,7 @@
 use mpc_protocol::{PartyNumber, RoundNumber};
 use round_based::Msg;
+
+use crate::transcript::TranscriptHash;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -11,8 +13,18 @@ pub(crate) trait Round:
     fn is_broadcast(&self) -> bool;
     /// Round number.
     fn round_number(&self) -> RoundNumber;
+    /// Party number of the sender.
+    fn sender(&self) -> &PartyNumber;
+    /// Transcript hash of all prior rounds.
+    fn transcript(&self) -> &TranscriptHash;
+    /// Set the transcript hash of all prior rounds.
+    fn set_transcript(&mut self, digest: TranscriptHash);
     /// Receiver for a peer to peer message.
     fn receiver(&self) -> Option<&PartyNumber>;
+    /// Sequence number of the message for the sender.
+    fn sequence(&self) -> u32;
+    /// Set the sequence number of the message.
+    fn set_sequence(&mut self, sequence: u32);
 }
 
 /// Wrapper for a round `Msg` that includes the round
@@ -28,6 +40,8 @@ where
     round: RoundNumber,
     sender: PartyNumber,
     receiver: Option<PartyNumber>,
+    transcript: TranscriptHash,
+    sequence: u32,
     body: O,
 }
 
@@ -43,9 +57,29 @@ where
         self.round
     }
 
+    fn sender(&self) -> &PartyNumber {
+        &self.sender
+    }
+
+    fn transcript(&self) -> &TranscriptHash {
+        &self.transcript
+    }
+
+    fn set_transcript(&mut self, digest: TranscriptHash) {
+        self.transcript = digest;
+    }
+
     fn receiver(&self) -> Option<&PartyNumber> {
         self.receiver.as_ref()
     }
+
+    fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    fn set_sequence(&mut self, sequence: u32) {
+        self.sequence = sequence;
+    }
 }
 
 impl<O> From<RoundMsg<O>> for Msg<O>
@@ -78,12 +112,49 @@ where
                 receiver: m
                     .receiver
                     .map(|v| PartyNumber::new(v).unwrap()),
+                transcript: Default::default(),
+                sequence: 0,
                 body: m.body,
             })
             .collect::<Vec<_>>()
     }
 }
 
+/// Leading fields of an encoded round message.
+///
+/// Decoding a header reads the round and sender of a
+/// message without knowing the type of the message body.
+#[derive(Debug, Deserialize)]
+pub(crate) struct RoundHeader {
+    pub round: RoundNumber,
+    pub sender: PartyNumber,
+    pub receiver: Option<PartyNumber>,
+}
+
+/// Assigns sequence numbers to outgoing messages.
+///
+/// Broadcast messages are numbered independently of the
+/// peer to peer messages for each receiver so that every
+/// receiver observes a contiguous sequence from a sender.
+#[derive(Debug, Default)]
+pub(crate) struct Sequence {
+    broadcast: u32,
+    peers: HashMap<PartyNumber, u32>,
+}
+
+impl Sequence {
+    /// Next sequence number for a message.
+    pub fn next(&mut self, receiver: Option<PartyNumber>) -> u32 {
+        let counter = match receiver {
+            Some(receiver) => self.peers.entry(receiver).or_default(),
+            None => &mut self.broadcast,
+        };
+        let sequence = *counter;
+        *counter += 1;
+        sequence
+    }
+}
+
 /// Buffers incoming messages.
 #[derive(Debug)]
 pub(crate) struct RoundBuffer<I> {
@@ -91,24 +162,39 @@ pub(crate) struct RoundBuffer<I> {
     /// for each round.
     expected: HashMap<RoundNumber, u16>,
 
-    /// Received messages.
-    messages: HashMap<RoundNumber, Vec<I>>,
+    /// Received messages and the party number of the sender.
+    messages: HashMap<RoundNumber, Vec<(PartyNumber, I)>>,
+
+    /// Next sequence number expected from each sender for
+    /// broadcast and peer to peer messages.
+    sequences: HashMap<(PartyNumber, bool), u32>,
 }
 
 impl<I> RoundBuffer<I> {
     /// Create a new round buffer with a fixed number
     /// of messages per round.
     pub fn new_fixed(rounds: u16, messages_per_round: u16) -> Self {
-        let mut expected = HashMap::new();
-        for i in 0..rounds {
-            expected.insert(
-                RoundNumber::new(i + 1).unwrap(),
-                messages_per_round,
-            );
-        }
+        Self::new(&vec![messages_per_round; rounds as usize])
+    }
+
+    /// Create a new round buffer with the number of messages
+    /// expected for each round.
+    ///
+    /// Use this when a round mixes broadcast and peer to peer
+    /// messages so the number of messages received from each
+    /// party differs between rounds.
+    pub fn new(messages_per_round: &[u16]) -> Self {
+        let expected = messages_per_round
+            .iter()
+            .enumerate()
+            .map(|(i, amount)| {
+                (RoundNumber::new(i as u16 + 1).unwrap(), *amount)
+            })
+            .collect();
         Self {
             expected,
             messages: Default::default(),
+            sequences: Default::default(),
         }
     }
 
@@ -117,10 +203,54 @@ impl<I> RoundBuffer<I> {
         self.expected.len()
     }
 
+    /// Check the sequence number of a message from a sender.
+    ///
+    /// Returns `false` if the message is a duplicate of a
+    /// message that was already received; if messages were
+    /// skipped the expected sequence number is returned.
+    ///
+    /// The expected sequence number only advances once the
+    /// message is accepted using `accept_sequence()`.
+    pub fn check_sequence(
+        &self,
+        sender: PartyNumber,
+        is_broadcast: bool,
+        sequence: u32,
+    ) -> std::result::Result<bool, u32> {
+        let expected = self
+            .sequences
+            .get(&(sender, is_broadcast))
+            .copied()
+            .unwrap_or_default();
+        if sequence < expected {
+            return Ok(false);
+        }
+        if sequence > expected {
+            return Err(expected);
+        }
+        Ok(true)
+    }
+
+    /// Advance the expected sequence number for a sender
+    /// once a message has been accepted.
+    pub fn accept_sequence(
+        &mut self,
+        sender: PartyNumber,
+        is_broadcast: bool,
+    ) {
+        *self.sequences.entry((sender, is_broadcast)).or_default() +=
+            1;
+    }
+
     /// Add a message to the buffer.
-    pub fn add_message(&mut self, round: RoundNumber, message: I) {
+    pub fn add_message(
+        &mut self,
+        round: RoundNumber,
+        sender: PartyNumber,
+        message: I,
+    ) {
         let messages = self.messages.entry(round).or_insert(vec![]);
-        messages.push(message);
+        messages.push((sender, message));
     }
 
     /// Determine if a round is ready to proceed.
@@ -142,7 +272,10 @@ impl<I> RoundBuffer<I> {
     /// If this is called before a round is ready the returned
     /// value will be incomplete or empty if no messages have
     /// been received for the round.
-    pub fn take(&mut self, round: RoundNumber) -> Vec<I> {
+    pub fn take(
+        &mut self,
+        round: RoundNumber,
+    ) -> Vec<(PartyNumber, I)> {
         if let Some(messages) = self.messages.remove(&round) {
             messages
         } else {
@@ -150,3 +283,52 @@ impl<I> RoundBuffer<I> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{RoundBuffer, Sequence};
+    use crate::number;
+
+    #[test]
+    fn sequence_per_receiver() {
+        let mut sequence = Sequence::default();
+        assert_eq!(0, sequence.next(None));
+        assert_eq!(0, sequence.next(Some(number(2))));
+        assert_eq!(1, sequence.next(None));
+        assert_eq!(0, sequence.next(Some(number(3))));
+        assert_eq!(1, sequence.next(Some(number(2))));
+    }
+
+    #[test]
+    fn sequence_replay_and_gap() {
+        let (one, two) = (number(1), number(2));
+        let mut rounds: RoundBuffer<()> =
+            RoundBuffer::new_fixed(1, 2);
+        assert_eq!(Ok(true), rounds.check_sequence(one, true, 0));
+        rounds.accept_sequence(one, true);
+
+        // Replayed message
+        assert_eq!(Ok(false), rounds.check_sequence(one, true, 0));
+        // Skipped message
+        assert_eq!(Err(1), rounds.check_sequence(one, true, 2));
+        assert_eq!(Ok(true), rounds.check_sequence(one, true, 1));
+
+        // Peer to peer messages and other senders are
+        // sequenced independently
+        assert_eq!(Ok(true), rounds.check_sequence(one, false, 0));
+        assert_eq!(Ok(true), rounds.check_sequence(two, true, 0));
+    }
+
+    #[test]
+    fn sequence_rejected_message_not_accepted() {
+        let one = number(1);
+        let mut rounds: RoundBuffer<()> =
+            RoundBuffer::new_fixed(1, 2);
+        // Checking a message that is then rejected does not
+        // advance the sequence so a valid message may follow
+        assert_eq!(Ok(true), rounds.check_sequence(one, true, 0));
+        assert_eq!(Ok(true), rounds.check_sequence(one, true, 0));
+        rounds.accept_sequence(one, true);
+        assert_eq!(Ok(true), rounds.check_sequence(one, true, 1));
+    }
+}
@@ -1,10 +1,35 @@
-use crate::Result;
+use crate::{timer, Error, Result, WaitOptions};
 use async_trait::async_trait;
 use futures::{select, FutureExt, StreamExt};
 use mpc_client::{Event, EventStream, NetworkTransport, Transport};
-use mpc_protocol::{log, SessionState};
+use mpc_protocol::{
+    derive_session_id, hex, log, ParticipantLimits, SessionId,
+    SessionRequest, SessionRole, SessionState,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use tokio::sync::Mutex;
 
+/// Lifecycle of a session from the perspective of a handler.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionStatus {
+    /// Session has not been created or joined.
+    #[default]
+    Pending,
+    /// Session was created on the server.
+    Created,
+    /// All participants are connected to the server.
+    Ready,
+    /// All the peer channels are connected.
+    Active,
+    /// Request to close the session has been sent.
+    Finishing,
+    /// Session was closed, expired or rejected.
+    Closed,
+}
+
 /// Trait for types that handle session related events.
 #[async_trait]
 pub trait SessionEventHandler {
@@ -13,6 +38,33 @@ pub trait SessionEventHandler {
         &mut self,
         event: Event,
     ) -> Result<Option<SessionState>>;
+
+    /// Clean up a session that will not become active.
+    async fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Current status of the session.
+    fn status(&self) -> SessionStatus {
+        SessionStatus::Pending
+    }
+}
+
+/// Policy consulted before a participant joins a session.
+pub trait JoinPolicy: Send + Sync {
+    /// Determine if the participant should join a session.
+    ///
+    /// The first participant in the session is the initiator.
+    fn approve(&self, session: &SessionState) -> bool;
+}
+
+impl<F> JoinPolicy for F
+where
+    F: Fn(&SessionState) -> bool + Send + Sync,
+{
+    fn approve(&self, session: &SessionState) -> bool {
+        self(session)
+    }
 }
 
 /// Variants that can create or join a session.
@@ -34,6 +86,55 @@ impl SessionEventHandler for SessionHandler {
             Self::Participant(s) => s.handle_event(event).await,
         }
     }
+
+    async fn close(&mut self) -> Result<()> {
+        match self {
+            Self::Initiator(s) => s.close().await,
+            Self::Participant(s) => s.close().await,
+        }
+    }
+
+    fn status(&self) -> SessionStatus {
+        match self {
+            Self::Initiator(s) => s.status(),
+            Self::Participant(s) => s.status(),
+        }
+    }
+}
+
+impl SessionHandler {
+    /// Public key of the transport for this handler.
+    fn public_key(&self) -> &[u8] {
+        match self {
+            Self::Initiator(s) => s.transport.public_key(),
+            Self::Participant(s) => s.transport.public_key(),
+        }
+    }
+
+    /// Request a new session for an initiator.
+    async fn new_session(&mut self) -> Result<()> {
+        if let Self::Initiator(s) = self {
+            s.new_session().await?;
+        }
+        Ok(())
+    }
+
+    /// Determine if this handler should take ownership
+    /// of an event for a session that is not yet routed.
+    fn claims(&self, event: &Event) -> bool {
+        match (self, event) {
+            (Self::Initiator(s), Event::SessionCreated(_)) => {
+                s.requested_session
+            }
+            (
+                Self::Participant(_),
+                Event::SessionReady(session)
+                | Event::SessionRejoined(session)
+                | Event::ParticipantAdded(session),
+            ) => session.is_participant(self.public_key()),
+            _ => false,
+        }
+    }
 }
 
 impl From<SessionHandler> for Transport {
@@ -49,8 +150,21 @@ impl From<SessionHandler> for Transport {
 pub struct SessionInitiator {
     transport: Transport,
     session_participants: Vec<Vec<u8>>,
+    roles: HashMap<Vec<u8>, SessionRole>,
     session_state: Mutex<Option<SessionState>>,
     requested_session: bool,
+    /// Nonce used to derive the session identifier.
+    nonce: Option<Vec<u8>>,
+    /// Minimum number of participants to regroup with.
+    regroup: Option<usize>,
+    /// Number of connected participants required for
+    /// the session to be ready.
+    quorum: Option<u16>,
+    /// Participants dropped when regrouping.
+    dropped: Vec<Vec<u8>>,
+    /// Limits on the number of session participants.
+    limits: ParticipantLimits,
+    status: SessionStatus,
 }
 
 impl SessionInitiator {
@@ -62,9 +176,143 @@ impl SessionInitiator {
         Self {
             transport,
             session_participants,
+            roles: HashMap::new(),
             session_state: Mutex::new(None),
             requested_session: false,
+            nonce: None,
+            regroup: None,
+            quorum: None,
+            dropped: Vec::new(),
+            limits: Default::default(),
+            status: SessionStatus::Pending,
+        }
+    }
+
+    /// Derive the session identifier from the participants
+    /// and a nonce.
+    ///
+    /// Participants can predict the session identifier using
+    /// [derive_session_id](mpc_protocol::derive_session_id)
+    /// with the public keys of all the participants.
+    ///
+    /// The session created by the server is closed if the
+    /// identifier was not derived from the nonce.
+    pub fn with_nonce(mut self, nonce: Vec<u8>) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    /// Determine if a session was derived from the nonce
+    /// and the requested participants.
+    fn is_derived(&self, session: &SessionState) -> bool {
+        match &self.nonce {
+            Some(nonce) => {
+                let mut keys =
+                    vec![self.transport.public_key().to_vec()];
+                keys.extend(
+                    self.session_participants.iter().cloned(),
+                );
+                session.nonce.as_ref() == Some(nonce)
+                    && derive_session_id(&keys, nonce)
+                        == session.session_id
+            }
+            None => true,
+        }
+    }
+
+    /// Regroup when some participants fail to connect.
+    ///
+    /// When the session times out the participants that did
+    /// not connect are dropped and a new session is created
+    /// for the remaining participants as long as the number of
+    /// participants (including the initiator) is at least the
+    /// given minimum.
+    pub fn with_regroup(mut self, min_participants: usize) -> Self {
+        self.regroup = Some(min_participants);
+        self
+    }
+
+    /// Proceed once a quorum of participants is connected.
+    ///
+    /// The quorum includes the initiator; when the session is
+    /// ready it only contains the participants that connected.
+    pub fn with_quorum(mut self, quorum: u16) -> Self {
+        self.quorum = Some(quorum);
+        self
+    }
+
+    /// Limit the number of session participants.
+    ///
+    /// The limits include the initiator; the session request
+    /// is validated before it is sent to the server.
+    pub fn with_limits(mut self, limits: ParticipantLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Participants that were dropped when regrouping.
+    pub fn dropped(&self) -> &[Vec<u8>] {
+        self.dropped.as_slice()
+    }
+
+    /// Handle a session timeout by regrouping without the
+    /// missing participants when possible.
+    async fn regroup(
+        &mut self,
+        session_id: SessionId,
+        missing: Vec<Vec<u8>>,
+    ) -> Result<()> {
+        let min_participants = if let Some(min) = self.regroup {
+            min
+        } else {
+            return Err(Error::SessionTimeout {
+                session_id,
+                missing,
+            });
+        };
+
+        let remaining: Vec<Vec<u8>> = self
+            .session_participants
+            .iter()
+            .filter(|k| !missing.contains(k))
+            .cloned()
+            .collect();
+
+        let is_initiator_missing = missing
+            .iter()
+            .any(|k| k.as_slice() == self.transport.public_key());
+        if is_initiator_missing
+            || remaining.len() + 1 < min_participants
+        {
+            return Err(Error::SessionTimeout {
+                session_id,
+                missing,
+            });
         }
+
+        tracing::info!(
+            id = ?session_id.to_string(),
+            dropped = %missing.len(),
+            "regroup session");
+
+        self.transport.close_session(session_id).await?;
+        *self.session_state.lock().await = None;
+        self.status = SessionStatus::Pending;
+        self.session_participants = remaining;
+        self.dropped.extend(missing);
+        self.requested_session = false;
+        self.new_session().await
+    }
+
+    /// Assign roles to the session participants by public key.
+    ///
+    /// Participants without a role are signers.
+    pub fn with_roles(
+        mut self,
+        roles: HashMap<Vec<u8>, SessionRole>,
+    ) -> Self {
+        self.roles = roles;
+        self
     }
 
     /// Lazily request to create new session only once.
@@ -72,9 +320,16 @@ impl SessionInitiator {
         if !self.requested_session
             && self.transport.is_connected().await
         {
-            self.transport
-                .new_session(self.session_participants.clone())
-                .await?;
+            let request = SessionRequest {
+                participant_keys: self.session_participants.clone(),
+                roles: self.roles.clone(),
+                nonce: self.nonce.clone(),
+                quorum: self.quorum,
+            };
+            request
+                .validate(self.transport.public_key(), &self.limits)
+                .map_err(mpc_client::Error::from)?;
+            self.transport.new_session(request).await?;
 
             self.requested_session = true;
         }
@@ -96,8 +351,19 @@ impl SessionEventHandler for SessionInitiator {
                     id = ?session.session_id.to_string(),
                     "session created");
 
+                if !self.is_derived(&session) {
+                    self.status = SessionStatus::Closed;
+                    self.transport
+                        .close_session(session.session_id)
+                        .await?;
+                    return Err(Error::SessionNotDerived(
+                        session.session_id,
+                    ));
+                }
+
                 let mut state = self.session_state.lock().await;
                 *state = Some(session);
+                self.status = SessionStatus::Created;
             }
             Event::SessionReady(session) => {
                 tracing::info!(
@@ -110,28 +376,114 @@ impl SessionEventHandler for SessionInitiator {
                 for key in connections {
                     self.transport.connect_peer(key).await?;
                 }
+
+                // Participants may have been narrowed to a quorum
+                let mut state = self.session_state.lock().await;
+                *state = Some(session);
+                self.status = SessionStatus::Ready;
             }
             Event::PeerConnected { peer_key } => {
                 let state = self.session_state.lock().await;
-                let session = state.as_ref().unwrap();
-                let connections =
-                    session.connections(self.transport.public_key());
-                if connections.contains(&peer_key) {
-                    self.transport
-                        .register_connection(
-                            &session.session_id,
-                            peer_key.as_slice(),
-                        )
-                        .await?;
+                // No session when a peer from a session
+                // that was regrouped connects late
+                if let Some(session) = state.as_ref() {
+                    let connections = session
+                        .connections(self.transport.public_key());
+                    if connections.contains(&peer_key) {
+                        self.transport
+                            .register_connection(
+                                &session.session_id,
+                                peer_key.as_slice(),
+                            )
+                            .await?;
+                    }
                 }
             }
             Event::SessionActive(session) => {
-                return Ok(Some(session))
+                self.status = SessionStatus::Active;
+                return Ok(Some(session));
+            }
+            Event::SessionFinished(session_id) => {
+                let state = self.session_state.lock().await;
+                if state.as_ref().map(|s| s.session_id)
+                    == Some(session_id)
+                {
+                    self.status = SessionStatus::Closed;
+                }
+            }
+            Event::SessionTimeout {
+                session_id,
+                missing,
+            } => {
+                let is_current = self
+                    .session_state
+                    .lock()
+                    .await
+                    .as_ref()
+                    .map(|s| s.session_id)
+                    == Some(session_id);
+                if is_current {
+                    self.regroup(session_id, missing).await?;
+                }
+            }
+            Event::SessionExpired(session_id) => {
+                let state = self.session_state.lock().await;
+                if state.as_ref().map(|s| s.session_id)
+                    == Some(session_id)
+                {
+                    self.status = SessionStatus::Closed;
+                    return Err(Error::SessionExpired(session_id));
+                }
+            }
+            Event::ConnectionLost | Event::Disconnected { .. } => {
+                self.status = SessionStatus::Closed;
+                return Err(Error::ConnectionLost);
+            }
+            Event::SessionDeclined {
+                session_id,
+                public_key,
+            } => {
+                let is_current = self
+                    .session_state
+                    .lock()
+                    .await
+                    .as_ref()
+                    .map(|s| s.session_id)
+                    == Some(session_id);
+                if is_current {
+                    if self.regroup.is_some() {
+                        self.regroup(session_id, vec![public_key])
+                            .await?;
+                    } else {
+                        self.close().await?;
+                        return Err(Error::SessionDeclined {
+                            session_id,
+                            public_key: hex::encode(public_key),
+                        });
+                    }
+                }
             }
             _ => {}
         }
         Ok(None)
     }
+
+    /// Close the session if it has been created.
+    async fn close(&mut self) -> Result<()> {
+        let session = self.session_state.lock().await.take();
+        if let Some(session) = session {
+            tracing::info!(
+                id = ?session.session_id.to_string(),
+                "close pending session");
+            self.status = SessionStatus::Finishing;
+            self.transport.close_session(session.session_id).await?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> SessionStatus {
+        self.status
+    }
 }
 
 impl From<SessionInitiator> for Transport {
@@ -141,9 +493,22 @@ impl From<SessionInitiator> for Transport {
 }
 
 /// Participate in a session.
+///
+/// A participant that is invited to an existing session
+/// handshakes with all the other participants and the
+/// session is returned once every channel is connected.
 pub struct SessionParticipant {
     transport: Transport,
     session_state: Mutex<Option<SessionState>>,
+    /// Peers an invited participant is waiting to connect.
+    pending: HashSet<Vec<u8>>,
+    /// Policy consulted before joining a session.
+    policy: Option<Arc<dyn JoinPolicy>>,
+    /// Limits on the number of session participants.
+    limits: Option<ParticipantLimits>,
+    /// Nonce the session identifier must be derived from.
+    nonce: Option<Vec<u8>>,
+    status: SessionStatus,
 }
 
 impl SessionParticipant {
@@ -152,7 +517,87 @@ impl SessionParticipant {
         Self {
             transport,
             session_state: Mutex::new(None),
+            pending: HashSet::new(),
+            policy: None,
+            limits: None,
+            nonce: None,
+            status: SessionStatus::Pending,
+        }
+    }
+
+    /// Consult a policy before joining a session.
+    ///
+    /// When the policy rejects a session the other
+    /// participants are notified that we declined.
+    pub fn with_policy(
+        mut self,
+        policy: Arc<dyn JoinPolicy>,
+    ) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Decline sessions with a number of participants
+    /// outside of the limits.
+    pub fn with_limits(mut self, limits: ParticipantLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Decline sessions with an identifier that was not
+    /// derived from the participants and the nonce.
+    ///
+    /// Sessions narrowed to a quorum are declined as the
+    /// identifier was derived from the requested participants.
+    pub fn with_nonce(mut self, nonce: Vec<u8>) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    /// Decline the session unless it is approved by the policy.
+    async fn approve(
+        transport: &mut Transport,
+        policy: Option<&Arc<dyn JoinPolicy>>,
+        limits: Option<&ParticipantLimits>,
+        session: &SessionState,
+    ) -> Result<()> {
+        if let Some(Err(e)) =
+            limits.map(|limits| limits.check(session.len()))
+        {
+            tracing::info!(
+                id = ?session.session_id.to_string(),
+                error = %e,
+                "session declined");
+            transport.decline_session(session.session_id).await?;
+            return Err(mpc_client::Error::from(e).into());
+        }
+
+        if let Some(policy) = policy {
+            if !policy.approve(session) {
+                tracing::info!(
+                    id = ?session.session_id.to_string(),
+                    "session declined");
+                transport.decline_session(session.session_id).await?;
+                return Err(Error::SessionRejected(
+                    session.session_id,
+                ));
+            }
         }
+        Ok(())
+    }
+
+    /// Rejoin a session after the transport has reconnected
+    /// to the server.
+    ///
+    /// Once the server accepts the request the participant
+    /// handshakes with all the other participants again so
+    /// that a protocol in flight can continue.
+    pub async fn rejoin(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<()> {
+        self.transport.rejoin_session(session_id).await?;
+        Ok(())
     }
 }
 
@@ -165,8 +610,39 @@ impl SessionEventHandler for SessionParticipant {
     ) -> Result<Option<SessionState>> {
         match event {
             Event::SessionReady(session) => {
+                let is_derived = self
+                    .nonce
+                    .as_ref()
+                    .map(|nonce| session.is_derived(nonce))
+                    .unwrap_or(true);
+                if !is_derived {
+                    tracing::info!(
+                        id = ?session.session_id.to_string(),
+                        "session declined");
+                    self.status = SessionStatus::Closed;
+                    self.transport
+                        .decline_session(session.session_id)
+                        .await?;
+                    return Err(Error::SessionNotDerived(
+                        session.session_id,
+                    ));
+                }
+
+                if let Err(e) = Self::approve(
+                    &mut self.transport,
+                    self.policy.as_ref(),
+                    self.limits.as_ref(),
+                    &session,
+                )
+                .await
+                {
+                    self.status = SessionStatus::Closed;
+                    return Err(e);
+                }
+
                 let mut state = self.session_state.lock().await;
                 *state = Some(session.clone());
+                self.status = SessionStatus::Ready;
 
                 tracing::info!(
                     id = ?session.session_id.to_string(),
@@ -181,6 +657,20 @@ impl SessionEventHandler for SessionParticipant {
             Event::PeerConnected { peer_key } => {
                 let state = self.session_state.lock().await;
                 if let Some(session) = state.as_ref() {
+                    if self.pending.remove(&peer_key) {
+                        self.transport
+                            .register_connection(
+                                &session.session_id,
+                                peer_key.as_slice(),
+                            )
+                            .await?;
+                        if self.pending.is_empty() {
+                            self.status = SessionStatus::Active;
+                            return Ok(Some(session.clone()));
+                        }
+                        return Ok(None);
+                    }
+
                     let connections = session
                         .connections(self.transport.public_key());
                     if connections.contains(&peer_key) {
@@ -198,13 +688,110 @@ impl SessionEventHandler for SessionParticipant {
                 }
             }
             Event::SessionActive(session) => {
+                self.status = SessionStatus::Active;
                 return Ok(Some(session));
             }
+            Event::SessionRejoined(session) => {
+                tracing::info!(
+                    id = ?session.session_id.to_string(),
+                    "session rejoined");
+
+                // Other participants lost their channels to us
+                // so we initiate the handshake with everyone
+                for key in
+                    session.recipients(self.transport.public_key())
+                {
+                    self.transport.connect_peer(&key).await?;
+                }
+
+                let mut state = self.session_state.lock().await;
+                *state = Some(session.clone());
+                self.status = SessionStatus::Active;
+                return Ok(Some(session));
+            }
+            Event::ParticipantAdded(session) => {
+                let mut state = self.session_state.lock().await;
+                let own_key = self.transport.public_key();
+                let is_invited = state.is_none()
+                    && session
+                        .all_participants
+                        .last()
+                        .map(|k| k.as_slice() == own_key)
+                        .unwrap_or(false);
+
+                if is_invited {
+                    tracing::info!(
+                        id = ?session.session_id.to_string(),
+                        "session invite");
+
+                    if let Err(e) = Self::approve(
+                        &mut self.transport,
+                        self.policy.as_ref(),
+                        self.limits.as_ref(),
+                        &session,
+                    )
+                    .await
+                    {
+                        self.status = SessionStatus::Closed;
+                        return Err(e);
+                    }
+
+                    let recipients = session
+                        .recipients(self.transport.public_key());
+                    self.pending =
+                        recipients.iter().cloned().collect();
+                    for key in recipients {
+                        self.transport.connect_peer(&key).await?;
+                    }
+                    *state = Some(session);
+                    self.status = SessionStatus::Ready;
+                } else if state.as_ref().map(|s| s.session_id)
+                    == Some(session.session_id)
+                {
+                    *state = Some(session);
+                }
+            }
+            Event::SessionExpired(session_id) => {
+                let state = self.session_state.lock().await;
+                if state.as_ref().map(|s| s.session_id)
+                    == Some(session_id)
+                {
+                    self.status = SessionStatus::Closed;
+                    return Err(Error::SessionExpired(session_id));
+                }
+            }
+            Event::ConnectionLost | Event::Disconnected { .. } => {
+                self.status = SessionStatus::Closed;
+                return Err(Error::ConnectionLost);
+            }
+            Event::SessionFinished(session_id) => {
+                let state = self.session_state.lock().await;
+                if state.as_ref().map(|s| s.session_id)
+                    == Some(session_id)
+                {
+                    self.status = SessionStatus::Closed;
+                }
+            }
+            Event::SessionClosed(session_id) => {
+                // Owner may regroup into a new session so
+                // forget the closed session
+                let mut state = self.session_state.lock().await;
+                if state.as_ref().map(|s| s.session_id)
+                    == Some(session_id)
+                {
+                    *state = None;
+                    self.status = SessionStatus::Closed;
+                }
+            }
             _ => {}
         }
 
         Ok(None)
     }
+
+    fn status(&self) -> SessionStatus {
+        self.status
+    }
 }
 
 impl From<SessionParticipant> for Transport {
@@ -213,17 +800,226 @@ impl From<SessionParticipant> for Transport {
     }
 }
 
+/// Routes session events to handlers for multiple
+/// concurrent sessions.
+///
+/// Handlers are assigned a session when the session is
+/// created or when a participant is first notified of the
+/// session; afterwards events are routed by session identifier.
+#[derive(Default)]
+pub struct SessionRouter {
+    /// Handlers that have not been assigned a session.
+    pending: Vec<SessionHandler>,
+    /// Handlers by session identifier.
+    handlers: HashMap<SessionId, SessionHandler>,
+    /// Sessions that have become active.
+    active: HashMap<SessionId, SessionState>,
+}
+
+impl SessionRouter {
+    /// Create a new session router.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a handler to create or join a session.
+    pub fn insert(&mut self, handler: SessionHandler) {
+        self.pending.push(handler);
+    }
+
+    /// Active sessions.
+    pub fn active_sessions(
+        &self,
+    ) -> impl Iterator<Item = &SessionState> {
+        self.active.values()
+    }
+
+    /// Get an active session.
+    pub fn get(
+        &self,
+        session_id: &SessionId,
+    ) -> Option<&SessionState> {
+        self.active.get(session_id)
+    }
+
+    /// Status of the handler for a session.
+    ///
+    /// Handlers are removed when a session is finished.
+    pub fn status(
+        &self,
+        session_id: &SessionId,
+    ) -> Option<SessionStatus> {
+        self.handlers.get(session_id).map(|h| h.status())
+    }
+
+    /// Remove the handler for a session.
+    ///
+    /// Returns the transport for the handler and the session
+    /// state when the session is active.
+    pub fn remove(
+        &mut self,
+        session_id: &SessionId,
+    ) -> Option<(Transport, Option<SessionState>)> {
+        let handler = self.handlers.remove(session_id)?;
+        Some((handler.into(), self.active.remove(session_id)))
+    }
+
+    async fn route(
+        &mut self,
+        session_id: SessionId,
+        event: Event,
+    ) -> Result<Option<SessionState>> {
+        if !self.handlers.contains_key(&session_id) {
+            if let Some(position) =
+                self.pending.iter().position(|h| h.claims(&event))
+            {
+                let handler = self.pending.remove(position);
+                self.handlers.insert(session_id, handler);
+            } else {
+                return Ok(None);
+            }
+        }
+
+        let is_regroup = matches!(
+            event,
+            Event::SessionTimeout { .. }
+                | Event::SessionDeclined { .. }
+        );
+        let handler = self.handlers.get_mut(&session_id).unwrap();
+        let result = handler.handle_event(event).await;
+        match &result {
+            Ok(Some(session)) => {
+                self.active.insert(session_id, session.clone());
+            }
+            // Initiator regrouped so wait for the new session
+            Ok(None)
+                if is_regroup
+                    && matches!(
+                        handler,
+                        SessionHandler::Initiator(_)
+                    ) =>
+            {
+                let handler =
+                    self.handlers.remove(&session_id).unwrap();
+                self.pending.push(handler);
+            }
+            Err(_) => {
+                self.handlers.remove(&session_id);
+                self.active.remove(&session_id);
+            }
+            _ => {}
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl SessionEventHandler for SessionRouter {
+    /// Route an event to the handler for the session.
+    ///
+    /// Returns the session state when a session becomes active.
+    async fn handle_event(
+        &mut self,
+        event: Event,
+    ) -> Result<Option<SessionState>> {
+        for handler in self.pending.iter_mut() {
+            handler.new_session().await?;
+        }
+
+        let session_id = match &event {
+            Event::SessionCreated(session)
+            | Event::SessionReady(session)
+            | Event::SessionActive(session)
+            | Event::SessionRejoined(session)
+            | Event::ParticipantAdded(session) => session.session_id,
+            Event::SessionTimeout { session_id, .. }
+            | Event::SessionDeclined { session_id, .. }
+            | Event::SessionExpired(session_id)
+            | Event::SessionClosed(session_id) => *session_id,
+            Event::SessionFinished(session_id) => {
+                self.handlers.remove(session_id);
+                self.active.remove(session_id);
+                return Ok(None);
+            }
+            Event::PeerConnected { peer_key } => {
+                // Peer connections are not bound to a session
+                // so notify every session that is not active,
+                // any other sessions activated by this peer are
+                // available from active_sessions()
+                let mut activated = None;
+                for (session_id, handler) in self.handlers.iter_mut()
+                {
+                    if self.active.contains_key(session_id) {
+                        continue;
+                    }
+                    let event = Event::PeerConnected {
+                        peer_key: peer_key.clone(),
+                    };
+                    if let Some(session) =
+                        handler.handle_event(event).await?
+                    {
+                        self.active
+                            .insert(*session_id, session.clone());
+                        activated.get_or_insert(session);
+                    }
+                }
+                return Ok(activated);
+            }
+            _ => return Ok(None),
+        };
+
+        self.route(session_id, event).await
+    }
+
+    /// Close the sessions that are not active.
+    async fn close(&mut self) -> Result<()> {
+        let pending: Vec<SessionId> = self
+            .handlers
+            .keys()
+            .filter(|id| !self.active.contains_key(id))
+            .copied()
+            .collect();
+        for session_id in pending {
+            if let Some(mut handler) =
+                self.handlers.remove(&session_id)
+            {
+                handler.close().await?;
+            }
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
+
 /// Wait for a session to become active.
 pub async fn wait_for_session<S>(
+    stream: &mut EventStream,
+    client_session: S,
+) -> Result<(Transport, SessionState)>
+where
+    S: SessionEventHandler + Into<Transport>,
+{
+    wait_for_session_with(stream, client_session, Default::default())
+        .await
+}
+
+/// Wait for a session to become active with a timeout
+/// and cancellation token.
+///
+/// When the wait times out or is cancelled the pending
+/// session is closed.
+pub async fn wait_for_session_with<S>(
     stream: &mut EventStream,
     mut client_session: S,
+    options: WaitOptions,
 ) -> Result<(Transport, SessionState)>
 where
     S: SessionEventHandler + Into<Transport>,
 {
+    let deadline = options.deadline();
     #[allow(unused_assignments)]
     let mut session: Option<SessionState> = None;
-    loop {
+    let error = loop {
         select! {
             event = stream.next().fuse() => {
                 match event {
@@ -232,13 +1028,30 @@ where
                         if let Some(active_session) =
                             client_session.handle_event(event).await? {
                             session = Some(active_session);
-                            break;
+                            break None;
                         }
                     }
                     _ => {}
                 }
             },
+            _ = timer::sleep_until(deadline).fuse() => {
+                break Some(Error::WaitTimeout);
+            },
+            _ = options.cancelled().fuse() => {
+                break Some(Error::Cancelled);
+            },
         }
+    };
+
+    if let Some(error) = error {
+        if let Err(e) = client_session.close().await {
+            tracing::warn!(
+                error = %e,
+                "failed to close pending session",
+            );
+        }
+        return Err(error);
     }
+
     Ok((client_session.into(), session.take().unwrap()))
 }
@@ -0,0 +1,46 @@
+//! Storage for key shares and other participant state.
+//!
+//! Values are opaque bytes so applications choose how key
+//! shares and driver state are serialized.
+use async_trait::async_trait;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::Result;
+
+/// Persistent storage for a participant.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait Storage {
+    /// Read the value for a key.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Write the value for a key.
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<()>;
+
+    /// Delete the value for a key.
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// Storage that keeps values in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    values: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Storage for MemoryStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        self.values.lock().unwrap().insert(key.to_owned(), value);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.values.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! Timers for driver deadlines.
+//!
+//! Timers are scheduled with tokio on native platforms and
+//! with the browser in webassembly.
+use std::time::Duration;
+use web_time::Instant;
+
+/// Wait until a deadline is reached.
+///
+/// When there is no deadline the future never completes.
+pub(crate) async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        #[cfg(not(all(
+            target_arch = "wasm32",
+            target_os = "unknown"
+        )))]
+        Some(deadline) => {
+            tokio::time::sleep_until(deadline.into()).await
+        }
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        Some(deadline) => {
+            browser::delay(
+                deadline.saturating_duration_since(Instant::now()),
+            )
+            .await
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for a duration.
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    tokio::time::sleep(duration).await;
+
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    browser::delay(duration).await;
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod browser {
+    use gloo_timers::callback::Timeout;
+    use std::{
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    };
+    use tokio::sync::oneshot;
+
+    /// Timer scheduled with the browser.
+    ///
+    /// The timeout is cleared when the future is dropped so
+    /// timers for deadlines that were not reached do not
+    /// accumulate.
+    pub(super) struct Delay {
+        _timeout: Timeout,
+        fired: oneshot::Receiver<()>,
+    }
+
+    // The timeout holds a javascript closure which is not
+    // `Send` but webassembly in the browser is single
+    // threaded and drivers must be `Send` on native platforms.
+    unsafe impl Send for Delay {}
+
+    impl Future for Delay {
+        type Output = ();
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Self::Output> {
+            Pin::new(&mut self.fired).poll(cx).map(|_| ())
+        }
+    }
+
+    /// Create a timer for a duration.
+    pub(super) fn delay(duration: Duration) -> Delay {
+        let (tx, fired) = oneshot::channel();
+        let millis = duration.as_millis().min(u32::MAX as u128);
+        let timeout = Timeout::new(millis as u32, move || {
+            let _ = tx.send(());
+        });
+        Delay {
+            _timeout: timeout,
+            fired,
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Running transcript of the broadcast messages for a protocol.
+use mpc_protocol::{PartyNumber, RoundNumber, SessionId};
+use sha3::{Digest, Sha3_256};
+use std::collections::BTreeMap;
+
+/// Hash of a transcript or message.
+pub(crate) type TranscriptHash = [u8; 32];
+
+/// Compute the hash of a message.
+pub(crate) fn hash(message: &[u8]) -> TranscriptHash {
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&Sha3_256::digest(message));
+    digest
+}
+
+/// Running hash of the broadcast messages for all
+/// completed rounds.
+///
+/// Each outgoing message commits to the transcript of all
+/// prior rounds and incoming messages are checked against
+/// the local transcript once a round is complete so that
+/// divergent views of the protocol are detected early.
+///
+/// Only broadcast messages are included as peer to peer
+/// messages are not seen by all parties.
+#[derive(Debug)]
+pub(crate) struct Transcript {
+    digest: TranscriptHash,
+    /// Broadcast messages for rounds that are not committed.
+    messages:
+        BTreeMap<RoundNumber, BTreeMap<PartyNumber, TranscriptHash>>,
+    /// Transcript hashes claimed by incoming messages.
+    claims: BTreeMap<RoundNumber, Vec<(PartyNumber, TranscriptHash)>>,
+}
+
+impl Transcript {
+    /// Create a new transcript bound to a session.
+    pub fn new(session_id: &SessionId) -> Self {
+        Self {
+            digest: hash(session_id.as_bytes()),
+            messages: Default::default(),
+            claims: Default::default(),
+        }
+    }
+
+    /// Current transcript hash.
+    pub fn digest(&self) -> &TranscriptHash {
+        &self.digest
+    }
+
+    /// Record a broadcast message for a round.
+    pub fn record(
+        &mut self,
+        round: RoundNumber,
+        sender: PartyNumber,
+        message: &[u8],
+    ) {
+        self.messages
+            .entry(round)
+            .or_default()
+            .insert(sender, hash(message));
+    }
+
+    /// Record the transcript hash claimed by an incoming message.
+    pub fn claim(
+        &mut self,
+        round: RoundNumber,
+        sender: PartyNumber,
+        digest: TranscriptHash,
+    ) {
+        self.claims.entry(round).or_default().push((sender, digest));
+    }
+
+    /// Verify the claims for a round match the local transcript.
+    ///
+    /// Must be called before the round is committed; if
+    /// a claim does not match the party number of the
+    /// sender is returned.
+    pub fn verify(
+        &mut self,
+        round: RoundNumber,
+    ) -> std::result::Result<(), PartyNumber> {
+        if let Some(claims) = self.claims.remove(&round) {
+            for (sender, digest) in claims {
+                if digest != self.digest {
+                    return Err(sender);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Commit the broadcast messages for a round to the transcript.
+    pub fn commit(&mut self, round: RoundNumber) {
+        let mut hasher = Sha3_256::new();
+        hasher.update(self.digest);
+        hasher.update(round.get().to_be_bytes());
+        if let Some(messages) = self.messages.remove(&round) {
+            for (sender, digest) in messages {
+                hasher.update(sender.get().to_be_bytes());
+                hasher.update(digest);
+            }
+        }
+        self.digest.copy_from_slice(&hasher.finalize());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transcript;
+    use crate::number;
+    use mpc_protocol::SessionId;
+
+    #[test]
+    fn transcript_bound_to_session() {
+        let first = Transcript::new(&SessionId::new_v4());
+        let second = Transcript::new(&SessionId::new_v4());
+        assert_ne!(first.digest(), second.digest());
+    }
+
+    #[test]
+    fn transcript_matching_claims() {
+        let session_id = SessionId::new_v4();
+        let mut local = Transcript::new(&session_id);
+        let mut remote = Transcript::new(&session_id);
+        for transcript in [&mut local, &mut remote] {
+            transcript.record(number(1), number(1), b"one");
+            transcript.record(number(1), number(2), b"two");
+            transcript.commit(number(1));
+        }
+
+        local.claim(number(2), number(2), *remote.digest());
+        assert_eq!(Ok(()), local.verify(number(2)));
+    }
+
+    #[test]
+    fn transcript_equivocation_detected() {
+        let session_id = SessionId::new_v4();
+        let mut local = Transcript::new(&session_id);
+        let mut remote = Transcript::new(&session_id);
+
+        // Party 3 sent a different broadcast to each party
+        local.record(number(1), number(3), b"message");
+        remote.record(number(1), number(3), b"equivocation");
+        local.commit(number(1));
+        remote.commit(number(1));
+
+        local.claim(number(2), number(2), *remote.digest());
+        assert_eq!(Err(number(2)), local.verify(number(2)));
+    }
+
+    #[test]
+    fn transcript_missing_broadcast_detected() {
+        let session_id = SessionId::new_v4();
+        let mut local = Transcript::new(&session_id);
+        let mut remote = Transcript::new(&session_id);
+
+        // Broadcast withheld from the local party
+        remote.record(number(1), number(3), b"message");
+        local.commit(number(1));
+        remote.commit(number(1));
+
+        local.claim(number(2), number(2), *remote.digest());
+        assert_eq!(Err(number(2)), local.verify(number(2)));
+    }
+}
@@ -3,27 +3,31 @@
 //! You should not use these functions directly, they are
 //! exposed so they can be shared between the client and server.
 use crate::{
-    Chunk, Encoding, Error, ProtocolState, Result, SealedEnvelope,
+    Encoding, Error, Header, ProtocolState, Rekey, Result,
+    SealedEnvelope,
 };
 
 /// Encrypt a message to send to the server.
 ///
+/// When rekey thresholds are given the sending side is
+/// rekeyed once a threshold is crossed.
+///
 /// The protocol must be in transport mode.
 #[doc(hidden)]
 pub async fn encrypt_server_channel(
     server: &mut ProtocolState,
     payload: &[u8],
     broadcast: bool,
+    rekey: Option<Rekey>,
 ) -> Result<SealedEnvelope> {
     match server {
         ProtocolState::Transport(transport) => {
-            let chunks = Chunk::split(payload, transport)?;
-            let envelope = SealedEnvelope {
+            let header = Header {
                 encoding: Encoding::Blob,
-                chunks,
                 broadcast,
+                ..Default::default()
             };
-            Ok(envelope)
+            SealedEnvelope::seal(transport, header, payload, rekey)
         }
         _ => Err(Error::NotTransportState),
     }
@@ -39,8 +43,8 @@ pub async fn decrypt_server_channel(
 ) -> Result<(Encoding, Vec<u8>)> {
     match server {
         ProtocolState::Transport(transport) => {
-            let contents = Chunk::join(envelope.chunks, transport)?;
-            Ok((envelope.encoding, contents))
+            let (header, contents) = envelope.open(transport)?;
+            Ok((header.encoding, contents))
         }
         _ => Err(Error::NotTransportState),
     }
@@ -85,12 +85,21 @@ pub(crate) mod types {
 
     pub const HANDSHAKE_SERVER: u8 = 1;
     pub const HANDSHAKE_PEER: u8 = 2;
+    pub const DELIVERED: u8 = 3;
+    pub const UNDELIVERED: u8 = 4;
 
     pub const TRANSPARENT: u8 = 128;
     pub const OPAQUE: u8 = 129;
 
     pub const OPAQUE_SERVER: u8 = 1;
     pub const OPAQUE_PEER: u8 = 2;
+    pub const OPAQUE_BROADCAST: u8 = 3;
+    pub const OPAQUE_ORDERED: u8 = 4;
+    pub const OPAQUE_TRACKED: u8 = 5;
+
+    pub const CORRELATION_NONE: u8 = 0;
+    pub const CORRELATION_REQUEST: u8 = 1;
+    pub const CORRELATION_RESPONSE: u8 = 2;
 
     pub const MEETING_NEW: u8 = 1;
     pub const MEETING_CREATED: u8 = 2;
@@ -105,7 +114,34 @@ pub(crate) mod types {
     pub const SESSION_TIMEOUT: u8 = 10;
     pub const SESSION_CLOSE: u8 = 11;
     pub const SESSION_FINISHED: u8 = 12;
+    pub const SESSION_REJOIN: u8 = 13;
+    pub const SESSION_REJOINED: u8 = 14;
+    pub const SESSION_EXPIRED: u8 = 15;
+    pub const SESSION_INVITE: u8 = 16;
+    pub const SESSION_PARTICIPANT_ADDED: u8 = 17;
+    pub const SESSION_PEER_LEFT: u8 = 18;
+    pub const SESSION_TRANSFER: u8 = 19;
+    pub const SESSION_OWNER_CHANGED: u8 = 20;
+    pub const SESSION_DECLINE: u8 = 21;
+    pub const SESSION_DECLINED: u8 = 22;
+    pub const SESSION_LIST: u8 = 23;
+    pub const SESSION_LIST_RESPONSE: u8 = 24;
+    pub const SESSION_FINISH: u8 = 25;
+    pub const SESSION_CLOSED: u8 = 26;
+    pub const SESSION_HEARTBEAT: u8 = 27;
+    pub const SESSION_PEER_TIMEOUT: u8 = 28;
+    pub const SESSION_VALUE_SET: u8 = 29;
+    pub const SESSION_VALUE_GET: u8 = 30;
+    pub const SESSION_VALUE: u8 = 31;
+
+    pub const ROLE_SIGNER: u8 = 1;
+    pub const ROLE_COORDINATOR: u8 = 2;
+    pub const ROLE_OBSERVER: u8 = 3;
 
     pub const ENCODING_BLOB: u8 = 1;
     pub const ENCODING_JSON: u8 = 2;
+    pub const ENCODING_CBOR: u8 = 3;
+    pub const ENCODING_MSGPACK: u8 = 4;
+    pub const ENCODING_SIGNAL: u8 = 5;
+    pub const ENCODING_CLOSE: u8 = 6;
 }
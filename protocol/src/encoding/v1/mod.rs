@@ -3,21 +3,21 @@ use binary_stream::futures::{
     BinaryReader, BinaryWriter, Decodable, Encodable,
 };
 use futures::io::{AsyncRead, AsyncSeek, AsyncWrite};
-use std::{collections::HashSet, io::Result};
+use std::{collections::HashSet, io::Result, num::NonZeroU64};
 
 use crate::{
     encoding::{
         decode_preamble, encode_preamble, encoding_error, types,
         MAX_BUFFER_SIZE,
     },
-    Chunk, Encoding, Error, HandshakeMessage, MeetingId,
-    MeetingState, OpaqueMessage, RequestMessage, ResponseMessage,
-    SealedEnvelope, ServerMessage, SessionId, SessionRequest,
-    SessionState, TransparentMessage,
+    Chunk, Error, HandshakeMessage, MeetingId, MeetingState,
+    OpaqueMessage, RequestMessage, ResponseMessage, SealedEnvelope,
+    ServerMessage, SessionId, SessionRequest, SessionState,
+    TransparentMessage,
 };
 
 /// Version for binary encoding.
-pub const VERSION: u16 = 1;
+pub const VERSION: u16 = 2;
 
 /// Encode a length-prefixed buffer.
 async fn encode_buffer<W: AsyncWrite + AsyncSeek + Unpin + Send>(
@@ -149,6 +149,19 @@ impl Encodable for TransparentMessage {
                 encode_buffer(writer, public_key).await?;
                 message.encode(writer).await?;
             }
+            Self::Delivered { public_key, id } => {
+                encode_buffer(writer, public_key).await?;
+                writer.write_u64(*id).await?;
+            }
+            Self::Undelivered {
+                public_key,
+                id,
+                reason,
+            } => {
+                encode_buffer(writer, public_key).await?;
+                writer.write_u64(*id).await?;
+                writer.write_string(reason).await?;
+            }
             Self::Noop => unreachable!(),
         }
         Ok(())
@@ -191,6 +204,22 @@ impl Decodable for TransparentMessage {
                     message,
                 };
             }
+            types::DELIVERED => {
+                let public_key = decode_buffer(reader).await?;
+                let id = reader.read_u64().await?;
+                *self =
+                    TransparentMessage::Delivered { public_key, id };
+            }
+            types::UNDELIVERED => {
+                let public_key = decode_buffer(reader).await?;
+                let id = reader.read_u64().await?;
+                let reason = reader.read_string().await?;
+                *self = TransparentMessage::Undelivered {
+                    public_key,
+                    id,
+                    reason,
+                };
+            }
             _ => {
                 return Err(encoding_error(
                     crate::Error::EncodingKind(id),
@@ -254,8 +283,15 @@ impl Encodable for ServerMessage {
             Self::SessionActive(response) => {
                 response.encode(writer).await?;
             }
-            Self::SessionTimeout(session_id) => {
+            Self::SessionTimeout {
+                session_id,
+                missing,
+            } => {
                 writer.write_bytes(session_id.as_bytes()).await?;
+                writer.write_u16(missing.len() as u16).await?;
+                for key in missing {
+                    encode_buffer(writer, key).await?;
+                }
             }
             Self::CloseSession(session_id) => {
                 writer.write_bytes(session_id.as_bytes()).await?;
@@ -263,6 +299,102 @@ impl Encodable for ServerMessage {
             Self::SessionFinished(session_id) => {
                 writer.write_bytes(session_id.as_bytes()).await?;
             }
+            Self::RejoinSession(session_id) => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+            }
+            Self::SessionRejoined(response) => {
+                response.encode(writer).await?;
+            }
+            Self::SessionExpired(session_id) => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+            }
+            Self::SessionPeerLeft {
+                session_id,
+                peer_key,
+            } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                encode_buffer(writer, peer_key).await?;
+            }
+            Self::InviteParticipant {
+                session_id,
+                public_key,
+            } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                encode_buffer(writer, public_key).await?;
+            }
+            Self::ParticipantAdded(response) => {
+                response.encode(writer).await?;
+            }
+            Self::TransferSession {
+                session_id,
+                public_key,
+            } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                encode_buffer(writer, public_key).await?;
+            }
+            Self::SessionOwnerChanged {
+                session_id,
+                owner_key,
+            } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                encode_buffer(writer, owner_key).await?;
+            }
+            Self::DeclineSession(session_id) => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+            }
+            Self::SessionDeclined {
+                session_id,
+                public_key,
+            } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                encode_buffer(writer, public_key).await?;
+            }
+            Self::ListSessions => {}
+            Self::FinishSession(session_id)
+            | Self::SessionClosed(session_id) => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+            }
+            Self::SessionList(sessions) => {
+                writer.write_u16(sessions.len() as u16).await?;
+                for session in sessions {
+                    session.encode(writer).await?;
+                }
+            }
+            Self::SessionHeartbeat(session_id) => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+            }
+            Self::SessionPeerTimeout {
+                session_id,
+                peer_key,
+            } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                encode_buffer(writer, peer_key).await?;
+            }
+            Self::SetSessionValue {
+                session_id,
+                key,
+                value,
+            } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                writer.write_string(key).await?;
+                encode_buffer(writer, value).await?;
+            }
+            Self::GetSessionValue { session_id, key } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                writer.write_string(key).await?;
+            }
+            Self::SessionValue {
+                session_id,
+                key,
+                value,
+            } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                writer.write_string(key).await?;
+                writer.write_bool(value.is_some()).await?;
+                if let Some(value) = value {
+                    encode_buffer(writer, value).await?;
+                }
+            }
             Self::Noop => unreachable!(),
         }
         Ok(())
@@ -380,7 +512,15 @@ impl Decodable for ServerMessage {
                         .try_into()
                         .map_err(encoding_error)?,
                 );
-                *self = ServerMessage::SessionTimeout(session_id);
+                let size = reader.read_u16().await? as usize;
+                let mut missing = Vec::with_capacity(size);
+                for _ in 0..size {
+                    missing.push(decode_buffer(reader).await?);
+                }
+                *self = ServerMessage::SessionTimeout {
+                    session_id,
+                    missing,
+                };
             }
             types::SESSION_CLOSE => {
                 let session_id = SessionId::from_bytes(
@@ -404,6 +544,244 @@ impl Decodable for ServerMessage {
                 );
                 *self = ServerMessage::SessionFinished(session_id);
             }
+            types::SESSION_REJOIN => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                *self = ServerMessage::RejoinSession(session_id);
+            }
+            types::SESSION_REJOINED => {
+                let mut session: SessionState = Default::default();
+                session.decode(reader).await?;
+                *self = ServerMessage::SessionRejoined(session);
+            }
+            types::SESSION_EXPIRED => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                *self = ServerMessage::SessionExpired(session_id);
+            }
+            types::SESSION_PEER_LEFT => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let peer_key = decode_buffer(reader).await?;
+
+                *self = ServerMessage::SessionPeerLeft {
+                    session_id,
+                    peer_key,
+                };
+            }
+            types::SESSION_INVITE => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let public_key = decode_buffer(reader).await?;
+
+                *self = ServerMessage::InviteParticipant {
+                    session_id,
+                    public_key,
+                };
+            }
+            types::SESSION_PARTICIPANT_ADDED => {
+                let mut session: SessionState = Default::default();
+                session.decode(reader).await?;
+                *self = ServerMessage::ParticipantAdded(session);
+            }
+            types::SESSION_TRANSFER => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let public_key = decode_buffer(reader).await?;
+
+                *self = ServerMessage::TransferSession {
+                    session_id,
+                    public_key,
+                };
+            }
+            types::SESSION_OWNER_CHANGED => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let owner_key = decode_buffer(reader).await?;
+
+                *self = ServerMessage::SessionOwnerChanged {
+                    session_id,
+                    owner_key,
+                };
+            }
+            types::SESSION_DECLINE => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                *self = ServerMessage::DeclineSession(session_id);
+            }
+            types::SESSION_DECLINED => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let public_key = decode_buffer(reader).await?;
+
+                *self = ServerMessage::SessionDeclined {
+                    session_id,
+                    public_key,
+                };
+            }
+            types::SESSION_LIST => {
+                *self = ServerMessage::ListSessions;
+            }
+            types::SESSION_FINISH => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                *self = ServerMessage::FinishSession(session_id);
+            }
+            types::SESSION_CLOSED => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                *self = ServerMessage::SessionClosed(session_id);
+            }
+            types::SESSION_LIST_RESPONSE => {
+                let size = reader.read_u16().await? as usize;
+                let mut sessions = Vec::with_capacity(size);
+                for _ in 0..size {
+                    let mut session: SessionState =
+                        Default::default();
+                    session.decode(reader).await?;
+                    sessions.push(session);
+                }
+                *self = ServerMessage::SessionList(sessions);
+            }
+            types::SESSION_HEARTBEAT => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                *self = ServerMessage::SessionHeartbeat(session_id);
+            }
+            types::SESSION_PEER_TIMEOUT => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let peer_key = decode_buffer(reader).await?;
+                *self = ServerMessage::SessionPeerTimeout {
+                    session_id,
+                    peer_key,
+                };
+            }
+            types::SESSION_VALUE_SET => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let key = reader.read_string().await?;
+                let value = decode_buffer(reader).await?;
+                *self = ServerMessage::SetSessionValue {
+                    session_id,
+                    key,
+                    value,
+                };
+            }
+            types::SESSION_VALUE_GET => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let key = reader.read_string().await?;
+                *self = ServerMessage::GetSessionValue {
+                    session_id,
+                    key,
+                };
+            }
+            types::SESSION_VALUE => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let key = reader.read_string().await?;
+                let value = if reader.read_bool().await? {
+                    Some(decode_buffer(reader).await?)
+                } else {
+                    None
+                };
+                *self = ServerMessage::SessionValue {
+                    session_id,
+                    key,
+                    value,
+                };
+            }
             _ => {
                 return Err(encoding_error(
                     crate::Error::EncodingKind(id),
@@ -441,6 +819,42 @@ impl Encodable for OpaqueMessage {
                 }
                 envelope.encode(writer).await?;
             }
+            Self::SessionBroadcast {
+                session_id,
+                envelopes,
+            } => {
+                writer.write_bytes(session_id.as_bytes()).await?;
+                writer.write_u16(envelopes.len() as u16).await?;
+                for (public_key, envelope) in envelopes {
+                    encode_buffer(writer, public_key).await?;
+                    envelope.encode(writer).await?;
+                }
+            }
+            Self::OrderedPeerMessage {
+                public_key,
+                session_id,
+                sequence,
+                envelope,
+            } => {
+                encode_buffer(writer, public_key).await?;
+                writer.write_bytes(session_id.as_bytes()).await?;
+                writer.write_u64(*sequence).await?;
+                envelope.encode(writer).await?;
+            }
+            Self::TrackedPeerMessage {
+                id,
+                public_key,
+                session_id,
+                envelope,
+            } => {
+                writer.write_u64(*id).await?;
+                encode_buffer(writer, public_key).await?;
+                writer.write_bool(session_id.is_some()).await?;
+                if let Some(id) = session_id {
+                    writer.write_bytes(id.as_bytes()).await?;
+                }
+                envelope.encode(writer).await?;
+            }
             Self::Noop => unreachable!(),
         }
         Ok(())
@@ -489,6 +903,77 @@ impl Decodable for OpaqueMessage {
                     envelope,
                 };
             }
+            types::OPAQUE_BROADCAST => {
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let size = reader.read_u16().await? as usize;
+                let mut envelopes = Vec::with_capacity(size);
+                for _ in 0..size {
+                    let public_key = decode_buffer(reader).await?;
+                    let mut envelope: SealedEnvelope =
+                        Default::default();
+                    envelope.decode(reader).await?;
+                    envelopes.push((public_key, envelope));
+                }
+                *self = OpaqueMessage::SessionBroadcast {
+                    session_id,
+                    envelopes,
+                };
+            }
+            types::OPAQUE_ORDERED => {
+                let public_key = decode_buffer(reader).await?;
+                let session_id = SessionId::from_bytes(
+                    reader
+                        .read_bytes(16)
+                        .await?
+                        .as_slice()
+                        .try_into()
+                        .map_err(encoding_error)?,
+                );
+                let sequence = reader.read_u64().await?;
+                let mut envelope: SealedEnvelope = Default::default();
+                envelope.decode(reader).await?;
+                *self = OpaqueMessage::OrderedPeerMessage {
+                    public_key,
+                    session_id,
+                    sequence,
+                    envelope,
+                };
+            }
+            types::OPAQUE_TRACKED => {
+                let id = reader.read_u64().await?;
+                let public_key = decode_buffer(reader).await?;
+                let has_session_id = reader.read_bool().await?;
+                let session_id = if has_session_id {
+                    let session_id = SessionId::from_bytes(
+                        reader
+                            .read_bytes(16)
+                            .await?
+                            .as_slice()
+                            .try_into()
+                            .map_err(encoding_error)?,
+                    );
+                    Some(session_id)
+                } else {
+                    None
+                };
+
+                let mut envelope: SealedEnvelope = Default::default();
+                envelope.decode(reader).await?;
+
+                *self = OpaqueMessage::TrackedPeerMessage {
+                    id,
+                    public_key,
+                    session_id,
+                    envelope,
+                };
+            }
             _ => {
                 return Err(encoding_error(
                     crate::Error::EncodingKind(id),
@@ -645,10 +1130,10 @@ impl Encodable for SealedEnvelope {
         &self,
         writer: &mut BinaryWriter<W>,
     ) -> Result<()> {
-        let id: u8 = self.encoding.into();
-        writer.write_u8(id).await?;
-        writer.write_bool(self.broadcast).await?;
-
+        writer.write_u64(self.counter).await?;
+        writer
+            .write_u64(self.interval.map(|i| i.get()).unwrap_or(0))
+            .await?;
         writer.write_u32(self.chunks.len() as u32).await?;
         for chunk in &self.chunks {
             chunk.encode(writer).await?;
@@ -663,22 +1148,8 @@ impl Decodable for SealedEnvelope {
         &mut self,
         reader: &mut BinaryReader<R>,
     ) -> Result<()> {
-        let id = reader.read_u8().await?;
-        match id {
-            types::ENCODING_BLOB => {
-                self.encoding = Encoding::Blob;
-            }
-            types::ENCODING_JSON => {
-                self.encoding = Encoding::Json;
-            }
-            _ => {
-                return Err(encoding_error(
-                    crate::Error::EncodingKind(id),
-                ))
-            }
-        }
-        self.broadcast = reader.read_bool().await?;
-
+        self.counter = reader.read_u64().await?;
+        self.interval = NonZeroU64::new(reader.read_u64().await?);
         let num_chunks = reader.read_u32().await?;
         for _ in 0..num_chunks {
             let mut chunk: Chunk = Default::default();
@@ -701,6 +1172,19 @@ impl Encodable for SessionRequest {
         for key in self.participant_keys.iter() {
             encode_buffer(writer, key).await?;
         }
+        writer.write_u16(self.roles.len() as u16).await?;
+        for (key, role) in self.roles.iter() {
+            encode_buffer(writer, key).await?;
+            writer.write_u8((*role).into()).await?;
+        }
+        writer.write_bool(self.nonce.is_some()).await?;
+        if let Some(nonce) = &self.nonce {
+            encode_buffer(writer, nonce).await?;
+        }
+        writer.write_bool(self.quorum.is_some()).await?;
+        if let Some(quorum) = self.quorum {
+            writer.write_u16(quorum).await?;
+        }
         Ok(())
     }
 }
@@ -716,6 +1200,22 @@ impl Decodable for SessionRequest {
             let key = decode_buffer(reader).await?;
             self.participant_keys.push(key);
         }
+        let size = reader.read_u16().await? as usize;
+        for _ in 0..size {
+            let key = decode_buffer(reader).await?;
+            let role = reader
+                .read_u8()
+                .await?
+                .try_into()
+                .map_err(encoding_error)?;
+            self.roles.insert(key, role);
+        }
+        if reader.read_bool().await? {
+            self.nonce = Some(decode_buffer(reader).await?);
+        }
+        if reader.read_bool().await? {
+            self.quorum = Some(reader.read_u16().await?);
+        }
         Ok(())
     }
 }
@@ -771,6 +1271,14 @@ impl Encodable for SessionState {
         for key in &self.all_participants {
             encode_buffer(writer, key).await?;
         }
+        writer.write_u16(self.roles.len() as u16).await?;
+        for role in &self.roles {
+            writer.write_u8((*role).into()).await?;
+        }
+        writer.write_bool(self.nonce.is_some()).await?;
+        if let Some(nonce) = &self.nonce {
+            encode_buffer(writer, nonce).await?;
+        }
         Ok(())
     }
 }
@@ -794,6 +1302,18 @@ impl Decodable for SessionState {
             let key = decode_buffer(reader).await?;
             self.all_participants.push(key);
         }
+        let size = reader.read_u16().await? as usize;
+        for _ in 0..size {
+            let role = reader
+                .read_u8()
+                .await?
+                .try_into()
+                .map_err(encoding_error)?;
+            self.roles.push(role);
+        }
+        if reader.read_bool().await? {
+            self.nonce = Some(decode_buffer(reader).await?);
+        }
         Ok(())
     }
 }
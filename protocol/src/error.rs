@@ -19,6 +19,30 @@ pub enum Error {
     #[error("invalid encoding kind identifier {0}")]
     EncodingKind(u8),
 
+    /// Error generated decoding a session role is invalid.
+    #[error("invalid session role identifier {0}")]
+    InvalidRole(u8),
+
+    /// Error generated decoding a fragment whose index is
+    /// not less than the number of fragments.
+    #[error("invalid fragment {0} of {1}")]
+    InvalidFragment(u32, u32),
+
+    /// Error generated when the header of a decrypted
+    /// envelope is truncated or malformed.
+    #[error("envelope header is invalid")]
+    InvalidHeader,
+
+    /// Error generated when an envelope has a counter below
+    /// the next nonce expected by the recipient.
+    #[error("envelope with counter {0} was replayed")]
+    ReplayedEnvelope(u64),
+
+    /// Error generated when an envelope counter skips more
+    /// rekeys than the recipient will perform.
+    #[error("envelope with counter {0} skips too many rekeys")]
+    RekeyGap(u64),
+
     /// Error generated when the noise pattern in a PEM does not
     /// match the pattern in use by the protocol.
     #[error(r#"noise protocol pattern mismatch, expecting "{0}""#)]
@@ -29,11 +53,43 @@ pub enum Error {
     #[error("encoding in PEM is invalid")]
     BadKeypairPem,
 
+    /// Error generated when a private key is not valid for
+    /// the noise protocol parameters.
+    #[error("private key is invalid")]
+    InvalidPrivateKey,
+
+    /// Error generated when the public key of a keypair
+    /// does not belong to the private key.
+    #[error("public key does not match the private key")]
+    KeypairMismatch,
+
     /// Error generated when a node expects to be in the transport
     /// protocol state.
     #[error("not transport protocol state")]
     NotTransportState,
 
+    /// Error generated when a session has fewer participants
+    /// than the minimum.
+    #[error("session requires at least {min} participants but has {actual}")]
+    TooFewParticipants {
+        /// Minimum number of participants.
+        min: usize,
+        /// Number of participants in the session.
+        actual: usize,
+    },
+
+    /// Error generated when a session has more participants
+    /// than the maximum.
+    #[error(
+        "session allows at most {max} participants but has {actual}"
+    )]
+    TooManyParticipants {
+        /// Maximum number of participants.
+        max: usize,
+        /// Number of participants in the session.
+        actual: usize,
+    },
+
     /// Error generated by input/output.
     #[error(transparent)]
     Io(#[from] std::io::Error),
@@ -1,7 +1,10 @@
 //! Helper functions for working with static keys.
 use crate::{
     constants::{PATTERN, PEM_PATTERN, PEM_PRIVATE, PEM_PUBLIC},
-    snow::params::NoiseParams,
+    snow::{
+        params::NoiseParams,
+        resolvers::{CryptoResolver, DefaultResolver},
+    },
     Error, Result,
 };
 use pem::Pem;
@@ -26,6 +29,22 @@ impl Keypair {
         })
     }
 
+    /// Create a keypair from an existing private key.
+    ///
+    /// The public key is derived from the private key.
+    pub fn from_private_key(
+        params: NoiseParams,
+        private_key: &[u8],
+    ) -> Result<Self> {
+        let public = derive_public_key(&params, private_key)?;
+        Ok(Self {
+            inner: snow::Keypair {
+                public,
+                private: private_key.to_vec(),
+            },
+        })
+    }
+
     /// Public key.
     pub fn public_key(&self) -> &[u8] {
         &self.inner.public
@@ -128,12 +147,20 @@ pub fn decode_keypair(keypair: impl AsRef<[u8]>) -> Result<Keypair> {
                 ));
             }
 
-            Ok(Keypair {
+            let keypair = Keypair {
                 inner: snow::Keypair {
                     public: second.into_contents(),
                     private: third.into_contents(),
                 },
-            })
+            };
+            let public = derive_public_key(
+                &PATTERN.parse()?,
+                keypair.private_key(),
+            )?;
+            if public != keypair.public_key() {
+                return Err(Error::KeypairMismatch);
+            }
+            Ok(keypair)
         } else {
             Err(Error::BadKeypairPem)
         }
@@ -142,9 +169,26 @@ pub fn decode_keypair(keypair: impl AsRef<[u8]>) -> Result<Keypair> {
     }
 }
 
+/// Derive the public key for a private key.
+fn derive_public_key(
+    params: &NoiseParams,
+    private_key: &[u8],
+) -> Result<Vec<u8>> {
+    let mut dh = DefaultResolver
+        .resolve_dh(&params.dh)
+        .ok_or(Error::InvalidPrivateKey)?;
+    if private_key.len() != dh.priv_len() {
+        return Err(Error::InvalidPrivateKey);
+    }
+    dh.set(private_key);
+    Ok(dh.pubkey().to_vec())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{decode_keypair, encode_keypair, generate_keypair};
+    use super::{
+        decode_keypair, encode_keypair, generate_keypair, Keypair,
+    };
     use crate::{
         Error, PATTERN, PEM_PATTERN, PEM_PRIVATE, PEM_PUBLIC, TAGLEN,
     };
@@ -194,6 +238,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn keypair_from_private_key() -> Result<()> {
+        let keypair = generate_keypair()?;
+        let imported = Keypair::from_private_key(
+            PATTERN.parse()?,
+            keypair.private_key(),
+        )?;
+        assert_eq!(keypair.public_key(), imported.public_key());
+
+        let result =
+            Keypair::from_private_key(PATTERN.parse()?, &[0; 7]);
+        assert!(matches!(result, Err(Error::InvalidPrivateKey)));
+        Ok(())
+    }
+
+    #[test]
+    fn decode_keypair_mismatch() -> Result<()> {
+        let keypair = generate_keypair()?;
+        let other = generate_keypair()?;
+        let pattern_pem = Pem::new(PEM_PATTERN, PATTERN.as_bytes());
+        let public_pem =
+            Pem::new(PEM_PUBLIC, other.public_key().to_vec());
+        let private_pem =
+            Pem::new(PEM_PRIVATE, keypair.private_key().to_vec());
+        let pem =
+            pem::encode_many(&[pattern_pem, public_pem, private_pem]);
+        let result = decode_keypair(&pem);
+        assert!(matches!(result, Err(Error::KeypairMismatch)));
+        Ok(())
+    }
+
     #[test]
     fn noise_transport_encrypt_decrypt() -> Result<()> {
         let builder_1 = snow::Builder::new(PATTERN.parse()?);
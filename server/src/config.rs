@@ -1,7 +1,10 @@
 //! Server configuration.
-use mpc_protocol::{decode_keypair, hex, Keypair};
+use mpc_protocol::{decode_keypair, hex, Keypair, Rekey};
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 use tokio::fs;
 
 use crate::{Error, Result};
@@ -22,6 +25,20 @@ pub struct ServerConfig {
     /// Configuration for TLS encryption.
     pub tls: Option<TlsConfig>,
 
+    /// Address for a plain TCP listener.
+    ///
+    /// Clients that can not use websockets connect to this
+    /// address with length-prefixed frames; the TLS
+    /// configuration does not apply to this listener.
+    pub tcp: Option<SocketAddr>,
+
+    /// Thresholds for rekeying the server channel.
+    ///
+    /// When no thresholds are specified messages sent to
+    /// clients are never rekeyed; clients choose when to
+    /// rekey the messages they send.
+    pub rekey: Option<Rekey>,
+
     /// Allow access to clients with these
     /// public keys.
     pub allow: Option<Vec<AccessKey>>,
@@ -70,7 +87,7 @@ pub struct TlsConfig {
 
 /// Configuration for server sessions.
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[serde(default, rename_all = "kebab-case")]
 pub struct SessionConfig {
     /// Timeout for sessions in seconds.
     ///
@@ -80,6 +97,14 @@ pub struct SessionConfig {
     /// Default is 5 minutes.
     pub timeout: u64,
 
+    /// Time to live for sessions in seconds.
+    ///
+    /// Sessions are marked for deletion once the time to
+    /// live has elapsed even when there is message activity.
+    ///
+    /// Default is 1 hour.
+    pub ttl: u64,
+
     /// Interval in seconds to reap expired sessions.
     ///
     /// Default is every 15 minutes.
@@ -100,15 +125,50 @@ pub struct SessionConfig {
     ///
     /// Default is 5 minutes.
     pub wait_timeout: u64,
+
+    /// Grace period in seconds that a disconnected participant
+    /// may rejoin a session.
+    ///
+    /// Sessions with a participant that has not rejoined
+    /// within the grace period are marked for deletion.
+    ///
+    /// Default is 2 minutes.
+    pub rejoin_grace: u64,
+
+    /// Timeout in seconds for session heartbeats.
+    ///
+    /// Participants that have sent a heartbeat and do not
+    /// send another within this time are reported to the
+    /// other participants as unresponsive. Zero disables
+    /// heartbeat checks.
+    ///
+    /// Default is 1 minute.
+    pub heartbeat_timeout: u64,
+
+    /// Maximum number of values published in a session.
+    ///
+    /// Default is 64.
+    pub max_values: usize,
+
+    /// Maximum size in bytes of a value published in a
+    /// session including the key.
+    ///
+    /// Default is 64KiB.
+    pub max_value_size: usize,
 }
 
 impl Default for SessionConfig {
     fn default() -> Self {
         Self {
             timeout: 300,
+            ttl: 3600,
             interval: 900,
             wait_interval: 15,
             wait_timeout: 300,
+            rejoin_grace: 120,
+            heartbeat_timeout: 60,
+            max_values: 64,
+            max_value_size: 64 * 1024,
         }
     }
 }
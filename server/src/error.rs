@@ -49,15 +49,35 @@ pub enum Error {
     #[error(r#"meeting "{0}" is full"#)]
     MeetingFull(MeetingId),
 
+    /// Error generated when creating a session with a derived
+    /// identifier that is already in use.
+    #[error("session already exists")]
+    SessionExists,
+
     /// Error generated when a session could not be found.
     #[error(r#"session "{0}" not found"#)]
     SessionNotFound(SessionId),
 
+    /// Error generated when publishing a value in a session
+    /// that already has the maximum number of values.
+    #[error(r#"session "{0}" has too many values, limit is {1}"#)]
+    SessionValueLimit(SessionId, usize),
+
+    /// Error generated when publishing a session value that
+    /// exceeds the maximum size.
+    #[error("session value of {0} bytes exceeds limit of {1} bytes")]
+    SessionValueTooLarge(usize, usize),
+
     /// Error generated attempting to relay to a peer in the context
     /// of a session but the target peer is not a session participant.
     #[error(r#"session "{0}" does not have participant "{1}""#)]
     NotSessionParticipant(SessionId, String),
 
+    /// Error generated when inviting a participant to a session
+    /// that is already a session participant.
+    #[error(r#"session "{0}" already has participant "{1}""#)]
+    AlreadySessionParticipant(SessionId, String),
+
     /// Error generated when the session timeout is not greater
     /// than the interval.
     #[error("session timeout must be greater than the interval")]
@@ -70,6 +90,11 @@ pub enum Error {
     )]
     SessionWaitConfig,
 
+    /// Error generated when a frame received by the TCP
+    /// listener exceeds the maximum size.
+    #[error("frame of {0} bytes exceeds the maximum size")]
+    FrameTooLarge(usize),
+
     /// Error generated by input/output.
     #[error(transparent)]
     Io(#[from] std::io::Error),
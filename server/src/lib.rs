@@ -8,6 +8,7 @@ mod config;
 mod error;
 mod server;
 mod service;
+mod tcp;
 mod websocket;
 
 pub use config::ServerConfig;
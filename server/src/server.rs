@@ -2,7 +2,7 @@ use futures::StreamExt;
 use std::{
     collections::HashMap, net::SocketAddr, sync::Arc, time::Duration,
 };
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use tokio_stream::wrappers::IntervalStream;
 
 use axum::{extract::Extension, routing::get, Router};
@@ -11,7 +11,7 @@ use tower_http::trace::TraceLayer;
 use uuid::Uuid;
 
 use mpc_protocol::{
-    hex, uuid, Keypair, MeetingManager, SessionManager,
+    hex, uuid, Keypair, MeetingManager, SessionId, SessionManager,
 };
 
 use crate::{
@@ -19,7 +19,13 @@ use crate::{
     Result,
 };
 
-use crate::{service::RelayService, websocket::Connection};
+use crate::{
+    service::{
+        notify_peers_unresponsive, notify_sessions_expired,
+        RelayService,
+    },
+    websocket::Connection,
+};
 
 pub type State = Arc<RwLock<ServerState>>;
 pub(crate) type Service = Arc<RelayService>;
@@ -39,13 +45,66 @@ async fn purge_expired(state: State, interval_secs: u64) {
             writer.meetings.remove_meeting(&key);
         }
 
-        let expired_sessions = writer
+        let mut removed_sessions = writer
             .sessions
             .expired_keys(writer.config.session.timeout);
         tracing::debug!(
-            expired_sessions = %expired_sessions.len());
-        for key in expired_sessions {
-            writer.sessions.remove_session(&key);
+            expired_sessions = %removed_sessions.len());
+
+        let abandoned_sessions = writer
+            .sessions
+            .abandoned_keys(writer.config.session.rejoin_grace);
+        tracing::debug!(
+            abandoned_sessions = %abandoned_sessions.len());
+        removed_sessions.extend(abandoned_sessions);
+
+        let mut expired = Vec::new();
+        for key in removed_sessions {
+            if let Some(session) =
+                writer.sessions.remove_session(&key)
+            {
+                let public_keys = session
+                    .public_keys()
+                    .into_iter()
+                    .map(|k| k.to_vec())
+                    .collect::<Vec<_>>();
+                expired.push((key, public_keys));
+            }
+        }
+
+        let inner = &mut *writer;
+        inner
+            .broadcasts
+            .retain(|id, _| inner.sessions.get_session(id).is_some());
+        drop(writer);
+
+        if let Err(e) =
+            notify_sessions_expired(Arc::clone(&state), expired).await
+        {
+            tracing::error!("{:#?}", e);
+        }
+    }
+}
+
+async fn check_heartbeats(state: State, timeout_secs: u64) {
+    let interval_secs = (timeout_secs / 2).max(1);
+    let interval =
+        tokio::time::interval(Duration::from_secs(interval_secs));
+    let mut stream = IntervalStream::new(interval);
+    while stream.next().await.is_some() {
+        let unresponsive = {
+            let mut writer = state.write().await;
+            writer.sessions.take_unresponsive(timeout_secs)
+        };
+        tracing::debug!(unresponsive = %unresponsive.len());
+
+        if let Err(e) = notify_peers_unresponsive(
+            Arc::clone(&state),
+            unresponsive,
+        )
+        .await
+        {
+            tracing::error!("{:#?}", e);
         }
     }
 }
@@ -70,6 +129,10 @@ pub struct ServerState {
 
     /// Session manager.
     pub(crate) sessions: SessionManager,
+
+    /// Locks that serialize the ordered broadcasts in each
+    /// session without holding the server state lock.
+    pub(crate) broadcasts: HashMap<SessionId, Arc<Mutex<()>>>,
 }
 
 /// Relay web server.
@@ -88,6 +151,7 @@ impl RelayServer {
                 active: Default::default(),
                 meetings: Default::default(),
                 sessions: Default::default(),
+                broadcasts: Default::default(),
             })),
         }
     }
@@ -100,7 +164,10 @@ impl RelayServer {
     ) -> Result<()> {
         let reader = self.state.read().await;
         let interval = reader.config.session.interval;
+        let heartbeat_timeout =
+            reader.config.session.heartbeat_timeout;
         let tls = reader.config.tls.as_ref().cloned();
+        let tcp = reader.config.tcp;
         drop(reader);
 
         // Spawn task to reap expired sessions
@@ -109,6 +176,28 @@ impl RelayServer {
             interval,
         ));
 
+        // Spawn task to detect unresponsive participants
+        if heartbeat_timeout > 0 {
+            tokio::task::spawn(check_heartbeats(
+                Arc::clone(&self.state),
+                heartbeat_timeout,
+            ));
+        }
+
+        // Spawn task to accept plain TCP connections
+        if let Some(tcp) = tcp {
+            let state = Arc::clone(&self.state);
+            let service =
+                Arc::new(RelayService::new(Arc::clone(&state)));
+            tokio::task::spawn(async move {
+                if let Err(e) =
+                    crate::tcp::listen(tcp, state, service).await
+                {
+                    tracing::error!("{:#?}", e);
+                }
+            });
+        }
+
         if let Some(tls) = tls {
             self.run_tls(addr, handle, tls).await
         } else {
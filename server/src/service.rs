@@ -11,7 +11,8 @@ use mpc_protocol::{
     channel::{decrypt_server_channel, encrypt_server_channel},
     decode, encode, hex, Encoding, HandshakeMessage, MeetingState,
     OpaqueMessage, ProtocolState, RequestMessage, ResponseMessage,
-    ServerMessage, SessionState, TransparentMessage,
+    SealedEnvelope, ServerMessage, SessionId, SessionState,
+    TransparentMessage,
 };
 
 use crate::{server::State, websocket::Connection, Error, Result};
@@ -156,62 +157,124 @@ async fn handle_request(
             session_id,
             envelope,
         }) => {
-            // When we have a session identifier check the session
-            // is valid and the target peer is a session participant.
-            if let Some(id) = session_id {
+            relay_peer_message(
+                &state, &conn, public_key, session_id, envelope,
+            )
+            .await?;
+        }
+        RequestMessage::Opaque(
+            OpaqueMessage::TrackedPeerMessage {
+                id,
+                public_key,
+                session_id,
+                envelope,
+            },
+        ) => {
+            let response = match relay_peer_message(
+                &state,
+                &conn,
+                public_key.clone(),
+                session_id,
+                envelope,
+            )
+            .await
+            {
+                Ok(_) => {
+                    TransparentMessage::Delivered { public_key, id }
+                }
+                Err(e) => TransparentMessage::Undelivered {
+                    public_key,
+                    id,
+                    reason: e.to_string(),
+                },
+            };
+            let buffer =
+                encode(&ResponseMessage::Transparent(response))
+                    .await?;
+            let mut writer = conn.write().await;
+            writer.send(buffer).await?;
+        }
+        RequestMessage::Opaque(OpaqueMessage::SessionBroadcast {
+            session_id,
+            envelopes,
+        }) => {
+            let from_public_key = {
+                let reader = conn.read().await;
+                reader.public_key.clone()
+            };
+
+            let (peers, lock) = {
                 let mut writer = state.write().await;
                 if let Some(session) =
-                    writer.sessions.touch_session(&id)
+                    writer.sessions.touch_session(&session_id)
                 {
-                    let public_keys = session.public_keys();
-                    let is_participant = public_keys
-                        .into_iter()
-                        .any(|k| k == public_key);
+                    let senders = std::iter::once(&from_public_key)
+                        .chain(envelopes.iter().map(|(key, _)| key));
+                    for key in senders {
+                        if !session.is_participant(key) {
+                            return Err(
+                                Error::NotSessionParticipant(
+                                    session_id,
+                                    hex::encode(key),
+                                ),
+                            );
+                        }
+                    }
+                } else {
+                    return Err(Error::SessionNotFound(session_id));
+                }
 
-                    if !is_participant {
-                        return Err(Error::NotSessionParticipant(
-                            id,
+                let mut peers = Vec::with_capacity(envelopes.len());
+                for (public_key, envelope) in envelopes {
+                    if let Some(peer) =
+                        writer.active.get(&public_key).map(Arc::clone)
+                    {
+                        peers.push((peer, envelope));
+                    } else {
+                        return Err(Error::PeerNotFound(
                             hex::encode(public_key),
                         ));
                     }
-                } else {
-                    return Err(Error::SessionNotFound(id));
                 }
-            }
 
-            let from_public_key = {
-                let reader = conn.read().await;
-                reader.public_key.clone()
+                let lock = Arc::clone(
+                    writer.broadcasts.entry(session_id).or_default(),
+                );
+                (peers, lock)
             };
 
-            let peer = {
-                let reader = state.read().await;
-                reader.active.get(&public_key).map(Arc::clone)
+            // Ordered broadcasts in a session are never
+            // interleaved; the server state is not locked
+            // whilst sending so other sessions are not blocked
+            let _guard = lock.lock().await;
+            let sequence = {
+                let mut writer = state.write().await;
+                writer
+                    .sessions
+                    .get_session_mut(&session_id)
+                    .map(|session| session.next_sequence())
+                    .ok_or(Error::SessionNotFound(session_id))?
             };
 
-            if let Some(peer) = peer {
-                let mut writer = peer.write().await;
-
-                tracing::debug!(
-                    to = ?hex::encode(&public_key),
-                    from = ?hex::encode(&from_public_key),
-                    "relay",
-                );
+            tracing::debug!(
+                from = ?hex::encode(&from_public_key),
+                session_id = %session_id,
+                sequence = %sequence,
+                "relay ordered broadcast",
+            );
 
+            for (peer, envelope) in peers {
                 let relayed = ResponseMessage::Opaque(
-                    OpaqueMessage::PeerMessage {
-                        public_key: from_public_key,
+                    OpaqueMessage::OrderedPeerMessage {
+                        public_key: from_public_key.clone(),
                         session_id,
+                        sequence,
                         envelope,
                     },
                 );
-
                 let buffer = encode(&relayed).await?;
-                writer.send(buffer).await?;
-            } else {
-                return Err(Error::PeerNotFound(hex::encode(
-                    public_key,
-                )));
+                let mut peer = peer.write().await;
+                peer.send(buffer).await?;
             }
         }
         RequestMessage::Opaque(OpaqueMessage::ServerMessage(
@@ -307,25 +370,57 @@ async fn wait_for_session_ready(
     state: State,
     owner: Connection,
     start_time: SystemTime,
-    session: SessionState,
+    mut session: SessionState,
 ) {
     let interval =
         tokio::time::interval(Duration::from_secs(interval_secs));
     let mut stream = IntervalStream::new(interval);
     while stream.next().await.is_some() {
-        let (ready, wait_timeout) = {
+        let (connected, quorum, wait_timeout) = {
             let reader = state.read().await;
             (
-                session.all_participants.iter().all(|public_key| {
-                    reader.active.get(public_key).is_some()
-                }),
+                session
+                    .all_participants
+                    .iter()
+                    .filter(|public_key| {
+                        reader.active.get(*public_key).is_some()
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>(),
+                reader
+                    .sessions
+                    .get_session(&session.session_id)
+                    .and_then(|s| s.quorum()),
                 Duration::from_secs(
                     reader.config.session.wait_timeout,
                 ),
             )
         };
+        let missing: Vec<_> = session
+            .all_participants
+            .iter()
+            .filter(|public_key| !connected.contains(public_key))
+            .cloned()
+            .collect();
 
-        if ready {
+        // Narrow the session to the connected participants
+        // once a quorum is reached
+        let mut is_quorum = false;
+        if !missing.is_empty()
+            && quorum.map(|q| connected.len() >= q).unwrap_or(false)
+        {
+            let mut writer = state.write().await;
+            if let Some(s) =
+                writer.sessions.get_session_mut(&session.session_id)
+            {
+                if s.retain_participants(&connected) {
+                    session = s.state(session.session_id);
+                    is_quorum = true;
+                }
+            }
+        }
+
+        if missing.is_empty() || is_quorum {
             if let Err(e) = notify_session_ready(
                 Arc::clone(&state),
                 session.clone(),
@@ -346,7 +441,8 @@ async fn wait_for_session_ready(
             let duration = start_time.elapsed().unwrap();
             if duration > wait_timeout {
                 if let Err(e) =
-                    notify_session_timeout(state, session).await
+                    notify_session_timeout(state, session, missing)
+                        .await
                 {
                     tracing::error!("{:#?}", e);
                 }
@@ -395,13 +491,13 @@ async fn wait_for_session_active(
         tokio::time::interval(Duration::from_secs(interval_secs));
     let mut stream = IntervalStream::new(interval);
     while stream.next().await.is_some() {
-        let (active, wait_timeout) = {
+        let (missing, wait_timeout) = {
             let reader = state.read().await;
             if let Some(session) =
                 reader.sessions.get_session(&session.session_id)
             {
                 (
-                    session.is_active(),
+                    session.unconnected_participants(),
                     Duration::from_secs(
                         reader.config.session.wait_timeout,
                     ),
@@ -411,7 +507,7 @@ async fn wait_for_session_active(
             }
         };
 
-        if active {
+        if missing.is_empty() {
             if let Err(e) =
                 notify_session_active(state, session).await
             {
@@ -422,7 +518,8 @@ async fn wait_for_session_active(
             let duration = start_time.elapsed().unwrap();
             if duration > wait_timeout {
                 if let Err(e) =
-                    notify_session_timeout(state, session).await
+                    notify_session_timeout(state, session, missing)
+                        .await
                 {
                     tracing::error!("{:#?}", e);
                 }
@@ -449,13 +546,17 @@ async fn notify_session_active(
 async fn notify_session_timeout(
     state: State,
     session: SessionState,
+    missing: Vec<Vec<u8>>,
 ) -> Result<()> {
     let public_keys: Vec<_> = session
         .all_participants
         .iter()
         .map(|key| key.to_vec())
         .collect();
-    let message = ServerMessage::SessionTimeout(session.session_id);
+    let message = ServerMessage::SessionTimeout {
+        session_id: session.session_id,
+        missing,
+    };
     notify_peers(state, public_keys, message).await?;
 
     Ok(())
@@ -520,23 +621,23 @@ async fn service(
             }
         }
         ServerMessage::NewSession(request) => {
-            let mut all_participants =
-                vec![public_key.as_ref().to_vec()];
-            all_participants
-                .append(&mut request.participant_keys.clone());
-
-            let (session_id, wait_interval) = {
+            let (response, wait_interval) = {
                 let mut writer = state.write().await;
-                let session_id = writer.sessions.new_session(
-                    public_key.as_ref().to_vec(),
-                    request.participant_keys,
-                );
-                (session_id, writer.config.session.wait_interval)
-            };
-
-            let response = SessionState {
-                session_id,
-                all_participants,
+                let ttl = writer.config.session.ttl;
+                let session_id = writer
+                    .sessions
+                    .new_session(
+                        public_key.as_ref().to_vec(),
+                        request,
+                        ttl,
+                    )
+                    .ok_or(Error::SessionExists)?;
+                let response = writer
+                    .sessions
+                    .get_session(&session_id)
+                    .unwrap()
+                    .state(session_id);
+                (response, writer.config.session.wait_interval)
             };
 
             tokio::task::spawn(wait_for_session_ready(
@@ -570,7 +671,7 @@ async fn service(
             }
         }
         ServerMessage::CloseSession(session_id) => {
-            {
+            let public_keys: Vec<Vec<u8>> = {
                 let reader = state.read().await;
                 if let Some(session) =
                     reader.sessions.get_session(&session_id)
@@ -578,33 +679,384 @@ async fn service(
                     if public_key.as_ref() != session.owner_key() {
                         return Err(Error::PermissionDenied);
                     }
+                    session
+                        .public_keys()
+                        .into_iter()
+                        .filter(|key| *key != public_key.as_ref())
+                        .map(|key| key.to_vec())
+                        .collect()
                 } else {
                     return Err(Error::SessionNotFound(session_id));
                 }
+            };
+
+            {
+                let mut writer = state.write().await;
+                writer.sessions.remove_session(&session_id);
             }
 
-            let mut writer = state.write().await;
-            writer.sessions.remove_session(&session_id);
+            let message = ServerMessage::SessionClosed(session_id);
+            notify_peers(state, public_keys, message).await?;
 
             Ok(Some(ServerMessage::SessionFinished(session_id)))
         }
+        ServerMessage::FinishSession(session_id) => {
+            let public_keys: Option<Vec<Vec<u8>>> = {
+                let mut writer = state.write().await;
+                if let Some(session) =
+                    writer.sessions.get_session_mut(&session_id)
+                {
+                    if !session.is_participant(public_key.as_ref()) {
+                        return Err(Error::NotSessionParticipant(
+                            session_id,
+                            hex::encode(public_key.as_ref()),
+                        ));
+                    }
+                    if session.finish(public_key.as_ref()) {
+                        let public_keys = session
+                            .public_keys()
+                            .into_iter()
+                            .map(|key| key.to_vec())
+                            .collect();
+                        writer.sessions.remove_session(&session_id);
+                        Some(public_keys)
+                    } else {
+                        None
+                    }
+                } else {
+                    return Err(Error::SessionNotFound(session_id));
+                }
+            };
+
+            if let Some(public_keys) = public_keys {
+                let message =
+                    ServerMessage::SessionFinished(session_id);
+                notify_peers(state, public_keys, message).await?;
+            }
+            Ok(None)
+        }
+        ServerMessage::InviteParticipant {
+            session_id,
+            public_key: invited_key,
+        } => {
+            let session = {
+                let mut writer = state.write().await;
+                if let Some(session) =
+                    writer.sessions.get_session_mut(&session_id)
+                {
+                    if public_key.as_ref() != session.owner_key() {
+                        return Err(Error::PermissionDenied);
+                    }
+                    if !session.add_participant(invited_key.clone()) {
+                        return Err(
+                            Error::AlreadySessionParticipant(
+                                session_id,
+                                hex::encode(invited_key),
+                            ),
+                        );
+                    }
+                    session.state(session_id)
+                } else {
+                    return Err(Error::SessionNotFound(session_id));
+                }
+            };
+
+            notify_participant_added(state, session).await?;
+            Ok(None)
+        }
+        ServerMessage::TransferSession {
+            session_id,
+            public_key: owner_key,
+        } => {
+            let public_keys = {
+                let mut writer = state.write().await;
+                if let Some(session) =
+                    writer.sessions.get_session_mut(&session_id)
+                {
+                    if public_key.as_ref() != session.owner_key() {
+                        return Err(Error::PermissionDenied);
+                    }
+                    if !session.transfer_ownership(&owner_key) {
+                        return Err(Error::NotSessionParticipant(
+                            session_id,
+                            hex::encode(&owner_key),
+                        ));
+                    }
+                    session
+                        .public_keys()
+                        .into_iter()
+                        .map(|key| key.to_vec())
+                        .collect()
+                } else {
+                    return Err(Error::SessionNotFound(session_id));
+                }
+            };
+
+            let message = ServerMessage::SessionOwnerChanged {
+                session_id,
+                owner_key,
+            };
+            notify_peers(state, public_keys, message).await?;
+            Ok(None)
+        }
+        ServerMessage::ListSessions => {
+            let reader = state.read().await;
+            let sessions = reader
+                .sessions
+                .participant_sessions(public_key.as_ref());
+            Ok(Some(ServerMessage::SessionList(sessions)))
+        }
+        ServerMessage::DeclineSession(session_id) => {
+            let public_keys = {
+                let reader = state.read().await;
+                if let Some(session) =
+                    reader.sessions.get_session(&session_id)
+                {
+                    if !session.is_participant(public_key.as_ref()) {
+                        return Err(Error::NotSessionParticipant(
+                            session_id,
+                            hex::encode(public_key.as_ref()),
+                        ));
+                    }
+                    session
+                        .public_keys()
+                        .into_iter()
+                        .filter(|key| *key != public_key.as_ref())
+                        .map(|key| key.to_vec())
+                        .collect()
+                } else {
+                    return Err(Error::SessionNotFound(session_id));
+                }
+            };
+
+            let message = ServerMessage::SessionDeclined {
+                session_id,
+                public_key: public_key.as_ref().to_vec(),
+            };
+            notify_peers(state, public_keys, message).await?;
+            Ok(None)
+        }
+        ServerMessage::RejoinSession(session_id) => {
+            let mut writer = state.write().await;
+            let grace = writer.config.session.rejoin_grace;
+            let response = if let Some(session) =
+                writer.sessions.get_session_mut(&session_id)
+            {
+                if !session.is_participant(public_key.as_ref()) {
+                    return Err(Error::NotSessionParticipant(
+                        session_id,
+                        hex::encode(public_key.as_ref()),
+                    ));
+                }
+                if session.is_abandoned(grace) {
+                    None
+                } else {
+                    session.rejoin(public_key.as_ref());
+                    Some(session.state(session_id))
+                }
+            } else {
+                return Err(Error::SessionNotFound(session_id));
+            };
+
+            if let Some(response) = response {
+                Ok(Some(ServerMessage::SessionRejoined(response)))
+            } else {
+                writer.sessions.remove_session(&session_id);
+                Err(Error::SessionNotFound(session_id))
+            }
+        }
+        ServerMessage::SetSessionValue {
+            session_id,
+            key,
+            value,
+        } => {
+            let public_keys: Vec<Vec<u8>> = {
+                let mut writer = state.write().await;
+                let max_values = writer.config.session.max_values;
+                let max_size = writer.config.session.max_value_size;
+                let size = key.len() + value.len();
+                if size > max_size {
+                    return Err(Error::SessionValueTooLarge(
+                        size, max_size,
+                    ));
+                }
+                if let Some(session) =
+                    writer.sessions.get_session_mut(&session_id)
+                {
+                    if !session.is_participant(public_key.as_ref()) {
+                        return Err(Error::NotSessionParticipant(
+                            session_id,
+                            hex::encode(public_key.as_ref()),
+                        ));
+                    }
+                    if session.value(&key).is_none()
+                        && session.value_count() >= max_values
+                    {
+                        return Err(Error::SessionValueLimit(
+                            session_id, max_values,
+                        ));
+                    }
+                    session.set_value(key.clone(), value.clone());
+                    session
+                        .public_keys()
+                        .into_iter()
+                        .map(|key| key.to_vec())
+                        .collect()
+                } else {
+                    return Err(Error::SessionNotFound(session_id));
+                }
+            };
+
+            let message = ServerMessage::SessionValue {
+                session_id,
+                key,
+                value: Some(value),
+            };
+            notify_peers(state, public_keys, message).await?;
+            Ok(None)
+        }
+        ServerMessage::GetSessionValue { session_id, key } => {
+            let reader = state.read().await;
+            if let Some(session) =
+                reader.sessions.get_session(&session_id)
+            {
+                if !session.is_participant(public_key.as_ref()) {
+                    return Err(Error::NotSessionParticipant(
+                        session_id,
+                        hex::encode(public_key.as_ref()),
+                    ));
+                }
+                let value = session.value(&key).map(|v| v.to_vec());
+                Ok(Some(ServerMessage::SessionValue {
+                    session_id,
+                    key,
+                    value,
+                }))
+            } else {
+                Err(Error::SessionNotFound(session_id))
+            }
+        }
+        ServerMessage::SessionHeartbeat(session_id) => {
+            let mut writer = state.write().await;
+            if let Some(session) =
+                writer.sessions.get_session_mut(&session_id)
+            {
+                if !session.is_participant(public_key.as_ref()) {
+                    return Err(Error::NotSessionParticipant(
+                        session_id,
+                        hex::encode(public_key.as_ref()),
+                    ));
+                }
+                session.heartbeat(public_key.as_ref());
+                Ok(None)
+            } else {
+                Err(Error::SessionNotFound(session_id))
+            }
+        }
         _ => Ok(None),
     }
 }
 
+async fn notify_participant_added(
+    state: State,
+    session: SessionState,
+) -> Result<()> {
+    let public_keys: Vec<_> = session
+        .all_participants
+        .iter()
+        .map(|key| key.to_vec())
+        .collect();
+    let message = ServerMessage::ParticipantAdded(session);
+    notify_peers(state, public_keys, message).await?;
+    Ok(())
+}
+
+/// Notify the other participants in sessions that a
+/// participant has disconnected.
+pub(crate) async fn notify_peer_left(
+    state: State,
+    peer_key: Vec<u8>,
+    sessions: Vec<(SessionId, Vec<Vec<u8>>)>,
+) -> Result<()> {
+    for (session_id, public_keys) in sessions {
+        let message = ServerMessage::SessionPeerLeft {
+            session_id,
+            peer_key: peer_key.clone(),
+        };
+        notify_peers(Arc::clone(&state), public_keys, message)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Notify the participants in sessions that a participant
+/// has stopped sending heartbeats.
+pub(crate) async fn notify_peers_unresponsive(
+    state: State,
+    unresponsive: Vec<(SessionId, Vec<u8>, Vec<Vec<u8>>)>,
+) -> Result<()> {
+    for (session_id, peer_key, public_keys) in unresponsive {
+        let message = ServerMessage::SessionPeerTimeout {
+            session_id,
+            peer_key,
+        };
+        if let Err(e) =
+            notify_peers(Arc::clone(&state), public_keys, message)
+                .await
+        {
+            tracing::warn!(
+                session_id = %session_id,
+                error = %e,
+                "notify peer timeout failed",
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Notify the participants in sessions that have expired.
+pub(crate) async fn notify_sessions_expired(
+    state: State,
+    sessions: Vec<(SessionId, Vec<Vec<u8>>)>,
+) -> Result<()> {
+    for (session_id, public_keys) in sessions {
+        let message = ServerMessage::SessionExpired(session_id);
+        // Keep notifying the remaining sessions
+        if let Err(e) =
+            notify_peers(Arc::clone(&state), public_keys, message)
+                .await
+        {
+            tracing::warn!(
+                session_id = %session_id,
+                error = %e,
+                "notify session expired failed",
+            );
+        }
+    }
+    Ok(())
+}
+
 /// Send a message to a collection of peers.
 async fn notify_peers(
     state: State,
     public_keys: Vec<Vec<u8>>,
     message: ServerMessage,
 ) -> Result<()> {
-    let reader = state.read().await;
-    for key in &public_keys {
-        if let Some(conn) = reader.active.get(key).map(Arc::clone) {
-            send_message(conn, &message, true).await?;
+    let connections: Vec<_> = {
+        let reader = state.read().await;
+        public_keys
+            .iter()
+            .filter_map(|key| reader.active.get(key).map(Arc::clone))
+            .collect()
+    };
+    // Notify every peer even when sending to one fails
+    let mut result = Ok(());
+    for conn in connections {
+        if let Err(e) = send_message(conn, &message, true).await {
+            result = Err(e);
         }
     }
-    Ok(())
+    result
 }
 
 /// Handle a server error.
@@ -636,6 +1088,68 @@ async fn handle_error(conn: Connection, error: Error) -> Result<()> {
     Ok(())
 }
 
+/// Relay an encrypted message to a peer.
+async fn relay_peer_message(
+    state: &State,
+    conn: &Connection,
+    public_key: Vec<u8>,
+    session_id: Option<SessionId>,
+    envelope: SealedEnvelope,
+) -> Result<()> {
+    // When we have a session identifier check the session
+    // is valid and the target peer is a session participant.
+    if let Some(id) = session_id {
+        let mut writer = state.write().await;
+        if let Some(session) = writer.sessions.touch_session(&id) {
+            let public_keys = session.public_keys();
+            let is_participant =
+                public_keys.into_iter().any(|k| k == public_key);
+
+            if !is_participant {
+                return Err(Error::NotSessionParticipant(
+                    id,
+                    hex::encode(public_key),
+                ));
+            }
+        } else {
+            return Err(Error::SessionNotFound(id));
+        }
+    }
+
+    let from_public_key = {
+        let reader = conn.read().await;
+        reader.public_key.clone()
+    };
+
+    let peer = {
+        let reader = state.read().await;
+        reader.active.get(&public_key).map(Arc::clone)
+    };
+
+    if let Some(peer) = peer {
+        let mut writer = peer.write().await;
+
+        tracing::debug!(
+            to = ?hex::encode(&public_key),
+            from = ?hex::encode(&from_public_key),
+            "relay",
+        );
+
+        let relayed =
+            ResponseMessage::Opaque(OpaqueMessage::PeerMessage {
+                public_key: from_public_key,
+                session_id,
+                envelope,
+            });
+
+        let buffer = encode(&relayed).await?;
+        writer.send(buffer).await?;
+    } else {
+        return Err(Error::PeerNotFound(hex::encode(public_key)));
+    }
+    Ok(())
+}
+
 /// Send a response message to a client over the server channel.
 async fn send_message(
     conn: Connection,
@@ -645,10 +1159,12 @@ async fn send_message(
     let mut writer = conn.write().await;
 
     let payload = encode(message).await?;
+    let rekey = writer.rekey;
     let envelope = encrypt_server_channel(
         writer.state.as_mut().unwrap(),
         &payload,
         broadcast,
+        rekey,
     )
     .await?;
 
@@ -0,0 +1,181 @@
+//! Plain TCP listener for clients that can not use websockets.
+//!
+//! Each frame is a big-endian `u32` length followed by the
+//! same compressed message carried in a websocket binary
+//! message; an empty frame is a keep-alive ping or pong.
+//! The first frame sent by a client is its public key.
+use axum::extract::ws::Message;
+use std::{io::ErrorKind, net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::mpsc,
+};
+
+use crate::{
+    server::{Service, State},
+    websocket::{accept, disconnect, Accepted, Connection},
+    Error, Result,
+};
+use mpc_protocol::zlib;
+
+/// Maximum size of a frame, must match the client.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Accept TCP connections on an address.
+pub(crate) async fn listen(
+    addr: SocketAddr,
+    state: State,
+    service: Service,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("listening for tcp on {}", addr);
+    loop {
+        let (stream, remote) = listener.accept().await?;
+        let state = Arc::clone(&state);
+        let service = Arc::clone(&service);
+        tokio::spawn(async move {
+            if let Err(e) =
+                handle_stream(stream, state, service).await
+            {
+                tracing::warn!(
+                    addr = %remote,
+                    error = %e,
+                    "tcp connection error",
+                );
+            }
+        });
+    }
+}
+
+async fn handle_stream(
+    stream: TcpStream,
+    state: State,
+    service: Service,
+) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let (mut reader, writer) = stream.into_split();
+
+    let public_key = match read_frame(&mut reader).await? {
+        Some(public_key) => public_key,
+        None => return Ok(()),
+    };
+    let Accepted {
+        conn,
+        outgoing_rx,
+        outgoing_tx,
+        service_reader,
+    } = match accept(&state, public_key).await {
+        Ok(accepted) => accepted,
+        Err(status) => {
+            tracing::debug!(
+                status = %status,
+                "tcp connection rejected",
+            );
+            return Ok(());
+        }
+    };
+
+    service.listen_socket(Arc::clone(&conn), service_reader);
+    tokio::spawn(write(
+        writer,
+        Arc::clone(&state),
+        Arc::clone(&conn),
+        outgoing_rx,
+    ));
+    read(reader, state, conn, outgoing_tx).await
+}
+
+async fn read(
+    mut reader: OwnedReadHalf,
+    state: State,
+    conn: Connection,
+    outgoing_tx: mpsc::Sender<Message>,
+) -> Result<()> {
+    let tx = {
+        let reader = conn.read().await;
+        reader.incoming.clone()
+    };
+
+    loop {
+        match read_frame(&mut reader).await {
+            Ok(Some(buffer)) if buffer.is_empty() => {
+                let _ =
+                    outgoing_tx.send(Message::Pong(Vec::new())).await;
+            }
+            Ok(Some(buffer)) => {
+                if let Ok(inflated) = zlib::inflate(&buffer) {
+                    tx.send(inflated).await?;
+                } else {
+                    tracing::warn!(
+                        "could not inflate message buffer"
+                    );
+                }
+            }
+            Ok(None) => {
+                let _ = outgoing_tx.send(Message::Close(None)).await;
+                disconnect(state, Arc::clone(&conn)).await;
+                return Ok(());
+            }
+            Err(e) => {
+                let _ = outgoing_tx.send(Message::Close(None)).await;
+                disconnect(state, Arc::clone(&conn)).await;
+                return Err(e);
+            }
+        }
+    }
+}
+
+async fn write(
+    mut writer: OwnedWriteHalf,
+    state: State,
+    conn: Connection,
+    mut outgoing_rx: mpsc::Receiver<Message>,
+) -> Result<()> {
+    while let Some(message) = outgoing_rx.recv().await {
+        let buffer = match message {
+            Message::Binary(buffer) => buffer,
+            Message::Pong(_) => Vec::new(),
+            Message::Close(_) => {
+                let _ = writer.shutdown().await;
+                return Ok(());
+            }
+            _ => continue,
+        };
+        if write_frame(&mut writer, &buffer).await.is_err() {
+            disconnect(state, Arc::clone(&conn)).await;
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+async fn write_frame(
+    writer: &mut OwnedWriteHalf,
+    buffer: &[u8],
+) -> Result<()> {
+    writer.write_u32(buffer.len() as u32).await?;
+    writer.write_all(buffer).await?;
+    Ok(())
+}
+
+async fn read_frame(
+    reader: &mut OwnedReadHalf,
+) -> Result<Option<Vec<u8>>> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(Error::FrameTooLarge(len));
+    }
+    let mut buffer = vec![0; len];
+    reader.read_exact(&mut buffer).await?;
+    Ok(Some(buffer))
+}
@@ -20,13 +20,14 @@ use tokio::sync::{mpsc, RwLock};
 
 use crate::{
     server::{Service, State},
+    service::notify_peer_left,
     Result,
 };
 use mpc_protocol::{
     hex,
     snow::{params::NoiseParams, Builder},
     uuid::Uuid,
-    ProtocolState, PATTERN,
+    ProtocolState, Rekey, PATTERN,
     zlib,
 };
 
@@ -58,6 +59,8 @@ pub struct WebSocketConnection {
     /// so we move out of the option and convert to
     /// transport mode and then put it back.
     pub(crate) state: Option<ProtocolState>,
+    /// Thresholds for rekeying messages sent to the client.
+    pub(crate) rekey: Option<Rekey>,
 }
 
 impl fmt::Debug for WebSocketConnection {
@@ -78,21 +81,29 @@ impl WebSocketConnection {
     }
 }
 
-/// Upgrade to a websocket connection.
-//#[debug_handler]
-pub async fn upgrade(
-    Extension(state): Extension<State>,
-    Extension(service): Extension<Service>,
-    Query(query): Query<WebSocketQuery>,
-    ws: WebSocketUpgrade,
-) -> std::result::Result<Response, StatusCode> {
-    tracing::debug!("websocket upgrade request");
+/// Channels for a connection accepted by the server.
+pub(crate) struct Accepted {
+    /// Connection state.
+    pub conn: Connection,
+    /// Receiver for messages sent to the client.
+    pub outgoing_rx: mpsc::Receiver<Message>,
+    /// Sender for messages sent to the client.
+    pub outgoing_tx: mpsc::Sender<Message>,
+    /// Receiver for buffers read from the client.
+    pub service_reader: mpsc::Receiver<Vec<u8>>,
+}
 
+/// Check the access lists and register a pending
+/// connection for a client public key.
+pub(crate) async fn accept(
+    state: &State,
+    public_key: Vec<u8>,
+) -> std::result::Result<Accepted, StatusCode> {
     let mut writer = state.write().await;
 
     // Check access lists
     if (writer.config.allow.is_some() || writer.config.deny.is_some())
-        && !writer.config.is_allowed_access(&query.public_key)
+        && !writer.config.is_allowed_access(&public_key)
     {
         return Err(StatusCode::FORBIDDEN);
     }
@@ -109,7 +120,7 @@ pub async fn upgrade(
 
     let responder = Builder::new(params)
         .local_private_key(writer.keypair.private_key())
-        .remote_public_key(&query.public_key)
+        .remote_public_key(&public_key)
         .build_responder()
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     let protocol_state =
@@ -121,38 +132,83 @@ pub async fn upgrade(
 
     let conn = Arc::new(RwLock::new(WebSocketConnection {
         id,
-        public_key: query.public_key,
+        public_key,
         outgoing: outgoing_tx.clone(),
         incoming,
         state: Some(protocol_state),
+        rekey: writer.config.rekey,
     }));
-    let socket_conn = Arc::clone(&conn);
-    writer.pending.insert(id, conn);
-    drop(writer);
+    writer.pending.insert(id, Arc::clone(&conn));
+
+    Ok(Accepted {
+        conn,
+        outgoing_rx,
+        outgoing_tx,
+        service_reader,
+    })
+}
+
+/// Upgrade to a websocket connection.
+//#[debug_handler]
+pub async fn upgrade(
+    Extension(state): Extension<State>,
+    Extension(service): Extension<Service>,
+    Query(query): Query<WebSocketQuery>,
+    ws: WebSocketUpgrade,
+) -> std::result::Result<Response, StatusCode> {
+    tracing::debug!("websocket upgrade request");
+
+    let Accepted {
+        conn,
+        outgoing_rx,
+        outgoing_tx,
+        service_reader,
+    } = accept(&state, query.public_key).await?;
 
     let socket_state = Arc::clone(&state);
     Ok(ws.on_upgrade(move |socket| {
-        service
-            .listen_socket(Arc::clone(&socket_conn), service_reader);
+        service.listen_socket(Arc::clone(&conn), service_reader);
         handle_socket(
             socket,
             socket_state,
-            socket_conn,
+            conn,
             outgoing_rx,
             outgoing_tx,
         )
     }))
 }
 
-async fn disconnect(state: State, conn: Connection) {
+/// Remove a connection and notify the peers in its sessions.
+///
+/// Both the read and write tasks may disconnect so peers
+/// are only notified by the call that removes the connection.
+pub(crate) async fn disconnect(state: State, conn: Connection) {
     let (id, public_key) = {
         let reader = conn.read().await;
         (reader.id, reader.public_key.clone())
     };
     tracing::debug!(public_key = ?hex::encode(&public_key), "disconnect");
-    let mut writer = state.write().await;
-    writer.pending.remove(&id);
-    writer.active.remove(&public_key);
+    let sessions = {
+        let mut writer = state.write().await;
+        let pending = writer.pending.remove(&id).is_some();
+        let active = writer
+            .active
+            .get(&public_key)
+            .map(|active| Arc::ptr_eq(active, &conn))
+            .unwrap_or(false);
+        if active {
+            writer.active.remove(&public_key);
+        }
+        if !pending && !active {
+            return;
+        }
+        writer.sessions.disconnect_participant(&public_key)
+    };
+    if let Err(e) =
+        notify_peer_left(state, public_key, sessions).await
+    {
+        tracing::error!("{:#?}", e);
+    }
 }
 
 async fn handle_socket(
@@ -44,11 +44,7 @@ pub async fn new_client_with_keypair<E: From<mpc_client::Error>>(
     server_public_key: Vec<u8>,
     keypair: Keypair,
 ) -> Result<(Client, EventLoop), E> {
-    let options = ClientOptions {
-        keypair,
-        server_public_key,
-        pattern: None,
-    };
+    let options = ClientOptions::new(keypair, server_public_key);
     let url = options.url(server);
     let (client, event_loop) = Client::new(&url, options).await?;
     Ok((client, event_loop))
@@ -42,7 +42,7 @@ pub async fn run(
                     .new_session(session_participants.clone())
                     .await?;
             }
-            Event::SessionTimeout(_) => {
+            Event::SessionTimeout { .. } => {
                 break;
             }
             _ => {}